@@ -73,6 +73,7 @@ update_audit_map_entry(bpf_sock_addr_t *ctx)
     }
     entry.destination_ipv4 = ctx->user_ip4; // we only support ipv4 so far.
     entry.destination_port = ctx->user_port;
+    entry.address_family = AF_INET;
     uint16_t source_port = ctx->msg_src_port;
     if (source_port == 0)
     {
@@ -39,6 +39,8 @@ typedef struct _sock_addr_audit_entry{
     int32_t is_admin;
     uint32_t destination_ipv4;
     uint16_t destination_port;
+    uint16_t address_family;      // AF_INET or AF_INET6
+    uint8_t destination_ipv6[16]; // set when address_family is AF_INET6
 }sock_addr_audit_entry_t;
 
 typedef struct _sock_addr_skip_process_entry{
@@ -79,6 +79,7 @@ update_local_map_entry(struct bpf_sock_addr *ctx)
     entry.destination_ipv4 = ctx->user_ip4; // we only support ipv4 so far.
     entry.destination_port = ctx->user_port;
     entry.protocol = ctx->protocol;
+    entry.address_family = AF_INET;
 
     __u64 ret = bpf_map_update_elem(&local_map, &pid_tip, &entry, 0);
     if (ret != 0)
@@ -154,6 +155,8 @@ update_audit_map_entry_sk(__u32 local_port, sock_addr_local_entry *local_entry)
     entry.is_root = local_entry->is_root;
     entry.destination_ipv4 = local_entry->destination_ipv4;
     entry.destination_port = local_entry->destination_port;
+    entry.address_family = local_entry->address_family;
+    __builtin_memcpy(entry.destination_ipv6, local_entry->destination_ipv6, sizeof(entry.destination_ipv6));
 
     __u64 ret = bpf_map_update_elem(&audit_map, &key, &entry, 0);
     if (ret != 0)
@@ -230,6 +233,7 @@ trace_v4(struct pt_regs *ctx, struct probe_sock *sk)
         entry.is_root = (uid == 0) ? 1 : 0; // root uid is 0.
         entry.destination_ipv4 = skc.skc_daddr;
         entry.destination_port = skc.skc_dport;
+        entry.address_family = AF_INET;
 
         __u64 ret = bpf_map_update_elem(&audit_map, &key, &entry, 0);
         if (ret != 0)
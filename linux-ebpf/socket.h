@@ -3,6 +3,7 @@
 #define BPF_SOCK_ADDR_VERDICT_PROCEED 1
 #define IPPROTO_TCP 6
 #define AF_INET 2 
+#define AF_INET6 10
 
 typedef struct _sock_addr_skip_process_entry
 {
@@ -38,6 +39,8 @@ typedef struct _sock_addr_audit_entry
     __u32 is_root;
     __u32 destination_ipv4;
     __u32 destination_port;
+    __u32 address_family;      // AF_INET or AF_INET6
+    __u32 destination_ipv6[4]; // set when address_family is AF_INET6
 } sock_addr_audit_entry;
 
 typedef struct _bpf_sock_tuple_ipv4
@@ -56,6 +59,8 @@ typedef struct _sock_addr_local_entry
     __u32 destination_ipv4;
     __u32 destination_port;
     __u32 protocol;
+    __u32 address_family;      // AF_INET or AF_INET6
+    __u32 destination_ipv6[4]; // set when address_family is AF_INET6
 } sock_addr_local_entry;
 
 typedef __u32 __bitwise __portpair;
@@ -1,5 +1,6 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+pub mod cidr;
 pub mod config;
 pub mod helpers;
 pub mod http;
@@ -0,0 +1,159 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr};
+
+/*
+CIDR notation of an ip address range, e.g. "127.0.0.0/8" or "fe80::/10".
+A plain ip address without the prefix length matches that single address.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn parse(cidr: &str) -> std::io::Result<Self> {
+        let cidr = cidr.trim();
+        let (ip, prefix_len) = match cidr.split_once('/') {
+            Some((ip, len)) => (ip, Some(len)),
+            None => (cidr, None),
+        };
+
+        let network = match ip.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(e) => {
+                let message = format!("Invalid ip address in CIDR '{}', error {}", cidr, e);
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        };
+        let max_prefix_len = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match prefix_len {
+            Some(len) => match len.parse::<u8>() {
+                Ok(len) if len <= max_prefix_len => len,
+                _ => {
+                    let message = format!("Invalid prefix length in CIDR '{}'", cidr);
+                    return Err(Error::new(ErrorKind::InvalidInput, message));
+                }
+            },
+            None => max_prefix_len,
+        };
+
+        Ok(Cidr {
+            network,
+            prefix_len,
+        })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(*ip) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+                Some(ip) => self.contains(&IpAddr::V4(ip)),
+                None => false,
+            },
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+
+    // the addresses in the ipv4 range, fails if it is an ipv6 range or has more than max_count addresses
+    pub fn ipv4_addresses(&self, max_count: usize) -> std::io::Result<Vec<Ipv4Addr>> {
+        let network = match self.network {
+            IpAddr::V4(ip) => u32::from(ip),
+            IpAddr::V6(_) => {
+                let message = format!("CIDR '{}' is not an ipv4 range", self);
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        };
+        let count = 1u64 << (32 - self.prefix_len as u32);
+        if count > max_count as u64 {
+            let message = format!(
+                "CIDR '{}' has {} addresses, more than {}",
+                self, count, max_count
+            );
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        let mask = u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0);
+        let first = network & mask;
+        Ok((0..count as u32)
+            .map(|i| Ipv4Addr::from(first + i))
+            .collect())
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cidr;
+    use std::net::IpAddr;
+
+    #[test]
+    fn cidr_test() {
+        let cidr = Cidr::parse("127.0.0.0/8").unwrap();
+        assert!(cidr.contains(&"127.0.0.1".parse::<IpAddr>().unwrap()));
+        assert!(cidr.contains(&"127.255.255.254".parse::<IpAddr>().unwrap()));
+        assert!(!cidr.contains(&"10.0.0.1".parse::<IpAddr>().unwrap()));
+        assert!(
+            cidr.contains(&"::ffff:127.0.0.1".parse::<IpAddr>().unwrap()),
+            "ipv4 mapped ipv6 address must match the ipv4 CIDR"
+        );
+        assert_eq!("127.0.0.0/8", cidr.to_string());
+
+        let cidr = Cidr::parse("10.1.2.3").unwrap();
+        assert!(cidr.contains(&"10.1.2.3".parse::<IpAddr>().unwrap()));
+        assert!(!cidr.contains(&"10.1.2.4".parse::<IpAddr>().unwrap()));
+
+        let cidr = Cidr::parse("0.0.0.0/0").unwrap();
+        assert!(cidr.contains(&"168.63.129.16".parse::<IpAddr>().unwrap()));
+
+        let cidr = Cidr::parse("fe80::/10").unwrap();
+        assert!(cidr.contains(&"fe80::1".parse::<IpAddr>().unwrap()));
+        assert!(!cidr.contains(&"::1".parse::<IpAddr>().unwrap()));
+        assert!(!cidr.contains(&"127.0.0.1".parse::<IpAddr>().unwrap()));
+
+        assert!(Cidr::parse("127.0.0.1/33").is_err());
+        assert!(Cidr::parse("127.0.0/8").is_err());
+        assert!(Cidr::parse("::1/129").is_err());
+
+        let addresses = Cidr::parse("10.0.0.5/30")
+            .unwrap()
+            .ipv4_addresses(4)
+            .unwrap();
+        assert_eq!(
+            vec!["10.0.0.4", "10.0.0.5", "10.0.0.6", "10.0.0.7"],
+            addresses
+                .iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<String>>()
+        );
+        assert!(Cidr::parse("10.0.0.0/29")
+            .unwrap()
+            .ipv4_addresses(4)
+            .is_err());
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().ipv4_addresses(4).is_err());
+        assert!(Cidr::parse("fe80::1").unwrap().ipv4_addresses(4).is_err());
+    }
+}
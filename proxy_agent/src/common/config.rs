@@ -1,10 +1,13 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+use crate::common::cidr::Cidr;
 use crate::common::constants;
 use once_cell::sync::Lazy;
 use proxy_agent_shared::misc_helpers;
 use serde_derive::{Deserialize, Serialize};
-use std::{env, path::PathBuf, time::Duration};
+use std::{env, net::Ipv4Addr, path::PathBuf, time::Duration};
+
+const REDACTED: &str = "<redacted>";
 
 #[cfg(not(windows))]
 const CONFIG_FILE_NAME: &str = "proxy-agent.json";
@@ -17,6 +20,11 @@ static SYSTEM_CONFIG: Lazy<Config> = Lazy::new(|| Config::default());
 pub fn get_cgroup_root() -> PathBuf {
     SYSTEM_CONFIG.get_cgroup_root()
 }
+// the unix domain socket serving the internal endpoints to the local tooling, None to disable it
+#[cfg(not(windows))]
+pub fn get_control_socket_path() -> Option<PathBuf> {
+    SYSTEM_CONFIG.get_control_socket_path()
+}
 pub fn get_logs_dir() -> PathBuf {
     PathBuf::from(SYSTEM_CONFIG.get_log_folder())
 }
@@ -53,6 +61,282 @@ pub fn get_ebpf_program_name() -> String {
     SYSTEM_CONFIG.get_ebpf_program_name().to_string()
 }
 
+pub fn get_audit_map_warning_threshold() -> u8 {
+    SYSTEM_CONFIG.get_audit_map_warning_threshold()
+}
+
+// the max entries of the audit map, None keeps the size built in the eBPF program
+pub fn get_audit_map_max_entries() -> Option<u32> {
+    SYSTEM_CONFIG.get_audit_map_max_entries()
+}
+
+pub fn get_forward_client_ip() -> bool {
+    SYSTEM_CONFIG.get_forward_client_ip()
+}
+
+// hashing the large binaries is expensive, the results are cached per binary
+pub fn get_collect_process_integrity() -> bool {
+    SYSTEM_CONFIG.get_collect_process_integrity()
+}
+
+// None means the clients are not restricted by the source ip address
+pub fn get_allowed_client_cidrs() -> Option<Vec<String>> {
+    SYSTEM_CONFIG.get_allowed_client_cidrs()
+}
+
+// check the config values once at startup, the error lists every invalid field
+pub fn validate() -> std::io::Result<()> {
+    SYSTEM_CONFIG.validate()
+}
+
+// the effective config values in json, used to report what the running process actually decided
+pub fn get_effective_config() -> String {
+    SYSTEM_CONFIG.get_effective_config()
+}
+
+#[cfg(feature = "fault-injection")]
+pub fn get_fault_injection() -> Option<FaultInjection> {
+    SYSTEM_CONFIG.get_fault_injection()
+}
+
+// the OTLP/HTTP traces endpoint, e.g. "http://127.0.0.1:4318/v1/traces", None means the spans are not exported
+#[cfg(feature = "otel")]
+pub fn get_otlp_traces_endpoint() -> Option<String> {
+    SYSTEM_CONFIG.get_otlp_traces_endpoint()
+}
+
+// None means the cached users never expire
+pub fn get_user_cache_ttl() -> Option<Duration> {
+    SYSTEM_CONFIG.get_user_cache_ttl().map(Duration::from_secs)
+}
+
+pub fn get_slow_request_threshold() -> Duration {
+    Duration::from_millis(SYSTEM_CONFIG.get_slow_request_threshold())
+}
+
+// 1 in N successful connection summaries is emitted as event
+pub fn get_connection_summary_sample_rate() -> u32 {
+    SYSTEM_CONFIG.get_connection_summary_sample_rate()
+}
+
+// reject, retry or forward
+pub fn get_invalid_audit_entry_policy() -> String {
+    SYSTEM_CONFIG.get_invalid_audit_entry_policy()
+}
+
+// fail, retry or forward
+pub fn get_signature_failure_policy() -> String {
+    SYSTEM_CONFIG.get_signature_failure_policy()
+}
+
+// preserve, destination or override
+pub fn get_host_header_policy() -> String {
+    SYSTEM_CONFIG.get_host_header_policy()
+}
+
+pub fn get_host_header_override() -> Option<String> {
+    SYSTEM_CONFIG.get_host_header_override()
+}
+
+pub fn get_shared_config_fetch_timeout() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_shared_config_fetch_timeout())
+}
+
+pub fn get_metadata_fetch_concurrency() -> usize {
+    SYSTEM_CONFIG.get_metadata_fetch_concurrency()
+}
+
+// how long the proxy listener waits for the in-flight connections at shutdown
+pub fn get_shutdown_grace_period() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_shutdown_grace_period())
+}
+
+// the in-flight connections are aborted after the grace period, and left behind at the hard deadline
+pub fn get_shutdown_hard_deadline() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_shutdown_hard_deadline())
+}
+
+// bounds the connect to host, and the wait for host to respond after the request is sent
+pub fn get_proxy_upstream_timeout() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_proxy_upstream_timeout())
+}
+
+// the max number of the connections queued or handled by the proxy listener, 0 means no limit
+pub fn get_max_active_connections() -> usize {
+    SYSTEM_CONFIG.get_max_active_connections()
+}
+
+// reject or wait
+pub fn get_connection_limit_policy() -> String {
+    SYSTEM_CONFIG.get_connection_limit_policy()
+}
+
+// the max body size of the signed requests, which are buffered to compute the signature
+pub fn get_request_body_low_limit_size() -> usize {
+    SYSTEM_CONFIG.get_request_body_low_limit_size()
+}
+
+// the max body size of the requests sent to host without signature
+pub fn get_request_body_large_limit_size() -> usize {
+    SYSTEM_CONFIG.get_request_body_large_limit_size()
+}
+
+// true to stream the unsigned request body to host without the large limit
+pub fn get_stream_unsigned_request_body() -> bool {
+    SYSTEM_CONFIG.get_stream_unsigned_request_body()
+}
+
+// true to keep the client connections alive and reuse their authorization decisions
+pub fn get_authorize_once_per_connection() -> bool {
+    SYSTEM_CONFIG.get_authorize_once_per_connection()
+}
+
+// the max body size of the host responses forwarded to the client, None means unlimited
+pub fn get_max_response_body_size() -> Option<usize> {
+    SYSTEM_CONFIG.get_max_response_body_size()
+}
+
+// the TLS settings of the upstream destination, None means the destination is connected over plain TCP
+pub fn get_upstream_tls(ip: &str, port: u16) -> Option<UpstreamTls> {
+    SYSTEM_CONFIG.get_upstream_tls(ip, port)
+}
+
+// the request rate allowed per client ip, 0 disables the rate limit
+pub fn get_rate_limit_requests_per_second() -> f64 {
+    SYSTEM_CONFIG.get_rate_limit_requests_per_second()
+}
+
+pub fn get_rate_limit_burst() -> u32 {
+    SYSTEM_CONFIG.get_rate_limit_burst()
+}
+
+pub fn get_upstream_retry_count() -> u32 {
+    SYSTEM_CONFIG.get_upstream_retry_count()
+}
+
+pub fn get_upstream_idle_timeout() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_upstream_idle_timeout())
+}
+
+pub fn get_tunnel_idle_timeout() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_tunnel_idle_timeout())
+}
+
+// max idle connections kept alive per upstream endpoint, 0 disables the reuse
+pub fn get_upstream_max_idle_connections() -> usize {
+    SYSTEM_CONFIG.get_upstream_max_idle_connections()
+}
+
+// the consecutive upstream failures to open the circuit of a destination, 0 disables the circuit breaker
+pub fn get_circuit_breaker_failure_threshold() -> u32 {
+    SYSTEM_CONFIG.get_circuit_breaker_failure_threshold()
+}
+
+pub fn get_circuit_breaker_window() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_circuit_breaker_window())
+}
+
+pub fn get_circuit_breaker_cool_down() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_circuit_breaker_cool_down())
+}
+
+// max retries of the WireServer and IMDS requests failed by the connection errors or 5xx
+pub fn get_wire_server_retry_count() -> u32 {
+    SYSTEM_CONFIG.get_wire_server_retry_count()
+}
+
+// the delay before the first retry, it doubles with each retry
+pub fn get_wire_server_retry_initial_delay() -> Duration {
+    Duration::from_millis(SYSTEM_CONFIG.get_wire_server_retry_initial_delay())
+}
+
+// stop retrying the WireServer request once the total duration is reached
+pub fn get_wire_server_retry_max_duration() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_wire_server_retry_max_duration())
+}
+
+// bound the connect and each read/write of a WireServer or IMDS request
+pub fn get_wire_server_request_timeout() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_wire_server_request_timeout())
+}
+
+// reuse the cached goal state until the ttl expires, 0 disables the cache
+pub fn get_goal_state_cache_ttl() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_goal_state_cache_ttl())
+}
+
+// retries after the redirector failed to start, e.g. the eBPF program is not ready yet
+pub fn get_redirector_start_retry_count() -> u32 {
+    SYSTEM_CONFIG.get_redirector_start_retry_count()
+}
+
+pub fn get_redirector_start_retry_delay() -> Duration {
+    Duration::from_millis(SYSTEM_CONFIG.get_redirector_start_retry_delay())
+}
+
+// write the per-connection log as json lines when it is 'json', the text format otherwise
+pub fn get_connection_log_format() -> String {
+    SYSTEM_CONFIG.get_connection_log_format()
+}
+
+// reuse the looked up process of a pid until the ttl expires, 0 disables the cache
+pub fn get_process_cache_ttl() -> Duration {
+    Duration::from_millis(SYSTEM_CONFIG.get_process_cache_ttl())
+}
+
+// the most recent connection summaries kept for the diagnostic endpoint, 0 disables it
+pub fn get_recent_connection_summary_count() -> usize {
+    SYSTEM_CONFIG.get_recent_connection_summary_count()
+}
+
+// compare the privilege paths and the identity names case-insensitively in the authorization rules
+pub fn get_case_insensitive_match() -> bool {
+    SYSTEM_CONFIG.get_case_insensitive_match()
+}
+
+// the destination ranges redirected in addition to, or excluded from, the host endpoints
+pub fn get_redirect_destinations() -> Vec<RedirectDestination> {
+    SYSTEM_CONFIG.get_redirect_destinations()
+}
+
+// the destination ranges the requests are forwarded to without the signature
+pub fn get_skip_signature_destinations() -> Vec<SkipSignatureDestination> {
+    SYSTEM_CONFIG.get_skip_signature_destinations()
+}
+
+// the client request headers removed before the request is forwarded to host
+pub fn get_request_header_deny_list() -> Vec<String> {
+    SYSTEM_CONFIG.get_request_header_deny_list()
+}
+
+// None means all the client request headers not denied are forwarded to host
+pub fn get_request_header_allow_list() -> Option<Vec<String>> {
+    SYSTEM_CONFIG.get_request_header_allow_list()
+}
+
+// the requests from these processes are always rejected, before the authorization rules
+pub fn get_denied_process_paths() -> Vec<String> {
+    SYSTEM_CONFIG.get_denied_process_paths()
+}
+
+// None means the proxy listener listens to all the local addresses
+pub fn get_listener_address() -> Option<Ipv4Addr> {
+    SYSTEM_CONFIG.get_listener_address()
+}
+
+// None means all the request methods are allowed
+pub fn get_allowed_methods() -> Option<Vec<String>> {
+    SYSTEM_CONFIG.get_allowed_methods()
+}
+
+pub fn get_key_absent_warning_duration() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_key_absent_warning_interval())
+}
+
+pub fn get_key_absent_critical_duration() -> Duration {
+    Duration::from_secs(SYSTEM_CONFIG.get_key_absent_critical_interval())
+}
+
 #[cfg(not(windows))]
 pub fn get_fallback_with_iptable_redirect() -> bool {
     SYSTEM_CONFIG.get_fallback_with_iptable_redirect()
@@ -75,12 +359,174 @@ pub struct Config {
     maxEventFileCount: Option<usize>,
     ebpfProgramName: String,
     #[serde(skip_serializing_if = "Option::is_none")]
+    auditMapWarningThreshold: Option<u8>, // percentage of the audit map capacity to emit the warning event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auditMapMaxEntries: Option<u32>, // caps the audit map size, the occupancy is reported against it; the size built in the eBPF program by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    forwardClientIp: Option<bool>, // true to append the client ip to the X-Forwarded-For header of the upstream request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collectProcessIntegrity: Option<bool>, // true to add the process binary sha256, and its signer on Windows, to the claims
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deniedProcessPaths: Option<Vec<String>>, // process full paths always rejected, '*' matches any characters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listenerAddress: Option<String>, // bind the proxy listener and redirect the connections to this ipv4 address only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowNonLoopbackListener: Option<bool>, // true to allow a listenerAddress reachable from the other hosts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keyAbsentWarningIntervalInSeconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keyAbsentCriticalIntervalInSeconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowedClientCidrs: Option<Vec<String>>, // client source ip ranges the proxy listener serves, e.g. "127.0.0.0/8"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    userCacheTtlInSeconds: Option<u64>, // expire the cached user name and groups after the ttl
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg(feature = "fault-injection")]
+    faultInjection: Option<FaultInjection>, // test only, inject faults to the upstream requests
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg(feature = "otel")]
+    otlpTracesEndpoint: Option<String>, // export the request spans to this OTLP/HTTP endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redactConfigPaths: Option<bool>, // true to redact the folder paths from the effective config event
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slowRequestThresholdInMilliseconds: Option<u64>, // emit the slow request warning event when a request takes longer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connectionSummarySampleRate: Option<u32>, // emit 1 in N 2xx connection summary events, the others are always emitted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    invalidAuditEntryPolicy: Option<String>, // reject, retry or forward the request when its audit entry is invalid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signatureFailurePolicy: Option<String>, // fail, retry or forward unsigned the request when it cannot be signed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostHeaderPolicy: Option<String>, // preserve, or rewrite to the destination or the override the upstream Host header
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hostHeaderOverride: Option<String>, // the upstream Host header with the override policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sharedConfigFetchTimeoutInSeconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadataFetchConcurrency: Option<usize>, // max number of the independent vm metadata fetches running in parallel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shutdownGracePeriodInSeconds: Option<u64>, // wait for the in-flight connections to finish at shutdown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shutdownHardDeadlineInSeconds: Option<u64>, // stop waiting for the aborted connections at shutdown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxyUpstreamTimeoutInSeconds: Option<u64>, // respond 504 to the client if the host does not respond in time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maxActiveConnections: Option<usize>, // the limit of the connections queued or handled by the proxy listener
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connectionLimitPolicy: Option<String>, // reject with 503 or wait to accept the new connections at maxActiveConnections
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requestBodyLowLimitSize: Option<usize>, // in bytes, respond 413 to the signed requests with larger body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requestBodyLargeLimitSize: Option<usize>, // in bytes, respond 413 to the unsigned requests with larger body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    streamUnsignedRequestBody: Option<bool>, // true to stream the unsigned request body to host instead of buffering it up to requestBodyLargeLimitSize
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authorizeOncePerConnection: Option<bool>, // true to keep the client connections alive and reuse the authorization decision while the claims, destination and path stay the same
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maxResponseBodySize: Option<usize>, // in bytes, terminate the host response with larger body, unlimited by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rateLimitRequestsPerSecond: Option<f64>, // refill rate of the per client ip token bucket
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rateLimitBurst: Option<u32>, // size of the per client ip token bucket
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstreamRetryCount: Option<u32>, // max retries of the idempotent requests failed by the connection errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstreamIdleTimeoutInSeconds: Option<u64>, // close the idle upstream connection after this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstreamMaxIdleConnections: Option<usize>, // keep up to this number of idle connections per upstream endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tunnelIdleTimeoutInSeconds: Option<u64>, // close the CONNECT tunnel without data in either direction for this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuitBreakerFailureThreshold: Option<u32>, // open the circuit of a destination after this many consecutive failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuitBreakerWindowInSeconds: Option<u64>, // the consecutive failures are counted within this window
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuitBreakerCoolDownInSeconds: Option<u64>, // reject the requests to an open circuit for this time before a probe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wireServerRetryCount: Option<u32>, // max retries of the WireServer requests, 0 disables the retry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wireServerRetryInitialDelayInMilliseconds: Option<u64>, // exponential backoff with jitter from this delay
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wireServerRetryMaxDurationInSeconds: Option<u64>, // max total duration of a WireServer request with its retries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wireServerRequestTimeoutInSeconds: Option<u64>, // fail the WireServer request if the host does not respond in time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    goalStateCacheTtlInSeconds: Option<u64>, // re-fetch the goal state from the WireServer after the ttl
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirectorStartRetryCount: Option<u32>, // extend it on the constrained hardware where the eBPF program is slow to be ready
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirectorStartRetryDelayInMilliseconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connectionLogFormat: Option<String>, // 'text' or 'json', the json lines carry the connection id as a field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    processCacheTtlInMilliseconds: Option<u64>, // the process path and command line of a pid are cached for the ttl
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recentConnectionSummaryCount: Option<usize>, // size of the ring buffer of the latest connection summaries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    caseInsensitiveMatch: Option<bool>, // default to true on Windows and false on Linux
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstreamTls: Option<Vec<UpstreamTls>>, // the upstream destinations connected over TLS
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirectDestinations: Option<Vec<RedirectDestination>>, // extra destination ranges to redirect or exclude
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipSignatureDestinations: Option<Vec<SkipSignatureDestination>>, // never sign the requests to these destination ranges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requestHeaderDenyList: Option<Vec<String>>, // header names removed from the client requests, case-insensitive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requestHeaderAllowList: Option<Vec<String>>, // only these client request headers are forwarded when set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowedMethods: Option<Vec<String>>, // respond 405 to the other request methods when set, case-insensitive
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[cfg(not(windows))]
     cgroupRoot: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg(not(windows))]
+    controlSocketPath: Option<String>, // serve the read-only internal endpoints on this unix domain socket too
     #[cfg(not(windows))]
     fallBackWithIpTableRedirect: Option<bool>, // fallback to iptable redirect if cgroup redirect is not supported, it should only be use for old kernel, some scenario like docker container may not work
 }
 
+#[cfg(feature = "fault-injection")]
+#[derive(Serialize, Deserialize, Clone)]
+#[allow(non_snake_case)]
+pub struct FaultInjection {
+    pub faultType: String, // fail, delay or status
+    pub rate: f64,         // fraction of the upstream requests to inject the fault, 0.0 ~ 1.0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delayInMilliseconds: Option<u64>, // for delay fault type
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>, // for status fault type, e.g. "503 Service Unavailable"
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[allow(non_snake_case)]
+pub struct UpstreamTls {
+    pub ip: String,
+    pub port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serverName: Option<String>, // the name to validate the server certificate with, default to the ip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caCertificatePath: Option<String>, // PEM file of the trusted root certificates, default to the public roots
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[allow(non_snake_case)]
+pub struct RedirectDestination {
+    pub cidr: String, // ipv4 range, e.g. "10.0.0.4/31"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>, // required to redirect, an excluded range without port matches all ports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<bool>, // true to never redirect the range, including the host endpoints in it
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[allow(non_snake_case)]
+pub struct SkipSignatureDestination {
+    pub cidr: String, // e.g. "10.0.0.4/31"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>, // None matches all ports
+}
+
 impl Config {
     pub fn from_json_file(file_path: PathBuf) -> Self {
         misc_helpers::json_read_from_file::<Config>(file_path.to_path_buf()).expect(&format!(
@@ -89,6 +535,290 @@ impl Config {
         ))
     }
 
+    // read the config file and check its values, fails instead of panicking on the invalid config
+    pub fn load_and_validate(file_path: PathBuf) -> std::io::Result<Self> {
+        let config = misc_helpers::json_read_from_file::<Config>(file_path.to_path_buf())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> std::io::Result<()> {
+        let mut errors = Vec::new();
+        for (name, value) in [
+            ("logFolder", self.get_log_folder()),
+            ("eventFolder", self.get_event_folder()),
+            ("latchKeyFolder", self.get_latch_key_folder()),
+            ("ebpfProgramName", self.get_ebpf_program_name()),
+        ] {
+            if value.trim().is_empty() {
+                errors.push(format!("{} must not be empty", name));
+            }
+        }
+        for (name, support) in [
+            ("wireServerSupport", self.get_wire_server_support()),
+            ("hostGAPluginSupport", self.get_host_gaplugin_support()),
+            ("imdsSupport", self.get_imds_support()),
+        ] {
+            if support > 2 {
+                errors.push(format!("{} {} is not 0, 1 or 2", name, support));
+            }
+        }
+        for (name, value) in [
+            ("monitorIntervalInSeconds", self.get_monitor_interval()),
+            (
+                "pollKeyStatusIntervalInSeconds",
+                self.get_poll_key_status_interval(),
+            ),
+            (
+                "wireServerRequestTimeoutInSeconds",
+                self.get_wire_server_request_timeout(),
+            ),
+            (
+                "proxyUpstreamTimeoutInSeconds",
+                self.get_proxy_upstream_timeout(),
+            ),
+            ("tunnelIdleTimeoutInSeconds", self.get_tunnel_idle_timeout()),
+        ] {
+            if value == 0 {
+                errors.push(format!("{} must be greater than 0", name));
+            }
+        }
+        for (name, value) in [
+            (
+                "metadataFetchConcurrency",
+                self.get_metadata_fetch_concurrency(),
+            ),
+            ("maxActiveConnections", self.get_max_active_connections()),
+        ] {
+            if value == 0 {
+                errors.push(format!("{} must be greater than 0", name));
+            }
+        }
+        if self.get_audit_map_warning_threshold() > 100 {
+            errors.push(format!(
+                "auditMapWarningThreshold {} is over 100 percent",
+                self.get_audit_map_warning_threshold()
+            ));
+        }
+        if self.get_key_absent_warning_interval() > self.get_key_absent_critical_interval() {
+            errors.push(
+                "keyAbsentWarningIntervalInSeconds must not exceed keyAbsentCriticalIntervalInSeconds"
+                    .to_string(),
+            );
+        }
+        if self.get_request_body_low_limit_size() > self.get_request_body_large_limit_size() {
+            errors.push(
+                "requestBodyLowLimitSize must not exceed requestBodyLargeLimitSize".to_string(),
+            );
+        }
+        if self.get_audit_map_max_entries() == Some(0) {
+            errors.push("auditMapMaxEntries must be greater than 0".to_string());
+        }
+        if self.get_max_response_body_size() == Some(0) {
+            errors.push("maxResponseBodySize must be greater than 0".to_string());
+        }
+        if self.get_shutdown_hard_deadline() <= self.get_shutdown_grace_period() {
+            errors.push(format!(
+                "shutdownHardDeadlineInSeconds {} must be greater than shutdownGracePeriodInSeconds {}",
+                self.get_shutdown_hard_deadline(),
+                self.get_shutdown_grace_period()
+            ));
+        }
+        if self.get_connection_summary_sample_rate() == 0 {
+            errors.push("connectionSummarySampleRate must be greater than 0".to_string());
+        }
+        let rate = self.get_rate_limit_requests_per_second();
+        if !rate.is_finite() || rate < 0.0 {
+            errors.push(format!("rateLimitRequestsPerSecond {} is not valid", rate));
+        } else if rate > 0.0 && self.get_rate_limit_burst() == 0 {
+            errors.push("rateLimitBurst must be greater than 0 with the rate limit".to_string());
+        }
+        if self.get_circuit_breaker_failure_threshold() > 0 {
+            if self.get_circuit_breaker_window() == 0 {
+                errors.push(
+                    "circuitBreakerWindowInSeconds must be greater than 0 with the circuit breaker"
+                        .to_string(),
+                );
+            }
+            if self.get_circuit_breaker_cool_down() == 0 {
+                errors.push(
+                    "circuitBreakerCoolDownInSeconds must be greater than 0 with the circuit breaker"
+                        .to_string(),
+                );
+            }
+        }
+        let policy = self.get_invalid_audit_entry_policy();
+        if ![
+            constants::INVALID_AUDIT_ENTRY_REJECT,
+            constants::INVALID_AUDIT_ENTRY_RETRY,
+            constants::INVALID_AUDIT_ENTRY_FORWARD,
+        ]
+        .contains(&policy.as_str())
+        {
+            errors.push(format!("invalidAuditEntryPolicy '{}' is not valid", policy));
+        }
+        let policy = self.get_signature_failure_policy();
+        if ![
+            constants::SIGNATURE_FAILURE_FAIL,
+            constants::SIGNATURE_FAILURE_RETRY,
+            constants::SIGNATURE_FAILURE_FORWARD,
+        ]
+        .contains(&policy.as_str())
+        {
+            errors.push(format!("signatureFailurePolicy '{}' is not valid", policy));
+        }
+        let policy = self.get_connection_limit_policy();
+        if ![
+            constants::CONNECTION_LIMIT_REJECT,
+            constants::CONNECTION_LIMIT_WAIT,
+        ]
+        .contains(&policy.as_str())
+        {
+            errors.push(format!("connectionLimitPolicy '{}' is not valid", policy));
+        }
+        let policy = self.get_host_header_policy();
+        if ![
+            constants::HOST_HEADER_PRESERVE,
+            constants::HOST_HEADER_DESTINATION,
+            constants::HOST_HEADER_OVERRIDE,
+        ]
+        .contains(&policy.as_str())
+        {
+            errors.push(format!("hostHeaderPolicy '{}' is not valid", policy));
+        }
+        match self.get_host_header_override() {
+            Some(host) if host.is_empty() || !host.chars().all(|c| c.is_ascii_graphic()) => {
+                errors.push(format!("hostHeaderOverride '{}' is not valid", host));
+            }
+            None if policy == constants::HOST_HEADER_OVERRIDE => {
+                errors.push("hostHeaderOverride is required by the override policy".to_string());
+            }
+            _ => {}
+        }
+        let format = self.get_connection_log_format();
+        if format != constants::TEXT_LOG_FORMAT && format != constants::JSON_LOG_FORMAT {
+            errors.push(format!("connectionLogFormat '{}' is not valid", format));
+        }
+        for cidr in self.get_allowed_client_cidrs().unwrap_or_default() {
+            if let Err(e) = Cidr::parse(&cidr) {
+                errors.push(format!("allowedClientCidrs: {}", e));
+            }
+        }
+        for tls in self.upstreamTls.iter().flatten() {
+            if tls.ip.parse::<std::net::IpAddr>().is_err() || tls.port == 0 {
+                errors.push(format!(
+                    "upstreamTls destination {}:{} is not valid",
+                    tls.ip, tls.port
+                ));
+            }
+        }
+        for destination in self.get_redirect_destinations() {
+            if let Err(e) = Cidr::parse(&destination.cidr) {
+                errors.push(format!("redirectDestinations: {}", e));
+            } else if !destination.exclude.unwrap_or(false) && destination.port.is_none() {
+                errors.push(format!(
+                    "redirectDestinations: port is required to redirect CIDR '{}'",
+                    destination.cidr
+                ));
+            }
+        }
+        for destination in self.get_skip_signature_destinations() {
+            if let Err(e) = Cidr::parse(&destination.cidr) {
+                errors.push(format!("skipSignatureDestinations: {}", e));
+            }
+        }
+        let header_lists = [
+            ("requestHeaderDenyList", self.get_request_header_deny_list()),
+            (
+                "requestHeaderAllowList",
+                self.get_request_header_allow_list().unwrap_or_default(),
+            ),
+        ];
+        for (field, names) in header_lists {
+            for name in names {
+                if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic() && b != b':') {
+                    errors.push(format!("{}: header name '{}' is not valid", field, name));
+                }
+            }
+        }
+        for path in self.get_denied_process_paths() {
+            if path.trim().is_empty() || path.chars().any(|c| c.is_control()) {
+                errors.push(format!("deniedProcessPaths: path '{}' is not valid", path));
+            }
+        }
+        if let Some(address) = &self.listenerAddress {
+            match address.parse::<Ipv4Addr>() {
+                Ok(ip) if ip.is_multicast() || ip.is_broadcast() => {
+                    errors.push(format!("listenerAddress '{}' is not valid", address))
+                }
+                // the unspecified address is the default wildcard listener
+                Ok(ip)
+                    if !ip.is_loopback()
+                        && !ip.is_unspecified()
+                        && !self.get_allow_non_loopback_listener() =>
+                {
+                    errors.push(format!(
+                        "listenerAddress '{}' is not a loopback address, set allowNonLoopbackListener to bind to it",
+                        address
+                    ))
+                }
+                Ok(_) => {}
+                Err(_) => errors.push(format!("listenerAddress '{}' is not valid", address)),
+            }
+        }
+        for method in self.get_allowed_methods().unwrap_or_default() {
+            if method.is_empty() || !method.bytes().all(|b| b.is_ascii_graphic()) {
+                errors.push(format!("allowedMethods: method '{}' is not valid", method));
+            }
+        }
+        #[cfg(not(windows))]
+        {
+            if self.cgroupRoot.is_some() && !self.get_cgroup_root().is_dir() {
+                errors.push(format!(
+                    "cgroupRoot {} does not exist",
+                    misc_helpers::path_to_string(self.get_cgroup_root())
+                ));
+            }
+            if let Some(path) = self.get_control_socket_path() {
+                let parent_exists = path.parent().map_or(false, |parent| parent.is_dir());
+                if !path.is_absolute() || !parent_exists {
+                    errors.push(format!(
+                        "controlSocketPath {} must be an absolute path in an existing folder",
+                        misc_helpers::path_to_string(path)
+                    ));
+                }
+            }
+        }
+        #[cfg(feature = "fault-injection")]
+        {
+            if let Some(fault) = self.get_fault_injection() {
+                if !(0.0..=1.0).contains(&fault.rate) {
+                    errors.push(format!("faultInjection rate {} is not valid", fault.rate));
+                }
+            }
+        }
+        #[cfg(feature = "otel")]
+        {
+            if let Some(endpoint) = self.get_otlp_traces_endpoint() {
+                match url::Url::parse(&endpoint) {
+                    Ok(url) if url.scheme() == "http" => {}
+                    _ => errors.push(format!(
+                        "otlpTracesEndpoint '{}' must be an http url",
+                        endpoint
+                    )),
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid config: {}", errors.join("; ")),
+        ))
+    }
+
     pub fn default() -> Self {
         // get config file full path from environment variable
         let mut config_file_full_path =
@@ -124,38 +854,459 @@ impl Config {
         &self.latchKeyFolder
     }
 
-    pub fn get_start_redirector(&self) -> bool {
-        self.startRedirector
-            .unwrap_or(constants::DEFAULT_START_REDIRECTOR)
+    pub fn get_start_redirector(&self) -> bool {
+        self.startRedirector
+            .unwrap_or(constants::DEFAULT_START_REDIRECTOR)
+    }
+
+    pub fn get_monitor_interval(&self) -> u64 {
+        self.monitorIntervalInSeconds
+    }
+
+    pub fn get_poll_key_status_interval(&self) -> u64 {
+        self.pollKeyStatusIntervalInSeconds
+    }
+
+    pub fn get_wire_server_support(&self) -> u8 {
+        self.wireServerSupport
+    }
+
+    pub fn get_host_gaplugin_support(&self) -> u8 {
+        self.hostGAPluginSupport
+    }
+
+    pub fn get_imds_support(&self) -> u8 {
+        self.imdsSupport
+    }
+
+    pub fn get_max_event_file_count(&self) -> usize {
+        self.maxEventFileCount
+            .unwrap_or(constants::DEFAULT_MAX_EVENT_FILE_COUNT)
+    }
+
+    pub fn get_ebpf_program_name(&self) -> &str {
+        &self.ebpfProgramName
+    }
+
+    pub fn get_audit_map_warning_threshold(&self) -> u8 {
+        self.auditMapWarningThreshold
+            .unwrap_or(constants::DEFAULT_AUDIT_MAP_WARNING_THRESHOLD)
+    }
+
+    pub fn get_audit_map_max_entries(&self) -> Option<u32> {
+        self.auditMapMaxEntries
+    }
+
+    pub fn get_forward_client_ip(&self) -> bool {
+        self.forwardClientIp
+            .unwrap_or(constants::DEFAULT_FORWARD_CLIENT_IP)
+    }
+
+    pub fn get_collect_process_integrity(&self) -> bool {
+        self.collectProcessIntegrity
+            .unwrap_or(constants::DEFAULT_COLLECT_PROCESS_INTEGRITY)
+    }
+
+    pub fn get_denied_process_paths(&self) -> Vec<String> {
+        self.deniedProcessPaths.clone().unwrap_or_default()
+    }
+
+    // the unspecified address is the same as the default
+    pub fn get_listener_address(&self) -> Option<Ipv4Addr> {
+        self.listenerAddress
+            .as_ref()
+            .and_then(|address| address.parse::<Ipv4Addr>().ok())
+            .filter(|ip| !ip.is_unspecified())
+    }
+
+    pub fn get_allow_non_loopback_listener(&self) -> bool {
+        self.allowNonLoopbackListener
+            .unwrap_or(constants::DEFAULT_ALLOW_NON_LOOPBACK_LISTENER)
+    }
+
+    pub fn get_allowed_client_cidrs(&self) -> Option<Vec<String>> {
+        self.allowedClientCidrs.clone()
+    }
+
+    pub fn get_upstream_tls(&self, ip: &str, port: u16) -> Option<UpstreamTls> {
+        self.upstreamTls
+            .as_ref()?
+            .iter()
+            .find(|tls| tls.ip == ip && tls.port == port)
+            .cloned()
+    }
+
+    pub fn get_redact_config_paths(&self) -> bool {
+        self.redactConfigPaths
+            .unwrap_or(constants::DEFAULT_REDACT_CONFIG_PATHS)
+    }
+
+    // resolved config values after applying the defaults,
+    // the latch key folder is always redacted as it locates the signing key
+    pub fn get_effective_config(&self) -> String {
+        let redact_paths = self.get_redact_config_paths();
+        let path = |p: String| {
+            if redact_paths {
+                REDACTED.to_string()
+            } else {
+                p
+            }
+        };
+
+        #[allow(unused_mut)]
+        let mut effective = serde_json::json!({
+            "logFolder": path(self.get_log_folder().to_string()),
+            "eventFolder": path(self.get_event_folder().to_string()),
+            "latchKeyFolder": REDACTED,
+            "startRedirector": self.get_start_redirector(),
+            "monitorIntervalInSeconds": self.get_monitor_interval(),
+            "pollKeyStatusIntervalInSeconds": self.get_poll_key_status_interval(),
+            "wireServerSupport": self.get_wire_server_support(),
+            "hostGAPluginSupport": self.get_host_gaplugin_support(),
+            "imdsSupport": self.get_imds_support(),
+            "maxEventFileCount": self.get_max_event_file_count(),
+            "ebpfProgramName": self.get_ebpf_program_name(),
+            "auditMapWarningThreshold": self.get_audit_map_warning_threshold(),
+            "keyAbsentWarningIntervalInSeconds": self.get_key_absent_warning_interval(),
+            "keyAbsentCriticalIntervalInSeconds": self.get_key_absent_critical_interval(),
+            "allowedClientCidrs": self.get_allowed_client_cidrs(),
+            "userCacheTtlInSeconds": self.get_user_cache_ttl(),
+            "redactConfigPaths": redact_paths,
+            "slowRequestThresholdInMilliseconds": self.get_slow_request_threshold(),
+            "invalidAuditEntryPolicy": self.get_invalid_audit_entry_policy(),
+            "sharedConfigFetchTimeoutInSeconds": self.get_shared_config_fetch_timeout(),
+            "metadataFetchConcurrency": self.get_metadata_fetch_concurrency(),
+            "shutdownGracePeriodInSeconds": self.get_shutdown_grace_period(),
+            "proxyUpstreamTimeoutInSeconds": self.get_proxy_upstream_timeout(),
+            "maxActiveConnections": self.get_max_active_connections(),
+            "requestBodyLowLimitSize": self.get_request_body_low_limit_size(),
+            "requestBodyLargeLimitSize": self.get_request_body_large_limit_size(),
+            "rateLimitRequestsPerSecond": self.get_rate_limit_requests_per_second(),
+            "rateLimitBurst": self.get_rate_limit_burst(),
+            "upstreamRetryCount": self.get_upstream_retry_count(),
+            "upstreamIdleTimeoutInSeconds": self.get_upstream_idle_timeout(),
+            "upstreamMaxIdleConnections": self.get_upstream_max_idle_connections(),
+            "tunnelIdleTimeoutInSeconds": self.get_tunnel_idle_timeout(),
+            "wireServerRetryCount": self.get_wire_server_retry_count(),
+            "wireServerRetryInitialDelayInMilliseconds": self.get_wire_server_retry_initial_delay(),
+            "wireServerRetryMaxDurationInSeconds": self.get_wire_server_retry_max_duration(),
+            "wireServerRequestTimeoutInSeconds": self.get_wire_server_request_timeout(),
+            "goalStateCacheTtlInSeconds": self.get_goal_state_cache_ttl(),
+            "redirectorStartRetryCount": self.get_redirector_start_retry_count(),
+            "redirectorStartRetryDelayInMilliseconds": self.get_redirector_start_retry_delay(),
+            "caseInsensitiveMatch": self.get_case_insensitive_match(),
+            "upstreamTls": self.upstreamTls.as_ref().map(|destinations| {
+                destinations
+                    .iter()
+                    .map(|tls| UpstreamTls {
+                        caCertificatePath: tls.caCertificatePath.clone().map(path),
+                        ..tls.clone()
+                    })
+                    .collect::<Vec<UpstreamTls>>()
+            }),
+        });
+        effective["connectionLogFormat"] = serde_json::json!(self.get_connection_log_format());
+        effective["processCacheTtlInMilliseconds"] =
+            serde_json::json!(self.get_process_cache_ttl());
+        effective["recentConnectionSummaryCount"] =
+            serde_json::json!(self.get_recent_connection_summary_count());
+        effective["redirectDestinations"] = serde_json::json!(self.get_redirect_destinations());
+        effective["skipSignatureDestinations"] =
+            serde_json::json!(self.get_skip_signature_destinations());
+        effective["requestHeaderDenyList"] = serde_json::json!(self.get_request_header_deny_list());
+        effective["requestHeaderAllowList"] =
+            serde_json::json!(self.get_request_header_allow_list());
+        effective["allowedMethods"] = serde_json::json!(self.get_allowed_methods());
+        effective["maxResponseBodySize"] = serde_json::json!(self.get_max_response_body_size());
+        effective["auditMapMaxEntries"] = serde_json::json!(self.get_audit_map_max_entries());
+        effective["connectionSummarySampleRate"] =
+            serde_json::json!(self.get_connection_summary_sample_rate());
+        effective["forwardClientIp"] = serde_json::json!(self.get_forward_client_ip());
+        effective["collectProcessIntegrity"] =
+            serde_json::json!(self.get_collect_process_integrity());
+        effective["deniedProcessPaths"] = serde_json::json!(self.get_denied_process_paths());
+        effective["listenerAddress"] = serde_json::json!(self.get_listener_address());
+        effective["allowNonLoopbackListener"] =
+            serde_json::json!(self.get_allow_non_loopback_listener());
+        effective["connectionLimitPolicy"] = serde_json::json!(self.get_connection_limit_policy());
+        effective["streamUnsignedRequestBody"] =
+            serde_json::json!(self.get_stream_unsigned_request_body());
+        effective["authorizeOncePerConnection"] =
+            serde_json::json!(self.get_authorize_once_per_connection());
+        effective["shutdownHardDeadlineInSeconds"] =
+            serde_json::json!(self.get_shutdown_hard_deadline());
+        effective["signatureFailurePolicy"] =
+            serde_json::json!(self.get_signature_failure_policy());
+        effective["hostHeaderPolicy"] = serde_json::json!(self.get_host_header_policy());
+        effective["hostHeaderOverride"] = serde_json::json!(self.get_host_header_override());
+        effective["circuitBreakerFailureThreshold"] =
+            serde_json::json!(self.get_circuit_breaker_failure_threshold());
+        effective["circuitBreakerWindowInSeconds"] =
+            serde_json::json!(self.get_circuit_breaker_window());
+        effective["circuitBreakerCoolDownInSeconds"] =
+            serde_json::json!(self.get_circuit_breaker_cool_down());
+        #[cfg(not(windows))]
+        {
+            effective["cgroupRoot"] =
+                serde_json::json!(path(misc_helpers::path_to_string(self.get_cgroup_root())));
+            effective["controlSocketPath"] = serde_json::json!(self
+                .get_control_socket_path()
+                .map(|p| path(misc_helpers::path_to_string(p))));
+            effective["fallBackWithIpTableRedirect"] =
+                serde_json::json!(self.get_fallback_with_iptable_redirect());
+        }
+        #[cfg(feature = "fault-injection")]
+        {
+            effective["faultInjection"] = serde_json::json!(self.get_fault_injection());
+        }
+        #[cfg(feature = "otel")]
+        {
+            effective["otlpTracesEndpoint"] = serde_json::json!(self.get_otlp_traces_endpoint());
+        }
+
+        effective.to_string()
+    }
+
+    pub fn get_slow_request_threshold(&self) -> u64 {
+        self.slowRequestThresholdInMilliseconds
+            .unwrap_or(constants::DEFAULT_SLOW_REQUEST_THRESHOLD_IN_MILLISECONDS)
+    }
+
+    pub fn get_connection_summary_sample_rate(&self) -> u32 {
+        self.connectionSummarySampleRate
+            .unwrap_or(constants::DEFAULT_CONNECTION_SUMMARY_SAMPLE_RATE)
+    }
+
+    pub fn get_invalid_audit_entry_policy(&self) -> String {
+        match &self.invalidAuditEntryPolicy {
+            Some(policy) => policy.to_lowercase(),
+            None => constants::DEFAULT_INVALID_AUDIT_ENTRY_POLICY.to_string(),
+        }
+    }
+
+    pub fn get_signature_failure_policy(&self) -> String {
+        match &self.signatureFailurePolicy {
+            Some(policy) => policy.to_lowercase(),
+            None => constants::DEFAULT_SIGNATURE_FAILURE_POLICY.to_string(),
+        }
+    }
+
+    pub fn get_host_header_policy(&self) -> String {
+        match &self.hostHeaderPolicy {
+            Some(policy) => policy.to_lowercase(),
+            None => constants::DEFAULT_HOST_HEADER_POLICY.to_string(),
+        }
+    }
+
+    pub fn get_host_header_override(&self) -> Option<String> {
+        self.hostHeaderOverride.clone()
+    }
+
+    pub fn get_shared_config_fetch_timeout(&self) -> u64 {
+        self.sharedConfigFetchTimeoutInSeconds
+            .unwrap_or(constants::DEFAULT_SHARED_CONFIG_FETCH_TIMEOUT_IN_SECONDS)
+    }
+
+    pub fn get_metadata_fetch_concurrency(&self) -> usize {
+        self.metadataFetchConcurrency
+            .unwrap_or(constants::DEFAULT_METADATA_FETCH_CONCURRENCY)
+    }
+
+    pub fn get_shutdown_grace_period(&self) -> u64 {
+        self.shutdownGracePeriodInSeconds
+            .unwrap_or(constants::DEFAULT_SHUTDOWN_GRACE_PERIOD_IN_SECONDS)
+    }
+
+    pub fn get_shutdown_hard_deadline(&self) -> u64 {
+        self.shutdownHardDeadlineInSeconds
+            .unwrap_or(constants::DEFAULT_SHUTDOWN_HARD_DEADLINE_IN_SECONDS)
+    }
+
+    pub fn get_proxy_upstream_timeout(&self) -> u64 {
+        self.proxyUpstreamTimeoutInSeconds
+            .unwrap_or(constants::DEFAULT_PROXY_UPSTREAM_TIMEOUT_IN_SECONDS)
+    }
+
+    pub fn get_max_active_connections(&self) -> usize {
+        self.maxActiveConnections
+            .unwrap_or(constants::DEFAULT_MAX_ACTIVE_CONNECTIONS)
+    }
+
+    pub fn get_connection_limit_policy(&self) -> String {
+        match &self.connectionLimitPolicy {
+            Some(policy) => policy.to_lowercase(),
+            None => constants::DEFAULT_CONNECTION_LIMIT_POLICY.to_string(),
+        }
+    }
+
+    pub fn get_request_body_low_limit_size(&self) -> usize {
+        self.requestBodyLowLimitSize
+            .unwrap_or(constants::DEFAULT_REQUEST_BODY_LOW_LIMIT_SIZE)
+    }
+
+    pub fn get_max_response_body_size(&self) -> Option<usize> {
+        self.maxResponseBodySize
+    }
+
+    pub fn get_request_body_large_limit_size(&self) -> usize {
+        self.requestBodyLargeLimitSize
+            .unwrap_or(constants::DEFAULT_REQUEST_BODY_LARGE_LIMIT_SIZE)
+    }
+
+    pub fn get_stream_unsigned_request_body(&self) -> bool {
+        self.streamUnsignedRequestBody
+            .unwrap_or(constants::DEFAULT_STREAM_UNSIGNED_REQUEST_BODY)
+    }
+
+    pub fn get_authorize_once_per_connection(&self) -> bool {
+        self.authorizeOncePerConnection
+            .unwrap_or(constants::DEFAULT_AUTHORIZE_ONCE_PER_CONNECTION)
+    }
+
+    pub fn get_rate_limit_requests_per_second(&self) -> f64 {
+        self.rateLimitRequestsPerSecond
+            .unwrap_or(constants::DEFAULT_RATE_LIMIT_REQUESTS_PER_SECOND)
+    }
+
+    pub fn get_rate_limit_burst(&self) -> u32 {
+        self.rateLimitBurst
+            .unwrap_or(constants::DEFAULT_RATE_LIMIT_BURST)
+    }
+
+    pub fn get_upstream_retry_count(&self) -> u32 {
+        self.upstreamRetryCount
+            .unwrap_or(constants::DEFAULT_UPSTREAM_RETRY_COUNT)
+    }
+
+    pub fn get_upstream_idle_timeout(&self) -> u64 {
+        self.upstreamIdleTimeoutInSeconds
+            .unwrap_or(constants::DEFAULT_UPSTREAM_IDLE_TIMEOUT_IN_SECONDS)
+    }
+
+    pub fn get_tunnel_idle_timeout(&self) -> u64 {
+        self.tunnelIdleTimeoutInSeconds
+            .unwrap_or(constants::DEFAULT_TUNNEL_IDLE_TIMEOUT_IN_SECONDS)
+    }
+
+    pub fn get_upstream_max_idle_connections(&self) -> usize {
+        self.upstreamMaxIdleConnections
+            .unwrap_or(constants::DEFAULT_UPSTREAM_MAX_IDLE_CONNECTIONS)
+    }
+
+    pub fn get_circuit_breaker_failure_threshold(&self) -> u32 {
+        self.circuitBreakerFailureThreshold
+            .unwrap_or(constants::DEFAULT_CIRCUIT_BREAKER_FAILURE_THRESHOLD)
+    }
+
+    pub fn get_circuit_breaker_window(&self) -> u64 {
+        self.circuitBreakerWindowInSeconds
+            .unwrap_or(constants::DEFAULT_CIRCUIT_BREAKER_WINDOW_IN_SECONDS)
+    }
+
+    pub fn get_circuit_breaker_cool_down(&self) -> u64 {
+        self.circuitBreakerCoolDownInSeconds
+            .unwrap_or(constants::DEFAULT_CIRCUIT_BREAKER_COOL_DOWN_IN_SECONDS)
+    }
+
+    pub fn get_wire_server_retry_count(&self) -> u32 {
+        self.wireServerRetryCount
+            .unwrap_or(constants::DEFAULT_WIRE_SERVER_RETRY_COUNT)
+    }
+
+    pub fn get_wire_server_retry_initial_delay(&self) -> u64 {
+        self.wireServerRetryInitialDelayInMilliseconds
+            .unwrap_or(constants::DEFAULT_WIRE_SERVER_RETRY_INITIAL_DELAY_IN_MILLISECONDS)
+    }
+
+    pub fn get_wire_server_retry_max_duration(&self) -> u64 {
+        self.wireServerRetryMaxDurationInSeconds
+            .unwrap_or(constants::DEFAULT_WIRE_SERVER_RETRY_MAX_DURATION_IN_SECONDS)
+    }
+
+    pub fn get_wire_server_request_timeout(&self) -> u64 {
+        self.wireServerRequestTimeoutInSeconds
+            .unwrap_or(constants::DEFAULT_WIRE_SERVER_REQUEST_TIMEOUT_IN_SECONDS)
+    }
+
+    pub fn get_goal_state_cache_ttl(&self) -> u64 {
+        self.goalStateCacheTtlInSeconds
+            .unwrap_or(constants::DEFAULT_GOAL_STATE_CACHE_TTL_IN_SECONDS)
+    }
+
+    pub fn get_redirector_start_retry_count(&self) -> u32 {
+        self.redirectorStartRetryCount
+            .unwrap_or(constants::DEFAULT_REDIRECTOR_START_RETRY_COUNT)
+    }
+
+    pub fn get_redirector_start_retry_delay(&self) -> u64 {
+        self.redirectorStartRetryDelayInMilliseconds
+            .unwrap_or(constants::DEFAULT_REDIRECTOR_START_RETRY_DELAY_IN_MILLISECONDS)
+    }
+
+    pub fn get_connection_log_format(&self) -> String {
+        match &self.connectionLogFormat {
+            Some(format) => format.to_lowercase(),
+            None => constants::DEFAULT_CONNECTION_LOG_FORMAT.to_string(),
+        }
+    }
+
+    pub fn get_process_cache_ttl(&self) -> u64 {
+        self.processCacheTtlInMilliseconds
+            .unwrap_or(constants::DEFAULT_PROCESS_CACHE_TTL_IN_MILLISECONDS)
+    }
+
+    pub fn get_recent_connection_summary_count(&self) -> usize {
+        self.recentConnectionSummaryCount
+            .unwrap_or(constants::DEFAULT_RECENT_CONNECTION_SUMMARY_COUNT)
+    }
+
+    pub fn get_case_insensitive_match(&self) -> bool {
+        self.caseInsensitiveMatch
+            .unwrap_or(constants::DEFAULT_CASE_INSENSITIVE_MATCH)
+    }
+
+    pub fn get_redirect_destinations(&self) -> Vec<RedirectDestination> {
+        self.redirectDestinations.clone().unwrap_or_default()
+    }
+
+    pub fn get_skip_signature_destinations(&self) -> Vec<SkipSignatureDestination> {
+        self.skipSignatureDestinations.clone().unwrap_or_default()
+    }
+
+    pub fn get_request_header_deny_list(&self) -> Vec<String> {
+        self.requestHeaderDenyList.clone().unwrap_or_default()
     }
 
-    pub fn get_monitor_interval(&self) -> u64 {
-        self.monitorIntervalInSeconds
+    pub fn get_request_header_allow_list(&self) -> Option<Vec<String>> {
+        self.requestHeaderAllowList.clone()
     }
 
-    pub fn get_poll_key_status_interval(&self) -> u64 {
-        self.pollKeyStatusIntervalInSeconds
+    pub fn get_allowed_methods(&self) -> Option<Vec<String>> {
+        self.allowedMethods.clone()
     }
 
-    pub fn get_wire_server_support(&self) -> u8 {
-        self.wireServerSupport
+    #[cfg(feature = "fault-injection")]
+    pub fn get_fault_injection(&self) -> Option<FaultInjection> {
+        self.faultInjection.clone()
     }
 
-    pub fn get_host_gaplugin_support(&self) -> u8 {
-        self.hostGAPluginSupport
+    #[cfg(feature = "otel")]
+    pub fn get_otlp_traces_endpoint(&self) -> Option<String> {
+        self.otlpTracesEndpoint.clone()
     }
 
-    pub fn get_imds_support(&self) -> u8 {
-        self.imdsSupport
+    pub fn get_user_cache_ttl(&self) -> Option<u64> {
+        self.userCacheTtlInSeconds
     }
 
-    pub fn get_max_event_file_count(&self) -> usize {
-        self.maxEventFileCount
-            .unwrap_or(constants::DEFAULT_MAX_EVENT_FILE_COUNT)
+    pub fn get_key_absent_warning_interval(&self) -> u64 {
+        self.keyAbsentWarningIntervalInSeconds
+            .unwrap_or(constants::DEFAULT_KEY_ABSENT_WARNING_INTERVAL_IN_SECONDS)
     }
 
-    pub fn get_ebpf_program_name(&self) -> &str {
-        &self.ebpfProgramName
+    pub fn get_key_absent_critical_interval(&self) -> u64 {
+        self.keyAbsentCriticalIntervalInSeconds
+            .unwrap_or(constants::DEFAULT_KEY_ABSENT_CRITICAL_INTERVAL_IN_SECONDS)
     }
 
     #[cfg(not(windows))]
@@ -166,6 +1317,11 @@ impl Config {
         }
     }
 
+    #[cfg(not(windows))]
+    pub fn get_control_socket_path(&self) -> Option<PathBuf> {
+        self.controlSocketPath.as_ref().map(PathBuf::from)
+    }
+
     #[cfg(not(windows))]
     pub fn get_fallback_with_iptable_redirect(&self) -> bool {
         self.fallBackWithIpTableRedirect
@@ -257,6 +1413,323 @@ mod tests {
             "get_ebpf_program_name mismatch"
         );
 
+        assert_eq!(
+            constants::DEFAULT_AUDIT_MAP_WARNING_THRESHOLD,
+            config.get_audit_map_warning_threshold(),
+            "get_audit_map_warning_threshold mismatch"
+        );
+
+        assert_eq!(
+            None,
+            config.get_audit_map_max_entries(),
+            "get_audit_map_max_entries mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_FORWARD_CLIENT_IP,
+            config.get_forward_client_ip(),
+            "get_forward_client_ip mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_COLLECT_PROCESS_INTEGRITY,
+            config.get_collect_process_integrity(),
+            "get_collect_process_integrity mismatch"
+        );
+
+        assert!(
+            config.get_denied_process_paths().is_empty(),
+            "get_denied_process_paths mismatch"
+        );
+
+        assert_eq!(
+            None,
+            config.get_listener_address(),
+            "get_listener_address mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_ALLOW_NON_LOOPBACK_LISTENER,
+            config.get_allow_non_loopback_listener(),
+            "get_allow_non_loopback_listener mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_KEY_ABSENT_WARNING_INTERVAL_IN_SECONDS,
+            config.get_key_absent_warning_interval(),
+            "get_key_absent_warning_interval mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_KEY_ABSENT_CRITICAL_INTERVAL_IN_SECONDS,
+            config.get_key_absent_critical_interval(),
+            "get_key_absent_critical_interval mismatch"
+        );
+
+        assert_eq!(
+            None,
+            config.get_allowed_client_cidrs(),
+            "get_allowed_client_cidrs mismatch"
+        );
+
+        assert_eq!(
+            None,
+            config.get_user_cache_ttl(),
+            "get_user_cache_ttl mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_SLOW_REQUEST_THRESHOLD_IN_MILLISECONDS,
+            config.get_slow_request_threshold(),
+            "get_slow_request_threshold mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_CONNECTION_SUMMARY_SAMPLE_RATE,
+            config.get_connection_summary_sample_rate(),
+            "get_connection_summary_sample_rate mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_INVALID_AUDIT_ENTRY_POLICY,
+            config.get_invalid_audit_entry_policy(),
+            "get_invalid_audit_entry_policy mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_SIGNATURE_FAILURE_POLICY,
+            config.get_signature_failure_policy(),
+            "get_signature_failure_policy mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_HOST_HEADER_POLICY,
+            config.get_host_header_policy(),
+            "get_host_header_policy mismatch"
+        );
+        assert_eq!(
+            None,
+            config.get_host_header_override(),
+            "get_host_header_override mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_SHARED_CONFIG_FETCH_TIMEOUT_IN_SECONDS,
+            config.get_shared_config_fetch_timeout(),
+            "get_shared_config_fetch_timeout mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_METADATA_FETCH_CONCURRENCY,
+            config.get_metadata_fetch_concurrency(),
+            "get_metadata_fetch_concurrency mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_SHUTDOWN_GRACE_PERIOD_IN_SECONDS,
+            config.get_shutdown_grace_period(),
+            "get_shutdown_grace_period mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_SHUTDOWN_HARD_DEADLINE_IN_SECONDS,
+            config.get_shutdown_hard_deadline(),
+            "get_shutdown_hard_deadline mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_PROXY_UPSTREAM_TIMEOUT_IN_SECONDS,
+            config.get_proxy_upstream_timeout(),
+            "get_proxy_upstream_timeout mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_MAX_ACTIVE_CONNECTIONS,
+            config.get_max_active_connections(),
+            "get_max_active_connections mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_CONNECTION_LIMIT_POLICY,
+            config.get_connection_limit_policy(),
+            "get_connection_limit_policy mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_REQUEST_BODY_LOW_LIMIT_SIZE,
+            config.get_request_body_low_limit_size(),
+            "get_request_body_low_limit_size mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_REQUEST_BODY_LARGE_LIMIT_SIZE,
+            config.get_request_body_large_limit_size(),
+            "get_request_body_large_limit_size mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_STREAM_UNSIGNED_REQUEST_BODY,
+            config.get_stream_unsigned_request_body(),
+            "get_stream_unsigned_request_body mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_AUTHORIZE_ONCE_PER_CONNECTION,
+            config.get_authorize_once_per_connection(),
+            "get_authorize_once_per_connection mismatch"
+        );
+
+        assert_eq!(
+            None,
+            config.get_max_response_body_size(),
+            "get_max_response_body_size mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_RATE_LIMIT_REQUESTS_PER_SECOND,
+            config.get_rate_limit_requests_per_second(),
+            "get_rate_limit_requests_per_second mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_RATE_LIMIT_BURST,
+            config.get_rate_limit_burst(),
+            "get_rate_limit_burst mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_UPSTREAM_RETRY_COUNT,
+            config.get_upstream_retry_count(),
+            "get_upstream_retry_count mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_UPSTREAM_IDLE_TIMEOUT_IN_SECONDS,
+            config.get_upstream_idle_timeout(),
+            "get_upstream_idle_timeout mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_TUNNEL_IDLE_TIMEOUT_IN_SECONDS,
+            config.get_tunnel_idle_timeout(),
+            "get_tunnel_idle_timeout mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_UPSTREAM_MAX_IDLE_CONNECTIONS,
+            config.get_upstream_max_idle_connections(),
+            "get_upstream_max_idle_connections mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_CIRCUIT_BREAKER_FAILURE_THRESHOLD,
+            config.get_circuit_breaker_failure_threshold(),
+            "get_circuit_breaker_failure_threshold mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_CIRCUIT_BREAKER_WINDOW_IN_SECONDS,
+            config.get_circuit_breaker_window(),
+            "get_circuit_breaker_window mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_CIRCUIT_BREAKER_COOL_DOWN_IN_SECONDS,
+            config.get_circuit_breaker_cool_down(),
+            "get_circuit_breaker_cool_down mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_WIRE_SERVER_RETRY_COUNT,
+            config.get_wire_server_retry_count(),
+            "get_wire_server_retry_count mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_WIRE_SERVER_RETRY_INITIAL_DELAY_IN_MILLISECONDS,
+            config.get_wire_server_retry_initial_delay(),
+            "get_wire_server_retry_initial_delay mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_WIRE_SERVER_RETRY_MAX_DURATION_IN_SECONDS,
+            config.get_wire_server_retry_max_duration(),
+            "get_wire_server_retry_max_duration mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_WIRE_SERVER_REQUEST_TIMEOUT_IN_SECONDS,
+            config.get_wire_server_request_timeout(),
+            "get_wire_server_request_timeout mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_GOAL_STATE_CACHE_TTL_IN_SECONDS,
+            config.get_goal_state_cache_ttl(),
+            "get_goal_state_cache_ttl mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_REDIRECTOR_START_RETRY_COUNT,
+            config.get_redirector_start_retry_count(),
+            "get_redirector_start_retry_count mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_REDIRECTOR_START_RETRY_DELAY_IN_MILLISECONDS,
+            config.get_redirector_start_retry_delay(),
+            "get_redirector_start_retry_delay mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_CONNECTION_LOG_FORMAT,
+            config.get_connection_log_format(),
+            "get_connection_log_format mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_PROCESS_CACHE_TTL_IN_MILLISECONDS,
+            config.get_process_cache_ttl(),
+            "get_process_cache_ttl mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_RECENT_CONNECTION_SUMMARY_COUNT,
+            config.get_recent_connection_summary_count(),
+            "get_recent_connection_summary_count mismatch"
+        );
+
+        assert_eq!(
+            constants::DEFAULT_CASE_INSENSITIVE_MATCH,
+            config.get_case_insensitive_match(),
+            "get_case_insensitive_match mismatch"
+        );
+
+        assert!(
+            config.get_redirect_destinations().is_empty(),
+            "get_redirect_destinations mismatch"
+        );
+
+        assert!(
+            config.get_skip_signature_destinations().is_empty(),
+            "get_skip_signature_destinations mismatch"
+        );
+
+        assert!(
+            config.get_request_header_deny_list().is_empty(),
+            "get_request_header_deny_list mismatch"
+        );
+        assert_eq!(
+            None,
+            config.get_request_header_allow_list(),
+            "get_request_header_allow_list mismatch"
+        );
+        assert_eq!(
+            None,
+            config.get_allowed_methods(),
+            "get_allowed_methods mismatch"
+        );
+
         #[cfg(not(windows))]
         {
             assert_eq!(
@@ -264,6 +1737,11 @@ mod tests {
                 config.get_cgroup_root(),
                 "get_cgroup_root mismatch"
             );
+            assert_eq!(
+                None,
+                config.get_control_socket_path(),
+                "get_control_socket_path mismatch"
+            );
         }
 
         #[cfg(not(windows))]
@@ -330,6 +1808,218 @@ mod tests {
         _ = fs::remove_dir_all(&temp_test_path);
     }
 
+    #[test]
+    fn effective_config_test() {
+        let mut temp_test_path: PathBuf = env::temp_dir();
+        temp_test_path.push("effective_config_test");
+        _ = fs::remove_dir_all(&temp_test_path);
+        match misc_helpers::try_create_folder(temp_test_path.to_path_buf()) {
+            Ok(_) => {}
+            Err(err) => panic!("Failed to create folder: {}", err),
+        }
+        let config_file_path = temp_test_path.join("test_config.json");
+        let mut config = create_config_file(config_file_path);
+
+        let effective: serde_json::Value =
+            serde_json::from_str(&config.get_effective_config()).unwrap();
+        assert_eq!(
+            2, effective["wireServerSupport"],
+            "configured value mismatch"
+        );
+        assert_eq!(
+            constants::DEFAULT_MAX_EVENT_FILE_COUNT,
+            effective["maxEventFileCount"].as_u64().unwrap() as usize,
+            "default value must be resolved"
+        );
+        assert_eq!(
+            r#"C:\logFolderName"#, effective["logFolder"],
+            "logFolder mismatch"
+        );
+        assert_eq!(
+            super::REDACTED,
+            effective["latchKeyFolder"],
+            "latchKeyFolder must be redacted"
+        );
+        assert!(
+            !config.get_effective_config().contains("latchKeyFolderName"),
+            "latch key folder must not be reported"
+        );
+
+        let tls = super::UpstreamTls {
+            ip: "168.63.129.16".to_string(),
+            port: 443,
+            serverName: Some("wireserver".to_string()),
+            caCertificatePath: Some("/etc/ssl/wireserver.pem".to_string()),
+        };
+        config.upstreamTls = Some(vec![tls.clone()]);
+        assert_eq!(Some(tls), config.get_upstream_tls("168.63.129.16", 443));
+        assert_eq!(
+            None,
+            config.get_upstream_tls("168.63.129.16", 80),
+            "the destination is matched by both ip and port"
+        );
+
+        config.redactConfigPaths = Some(true);
+        let effective: serde_json::Value =
+            serde_json::from_str(&config.get_effective_config()).unwrap();
+        assert_eq!(super::REDACTED, effective["logFolder"]);
+        assert_eq!(super::REDACTED, effective["eventFolder"]);
+        assert_eq!(true, effective["redactConfigPaths"]);
+        assert_eq!(
+            super::REDACTED,
+            effective["upstreamTls"][0]["caCertificatePath"]
+        );
+        assert_eq!("wireserver", effective["upstreamTls"][0]["serverName"]);
+
+        // clean up
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn listener_address_test() {
+        let mut temp_test_path: PathBuf = env::temp_dir();
+        temp_test_path.push("listener_address_test");
+        _ = fs::remove_dir_all(&temp_test_path);
+        misc_helpers::try_create_folder(temp_test_path.to_path_buf()).unwrap();
+        let config_file_path = temp_test_path.join("test_config.json");
+        let mut config = create_config_file(config_file_path);
+
+        config.listenerAddress = Some("0.0.0.0".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            None,
+            config.get_listener_address(),
+            "the unspecified address listens to all the local addresses"
+        );
+        config.listenerAddress = Some("127.0.0.2".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            Some(std::net::Ipv4Addr::new(127, 0, 0, 2)),
+            config.get_listener_address()
+        );
+
+        // the invalid addresses are rejected even with the opt-in
+        config.allowNonLoopbackListener = Some(true);
+        for address in ["224.0.0.1", "255.255.255.255", "::1", "localhost", ""] {
+            config.listenerAddress = Some(address.to_string());
+            let message = config.validate().err().unwrap().to_string();
+            assert!(
+                message.contains(&format!("listenerAddress '{}' is not valid", address)),
+                "{address} must be invalid, got {message}"
+            );
+        }
+
+        // the address reachable from the other hosts needs the opt-in
+        config.allowNonLoopbackListener = None;
+        config.listenerAddress = Some("10.0.0.4".to_string());
+        let message = config.validate().err().unwrap().to_string();
+        assert!(
+            message.contains("allowNonLoopbackListener"),
+            "the non-loopback address must be rejected, got {message}"
+        );
+        config.allowNonLoopbackListener = Some(true);
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            Some(std::net::Ipv4Addr::new(10, 0, 0, 4)),
+            config.get_listener_address()
+        );
+        let effective: serde_json::Value =
+            serde_json::from_str(&config.get_effective_config()).unwrap();
+        assert_eq!("10.0.0.4", effective["listenerAddress"]);
+        assert_eq!(true, effective["allowNonLoopbackListener"]);
+
+        // clean up
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn validate_config_test() {
+        let mut temp_test_path: PathBuf = env::temp_dir();
+        temp_test_path.push("validate_config_test");
+        _ = fs::remove_dir_all(&temp_test_path);
+        misc_helpers::try_create_folder(temp_test_path.to_path_buf()).unwrap();
+        let config_file_path = temp_test_path.join("test_config.json");
+
+        let config = create_config_file(config_file_path.to_path_buf());
+        assert!(config.validate().is_ok(), "the test config must be valid");
+
+        let data = r#"{
+            "logFolder": "",
+            "eventFolder": "C:\\eventFolderName",
+            "latchKeyFolder": "C:\\latchKeyFolderName",
+            "monitorIntervalInSeconds": 0,
+            "pollKeyStatusIntervalInSeconds": 15,
+            "wireServerSupport": 3,
+            "hostGAPluginSupport": 1,
+            "imdsSupport": 1,
+            "ebpfProgramName": "ebpfProgramName",
+            "connectionLogFormat": "xml",
+            "allowedClientCidrs": ["127.0.0.1/33"],
+            "requestHeaderDenyList": ["x-ms-azure-host-claims:"],
+            "allowedMethods": ["GET", "M-SEARCH", "BAD METHOD"],
+            "circuitBreakerFailureThreshold": 5,
+            "circuitBreakerCoolDownInSeconds": 0,
+            "skipSignatureDestinations": [{"cidr": "10.0.0.0/33"}],
+            "maxResponseBodySize": 0,
+            "auditMapMaxEntries": 0,
+            "connectionSummarySampleRate": 0,
+            "shutdownHardDeadlineInSeconds": 5,
+            "signatureFailurePolicy": "drop",
+            "hostHeaderPolicy": "rewrite",
+            "hostHeaderOverride": "bad host",
+            "deniedProcessPaths": ["/usr/bin/python3", " "],
+            "connectionLimitPolicy": "queue"
+        }"#;
+        File::create(&config_file_path)
+            .unwrap()
+            .write_all(data.as_bytes())
+            .unwrap();
+        let message = Config::load_and_validate(config_file_path.to_path_buf())
+            .err()
+            .expect("the invalid config must fail")
+            .to_string();
+        for field in [
+            "logFolder",
+            "monitorIntervalInSeconds",
+            "wireServerSupport",
+            "connectionLogFormat",
+            "allowedClientCidrs",
+            "requestHeaderDenyList",
+            "allowedMethods",
+            "circuitBreakerCoolDownInSeconds",
+            "skipSignatureDestinations",
+            "maxResponseBodySize",
+            "auditMapMaxEntries",
+            "connectionSummarySampleRate",
+            "shutdownHardDeadlineInSeconds",
+            "signatureFailurePolicy",
+            "hostHeaderPolicy",
+            "hostHeaderOverride",
+            "deniedProcessPaths",
+            "connectionLimitPolicy",
+        ] {
+            assert!(
+                message.contains(field),
+                "every invalid field must be reported, missing {field} in {message}"
+            );
+        }
+
+        // the config shipped with the agent
+        #[cfg(not(windows))]
+        let shipped_config = "GuestProxyAgent.linux.json";
+        #[cfg(windows)]
+        let shipped_config = "GuestProxyAgent.windows.json";
+        let shipped_config_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("config")
+            .join(shipped_config);
+        if let Err(e) = Config::load_and_validate(shipped_config_path) {
+            panic!("the shipped config must be valid: {}", e);
+        }
+
+        // clean up
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
     fn create_config_file(file_path: PathBuf) -> Config {
         let data = r#"{
             "logFolder": "C:\\logFolderName",
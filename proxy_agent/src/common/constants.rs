@@ -17,22 +17,108 @@ pub const IMDS_IP_NETWORK_BYTE_ORDER: u32 = 0xFEA9FEA9; //"169.254.169.254";
 pub const PROXY_AGENT_IP_NETWORK_BYTE_ORDER: u32 = 0x100007F; //"127.0.0.1";
 
 pub const EMPTY_GUID: &str = "00000000-0000-0000-0000-000000000000";
+pub const MAX_STATUS_MESSAGE_LENGTH: usize = 1024; // in bytes, the longer module status messages are ellipsized
 
 pub const AUTHORIZATION_SCHEME: &str = "Azure-HMAC-SHA256";
+pub const AUTHORIZATION_SCHEME_SHA384: &str = "Azure-HMAC-SHA384";
+pub const AUTHORIZATION_SCHEME_SHA512: &str = "Azure-HMAC-SHA512";
+pub const AUTHORIZATION_SCHEMES: [&str; 3] = [
+    AUTHORIZATION_SCHEME,
+    AUTHORIZATION_SCHEME_SHA384,
+    AUTHORIZATION_SCHEME_SHA512,
+];
 pub const KEY_DELIVERY_METHOD_HTTP: &str = "http";
 pub const KEY_DELIVERY_METHOD_VTPM: &str = "vtpm";
-pub const CLAIMS_IS_ROOT: &str = "isRoot";
 
 pub const CLAIMS_HEADER: &str = "x-ms-azure-host-claims";
 pub const AUTHORIZATION_HEADER: &str = "x-ms-azure-host-authorization";
 pub const DATE_HEADER: &str = "x-ms-azure-host-date";
+pub const UNSIGNED_RESPONSE_AUTHORIZATION: &str = "unsigned"; // the response authorization before the key is latched
+pub const PROXY_ERROR_HEADER: &str = "x-ms-proxy-error"; // the failure class of the upstream request
 pub const METADATA_HEADER: &str = "Metadata";
 pub const CONNECTION_HEADER: &str = "connection";
 
+// policies for the invalid audit entries, e.g. the zero destination read from a partially written eBPF map
+pub const INVALID_AUDIT_ENTRY_REJECT: &str = "reject";
+pub const INVALID_AUDIT_ENTRY_RETRY: &str = "retry";
+pub const INVALID_AUDIT_ENTRY_FORWARD: &str = "forward";
+
+// policies for the requests failed to be signed, e.g. the latched key is not valid hex
+pub const SIGNATURE_FAILURE_FAIL: &str = "fail";
+pub const SIGNATURE_FAILURE_RETRY: &str = "retry";
+pub const SIGNATURE_FAILURE_FORWARD: &str = "forward";
+
+// policies for the Host header of the upstream requests
+pub const HOST_HEADER_PRESERVE: &str = "preserve"; // keep the client Host header
+pub const HOST_HEADER_DESTINATION: &str = "destination"; // the destination looked up in the audit map
+pub const HOST_HEADER_OVERRIDE: &str = "override"; // the configured hostHeaderOverride value
+
+// policies for the new connections when the max active connections are reached
+pub const CONNECTION_LIMIT_REJECT: &str = "reject"; // respond 503 to the new connections
+pub const CONNECTION_LIMIT_WAIT: &str = "wait"; // stop accepting until a connection finishes
+
+// internal endpoints served to the direct loopback requests
+pub const USER_CACHE_ENDPOINT: &str = "/proxyagent/usercache";
+pub const METRICS_ENDPOINT: &str = "/proxyagent/metrics";
+pub const PROVISION_STATE_ENDPOINT: &str = "/proxyagent/provisionstate";
+pub const RECENT_CONNECTIONS_ENDPOINT: &str = "/proxyagent/recent";
+pub const AUTHORIZATION_SIMULATE_ENDPOINT: &str = "/proxyagent/simulate";
+pub const SELF_TEST_ENDPOINT: &str = "/proxyagent/selftest";
+// served on the control socket only, e.g. POST /proxyagent/rebind?port=3081
+pub const REBIND_ENDPOINT: &str = "/proxyagent/rebind";
+
 // Default Config Settings
 pub const DEFAULT_START_REDIRECTOR: bool = true;
 pub const DEFAULT_MAX_EVENT_FILE_COUNT: usize = 30;
 pub const DEFAULT_FALLBACK_WITH_IPTABLE_REDIRECT: bool = false;
+pub const DEFAULT_AUDIT_MAP_WARNING_THRESHOLD: u8 = 80;
+pub const DEFAULT_FORWARD_CLIENT_IP: bool = false;
+pub const DEFAULT_COLLECT_PROCESS_INTEGRITY: bool = false;
+pub const DEFAULT_ALLOW_NON_LOOPBACK_LISTENER: bool = false;
+pub const DEFAULT_KEY_ABSENT_WARNING_INTERVAL_IN_SECONDS: u64 = 300; // 5 minutes
+pub const DEFAULT_KEY_ABSENT_CRITICAL_INTERVAL_IN_SECONDS: u64 = 1800; // 30 minutes
+pub const DEFAULT_REDACT_CONFIG_PATHS: bool = false;
+pub const DEFAULT_SLOW_REQUEST_THRESHOLD_IN_MILLISECONDS: u64 = 5000; // 5 seconds
+pub const DEFAULT_CONNECTION_SUMMARY_SAMPLE_RATE: u32 = 1; // emit every connection summary event
+pub const DEFAULT_INVALID_AUDIT_ENTRY_POLICY: &str = INVALID_AUDIT_ENTRY_REJECT;
+pub const DEFAULT_SIGNATURE_FAILURE_POLICY: &str = SIGNATURE_FAILURE_FAIL;
+pub const DEFAULT_HOST_HEADER_POLICY: &str = HOST_HEADER_PRESERVE;
+pub const DEFAULT_SHARED_CONFIG_FETCH_TIMEOUT_IN_SECONDS: u64 = 30;
+pub const DEFAULT_METADATA_FETCH_CONCURRENCY: usize = 1; // fetch the vm metadata sequentially
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD_IN_SECONDS: u64 = 10;
+pub const DEFAULT_SHUTDOWN_HARD_DEADLINE_IN_SECONDS: u64 = 30;
+pub const DEFAULT_PROXY_UPSTREAM_TIMEOUT_IN_SECONDS: u64 = 60;
+pub const DEFAULT_MAX_ACTIVE_CONNECTIONS: usize = 1024;
+pub const DEFAULT_CONNECTION_LIMIT_POLICY: &str = CONNECTION_LIMIT_REJECT;
+pub const DEFAULT_REQUEST_BODY_LOW_LIMIT_SIZE: usize = 100 * 1024; // 100KB
+pub const DEFAULT_REQUEST_BODY_LARGE_LIMIT_SIZE: usize = 100 * 1024 * 1024; // 100MB
+pub const DEFAULT_STREAM_UNSIGNED_REQUEST_BODY: bool = false;
+pub const DEFAULT_AUTHORIZE_ONCE_PER_CONNECTION: bool = false; // authorize every request on its own connection
+pub const DEFAULT_RATE_LIMIT_REQUESTS_PER_SECOND: f64 = 0.0; // no rate limit
+pub const DEFAULT_RATE_LIMIT_BURST: u32 = 100;
+pub const DEFAULT_UPSTREAM_RETRY_COUNT: u32 = 1; // retry the idempotent requests once on connection errors
+pub const DEFAULT_UPSTREAM_IDLE_TIMEOUT_IN_SECONDS: u64 = 30;
+pub const DEFAULT_UPSTREAM_MAX_IDLE_CONNECTIONS: usize = 0; // do not keep the upstream connections alive
+pub const DEFAULT_TUNNEL_IDLE_TIMEOUT_IN_SECONDS: u64 = 300;
+pub const DEFAULT_CIRCUIT_BREAKER_FAILURE_THRESHOLD: u32 = 0; // no circuit breaker
+pub const DEFAULT_CIRCUIT_BREAKER_WINDOW_IN_SECONDS: u64 = 30;
+pub const DEFAULT_CIRCUIT_BREAKER_COOL_DOWN_IN_SECONDS: u64 = 30;
+pub const DEFAULT_WIRE_SERVER_RETRY_COUNT: u32 = 3;
+pub const DEFAULT_WIRE_SERVER_RETRY_INITIAL_DELAY_IN_MILLISECONDS: u64 = 500;
+pub const DEFAULT_WIRE_SERVER_RETRY_MAX_DURATION_IN_SECONDS: u64 = 20;
+pub const DEFAULT_WIRE_SERVER_REQUEST_TIMEOUT_IN_SECONDS: u64 = 30;
+pub const DEFAULT_GOAL_STATE_CACHE_TTL_IN_SECONDS: u64 = 60;
+pub const DEFAULT_REDIRECTOR_START_RETRY_COUNT: u32 = 4; // 5 attempts in total
+pub const DEFAULT_REDIRECTOR_START_RETRY_DELAY_IN_MILLISECONDS: u64 = 10;
+pub const TEXT_LOG_FORMAT: &str = "text";
+pub const JSON_LOG_FORMAT: &str = "json";
+pub const DEFAULT_CONNECTION_LOG_FORMAT: &str = TEXT_LOG_FORMAT;
+pub const DEFAULT_PROCESS_CACHE_TTL_IN_MILLISECONDS: u64 = 1000; // keep it short as the pids are reused
+pub const DEFAULT_RECENT_CONNECTION_SUMMARY_COUNT: usize = 100;
+#[cfg(windows)]
+pub const DEFAULT_CASE_INSENSITIVE_MATCH: bool = true; // windows paths and account names are case-insensitive
+#[cfg(not(windows))]
+pub const DEFAULT_CASE_INSENSITIVE_MATCH: bool = false;
 
 pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
 pub const EGID: u32 = 3080;
@@ -1,9 +1,15 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+use super::constants;
+use super::logger;
 use once_cell::sync::Lazy;
 use proxy_agent_shared::misc_helpers;
+use proxy_agent_shared::telemetry::event_logger;
 use proxy_agent_shared::telemetry::span::SimpleSpan;
 use std::io::{Error, ErrorKind};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
 
 #[cfg(not(windows))]
 use sysinfo::{System, SystemExt};
@@ -53,24 +59,174 @@ pub fn get_long_os_version() -> String {
     CURRENT_OS_INFO.1.to_string()
 }
 
-pub fn compute_signature(hex_encoded_key: String, input_to_sign: &[u8]) -> std::io::Result<String> {
-    match hex::decode(hex_encoded_key.to_string()) {
-        Ok(key) => {
-            let mut mac = hmac_sha256::HMAC::new(key);
-            mac.update(input_to_sign);
-            let result = mac.finalize();
-            Ok(hex::encode(result))
+pub fn compute_signature(
+    authorization_scheme: &str,
+    hex_encoded_key: String,
+    input_to_sign: &[u8],
+) -> std::io::Result<String> {
+    let mut signer = Signer::new(authorization_scheme, &hex_encoded_key)?;
+    signer.update(input_to_sign);
+    Ok(signer.finalize())
+}
+
+// the HMAC algorithm is selected by the authorization scheme carried with the key
+enum Mac {
+    Sha256(hmac_sha256::HMAC),
+    Sha384(hmac_sha512::sha384::HMAC),
+    Sha512(hmac_sha512::HMAC),
+}
+
+/*
+    Compute the HMAC signature of the authorization scheme incrementally,
+    the input can be fed in parts without assembling it into one buffer first.
+*/
+pub struct Signer {
+    authorization_scheme: &'static str,
+    mac: Mac,
+}
+
+impl Signer {
+    pub fn new(authorization_scheme: &str, hex_encoded_key: &str) -> std::io::Result<Self> {
+        let key = match hex::decode(hex_encoded_key) {
+            Ok(key) => key,
+            Err(e) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "hex_encoded_key '{}' is invalid, error: {}",
+                        hex_encoded_key, e
+                    ),
+                ))
+            }
+        };
+        let (authorization_scheme, mac) = match authorization_scheme {
+            constants::AUTHORIZATION_SCHEME => (
+                constants::AUTHORIZATION_SCHEME,
+                Mac::Sha256(hmac_sha256::HMAC::new(key)),
+            ),
+            constants::AUTHORIZATION_SCHEME_SHA384 => (
+                constants::AUTHORIZATION_SCHEME_SHA384,
+                Mac::Sha384(hmac_sha512::sha384::HMAC::new(key)),
+            ),
+            constants::AUTHORIZATION_SCHEME_SHA512 => (
+                constants::AUTHORIZATION_SCHEME_SHA512,
+                Mac::Sha512(hmac_sha512::HMAC::new(key)),
+            ),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "authorization scheme '{}' is not supported",
+                        authorization_scheme
+                    ),
+                ))
+            }
+        };
+        Ok(Signer {
+            authorization_scheme,
+            mac,
+        })
+    }
+
+    pub fn update(&mut self, input: &[u8]) {
+        match &mut self.mac {
+            Mac::Sha256(mac) => mac.update(input),
+            Mac::Sha384(mac) => mac.update(input),
+            Mac::Sha512(mac) => mac.update(input),
         }
-        Err(e) => {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!(
-                    "hex_encoded_key '{}' is invalid, error: {}",
-                    hex_encoded_key, e
-                ),
-            ));
+    }
+
+    // hex encoded signature of all the input fed so far
+    pub fn finalize(self) -> String {
+        match self.mac {
+            Mac::Sha256(mac) => hex::encode(mac.finalize()),
+            Mac::Sha384(mac) => hex::encode(mac.finalize()),
+            Mac::Sha512(mac) => hex::encode(mac.finalize()),
         }
     }
+
+    pub fn build_authorization_header(self, key_guid: &str) -> String {
+        let authorization_scheme = self.authorization_scheme;
+        format!("{} {} {}", authorization_scheme, key_guid, self.finalize())
+    }
+}
+
+/*
+    Build the x-ms-azure-host-authorization header value:
+        <authorization scheme> <key guid> <hex encoded HMAC signature of the input>
+    The HMAC algorithm is SHA-256, SHA-384 or SHA-512 as the authorization scheme of the key names.
+    The input is the Request::as_sig_input() of the request to sign.
+*/
+pub fn build_authorization_header(
+    authorization_scheme: &str,
+    hex_encoded_key: &str,
+    key_guid: &str,
+    input_to_sign: &[u8],
+) -> std::io::Result<String> {
+    let mut signer = Signer::new(authorization_scheme, hex_encoded_key)?;
+    signer.update(input_to_sign);
+    Ok(signer.build_authorization_header(key_guid))
+}
+
+/*
+    Verify the x-ms-azure-host-authorization header value is built by build_authorization_header
+    with the same authorization scheme, key and input; the key guid in the header is not checked.
+*/
+pub fn verify_authorization_header(
+    authorization_header: &str,
+    authorization_scheme: &str,
+    hex_encoded_key: &str,
+    input_to_sign: &[u8],
+) -> bool {
+    let parts: Vec<&str> = authorization_header.split_whitespace().collect();
+    if parts.len() != 3 || parts[0] != authorization_scheme {
+        return false;
+    }
+
+    match compute_signature(
+        authorization_scheme,
+        hex_encoded_key.to_string(),
+        input_to_sign,
+    ) {
+        Ok(signature) => {
+            let expected = signature.as_bytes();
+            let actual = parts[2].to_lowercase();
+            let actual = actual.as_bytes();
+            // compare in constant time
+            expected.len() == actual.len()
+                && expected
+                    .iter()
+                    .zip(actual.iter())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        }
+        Err(_) => false,
+    }
+}
+
+// run the function on a new thread and wait for its result up to the timeout,
+// the thread is left running and its result is dropped if it times out
+pub fn run_with_timeout<T, F>(f: F, timeout: Duration) -> std::io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> std::io::Result<T> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        _ = sender.send(f());
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(Error::new(
+            ErrorKind::TimedOut,
+            format!("Timed out after {}ms.", timeout.as_millis()),
+        )),
+        Err(RecvTimeoutError::Disconnected) => Err(Error::new(
+            ErrorKind::Other,
+            "The thread exited without the result.",
+        )),
+    }
 }
 
 // replace xml escape characters
@@ -88,13 +244,46 @@ pub fn get_elapsed_time_in_millisec() -> u128 {
     START.get_elapsed_time_in_millisec()
 }
 
-pub fn write_startup_event(task: &str, method_name: &str, module_name: &str, logger_key: &str) ->String{
+pub fn write_startup_event(
+    task: &str,
+    method_name: &str,
+    module_name: &str,
+    logger_key: &str,
+) -> String {
     START.write_event(task, method_name, module_name, logger_key)
 }
 
+// cap the module status message, so a long error message does not bloat the aggregate status sent to host,
+// the full message is written to the event log
+pub fn cap_status_message(message: String, module_name: &str) -> String {
+    if message.len() <= constants::MAX_STATUS_MESSAGE_LENGTH {
+        return message;
+    }
+
+    event_logger::write_event(
+        event_logger::WARN_LEVEL,
+        format!(
+            "Status message is too long, truncating to {} bytes. Message: {}",
+            constants::MAX_STATUS_MESSAGE_LENGTH,
+            message
+        ),
+        "get_status",
+        module_name,
+        logger::AGENT_LOGGER_KEY,
+    );
+    format!(
+        "{}...",
+        misc_helpers::truncate_on_char_boundary(&message, constants::MAX_STATUS_MESSAGE_LENGTH)
+    )
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::common::constants;
     use std::io::ErrorKind;
+
+    const SCHEME: &str = constants::AUTHORIZATION_SCHEME;
+
     #[test]
     fn get_system_info_tests() {
         let ram = super::get_ram_in_mb();
@@ -112,11 +301,16 @@ mod tests {
         let hex_encoded_key = "4A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59";
         let message = "Hello world";
         let result =
-            super::compute_signature(hex_encoded_key.to_string(), message.as_bytes()).unwrap();
+            super::compute_signature(SCHEME, hex_encoded_key.to_string(), message.as_bytes())
+                .unwrap();
         println!("compute_signature: {result}");
         let invalid_hex_encoded_key =
             "YA404E635266556A586E3272357538782F413F4428472B4B6250645367566B59";
-        match super::compute_signature(invalid_hex_encoded_key.to_string(), message.as_bytes()) {
+        match super::compute_signature(
+            SCHEME,
+            invalid_hex_encoded_key.to_string(),
+            message.as_bytes(),
+        ) {
             Ok(_) => {
                 assert!(false, "invalid key should fail.");
             }
@@ -130,4 +324,195 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn signer_test() {
+        let hex_encoded_key = "4A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59";
+        let message = "Hello world";
+        let mut signer = super::Signer::new(SCHEME, hex_encoded_key).unwrap();
+        for part in ["Hello", " ", "world"] {
+            signer.update(part.as_bytes());
+        }
+        assert_eq!(
+            super::compute_signature(SCHEME, hex_encoded_key.to_string(), message.as_bytes())
+                .unwrap(),
+            signer.finalize(),
+            "signature must not depend on how the input is split"
+        );
+        assert!(super::Signer::new(SCHEME, "invalid").is_err());
+    }
+
+    #[test]
+    fn authorization_header_test() {
+        let hex_encoded_key = "4A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59";
+        let key_guid = "9cf81e97-0316-4ad3-94a7-8ccbdee8ccbf";
+        let input =
+            "GET\n\nx-ms-azure-host-date:Mon, 01 Jan 2024 00:00:00 GMT\n/machine\ncomp=goalstate";
+
+        let header =
+            super::build_authorization_header(SCHEME, hex_encoded_key, key_guid, input.as_bytes())
+                .unwrap();
+        let parts: Vec<&str> = header.split(' ').collect();
+        assert_eq!(
+            3,
+            parts.len(),
+            "header must have scheme, key guid and signature"
+        );
+        assert_eq!(crate::common::constants::AUTHORIZATION_SCHEME, parts[0]);
+        assert_eq!(key_guid, parts[1]);
+        assert_eq!(
+            super::compute_signature(SCHEME, hex_encoded_key.to_string(), input.as_bytes())
+                .unwrap(),
+            parts[2]
+        );
+
+        // round trip
+        assert!(super::verify_authorization_header(
+            &header,
+            SCHEME,
+            hex_encoded_key,
+            input.as_bytes()
+        ));
+        assert!(
+            !super::verify_authorization_header(
+                &header,
+                SCHEME,
+                hex_encoded_key,
+                b"tampered input"
+            ),
+            "signature must not match a different input"
+        );
+        let other_key = "5A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59";
+        assert!(
+            !super::verify_authorization_header(&header, SCHEME, other_key, input.as_bytes()),
+            "signature must not match a different key"
+        );
+        let header_with_other_scheme = header.replacen(parts[0], "Other-Scheme", 1);
+        assert!(!super::verify_authorization_header(
+            &header_with_other_scheme,
+            SCHEME,
+            hex_encoded_key,
+            input.as_bytes()
+        ));
+        assert!(!super::verify_authorization_header(
+            "",
+            SCHEME,
+            hex_encoded_key,
+            input.as_bytes()
+        ));
+
+        // invalid key
+        assert!(
+            super::build_authorization_header(SCHEME, "invalid", key_guid, input.as_bytes())
+                .is_err()
+        );
+        assert!(!super::verify_authorization_header(
+            &header,
+            SCHEME,
+            "invalid",
+            input.as_bytes()
+        ));
+    }
+
+    #[test]
+    fn authorization_scheme_test() {
+        // RFC 4231 test case 2, key "Jefe" and data "what do ya want for nothing?"
+        let hex_encoded_key = "4a656665";
+        let input = b"what do ya want for nothing?";
+        let key_guid = "9cf81e97-0316-4ad3-94a7-8ccbdee8ccbf";
+        for (scheme, signature) in [
+            (
+                constants::AUTHORIZATION_SCHEME,
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            ),
+            (
+                constants::AUTHORIZATION_SCHEME_SHA384,
+                "af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649",
+            ),
+            (
+                constants::AUTHORIZATION_SCHEME_SHA512,
+                "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+            ),
+        ] {
+            assert_eq!(
+                signature,
+                super::compute_signature(scheme, hex_encoded_key.to_string(), input).unwrap(),
+                "{} signature mismatch",
+                scheme
+            );
+
+            // the scheme of the key is reflected in the header
+            let header =
+                super::build_authorization_header(scheme, hex_encoded_key, key_guid, input).unwrap();
+            assert_eq!(format!("{} {} {}", scheme, key_guid, signature), header);
+            assert!(super::verify_authorization_header(
+                &header,
+                scheme,
+                hex_encoded_key,
+                input
+            ));
+            for other_scheme in constants::AUTHORIZATION_SCHEMES {
+                if other_scheme != scheme {
+                    assert!(
+                        !super::verify_authorization_header(
+                            &header,
+                            other_scheme,
+                            hex_encoded_key,
+                            input
+                        ),
+                        "{} header must not be verified as {}",
+                        scheme,
+                        other_scheme
+                    );
+                }
+            }
+        }
+
+        let e = super::Signer::new("Azure-HMAC-MD5", hex_encoded_key)
+            .err()
+            .expect("the unsupported scheme must fail");
+        assert_eq!(ErrorKind::InvalidInput, e.kind());
+        assert!(e.to_string().contains("Azure-HMAC-MD5"));
+    }
+
+    #[test]
+    fn cap_status_message_test() {
+        let mut temp_test_path = std::env::temp_dir();
+        temp_test_path.push("cap_status_message_test");
+        proxy_agent_shared::logger_manager::init_logger(
+            crate::common::logger::AGENT_LOGGER_KEY.to_string(),
+            temp_test_path.to_path_buf(),
+            "logger_key".to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let message = "x".repeat(crate::common::constants::MAX_STATUS_MESSAGE_LENGTH);
+        assert_eq!(
+            message,
+            super::cap_status_message(message.to_string(), "test")
+        );
+        let message = "x".repeat(2 * crate::common::constants::MAX_STATUS_MESSAGE_LENGTH);
+        assert_eq!(
+            format!(
+                "{}...",
+                "x".repeat(crate::common::constants::MAX_STATUS_MESSAGE_LENGTH)
+            ),
+            super::cap_status_message(message, "test")
+        );
+
+        // a localized OS error message, the limit falls inside the 2 bytes 'é'
+        let message = format!(
+            "x{}",
+            "é".repeat(crate::common::constants::MAX_STATUS_MESSAGE_LENGTH)
+        );
+        let capped = super::cap_status_message(message, "test");
+        assert_eq!(
+            format!(
+                "x{}...",
+                "é".repeat(crate::common::constants::MAX_STATUS_MESSAGE_LENGTH / 2 - 1)
+            ),
+            capped
+        );
+    }
 }
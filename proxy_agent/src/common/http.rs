@@ -1,18 +1,28 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+pub mod connection_pool;
 pub mod headers;
 pub mod http_request;
 pub mod request;
 pub mod response;
+pub mod tls;
 
 #[cfg(windows)]
 mod windows;
 
+#[cfg(windows)]
+use crate::common::helpers;
 use crate::common::http::http_request::HttpRequest;
 use request::Request;
 use response::Response;
 use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
+#[cfg(not(windows))]
+use std::net::{IpAddr, SocketAddr};
+use std::net::{Shutdown, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{
     io::{prelude::*, BufReader},
     net::TcpStream,
@@ -23,6 +33,9 @@ use self::headers::Headers;
 pub const LF: &str = "\n";
 pub const CRLF: &str = "\r\n";
 pub const DOUBLE_CRLF: &str = "\r\n\r\n";
+const TUNNEL_BUF_SIZE: usize = 16 * 1024;
+// the chunk-size lines and the trailer lines of the chunked body are read up to this length
+const MAX_CHUNK_LINE_LEN: usize = 8 * 1024;
 
 // receive TcpStream in string format
 // the stream len must less than DEFAULT_BUF_SIZE
@@ -47,7 +60,48 @@ pub fn receive_data_in_string(stream: &TcpStream) -> std::io::Result<String> {
 // both the request and response body are string and small
 pub fn get_response_in_string(http_req: &mut HttpRequest) -> std::io::Result<Response> {
     let addrs = format!("{}:{}", http_req.get_host(), http_req.get_port());
-    let mut client = TcpStream::connect(addrs)?;
+    let client = TcpStream::connect(addrs)?;
+    send_request_in_string(client, http_req)
+}
+
+// same as get_response_in_string,
+// the connect and each read/write of the request are bounded by the timeout
+pub fn get_response_in_string_with_timeout(
+    http_req: &mut HttpRequest,
+    timeout: Duration,
+) -> std::io::Result<Response> {
+    let addrs = format!("{}:{}", http_req.get_host(), http_req.get_port());
+    let client = connect_with_timeout(&addrs, timeout)?;
+    send_request_in_string(client, http_req)
+}
+
+// connect to the address and set the read/write timeout of the stream
+pub fn connect_with_timeout(addrs: &str, timeout: Duration) -> std::io::Result<TcpStream> {
+    let address = match addrs.to_socket_addrs()?.next() {
+        Some(address) => address,
+        None => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Cannot resolve address {}", addrs),
+            ))
+        }
+    };
+    let stream = TcpStream::connect_timeout(&address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    Ok(stream)
+}
+
+// the read timeout returns WouldBlock on Linux and TimedOut on Windows
+pub fn is_timeout_error(e: &Error) -> bool {
+    e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock
+}
+
+fn send_request_in_string(
+    mut client: TcpStream,
+    http_req: &mut HttpRequest,
+) -> std::io::Result<Response> {
     _ = client.write_all(http_req.request.to_raw_string().as_bytes());
     _ = client.flush();
 
@@ -69,6 +123,27 @@ pub fn get_response_in_string(http_req: &mut HttpRequest) -> std::io::Result<Res
 }
 
 pub fn receive_request_data(stream: &TcpStream) -> std::io::Result<Request> {
+    receive_request_data_with_body_limit(stream, |_| usize::MAX)
+}
+
+// receive the request, the body is not read if its Content-Length exceeds the limit of the request,
+// the caller should reject the request by checking the Content-Length against the limit again
+pub fn receive_request_data_with_body_limit<F>(
+    stream: &TcpStream,
+    body_limit: F,
+) -> std::io::Result<Request>
+where
+    F: Fn(&Request) -> usize,
+{
+    receive_request_data_from(stream, body_limit)
+}
+
+// receive the request from any stream, e.g. the unix domain socket of the control listener
+pub fn receive_request_data_from<R, F>(stream: R, body_limit: F) -> std::io::Result<Request>
+where
+    R: Read,
+    F: Fn(&Request) -> usize,
+{
     let mut reader = BufReader::new(stream);
     let mut line = String::new();
 
@@ -80,23 +155,43 @@ pub fn receive_request_data(stream: &TcpStream) -> std::io::Result<Request> {
     // the body will send at next socket data
     if !request.expect_continue_request() {
         let content_length = request.headers.get_content_length()?;
-        request.set_body(receive_body_internal(&mut reader, content_length)?);
+        if request.headers.is_chunked_transfer_encoding() {
+            // keep the chunked body read ahead with the headers, the rest is still in the stream
+            request.set_body(reader.buffer().to_vec());
+        } else if content_length <= body_limit(&request) {
+            request.set_body(receive_body_internal(&mut reader, content_length)?);
+        } else {
+            // keep the body read ahead with the headers, the rest is still in the stream
+            let read_ahead = reader.buffer().len().min(content_length);
+            request.set_body(reader.buffer()[..read_ahead].to_vec());
+        }
     }
 
     Ok(request)
 }
 
 pub fn receive_response_data(stream: &TcpStream) -> std::io::Result<Response> {
+    receive_response_data_with_limit(stream, None)
+}
+
+// receive the response, the body larger than max_body_len is not read
+pub fn receive_response_data_with_limit(
+    stream: &TcpStream,
+    max_body_len: Option<usize>,
+) -> std::io::Result<Response> {
     let mut reader = BufReader::new(stream);
     let mut response = read_response_without_body(&mut reader)?;
 
     let content_length = response.headers.get_content_length()?;
+    if let Some(limit) = max_body_len.filter(|limit| content_length > *limit) {
+        return Err(body_too_large_error(limit));
+    }
     response.set_body(receive_body_internal(&mut reader, content_length)?);
 
     Ok(response)
 }
 
-fn read_header_lines(reader: &mut BufReader<&TcpStream>) -> std::io::Result<String> {
+fn read_header_lines<R: BufRead>(reader: &mut R) -> std::io::Result<String> {
     let mut lines = String::new();
 
     loop {
@@ -114,18 +209,19 @@ fn read_header_lines(reader: &mut BufReader<&TcpStream>) -> std::io::Result<Stri
     Ok(lines)
 }
 
-fn receive_body_internal(
-    reader: &mut BufReader<&TcpStream>,
-    len: usize,
-) -> std::io::Result<Vec<u8>> {
+fn receive_body_internal<R: BufRead>(reader: &mut R, len: usize) -> std::io::Result<Vec<u8>> {
     let mut data: Vec<u8> = Vec::new();
     while data.len() < len {
         match reader.fill_buf() {
             Ok(d) => {
-                let received = d.to_vec();
-                let received_len = received.len();
+                if d.is_empty() {
+                    // connection closed
+                    break;
+                }
+                // copy the buffered data once, straight into the body
+                data.extend_from_slice(d);
+                let received_len = d.len();
                 reader.consume(received_len);
-                data.extend(received.iter().copied());
             }
             Err(_e) => {
                 // read timeout, assume no more incoming data in the TcpStream
@@ -142,9 +238,9 @@ pub fn receive_body(stream: &TcpStream, content_length: usize) -> std::io::Resul
     receive_body_internal(&mut reader, content_length)
 }
 
-fn stream_body_internal(
-    mut reader: BufReader<&TcpStream>,
-    mut dest_stream: &TcpStream,
+fn stream_body_internal<R: BufRead, W: Write>(
+    mut reader: R,
+    mut dest_stream: W,
     len: usize,
 ) -> std::io::Result<usize> {
     let mut received: usize = 0;
@@ -152,8 +248,13 @@ fn stream_body_internal(
     while received < len {
         match reader.fill_buf() {
             Ok(d) => {
-                let read = d.len();
-                dest_stream.write_all(d)?;
+                if d.is_empty() {
+                    // connection closed
+                    break;
+                }
+                // never write the data after the body
+                let read = d.len().min(len - received);
+                dest_stream.write_all(&d[..read])?;
                 reader.consume(read);
                 received = received + read;
             }
@@ -168,11 +269,155 @@ fn stream_body_internal(
     Ok(received)
 }
 
+fn body_too_large_error(limit: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Response body exceeds the limit of {} bytes", limit),
+    )
+}
+
+// read a chunk-size line or a trailer line of the chunked body, it fails if longer than MAX_CHUNK_LINE_LEN
+fn read_chunk_line<R: BufRead>(reader: &mut R, line: &mut String) -> std::io::Result<usize> {
+    let len = reader
+        .by_ref()
+        .take(MAX_CHUNK_LINE_LEN as u64 + 1)
+        .read_line(line)?;
+    if len > MAX_CHUNK_LINE_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Chunk line exceeds the limit of {} bytes",
+                MAX_CHUNK_LINE_LEN
+            ),
+        ));
+    }
+    Ok(len)
+}
+
+fn chunk_size_overflow_error(size: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Chunk size '{}' is too large", size),
+    )
+}
+
+// stream the chunked body as-is to dest stream, including the chunk-size lines,
+// the last chunk and the trailer section.
+// returns the length of the chunk data forwarded,
+// stops in the middle of the body if the chunk data would exceed max_len.
+fn stream_chunked_body_internal<R: BufRead, W: Write>(
+    reader: &mut R,
+    mut dest_stream: W,
+    max_len: Option<usize>,
+) -> std::io::Result<usize> {
+    let mut forwarded: usize = 0;
+
+    loop {
+        let mut size_line = String::new();
+        if read_chunk_line(reader, &mut size_line)? == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Connection closed before the last chunk",
+            ));
+        }
+        dest_stream.write_all(size_line.as_bytes())?;
+
+        // chunk-size [; chunk-ext] CRLF
+        let size = size_line.split(';').next().unwrap_or("").trim();
+        let chunk_size = match usize::from_str_radix(size, 16) {
+            Ok(len) => len,
+            Err(e) => {
+                let message = format!("Failed parse chunk size '{}', error {}", size, e);
+                return Err(Error::new(ErrorKind::InvalidData, message));
+            }
+        };
+        if chunk_size == 0 {
+            break;
+        }
+        let total = match forwarded.checked_add(chunk_size) {
+            Some(total) => total,
+            None => return Err(chunk_size_overflow_error(size)),
+        };
+        if let Some(limit) = max_len.filter(|limit| total > *limit) {
+            dest_stream.flush()?;
+            return Err(body_too_large_error(limit));
+        }
+
+        // chunk data and its CRLF
+        let expected = match chunk_size.checked_add(CRLF.len()) {
+            Some(len) => len as u64,
+            None => return Err(chunk_size_overflow_error(size)),
+        };
+        let copied = std::io::copy(&mut reader.by_ref().take(expected), &mut dest_stream)?;
+        if copied != expected {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Connection closed in the middle of a chunk",
+            ));
+        }
+        forwarded = total;
+    }
+
+    // trailer section, ends with an empty line
+    loop {
+        let mut line = String::new();
+        if read_chunk_line(reader, &mut line)? == 0 {
+            break;
+        }
+        dest_stream.write_all(line.as_bytes())?;
+        if line.trim().is_empty() {
+            break;
+        }
+    }
+
+    dest_stream.flush()?;
+    Ok(forwarded)
+}
+
+// stream the chunked body from the client stream to dest stream, after the part read ahead with the headers,
+// the malformed chunked body or the client stream failure is returned as the client stream error
+pub fn stream_chunked_body<W: Write>(
+    source_stream: &TcpStream,
+    read_ahead: &[u8],
+    dest_stream: W,
+) -> std::io::Result<usize> {
+    let mut reader = BufReader::new(read_ahead.chain(source_stream));
+    let mut dest_stream = DestStream {
+        stream: dest_stream,
+        failed: false,
+    };
+    match stream_chunked_body_internal(&mut reader, &mut dest_stream, None) {
+        Ok(len) => Ok(len),
+        Err(e) if dest_stream.failed => Err(e),
+        Err(e) => Err(to_client_stream_error(e)),
+    }
+}
+
+// remembers whether writing to the dest stream failed
+struct DestStream<W: Write> {
+    stream: W,
+    failed: bool,
+}
+
+impl<W: Write> Write for DestStream<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.stream.write(buf);
+        self.failed = written.is_err();
+        written
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let flushed = self.stream.flush();
+        self.failed = flushed.is_err();
+        flushed
+    }
+}
+
 // receive body from source stream and,
-// send to dest stream directly
-pub fn stream_body(
+// send to dest stream directly, e.g. the tcp or tls stream to host
+pub fn stream_body<W: Write>(
     source_stream: &TcpStream,
-    dest_stream: &TcpStream,
+    dest_stream: &mut W,
     content_length: usize,
 ) -> std::io::Result<usize> {
     let reader = BufReader::new(source_stream);
@@ -180,13 +425,27 @@ pub fn stream_body(
 }
 
 // forward response from server TcpStream to client TcpStream
-// insert extra headers if have
+// insert extra headers if have, the hop-by-hop headers are not forwarded to the client,
+// the response is terminated after max_body_len bytes of body, with an InvalidData error
 pub fn forward_response(
     server_stream: &TcpStream,
-    mut client_stream: &TcpStream,
+    client_stream: &TcpStream,
     extra_headers: HashMap<&str, &str>,
+    max_body_len: Option<usize>,
+) -> std::io::Result<(Response, usize)> {
+    forward_response_from(server_stream, client_stream, extra_headers, max_body_len)
+}
+
+// forward response read from any server stream, e.g. the TLS stream, to client TcpStream,
+// the errors writing to the client are told apart by is_client_stream_error
+pub fn forward_response_from<R: Read>(
+    server_stream: R,
+    client_stream: &TcpStream,
+    extra_headers: HashMap<&str, &str>,
+    max_body_len: Option<usize>,
 ) -> std::io::Result<(Response, usize)> {
     let mut response_reader = BufReader::new(server_stream);
+    let mut client_stream = ClientStream(client_stream);
 
     let mut response_without_body;
     match read_response_without_body(&mut response_reader) {
@@ -207,14 +466,33 @@ pub fn forward_response(
             .headers
             .add_header(key.to_string(), value.to_string());
     }
-    match client_stream.write_all(&response_without_body.to_raw_bytes()) {
+    // the returned response keeps the hop-by-hop headers of host, they decide whether its connection is reusable
+    let host_headers = response_without_body.headers.copy();
+    response_without_body.headers.remove_hop_by_hop_headers();
+    let written = client_stream.write_all(&response_without_body.to_raw_bytes());
+    response_without_body.headers = host_headers;
+    match written {
         Ok(_) => {}
         Err(e) => {
-            let message = format!("Failed to write response without body to Guest - {}", e);
-            return Err(Error::new(e.kind(), message));
+            return Err(add_error_context(
+                e,
+                "Failed to write response without body to Guest -",
+            ));
         }
     }
 
+    // stream chunked body, the trailers after the last chunk are forwarded unmodified
+    if response_without_body.headers.is_chunked_transfer_encoding() {
+        let forwarded;
+        match stream_chunked_body_internal(&mut response_reader, &mut client_stream, max_body_len) {
+            Ok(len) => forwarded = len,
+            Err(e) => {
+                return Err(add_error_context(e, "Failed to stream chunked body"));
+            }
+        }
+        return Ok((response_without_body, forwarded));
+    }
+
     // stream body
     let content_length;
     match response_without_body.headers.get_content_length() {
@@ -225,21 +503,74 @@ pub fn forward_response(
         }
     }
 
+    let limit = max_body_len.unwrap_or(usize::MAX);
     let forwarded;
-    match stream_body_internal(response_reader, client_stream, content_length) {
+    match stream_body_internal(
+        response_reader,
+        &mut client_stream,
+        content_length.min(limit),
+    ) {
         Ok(len) => forwarded = len,
         Err(e) => {
-            let message = format!("Failed to stream body {}", e);
-            return Err(Error::new(e.kind(), message));
+            return Err(add_error_context(e, "Failed to stream body"));
         }
     }
+    if content_length > limit {
+        let e = body_too_large_error(limit);
+        let message = format!("Failed to stream body {}", e);
+        return Err(Error::new(e.kind(), message));
+    }
 
     Ok((response_without_body, forwarded))
 }
 
-fn read_response_without_body(
-    response_reader: &mut BufReader<&TcpStream>,
-) -> std::io::Result<Response> {
+// the error of the client stream, e.g. writing the response to it or reading the malformed chunked body from it,
+// it is not a failure of the server
+#[derive(Debug)]
+struct ClientStreamError(String);
+
+impl std::fmt::Display for ClientStreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ClientStreamError {}
+
+// returns true if the error is from the client stream
+pub fn is_client_stream_error(e: &Error) -> bool {
+    e.get_ref()
+        .map_or(false, |inner| inner.is::<ClientStreamError>())
+}
+
+// the context message is added without losing the client stream error
+fn add_error_context(e: Error, context: &str) -> Error {
+    let message = format!("{} {}", context, e);
+    if is_client_stream_error(&e) {
+        Error::new(e.kind(), ClientStreamError(message))
+    } else {
+        Error::new(e.kind(), message)
+    }
+}
+
+fn to_client_stream_error(e: Error) -> Error {
+    Error::new(e.kind(), ClientStreamError(e.to_string()))
+}
+
+// tags the errors writing to the client stream as ClientStreamError
+struct ClientStream<'a>(&'a TcpStream);
+
+impl Write for ClientStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf).map_err(to_client_stream_error)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush().map_err(to_client_stream_error)
+    }
+}
+
+fn read_response_without_body<R: BufRead>(response_reader: &mut R) -> std::io::Result<Response> {
     let mut line = String::new();
     response_reader.read_line(&mut line)?;
     let mut response = Response::from_first_line(line);
@@ -249,26 +580,156 @@ fn read_response_without_body(
     Ok(response)
 }
 
+// connect to the server, returns TimedOut error if it cannot connect within the timeout
 pub fn connect_to_server(
     ip: String,
     port: u16,
     _client_stream: &TcpStream,
+    timeout: Duration,
 ) -> std::io::Result<TcpStream> {
     let server_stream;
     #[cfg(windows)]
     {
-        server_stream = windows::connect_with_redirect_record(ip, port, _client_stream)?;
+        // the redirect record connect is blocking, run it on the side to bound the wait
+        let client_stream = _client_stream.try_clone()?;
+        server_stream = helpers::run_with_timeout(
+            move || windows::connect_with_redirect_record(ip, port, &client_stream),
+            timeout,
+        )?;
     }
     #[cfg(not(windows))]
     {
         // Linux does not have the redirect record feature,
         // hence it will avoid the redirect by skip_process_map in ebpf program.
-        server_stream = TcpStream::connect(format!("{}:{}", ip, port))?;
+        let address = match ip.parse::<IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, port),
+            Err(e) => {
+                let message = format!("Invalid server ip {} - {}", ip, e);
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        };
+        server_stream = TcpStream::connect_timeout(&address, timeout)?;
     }
 
     Ok(server_stream)
 }
 
+// bound every read and write on the server stream by the time left before the deadline,
+// returns TimedOut error if the deadline has passed
+pub fn set_deadline(server_stream: &TcpStream, deadline: Instant) -> std::io::Result<()> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(Error::new(
+            ErrorKind::TimedOut,
+            "The deadline of the server stream has passed.",
+        ));
+    }
+
+    server_stream.set_read_timeout(Some(remaining))?;
+    server_stream.set_write_timeout(Some(remaining))
+}
+
+// wait until the server starts to respond, the response data is not consumed,
+// returns TimedOut error if nothing is received before the deadline
+pub fn wait_for_response(server_stream: &TcpStream, deadline: Instant) -> std::io::Result<()> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(Error::new(
+            ErrorKind::TimedOut,
+            "No response from the server before the deadline.",
+        ));
+    }
+
+    let read_timeout = server_stream.read_timeout()?;
+    server_stream.set_read_timeout(Some(remaining))?;
+    let mut buf = [0u8; 1];
+    let result = server_stream.peek(&mut buf);
+    server_stream.set_read_timeout(read_timeout)?;
+
+    match result {
+        Ok(0) => Err(Error::new(
+            ErrorKind::ConnectionAborted,
+            "The connection is closed by the server.",
+        )),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+            Err(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "No response from the server in {}ms.",
+                    remaining.as_millis()
+                ),
+            ))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// relay the bytes in both directions until either side closes its connection,
+// or no data is relayed in either direction for the idle timeout,
+// returns the byte counts relayed from the client to the server and from the server to the client
+pub fn tunnel(
+    client_stream: &TcpStream,
+    server_stream: &TcpStream,
+    idle_timeout: Duration,
+) -> (u64, u64) {
+    if client_stream.set_read_timeout(Some(idle_timeout)).is_err()
+        || server_stream.set_read_timeout(Some(idle_timeout)).is_err()
+    {
+        return (0, 0);
+    }
+    let last_relayed = Arc::new(Mutex::new(Instant::now()));
+    let upload = match (client_stream.try_clone(), server_stream.try_clone()) {
+        (Ok(client), Ok(server)) => {
+            let last_relayed = last_relayed.clone();
+            thread::spawn(move || {
+                let sent = relay(&client, &server, &last_relayed, idle_timeout);
+                _ = server.shutdown(Shutdown::Write);
+                sent
+            })
+        }
+        _ => return (0, 0),
+    };
+
+    let received = relay(server_stream, client_stream, &last_relayed, idle_timeout);
+    // the server is done, stop reading from the client as well
+    _ = client_stream.shutdown(Shutdown::Both);
+    let sent = upload.join().unwrap_or(0);
+    _ = server_stream.shutdown(Shutdown::Both);
+
+    (sent, received)
+}
+
+// a read timeout of one direction does not end the relay while the other direction is still active
+fn relay(
+    mut reader: &TcpStream,
+    mut writer: &TcpStream,
+    last_relayed: &Mutex<Instant>,
+    idle_timeout: Duration,
+) -> u64 {
+    let mut buf = [0u8; TUNNEL_BUF_SIZE];
+    let mut relayed = 0;
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                if last_relayed.lock().unwrap().elapsed() >= idle_timeout {
+                    break;
+                }
+                continue;
+            }
+            Err(_) => break,
+        };
+        if writer.write_all(&buf[..len]).is_err() {
+            break;
+        }
+        *last_relayed.lock().unwrap() = Instant::now();
+        relayed += len as u64;
+    }
+    relayed
+}
+
 pub fn htons(u: u16) -> u16 {
     u.to_be()
 }
@@ -286,17 +747,438 @@ mod tests {
     use crate::common::http::http_request::HttpRequest;
     use crate::common::http::response::Response;
     use crate::common::http::Request;
+    use std::collections::HashMap;
     use std::fs;
-    use std::io::Write;
+    use std::io::{Read, Write};
     use std::net::TcpListener;
     use std::net::TcpStream;
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use url::Url;
 
     const ENDPOINT_ADDRESS: &str = "127.0.0.1:8082";
+
+    #[test]
+    fn forward_chunked_response_with_trailers_test() {
+        const CHUNKED_BODY: &str =
+            "4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\ngrpc-status: 0\r\ngrpc-message: OK\r\n\r\n";
+
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+        let upstream_thread = thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let head = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: grpc-status, grpc-message\r\n\r\n";
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(CHUNKED_BODY.as_bytes()).unwrap();
+            stream.flush().unwrap();
+        });
+        let server_stream = TcpStream::connect(upstream_address).unwrap();
+
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (client_stream, _) = client_listener.accept().unwrap();
+
+        let (response, forwarded) =
+            http::forward_response(&server_stream, &client_stream, HashMap::new(), None).unwrap();
+        upstream_thread.join().unwrap();
+        assert_eq!(Response::OK, response.status, "response.status must be OK");
+        assert_eq!(9, forwarded, "forwarded chunk data length mismatch");
+
+        drop(client_stream);
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        let body_start = received.find(http::DOUBLE_CRLF).unwrap() + http::DOUBLE_CRLF.len();
+        assert_eq!(
+            CHUNKED_BODY,
+            &received[body_start..],
+            "chunked body and trailers must reach the client unmodified"
+        );
+    }
+
+    #[test]
+    fn stream_chunked_body_limits_test() {
+        let stream = |body: &[u8], max_len: Option<usize>| {
+            let mut reader = std::io::BufReader::new(body);
+            super::stream_chunked_body_internal(&mut reader, Vec::new(), max_len)
+        };
+
+        // the chunk size overflows
+        let e = stream(b"ffffffffffffffff\r\n", None).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+        let e = stream(b"4\r\nWiki\r\nffffffffffffffff\r\n", Some(1024)).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+
+        // the chunk-size line and the trailer line are capped
+        let long_line = format!("4;{}\r\nWiki\r\n0\r\n\r\n", "e".repeat(16 * 1024));
+        let e = stream(long_line.as_bytes(), None).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+        let long_trailer = format!(
+            "4\r\nWiki\r\n0\r\nx-trailer: {}\r\n\r\n",
+            "t".repeat(16 * 1024)
+        );
+        let e = stream(long_trailer.as_bytes(), None).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+
+        assert_eq!(4, stream(b"4\r\nWiki\r\n0\r\n\r\n", None).unwrap());
+    }
+
+    #[test]
+    fn forward_response_body_unmodified_test() {
+        // every byte value, and large enough to be read in multiple buffers
+        let body: Vec<u8> = (0..1024 * 1024).map(|i| (i % 256) as u8).collect();
+        let upstream_body = body.clone();
+
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+        let upstream_thread = thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                upstream_body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&upstream_body).unwrap();
+            stream.flush().unwrap();
+        });
+        let server_stream = TcpStream::connect(upstream_address).unwrap();
+
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (client_stream, _) = client_listener.accept().unwrap();
+        let client_thread = thread::spawn(move || {
+            let mut client = client;
+            let mut received = Vec::new();
+            client.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let (response, forwarded) =
+            http::forward_response(&server_stream, &client_stream, HashMap::new(), None).unwrap();
+        upstream_thread.join().unwrap();
+        assert_eq!(Response::OK, response.status, "response.status must be OK");
+        assert_eq!(body.len(), forwarded, "forwarded body length mismatch");
+
+        drop(client_stream);
+        let received = client_thread.join().unwrap();
+        let body_start = received
+            .windows(http::DOUBLE_CRLF.len())
+            .position(|w| w == http::DOUBLE_CRLF.as_bytes())
+            .unwrap()
+            + http::DOUBLE_CRLF.len();
+        assert!(
+            body == received[body_start..],
+            "forwarded body must be identical to the upstream body"
+        );
+    }
+
+    #[test]
+    fn forward_response_body_limit_test() {
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (client_stream, _) = client_listener.accept().unwrap();
+
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n0123456789";
+        let (_, forwarded) = http::forward_response_from(
+            response.as_bytes(),
+            &client_stream,
+            HashMap::new(),
+            Some(10),
+        )
+        .unwrap();
+        assert_eq!(10, forwarded, "the body within the limit is forwarded");
+        let e = http::forward_response_from(
+            response.as_bytes(),
+            &client_stream,
+            HashMap::new(),
+            Some(4),
+        )
+        .err()
+        .expect("the body over the limit must fail");
+        assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+
+        let chunked = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n";
+        let e = http::forward_response_from(
+            chunked.as_bytes(),
+            &client_stream,
+            HashMap::new(),
+            Some(8),
+        )
+        .err()
+        .expect("the body over the limit must fail");
+        assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+
+        drop(client_stream);
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        assert!(received.contains("0123456789"));
+        assert!(
+            received.ends_with("\r\n\r\n4\r\nWiki\r\n5\r\n"),
+            "the response is terminated before the chunk over the limit"
+        );
+        assert!(
+            received.contains("\r\n\r\n0123HTTP/1.1"),
+            "only the body up to the limit is forwarded"
+        );
+    }
+
+    #[test]
+    fn forward_response_hop_by_hop_test() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+        let upstream_thread = thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let head = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close, X-Hop\r\nKeep-Alive: timeout=5\r\nX-Hop: 1\r\nX-End: 1\r\n\r\nok";
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.flush().unwrap();
+        });
+        let server_stream = TcpStream::connect(upstream_address).unwrap();
+
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (client_stream, _) = client_listener.accept().unwrap();
+
+        let (response, forwarded) =
+            http::forward_response(&server_stream, &client_stream, HashMap::new(), None).unwrap();
+        upstream_thread.join().unwrap();
+        assert_eq!(2, forwarded);
+        assert!(
+            !response.is_keep_alive(),
+            "the returned response must keep the Connection header of host"
+        );
+
+        drop(client_stream);
+        let mut received = String::new();
+        client.read_to_string(&mut received).unwrap();
+        let received = Response::from_raw_data(received);
+        for name in ["Connection", "Keep-Alive", "X-Hop"] {
+            assert_eq!(
+                None,
+                received.headers.get_header(name),
+                "the hop-by-hop header {name} must not reach the client"
+            );
+        }
+        assert_eq!(Some("1".to_string()), received.headers.get_header("X-End"));
+        assert_eq!(
+            Some("2".to_string()),
+            received.headers.get_header("Content-Length")
+        );
+    }
+
+    #[test]
+    fn h2c_upgrade_test() {
+        let raw_request = "GET /machine?comp=goalstate HTTP/1.1\r\nHost: 168.63.129.16\r\nConnection: Upgrade, HTTP2-Settings, keep-alive\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\r\n";
+        let mut request = Request::from_raw_request(raw_request.to_string()).unwrap();
+        assert!(!request.is_http2_preface());
+        assert!(request.headers.remove_h2c_upgrade());
+        assert_eq!(
+            None,
+            request.headers.get_header(headers::UPGRADE_HEADER_NAME)
+        );
+        assert_eq!(
+            None,
+            request
+                .headers
+                .get_header(headers::HTTP2_SETTINGS_HEADER_NAME)
+        );
+        assert_eq!(
+            Some("keep-alive".to_string()),
+            request.headers.get_header("connection")
+        );
+        assert_eq!(
+            Some("168.63.129.16".to_string()),
+            request.headers.get_header(headers::HOST_HEADER_NAME)
+        );
+        assert!(
+            !request.headers.remove_h2c_upgrade(),
+            "nothing to remove the second time"
+        );
+
+        // keep the other upgrade protocols
+        let raw_request = "GET / HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: websocket, h2c\r\nHTTP2-Settings: AAMAAABk\r\n\r\n";
+        let mut request = Request::from_raw_request(raw_request.to_string()).unwrap();
+        assert!(request.headers.remove_h2c_upgrade());
+        assert_eq!(
+            Some("websocket".to_string()),
+            request.headers.get_header(headers::UPGRADE_HEADER_NAME)
+        );
+        assert_eq!(
+            Some("Upgrade".to_string()),
+            request.headers.get_header("connection")
+        );
+
+        let raw_request = "GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n";
+        let mut request = Request::from_raw_request(raw_request.to_string()).unwrap();
+        assert!(!request.headers.remove_h2c_upgrade());
+        assert_eq!(
+            Some("websocket".to_string()),
+            request.headers.get_header(headers::UPGRADE_HEADER_NAME)
+        );
+
+        // HTTP/2 prior knowledge preface
+        let request =
+            Request::from_raw_request("PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_string()).unwrap();
+        assert!(request.is_http2_preface());
+    }
+
+    #[test]
+    fn wait_for_response_test() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+        let client = TcpStream::connect(upstream_address).unwrap();
+        let (mut server, _) = upstream.accept().unwrap();
+
+        // server does not respond
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let e = http::wait_for_response(&client, deadline).unwrap_err();
+        assert_eq!(std::io::ErrorKind::TimedOut, e.kind());
+        let e = http::wait_for_response(&client, deadline).unwrap_err();
+        assert_eq!(
+            std::io::ErrorKind::TimedOut,
+            e.kind(),
+            "deadline has passed"
+        );
+        assert_eq!(
+            None,
+            client.read_timeout().unwrap(),
+            "read timeout restored"
+        );
+
+        // server responds, the response is not consumed
+        server.write_all(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        http::wait_for_response(&client, deadline).unwrap();
+        let response = http::receive_response_data(&client).unwrap();
+        assert_eq!(Response::OK, response.status);
+
+        // server closes the connection
+        drop(server);
+        let e = http::wait_for_response(&client, deadline).unwrap_err();
+        assert_eq!(std::io::ErrorKind::ConnectionAborted, e.kind());
+    }
+
+    #[test]
+    fn tunnel_test() {
+        // upstream echo server
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+        let upstream_thread = thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => stream.write_all(&buf[..len]).unwrap(),
+                }
+            }
+        });
+
+        let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(proxy.local_addr().unwrap()).unwrap();
+        let (client_stream, _) = proxy.accept().unwrap();
+        let server_stream = TcpStream::connect(upstream_address).unwrap();
+        let tunnel_thread = thread::spawn(move || {
+            http::tunnel(&client_stream, &server_stream, Duration::from_secs(10))
+        });
+
+        client.write_all(b"hello tunnel").unwrap();
+        let mut buf = [0u8; 12];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"hello tunnel", &buf);
+
+        // client closes its side, the tunnel ends
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let (sent, received) = tunnel_thread.join().unwrap();
+        assert_eq!(12, sent);
+        assert_eq!(12, received);
+        assert_eq!(0, client.read(&mut buf).unwrap(), "tunnel is closed");
+        upstream_thread.join().unwrap();
+    }
+
+    #[test]
+    fn tunnel_idle_timeout_test() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream_address = upstream.local_addr().unwrap();
+        let upstream_thread = thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            // keep sending for a while, the tunnel must stay open while the data flows in one direction
+            for _ in 0..6 {
+                stream.write_all(b"ping").unwrap();
+                thread::sleep(Duration::from_millis(100));
+            }
+            // then hold the connection open without any data
+            let mut buf = [0u8; 16];
+            _ = stream.read(&mut buf);
+        });
+
+        let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(proxy.local_addr().unwrap()).unwrap();
+        let (client_stream, _) = proxy.accept().unwrap();
+        let server_stream = TcpStream::connect(upstream_address).unwrap();
+        let start = Instant::now();
+        let tunnel_thread = thread::spawn(move || {
+            http::tunnel(&client_stream, &server_stream, Duration::from_millis(300))
+        });
+
+        let mut buf = [0u8; 24];
+        client.read_exact(&mut buf).unwrap();
+        // neither side closes its connection, the idle tunnel ends
+        let (sent, received) = tunnel_thread.join().unwrap();
+        assert_eq!(0, sent);
+        assert_eq!(24, received);
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "idle tunnel must be closed by the idle timeout"
+        );
+        assert_eq!(0, client.read(&mut buf).unwrap(), "tunnel is closed");
+        upstream_thread.join().unwrap();
+    }
+
+    #[test]
+    fn client_stream_error_test() {
+        let logger_key = "client_stream_error_test";
+        let mut temp_test_path = std::env::temp_dir();
+        temp_test_path.push(logger_key);
+        proxy_agent_shared::logger_manager::init_logger(
+            crate::common::logger::AGENT_LOGGER_KEY.to_string(),
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let response =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nbody\r\n0\r\n\r\n";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (client_stream, _) = listener.accept().unwrap();
+
+        // the server stream failure is not a client stream error
+        struct ResetStream;
+        impl Read for ResetStream {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionReset,
+                    "reset by host",
+                ))
+            }
+        }
+        let server_stream = (&response[..response.len() - 5]).chain(ResetStream);
+        let e = http::forward_response_from(server_stream, &client_stream, HashMap::new(), None)
+            .err()
+            .unwrap();
+        assert!(!http::is_client_stream_error(&e));
+
+        // the client stream is closed, writing the response to it fails
+        client_stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let e = http::forward_response_from(&response[..], &client_stream, HashMap::new(), None)
+            .err()
+            .unwrap();
+        assert!(http::is_client_stream_error(&e));
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
     #[test]
     fn http_binary_body_test() {
         let shut_down: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
@@ -0,0 +1,170 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// the idle connections with their idle since time, keyed by the (ip, port) of the endpoint
+type IdleConnections = HashMap<(String, u16), Vec<(TcpStream, Instant)>>;
+
+/*
+Keep-alive pool of the idle upstream connections, keyed by the (ip, port) of the endpoint.
+The connections idle longer than the idle timeout, or closed by the server meanwhile, are discarded at checkout.
+ */
+pub struct ConnectionPool {
+    idle_connections: Mutex<IdleConnections>,
+    idle_timeout: Duration,
+    max_idle_per_endpoint: usize,
+}
+
+impl ConnectionPool {
+    pub fn new(idle_timeout: Duration, max_idle_per_endpoint: usize) -> Self {
+        ConnectionPool {
+            idle_connections: Mutex::new(HashMap::new()),
+            idle_timeout,
+            max_idle_per_endpoint,
+        }
+    }
+
+    // take the most recent idle connection to the endpoint which is still usable
+    pub fn checkout(&self, ip: &str, port: u16) -> Option<TcpStream> {
+        let mut idle_connections = self.idle_connections.lock().unwrap();
+        let connections = idle_connections.get_mut(&(ip.to_string(), port))?;
+        while let Some((stream, idle_since)) = connections.pop() {
+            if idle_since.elapsed() < self.idle_timeout && is_reusable(&stream) {
+                return Some(stream);
+            }
+            // dropping the stream closes the connection
+        }
+
+        None
+    }
+
+    // return the connection to the pool after a complete response,
+    // returns false if the connection is closed as the pool for the endpoint is full
+    pub fn checkin(&self, ip: &str, port: u16, stream: TcpStream) -> bool {
+        let mut idle_connections = self.idle_connections.lock().unwrap();
+        let connections = idle_connections.entry((ip.to_string(), port)).or_default();
+        connections.retain(|(_, idle_since)| idle_since.elapsed() < self.idle_timeout);
+        if connections.len() >= self.max_idle_per_endpoint {
+            return false;
+        }
+
+        connections.push((stream, Instant::now()));
+        true
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle_connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|connections| connections.len())
+            .sum()
+    }
+}
+
+// an idle connection must not have any data to read,
+// read 0 byte means the server half-closed it, any data means the previous response is not fully consumed
+fn is_reusable(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut buf = [0u8; 1];
+    let result = stream.peek(&mut buf);
+    if stream.set_nonblocking(false).is_err() {
+        return false;
+    }
+
+    match result {
+        Ok(_) => false,
+        Err(e) => e.kind() == ErrorKind::WouldBlock,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionPool;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    fn connect(listener: &TcpListener) -> (TcpStream, TcpStream) {
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn connection_pool_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let ip = "127.0.0.1";
+
+        let pool = ConnectionPool::new(Duration::from_secs(60), 2);
+        assert!(pool.checkout(ip, port).is_none(), "pool starts empty");
+
+        // reuse the idle connection
+        let (client, _server) = connect(&listener);
+        let local_addr = client.local_addr().unwrap();
+        assert!(pool.checkin(ip, port, client));
+        assert_eq!(1, pool.idle_count());
+        let reused = pool.checkout(ip, port).unwrap();
+        assert_eq!(local_addr, reused.local_addr().unwrap());
+        assert_eq!(0, pool.idle_count());
+        assert!(pool.checkout(ip, port).is_none());
+        assert!(
+            pool.checkout(ip, port + 1).is_none(),
+            "connections are kept per endpoint"
+        );
+
+        // max idle connections per endpoint
+        let (client1, _server1) = connect(&listener);
+        let (client2, _server2) = connect(&listener);
+        let (client3, _server3) = connect(&listener);
+        assert!(pool.checkin(ip, port, client1));
+        assert!(pool.checkin(ip, port, client2));
+        assert!(!pool.checkin(ip, port, client3), "pool is full");
+        assert_eq!(2, pool.idle_count());
+        _ = pool.checkout(ip, port);
+        _ = pool.checkout(ip, port);
+
+        // discard the connection half-closed by the server
+        let (client, server) = connect(&listener);
+        assert!(pool.checkin(ip, port, client));
+        drop(server);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(
+            pool.checkout(ip, port).is_none(),
+            "half-closed connection must be discarded"
+        );
+
+        // discard the connection with unexpected data
+        let (client, mut server) = connect(&listener);
+        assert!(pool.checkin(ip, port, client));
+        server.write_all(b"HTTP/1.1 200 OK\r\n").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(
+            pool.checkout(ip, port).is_none(),
+            "connection with unread data must be discarded"
+        );
+
+        // discard the expired connection
+        let pool = ConnectionPool::new(Duration::from_millis(20), 2);
+        let (client, _server) = connect(&listener);
+        assert!(pool.checkin(ip, port, client));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(
+            pool.checkout(ip, port).is_none(),
+            "expired connection must be discarded"
+        );
+
+        // pool disabled
+        let pool = ConnectionPool::new(Duration::from_secs(60), 0);
+        let (client, _server) = connect(&listener);
+        assert!(!pool.checkin(ip, port, client));
+        assert_eq!(0, pool.idle_count());
+    }
+}
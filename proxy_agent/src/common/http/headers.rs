@@ -4,10 +4,48 @@ use crate::common::{constants, logger};
 use itertools::Itertools;
 use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
 
 pub const CONTENT_LENGTH_HEADER_NAME: &str = "Content-Length";
+pub const CONTENT_TYPE_HEADER_NAME: &str = "Content-Type";
 pub const EXPECT_HEADER_NAME: &str = "Expect";
 pub const EXPECT_HEADER_VALUE: &str = "100-continue";
+pub const TRANSFER_ENCODING_HEADER_NAME: &str = "Transfer-Encoding";
+pub const CHUNKED_TRANSFER_ENCODING: &str = "chunked";
+pub const HOST_HEADER_NAME: &str = "Host";
+pub const UPGRADE_HEADER_NAME: &str = "Upgrade";
+pub const HTTP2_SETTINGS_HEADER_NAME: &str = "HTTP2-Settings";
+pub const RETRY_AFTER_HEADER_NAME: &str = "Retry-After";
+pub const ALLOW_HEADER_NAME: &str = "Allow";
+pub const H2C_UPGRADE_PROTOCOL: &str = "h2c";
+pub const KEEP_ALIVE_HEADER_NAME: &str = "Keep-Alive";
+pub const PROXY_CONNECTION_HEADER_NAME: &str = "Proxy-Connection";
+pub const PROXY_AUTHENTICATE_HEADER_NAME: &str = "Proxy-Authenticate";
+pub const PROXY_AUTHORIZATION_HEADER_NAME: &str = "Proxy-Authorization";
+pub const TE_HEADER_NAME: &str = "TE";
+pub const X_FORWARDED_FOR_HEADER_NAME: &str = "X-Forwarded-For";
+pub const X_ORIGINAL_HOST_HEADER_NAME: &str = "X-Original-Host";
+
+/*
+    The hop-by-hop headers (RFC 7230 section 6.1) only apply to the connection they are received from.
+    Transfer-Encoding and Trailer are not listed: the chunked body and its trailers are relayed as they are,
+    so the framing headers still describe the forwarded message.
+*/
+const HOP_BY_HOP_HEADER_NAMES: [&str; 7] = [
+    constants::CONNECTION_HEADER,
+    KEEP_ALIVE_HEADER_NAME,
+    PROXY_CONNECTION_HEADER_NAME,
+    PROXY_AUTHENTICATE_HEADER_NAME,
+    PROXY_AUTHORIZATION_HEADER_NAME,
+    TE_HEADER_NAME,
+    UPGRADE_HEADER_NAME,
+];
+// the headers framing the message are never removed, even if the Connection header lists them
+const FRAMING_HEADER_NAMES: [&str; 3] = [
+    CONTENT_LENGTH_HEADER_NAME,
+    TRANSFER_ENCODING_HEADER_NAME,
+    HOST_HEADER_NAME,
+];
 
 pub struct Headers {
     // hash map for the headers
@@ -67,11 +105,11 @@ impl Headers {
     }
 
     //CanonicalizedHeaders
-    //Convert the header names to lowercase
-    //Sort the headers lexicographically by header name, in ascending order. Duplicates are not permitted.
-    ///Replace any linear whitespaces with a single space (See RFC 2616 Section 4.2  for details). Do not replace whitespace in quoted strings
-    //Trim any whitespace around the colon in the header
-    //Construct the final string by joining all headers in the list separated by "\n"
+    //Convert the header names to lowercase
+    //Sort the headers lexicographically by header name, in ascending order. Duplicates are not permitted, the last one added wins.
+    //Trim any whitespace around the header value, the whitespaces within the value are kept as they are
+    //Skip the Authorization header
+    //Construct the final string by appending "\n" to each header
     pub fn to_canonicalized_string(&self) -> String {
         let mut canonicalized_headers = String::new();
         let separator = String::from(super::LF);
@@ -105,6 +143,152 @@ impl Headers {
         raw_headers
     }
 
+    pub fn get_header(&self, key: &str) -> Option<String> {
+        self.map
+            .get(&key.to_lowercase())
+            .map(|header| header.1.to_string())
+    }
+
+    pub fn remove_header(&mut self, key: &str) -> Option<String> {
+        self.map.remove(&key.to_lowercase()).map(|header| header.1)
+    }
+
+    /*
+        Remove the HTTP/2 cleartext upgrade (RFC 7540 section 3.2) from the request headers:
+            Upgrade: h2c, HTTP2-Settings and their tokens in the Connection header.
+        The request is then served over HTTP/1.1 as if the upgrade was ignored,
+        instead of letting the host switch the protocol that the proxy cannot forward.
+        Returns true if the upgrade is removed.
+    */
+    pub fn remove_h2c_upgrade(&mut self) -> bool {
+        let upgrade = match self.get_header(UPGRADE_HEADER_NAME) {
+            Some(upgrade) => upgrade,
+            None => return false,
+        };
+        let remove_token = |value: &str, token: &str| -> String {
+            value
+                .split(',')
+                .map(|t| t.trim())
+                .filter(|t| !t.is_empty() && !t.eq_ignore_ascii_case(token))
+                .collect::<Vec<&str>>()
+                .join(", ")
+        };
+
+        let remaining_upgrade = remove_token(&upgrade, H2C_UPGRADE_PROTOCOL);
+        if remaining_upgrade == upgrade.trim() {
+            return false;
+        }
+        self.remove_header(HTTP2_SETTINGS_HEADER_NAME);
+
+        let mut removed_connection_tokens = vec![HTTP2_SETTINGS_HEADER_NAME];
+        if remaining_upgrade.is_empty() {
+            self.remove_header(UPGRADE_HEADER_NAME);
+            removed_connection_tokens.push(UPGRADE_HEADER_NAME);
+        } else {
+            self.add_header(UPGRADE_HEADER_NAME.to_string(), remaining_upgrade);
+        }
+
+        if let Some(connection) = self.get_header(constants::CONNECTION_HEADER) {
+            let mut connection = connection;
+            for token in removed_connection_tokens {
+                connection = remove_token(&connection, token);
+            }
+            if connection.is_empty() {
+                self.remove_header(constants::CONNECTION_HEADER);
+            } else {
+                self.add_header(constants::CONNECTION_HEADER.to_string(), connection);
+            }
+        }
+
+        true
+    }
+
+    /*
+        Remove the headers in the deny list, and the headers not in the allow list if it is set.
+        The header names are compared case-insensitively.
+        Returns the original names of the removed headers, sorted.
+    */
+    pub fn filter(&mut self, deny_list: &[String], allow_list: Option<&[String]>) -> Vec<String> {
+        let is_listed =
+            |list: &[String], key: &str| list.iter().any(|name| name.eq_ignore_ascii_case(key));
+        let mut removed = Vec::new();
+        self.map.retain(|key, header| {
+            let keep = !is_listed(deny_list, key)
+                && allow_list.map_or(true, |allow_list| is_listed(allow_list, key));
+            if !keep {
+                removed.push(header.0.to_string());
+            }
+            keep
+        });
+        removed.sort();
+        removed
+    }
+
+    // remove the hop-by-hop headers and the headers listed in the Connection header, returns the removed header names
+    pub fn remove_hop_by_hop_headers(&mut self) -> Vec<String> {
+        let mut names: Vec<String> = match self.get_header(constants::CONNECTION_HEADER) {
+            Some(connection) => connection
+                .split(',')
+                .map(|token| token.trim())
+                .filter(|token| {
+                    !token.is_empty()
+                        && !FRAMING_HEADER_NAMES
+                            .iter()
+                            .any(|name| name.eq_ignore_ascii_case(token))
+                })
+                .map(|token| token.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+        names.extend(HOP_BY_HOP_HEADER_NAMES.iter().map(|name| name.to_string()));
+        self.filter(&names, None)
+    }
+
+    /*
+        Append the client ip to the X-Forwarded-For header.
+        The values set by the client are kept only if they are ip addresses, so a client cannot inject the other content.
+        Returns the dropped values.
+    */
+    pub fn append_forwarded_for(&mut self, client_ip: &str) -> Vec<String> {
+        let mut forwarded_for = Vec::new();
+        let mut dropped = Vec::new();
+        if let Some(value) = self.get_header(X_FORWARDED_FOR_HEADER_NAME) {
+            for ip in value.split(',').map(|ip| ip.trim()) {
+                if ip.parse::<IpAddr>().is_ok() {
+                    forwarded_for.push(ip.to_string());
+                } else if !ip.is_empty() {
+                    dropped.push(ip.to_string());
+                }
+            }
+        }
+        forwarded_for.push(client_ip.to_string());
+        self.remove_header(X_FORWARDED_FOR_HEADER_NAME);
+        self.add_header(
+            X_FORWARDED_FOR_HEADER_NAME.to_string(),
+            forwarded_for.join(", "),
+        );
+        dropped
+    }
+
+    /*
+        Replace the Host header and keep the original one in the X-Original-Host header.
+        The X-Original-Host header set by the client is always replaced, so it cannot be spoofed.
+        Returns the original Host header.
+    */
+    pub fn rewrite_host(&mut self, host: &str) -> Option<String> {
+        let original_host = self.get_header(HOST_HEADER_NAME);
+        self.remove_header(X_ORIGINAL_HOST_HEADER_NAME);
+        if let Some(original_host) = &original_host {
+            self.add_header(
+                X_ORIGINAL_HOST_HEADER_NAME.to_string(),
+                original_host.to_string(),
+            );
+        }
+        self.remove_header(HOST_HEADER_NAME);
+        self.add_header(HOST_HEADER_NAME.to_string(), host.to_string());
+        original_host
+    }
+
     pub fn len(&self) -> usize {
         self.map.len()
     }
@@ -120,6 +304,18 @@ impl Headers {
         false
     }
 
+    pub fn is_chunked_transfer_encoding(&self) -> bool {
+        let transfer_encoding_key = TRANSFER_ENCODING_HEADER_NAME.to_lowercase();
+        if self.map.contains_key(&transfer_encoding_key) {
+            return self.map[&transfer_encoding_key]
+                .1
+                .to_lowercase()
+                .contains(CHUNKED_TRANSFER_ENCODING);
+        }
+
+        false
+    }
+
     pub fn get_content_length_as_string(&self) -> String {
         let content_length_key = CONTENT_LENGTH_HEADER_NAME.to_lowercase();
         if self.map.contains_key(&content_length_key) {
@@ -146,9 +342,19 @@ impl Headers {
     }
 }
 
+// the visible ascii characters, spaces and tabs only,
+// so the value can neither end the header line nor start another header
+pub fn is_valid_header_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|b| b == b' ' || b == b'\t' || (0x21..=0x7e).contains(&b))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::common::http::headers::Headers;
+    use crate::common::http::headers::{
+        Headers, HOST_HEADER_NAME, X_FORWARDED_FOR_HEADER_NAME, X_ORIGINAL_HOST_HEADER_NAME,
+    };
 
     #[test]
     fn headers_test() {
@@ -229,4 +435,124 @@ x-powered-by:ASP.NET
             "to_canonicalized_string mismatch with copied_headers"
         );
     }
+
+    #[test]
+    fn filter_test() {
+        let raw_string = "Host: 168.63.129.16
+        Content-Length: 0
+        X-Powered-By: ASP.NET
+        x-ms-azure-host-authorization: 0000
+        X-Custom: value";
+        let mut headers = Headers::from_raw_data(raw_string.to_string());
+
+        let removed = headers.filter(&["X-MS-Azure-Host-Authorization".to_string()], None);
+        assert_eq!(vec!["x-ms-azure-host-authorization"], removed);
+        assert_eq!(4, headers.len());
+
+        let allow_list = ["host".to_string(), "content-length".to_string()];
+        let removed = headers.filter(&[], Some(&allow_list));
+        assert_eq!(vec!["X-Custom", "X-Powered-By"], removed);
+        assert_eq!(
+            Some("168.63.129.16".to_string()),
+            headers.get_header("Host")
+        );
+        assert_eq!(2, headers.len());
+
+        let removed = headers.filter(&["host".to_string()], Some(&allow_list));
+        assert_eq!(
+            vec!["Host"],
+            removed,
+            "the deny list takes precedence over the allow list"
+        );
+    }
+
+    #[test]
+    fn append_forwarded_for_test() {
+        let mut headers = Headers::new();
+        assert!(headers.append_forwarded_for("10.0.0.4").is_empty());
+        assert_eq!(
+            Some("10.0.0.4".to_string()),
+            headers.get_header("x-forwarded-for")
+        );
+
+        let mut headers = Headers::from_raw_data(
+            "x-forwarded-for: 192.168.1.10, unknown;for=spoofed,  fd00::1,".to_string(),
+        );
+        let dropped = headers.append_forwarded_for("127.0.0.1");
+        assert_eq!(vec!["unknown;for=spoofed"], dropped);
+        assert_eq!(
+            Some("192.168.1.10, fd00::1, 127.0.0.1".to_string()),
+            headers.get_header(X_FORWARDED_FOR_HEADER_NAME)
+        );
+        assert_eq!(1, headers.len());
+    }
+
+    #[test]
+    fn rewrite_host_test() {
+        let mut headers = Headers::from_raw_data(
+            "host: metadata.azure.internal\nx-original-host: spoofed".to_string(),
+        );
+        assert_eq!(
+            Some("metadata.azure.internal".to_string()),
+            headers.rewrite_host("169.254.169.254")
+        );
+        assert_eq!(
+            Some("169.254.169.254".to_string()),
+            headers.get_header(HOST_HEADER_NAME)
+        );
+        assert_eq!(
+            Some("metadata.azure.internal".to_string()),
+            headers.get_header(X_ORIGINAL_HOST_HEADER_NAME)
+        );
+        assert_eq!(2, headers.len());
+        assert_eq!(
+            "host:169.254.169.254\nx-original-host:metadata.azure.internal\n",
+            headers.to_canonicalized_string(),
+            "the signature input has the rewritten Host header"
+        );
+
+        let mut headers = Headers::from_raw_data("x-original-host: spoofed".to_string());
+        assert_eq!(None, headers.rewrite_host("168.63.129.16:32526"));
+        assert_eq!(
+            None,
+            headers.get_header(X_ORIGINAL_HOST_HEADER_NAME),
+            "no original host to preserve"
+        );
+        assert_eq!(1, headers.len());
+    }
+
+    #[test]
+    fn remove_hop_by_hop_headers_test() {
+        let raw_string = "Host: 168.63.129.16
+        Content-Length: 10
+        Connection: keep-alive, X-Session, Content-Length
+        Keep-Alive: timeout=5
+        Proxy-Connection: keep-alive
+        Proxy-Authorization: Basic dGVzdA==
+        TE: trailers
+        Upgrade: websocket
+        X-Session: 1
+        X-Custom: value";
+        let mut headers = Headers::from_raw_data(raw_string.to_string());
+        let removed = headers.remove_hop_by_hop_headers();
+        assert_eq!(
+            vec![
+                "Connection",
+                "Keep-Alive",
+                "Proxy-Authorization",
+                "Proxy-Connection",
+                "TE",
+                "Upgrade",
+                "X-Session"
+            ],
+            removed
+        );
+        assert_eq!(
+            Some("10".to_string()),
+            headers.get_header("Content-Length"),
+            "the framing header listed in the Connection header must be kept"
+        );
+        assert_eq!(3, headers.len());
+        assert!(headers.remove_hop_by_hop_headers().is_empty());
+    }
 }
@@ -1,11 +1,13 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
-use url::Url;
+use url::{Position, Url};
 
 use super::request::Request;
 use crate::common::constants;
 use crate::common::helpers;
 use crate::common::logger;
+use crate::key_keeper::key::Key;
+use crate::proxy::HostClaims;
 use proxy_agent_shared::misc_helpers;
 
 pub struct HttpRequest {
@@ -21,12 +23,98 @@ impl HttpRequest {
         }
     }
 
+    // thin wrapper of RequestBuilder for the callers with the request already built
     pub fn new_proxy_agent_request(
         uri: Url,
-        mut request: Request,
+        request: Request,
         key_guid: String,
         key: String,
     ) -> std::io::Result<Self> {
+        let mut signing_key = Key::empty();
+        signing_key.guid = key_guid;
+        signing_key.key = key;
+        RequestBuilder {
+            uri,
+            request,
+            key: Some(signing_key),
+        }
+        .build()
+    }
+
+    pub fn clone_without_body(uri: Url, request: &Request) -> Self {
+        HttpRequest {
+            uri: uri,
+            request: request.clone_without_body(),
+        }
+    }
+
+    pub fn get_host(&self) -> String {
+        match self.uri.host_str() {
+            Some(host) => host.to_owned(),
+            None => "".to_owned(),
+        }
+    }
+
+    pub fn get_port(&self) -> u16 {
+        match self.uri.port_or_known_default() {
+            Some(port) => port,
+            None => 0,
+        }
+    }
+}
+
+/*
+Build the request sent by the agent to the host endpoints with all the required headers, e.g.
+RequestBuilder::new(url).method("POST").header("x-ms-version", "2012-11-30").body(data).sign_with(&key).build()
+The request is signed at last in 'build', after its headers and body are set,
+it is not signed if the key is empty.
+ */
+pub struct RequestBuilder {
+    uri: Url,
+    request: Request,
+    key: Option<Key>,
+}
+
+impl RequestBuilder {
+    // GET the path and query of the uri by default
+    pub fn new(uri: Url) -> Self {
+        let request = Request::new(uri[Position::BeforePath..].to_string(), "GET".to_string());
+        RequestBuilder {
+            uri,
+            request,
+            key: None,
+        }
+    }
+
+    pub fn method(mut self, method: &str) -> Self {
+        self.request.method = method.to_string();
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.request
+            .headers
+            .add_header(name.to_string(), value.to_string());
+        self
+    }
+
+    // the Content-Length header is set to the body length
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.request.headers.add_header(
+            super::headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
+            body.len().to_string(),
+        );
+        self.request.set_body(body);
+        self
+    }
+
+    pub fn sign_with(mut self, key: &Key) -> Self {
+        self.key = Some(key.clone());
+        self
+    }
+
+    pub fn build(self) -> std::io::Result<HttpRequest> {
+        let mut request = self.request;
         //connection:Close
         request.headers.add_header(
             constants::CONNECTION_HEADER.to_string(),
@@ -41,29 +129,21 @@ impl HttpRequest {
         );
         request.headers.add_header(
             constants::CLAIMS_HEADER.to_string(),
-            format!("{{ \"{}\": \"{}\"}}", constants::CLAIMS_IS_ROOT, true,),
+            HostClaims::new(true).to_header_value()?,
         );
-        let mut http_request = HttpRequest::new(uri, request);
+        let mut http_request = HttpRequest::new(self.uri, request);
         http_request
             .request
             .headers
             .add_header("Host".to_string(), http_request.get_host());
 
-        if key != "" {
-            let input_to_sign = http_request.request.as_sig_input();
-            let authorization_value = format!(
-                "{} {} {}",
-                constants::AUTHORIZATION_SCHEME,
-                key_guid,
-                helpers::compute_signature(key.to_string(), &input_to_sign.as_slice())?
-            );
-            match String::from_utf8(input_to_sign) {
-                Ok(data) => {
-                    logger::write_information(format!(
-                        "Computed the signature with input: {}",
-                        data
-                    ))
-                }
+        if let Some(key) = self.key.filter(|key| key.key != "") {
+            add_authorization_header(&mut http_request.request, &key)?;
+            match String::from_utf8(http_request.request.as_sig_input()) {
+                Ok(data) => logger::write_information(format!(
+                    "Computed the signature with input: {}",
+                    data
+                )),
                 Err(e) => {
                     logger::write_information(format!(
                         "Failed convert the input_to_sign to string, error {}",
@@ -71,33 +151,131 @@ impl HttpRequest {
                     ));
                 }
             }
-            http_request.request.headers.add_header(
-                constants::AUTHORIZATION_HEADER.to_string(),
-                authorization_value.to_string(),
-            );
         }
 
         Ok(http_request)
     }
+}
 
-    pub fn clone_without_body(uri: Url, request: &Request) -> Self {
-        HttpRequest {
-            uri: uri,
-            request: request.clone_without_body(),
-        }
-    }
+// add the x-ms-azure-host-authorization header signed by the key with its authorization scheme and return its value,
+// the signature input is fed to the signer in parts, so the body is not copied
+pub fn add_authorization_header(request: &mut Request, key: &Key) -> std::io::Result<String> {
+    let mut signer = helpers::Signer::new(&key.authorizationScheme, &key.key)?;
+    request.update_sig_input(|part| signer.update(part));
+    let authorization_value = signer.build_authorization_header(&key.guid);
+    request.headers.add_header(
+        constants::AUTHORIZATION_HEADER.to_string(),
+        authorization_value.to_string(),
+    );
+    Ok(authorization_value)
+}
 
-    pub fn get_host(&self) -> String {
-        match self.uri.host_str() {
-            Some(host) => host.to_owned(),
-            None => "".to_owned(),
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use super::RequestBuilder;
+    use crate::common::constants;
+    use crate::common::helpers;
+    use crate::common::http::headers;
+    use crate::common::logger;
+    use crate::key_keeper::key::Key;
+    use proxy_agent_shared::logger_manager;
+    use std::env;
+    use url::Url;
 
-    pub fn get_port(&self) -> u16 {
-        match self.uri.port_or_known_default() {
-            Some(port) => port,
-            None => 0,
+    #[test]
+    fn request_builder_test() {
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push("request_builder_test");
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(),
+            temp_test_path.to_path_buf(),
+            "logger_key".to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let url = Url::parse("http://168.63.129.16/machine/?comp=telemetrydata").unwrap();
+        let mut key = Key::empty();
+        key.key = "4A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59".to_string();
+
+        let http_request = RequestBuilder::new(url.clone())
+            .method("POST")
+            .header("x-ms-version", "2012-11-30")
+            .body(b"<data/>".to_vec())
+            .sign_with(&key)
+            .build()
+            .unwrap();
+        let request = &http_request.request;
+        assert_eq!("POST", request.method);
+        assert_eq!("/machine/?comp=telemetrydata", request.url);
+        assert_eq!(b"<data/>".to_vec(), request.get_body().to_vec());
+        for (name, value) in [
+            ("x-ms-version", Some("2012-11-30")),
+            (headers::CONTENT_LENGTH_HEADER_NAME, Some("7")),
+            (headers::HOST_HEADER_NAME, Some("168.63.129.16")),
+            (constants::METADATA_HEADER, Some("True")),
+            (constants::CONNECTION_HEADER, Some("Close")),
+            (constants::DATE_HEADER, None),
+            (constants::CLAIMS_HEADER, None),
+        ] {
+            let header = request.headers.get_header(name);
+            assert!(header.is_some(), "header {} is required", name);
+            if let Some(value) = value {
+                assert_eq!(Some(value.to_string()), header);
+            }
         }
+        // all the headers are signed
+        let expected = helpers::build_authorization_header(
+            &key.authorizationScheme,
+            &key.key,
+            &key.guid,
+            &request.as_sig_input(),
+        )
+        .unwrap();
+        assert_eq!(
+            Some(expected),
+            request.headers.get_header(constants::AUTHORIZATION_HEADER)
+        );
+
+        // signed with the algorithm of the key
+        let mut sha512_key = key.clone();
+        sha512_key.authorizationScheme = constants::AUTHORIZATION_SCHEME_SHA512.to_string();
+        let http_request = RequestBuilder::new(url.clone())
+            .sign_with(&sha512_key)
+            .build()
+            .unwrap();
+        let authorization = http_request
+            .request
+            .headers
+            .get_header(constants::AUTHORIZATION_HEADER)
+            .unwrap();
+        assert!(authorization.starts_with("Azure-HMAC-SHA512 "));
+        assert!(helpers::verify_authorization_header(
+            &authorization,
+            constants::AUTHORIZATION_SCHEME_SHA512,
+            &sha512_key.key,
+            &http_request.request.as_sig_input()
+        ));
+        sha512_key.authorizationScheme = "Azure-HMAC-MD5".to_string();
+        assert!(RequestBuilder::new(url.clone())
+            .sign_with(&sha512_key)
+            .build()
+            .is_err());
+
+        // not signed with the empty key
+        let http_request = RequestBuilder::new(url.clone())
+            .sign_with(&Key::empty())
+            .build()
+            .unwrap();
+        assert_eq!("GET", http_request.request.method);
+        assert!(http_request
+            .request
+            .headers
+            .get_header(constants::AUTHORIZATION_HEADER)
+            .is_none());
+
+        let mut key = Key::empty();
+        key.key = "invalid".to_string();
+        assert!(RequestBuilder::new(url).sign_with(&key).build().is_err());
     }
 }
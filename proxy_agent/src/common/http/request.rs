@@ -1,5 +1,6 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+use crate::common::constants;
 use crate::common::http::headers::Headers;
 use itertools::Itertools;
 use std::{
@@ -143,7 +144,7 @@ impl Request {
         raw_data
     }
 
-    fn get_raw_string_without_body(&self) -> String {
+    pub fn get_raw_string_without_body(&self) -> String {
         let mut raw_data = String::new();
 
         // first line
@@ -185,66 +186,40 @@ impl Request {
         }
     }
 
-    fn get_url_path_and_canonicalized_parameters(&self) -> (String, String) {
-        let mut url;
-        match Url::parse(&self.url) {
-            Ok(u) => url = u,
-            Err(_) => {
-                url = Url::parse("http://127.0.0.1").unwrap();
-                match url.join(&self.url) {
-                    Ok(u) => url = u,
-                    Err(_) => return (self.url.to_string(), "".to_string()),
-                }
-            }
-        }
-
-        let path = String::from(url.path());
-
-        let parameters = url.query_pairs();
-        let mut pairs: HashMap<String, String> = HashMap::new();
-        let mut canonicalized_parameters = String::new();
-        if parameters.count() > 0 {
-            for p in parameters {
-                // Convert the parameter name to lowercase
-                pairs.insert(p.0.to_lowercase(), p.1.to_string());
-            }
+    pub fn as_sig_input(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(self.body.len() + 256);
+        self.update_sig_input(|part| data.extend(part));
 
-            // Sort the parameters lexicographically by parameter name, in ascending order.
-            let mut first = true;
-            for key in pairs.keys().sorted() {
-                if !first {
-                    canonicalized_parameters.push_str("&");
-                }
-                first = false;
-                // Join each parameter key value pair with '='
-                let p = format!("{}={}", key, pairs[key]);
-                canonicalized_parameters.push_str(&p);
-            }
-        }
+        data
+    }
 
-        (path, canonicalized_parameters)
+    // feed the signature input to 'update' part by part in order,
+    // the body is fed in place without copying it
+    pub fn update_sig_input<F: FnMut(&[u8])>(&self, update: F) {
+        update_sig_input(&self.method, &self.body, &self.headers, &self.url, update);
     }
 
-    /*
-        StringToSign = Method + "\n" +
-               HexEncoded(Body) + "\n" +
-               CanonicalizedHeaders + "\n"
-               UrlEncodedPath + "\n"
-               CanonicalizedParameters;
-    */
-    pub fn as_sig_input(&self) -> Vec<u8> {
-        let mut data: Vec<u8> = self.method.as_bytes().to_vec();
-        data.extend(super::LF.as_bytes());
-        data.extend(self.body.clone());
-        data.extend(super::LF.as_bytes());
-        data.extend(self.headers.to_canonicalized_string().as_bytes());
+    // the HTTP/2 connection preface sent by the clients with prior knowledge, RFC 7540 section 3.5
+    pub fn is_http2_preface(&self) -> bool {
+        self.method == "PRI" && self.url == "*" && self.version == "HTTP/2.0"
+    }
 
-        let path_para = self.get_url_path_and_canonicalized_parameters();
-        data.extend(path_para.0.as_bytes());
-        data.extend(super::LF.as_bytes());
-        data.extend(path_para.1.as_bytes());
+    // CONNECT host:port asks the proxy to open a tunnel, RFC 9110 section 9.3.6
+    pub fn is_connect_request(&self) -> bool {
+        self.method.eq_ignore_ascii_case("CONNECT")
+    }
 
-        data
+    // the client sends the next request on the same connection unless it asks to close it
+    pub fn is_keep_alive(&self) -> bool {
+        if self.version.trim() != "HTTP/1.1" {
+            return false;
+        }
+        match self.headers.get_header(constants::CONNECTION_HEADER) {
+            Some(connection) => !connection
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("close")),
+            None => true,
+        }
     }
 
     pub fn expect_continue_request(&self) -> bool {
@@ -292,6 +267,82 @@ impl Request {
     }
 }
 
+/*
+   The signing contract, the bytes signed by the agent and verified by the host:
+       StringToSign = Method + "\n" +
+              Body + "\n" +
+              CanonicalizedHeaders +
+              UrlPath + "\n" +
+              CanonicalizedParameters;
+   Method is as received, the case is kept.
+   Body is the raw body bytes, not encoded; it is empty when the request has no body.
+   CanonicalizedHeaders is "name:value\n" for each header sorted by the lowercase name,
+       the value is trimmed, the Authorization header is excluded;
+       the duplicate header names are not permitted, the last one received wins.
+   UrlPath is the percent-encoded path of the url, the absolute and the origin form urls have the same path.
+   CanonicalizedParameters is "name=value" joined by "&" sorted by the lowercase name,
+       the value is percent-decoded; the duplicate parameter names are not permitted, the last one wins.
+*/
+pub fn update_sig_input<F: FnMut(&[u8])>(
+    method: &str,
+    body: &[u8],
+    headers: &Headers,
+    url: &str,
+    mut update: F,
+) {
+    update(method.as_bytes());
+    update(super::LF.as_bytes());
+    update(body);
+    update(super::LF.as_bytes());
+    update(headers.to_canonicalized_string().as_bytes());
+
+    let path_para = canonicalize_url(url);
+    update(path_para.0.as_bytes());
+    update(super::LF.as_bytes());
+    update(path_para.1.as_bytes());
+}
+
+// returns the url path and the canonicalized query parameters of the signature input
+fn canonicalize_url(raw_url: &str) -> (String, String) {
+    let mut url;
+    match Url::parse(raw_url) {
+        Ok(u) => url = u,
+        Err(_) => {
+            url = Url::parse("http://127.0.0.1").unwrap();
+            match url.join(raw_url) {
+                Ok(u) => url = u,
+                Err(_) => return (raw_url.to_string(), "".to_string()),
+            }
+        }
+    }
+
+    let path = String::from(url.path());
+
+    let parameters = url.query_pairs();
+    let mut pairs: HashMap<String, String> = HashMap::new();
+    let mut canonicalized_parameters = String::new();
+    if parameters.count() > 0 {
+        for p in parameters {
+            // Convert the parameter name to lowercase
+            pairs.insert(p.0.to_lowercase(), p.1.to_string());
+        }
+
+        // Sort the parameters lexicographically by parameter name, in ascending order.
+        let mut first = true;
+        for key in pairs.keys().sorted() {
+            if !first {
+                canonicalized_parameters.push_str("&");
+            }
+            first = false;
+            // Join each parameter key value pair with '='
+            let p = format!("{}={}", key, pairs[key]);
+            canonicalized_parameters.push_str(&p);
+        }
+    }
+
+    (path, canonicalized_parameters)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -337,7 +388,7 @@ mod tests {
             "to_raw_string len() mismatch when body with multple empty lines"
         );
 
-        let path_para = request.get_url_path_and_canonicalized_parameters();
+        let path_para = super::canonicalize_url(&request.url);
         assert_eq!("/c/msdownload/update/others/2023/02/38363234_2e2f6538d77706f479374be2eec956c5a7544925.cab",
          path_para.0, "path mismatch");
         assert_eq!("", path_para.1, "query parameters must be empty");
@@ -351,7 +402,7 @@ mod tests {
         raw_string.push_str(super::super::CRLF);
         let request = Request::from_raw_request(raw_string.to_string()).unwrap();
 
-        let path_para = request.get_url_path_and_canonicalized_parameters();
+        let path_para = super::canonicalize_url(&request.url);
         assert_eq!("/machine/a8016240-7286-49ef-8981-63520cb8f6d0/49c242ba%2Dc18a%2D4f6c%2D8cf8%2D85ff790b6431.%5Fzpeng%2Debpf%2Dvm2",
          path_para.0, "path mismatch");
         assert_eq!(
@@ -359,4 +410,204 @@ mod tests {
             "query parameters mismatch"
         );
     }
+
+    #[test]
+    fn sig_input_test() {
+        // the exact bytes of the signing contract
+        let mut request = Request::new(
+            "http://168.63.129.16/machine/?comp=goalstate&Incarnation=1".to_string(),
+            "GET".to_string(),
+        );
+        request
+            .headers
+            .add_header("x-ms-version".to_string(), " 2012-11-30 ".to_string());
+        request
+            .headers
+            .add_header("Host".to_string(), "168.63.129.16".to_string());
+        request.headers.add_header(
+            crate::common::constants::AUTHORIZATION_HEADER.to_string(),
+            "the signature".to_string(),
+        );
+        let expected =
+            "GET\n\nhost:168.63.129.16\nx-ms-version:2012-11-30\n/machine/\ncomp=goalstate&incarnation=1";
+        assert_eq!(expected.as_bytes(), request.as_sig_input());
+
+        // the origin form url has the same signature input
+        request.url = "/machine/?comp=goalstate&Incarnation=1".to_string();
+        assert_eq!(expected.as_bytes(), request.as_sig_input());
+
+        // the binary body is signed as it is
+        let body = vec![0u8, 0xff, b'\n', b'\r', 0x80];
+        request.set_body(body.to_vec());
+        let mut expected_input = b"GET\n".to_vec();
+        expected_input.extend(&body);
+        expected_input.extend(b"\nhost:168.63.129.16\nx-ms-version:2012-11-30\n/machine/\ncomp=goalstate&incarnation=1");
+        assert_eq!(expected_input, request.as_sig_input());
+
+        // no header adds no line, the percent-encoded path is kept and the parameter values are decoded
+        let request = Request::new("/vmAgentLog%2Dx?B=a%20b&a=1".to_string(), "PUT".to_string());
+        assert_eq!(
+            b"PUT\n\n/vmAgentLog%2Dx\na=1&b=a b".to_vec(),
+            request.as_sig_input()
+        );
+    }
+
+    // a tiny xorshift generator, the failed case is reproduced by its seed
+    struct Fuzzer(u64);
+
+    impl Fuzzer {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn token(&mut self) -> String {
+            const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789-";
+            let len = 1 + self.below(12);
+            (0..len)
+                .map(|_| CHARS[self.below(CHARS.len())] as char)
+                .collect()
+        }
+
+        // the printable ascii with the spaces within and around it
+        fn text(&mut self) -> String {
+            let len = self.below(24);
+            let mut text: String = (0..len)
+                .map(|_| (b' ' + self.below(95) as u8) as char)
+                .collect();
+            for _ in 0..self.below(3) {
+                text.insert(0, ' ');
+                text.push('\t');
+            }
+            text
+        }
+
+        fn random_case(&mut self, s: &str) -> String {
+            s.chars()
+                .map(|c| {
+                    if self.below(2) == 0 {
+                        c.to_ascii_uppercase()
+                    } else {
+                        c
+                    }
+                })
+                .collect()
+        }
+
+        fn shuffle<T>(&mut self, items: &mut [T]) {
+            for i in (1..items.len()).rev() {
+                items.swap(i, self.below(i + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn sig_input_fuzz_test() {
+        use std::collections::BTreeMap;
+        const METHODS: [&str; 5] = ["GET", "POST", "PUT", "delete", "Patch"];
+
+        for seed in 1..=1000u64 {
+            let mut fuzzer = Fuzzer(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+            // the lowercase header name -> value, the last duplicate wins
+            let mut headers = BTreeMap::new();
+            for _ in 0..fuzzer.below(10) {
+                headers.insert(fuzzer.token(), fuzzer.text());
+            }
+            if fuzzer.below(3) == 0 {
+                headers.insert(
+                    crate::common::constants::AUTHORIZATION_HEADER.to_string(),
+                    fuzzer.text(),
+                );
+            }
+            let mut parameters = BTreeMap::new();
+            for _ in 0..fuzzer.below(5) {
+                parameters.insert(fuzzer.token(), fuzzer.token());
+            }
+            let path = (0..fuzzer.below(4))
+                .map(|_| {
+                    let segment = fuzzer.token();
+                    format!("/{}", fuzzer.random_case(&segment))
+                })
+                .collect::<String>();
+            let path = if path.is_empty() {
+                "/".to_string()
+            } else {
+                path
+            };
+            let body: Vec<u8> = match fuzzer.below(3) {
+                0 => Vec::new(),
+                _ => (0..fuzzer.below(64)).map(|_| fuzzer.next() as u8).collect(),
+            };
+            let method = METHODS[fuzzer.below(METHODS.len())];
+
+            // the reference of the signing contract
+            let mut expected = format!("{}\n", method).into_bytes();
+            expected.extend(&body);
+            expected.push(b'\n');
+            for (name, value) in &headers {
+                if name != crate::common::constants::AUTHORIZATION_HEADER {
+                    expected.extend(format!("{}:{}\n", name, value.trim()).as_bytes());
+                }
+            }
+            expected.extend(format!("{}\n", path).as_bytes());
+            let query = parameters
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<String>>()
+                .join("&");
+            expected.extend(query.as_bytes());
+
+            // the same request in the origin and absolute forms, with the different orders and casings
+            for absolute_url in [false, true] {
+                let mut query_pairs: Vec<String> = parameters
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", fuzzer.random_case(name), value))
+                    .collect();
+                fuzzer.shuffle(&mut query_pairs);
+                if let Some((name, _)) = parameters.iter().next() {
+                    // the stale duplicate parameter is sent first
+                    query_pairs.insert(0, format!("{}=stale", name));
+                }
+                let mut url = path.to_string();
+                if !query_pairs.is_empty() {
+                    url = format!("{}?{}", url, query_pairs.join("&"));
+                }
+                if absolute_url {
+                    url = format!("http://168.63.129.16{}", url);
+                }
+
+                let mut request = Request::new(url.to_string(), method.to_string());
+                let mut names: Vec<&String> = headers.keys().collect();
+                fuzzer.shuffle(&mut names);
+                for name in names {
+                    if fuzzer.below(4) == 0 {
+                        // the stale duplicate header is added first
+                        request
+                            .headers
+                            .add_header(fuzzer.random_case(name), fuzzer.text());
+                    }
+                    request
+                        .headers
+                        .add_header(fuzzer.random_case(name), headers[name].to_string());
+                }
+                request.set_body(body.to_vec());
+
+                assert_eq!(
+                    String::from_utf8_lossy(&expected),
+                    String::from_utf8_lossy(&request.as_sig_input()),
+                    "signature input mismatch with seed {} and url '{}'",
+                    seed,
+                    url
+                );
+                assert_eq!(expected, request.as_sig_input(), "seed {}", seed);
+            }
+        }
+    }
 }
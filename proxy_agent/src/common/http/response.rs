@@ -1,6 +1,7 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
-use crate::common::http::headers::Headers;
+use crate::common::constants;
+use crate::common::http::headers::{self, Headers};
 use std::io::{Error, ErrorKind};
 
 /*
@@ -22,10 +23,21 @@ pub struct Response {
 impl Response {
     pub const MISDIRECTED: &'static str = "421 Misdirected Request";
     pub const FORBIDDEN: &'static str = "403 Forbidden Request";
+    pub const METHOD_NOT_ALLOWED: &'static str = "405 Method Not Allowed";
     pub const BAD_GATEWAY: &'static str = "502 Bad Gateway";
+    pub const PAYLOAD_TOO_LARGE: &'static str = "413 Payload Too Large";
+    pub const TOO_MANY_REQUESTS: &'static str = "429 Too Many Requests";
     pub const CONTINUE: &'static str = "100 Continue";
     pub const BAD_REQUEST: &'static str = "400 Bad Request";
+    pub const NOT_FOUND: &'static str = "404 Not Found";
     pub const OK: &'static str = "200 OK";
+    pub const ACCEPTED: &'static str = "202 Accepted";
+    pub const CONNECTION_ESTABLISHED: &'static str = "200 Connection Established";
+    pub const SERVICE_UNAVAILABLE: &'static str = "503 Service Unavailable";
+    pub const INTERNAL_SERVER_ERROR: &'static str = "500 Internal Server Error";
+    pub const GATEWAY_TIMEOUT: &'static str = "504 Gateway Timeout";
+    pub const NOT_IMPLEMENTED: &'static str = "501 Not Implemented";
+    pub const HTTP_VERSION_NOT_SUPPORTED: &'static str = "505 HTTP Version Not Supported";
 
     pub fn new(status: String, body: String) -> Self {
         Response {
@@ -169,9 +181,36 @@ impl Response {
         self.status == Response::CONTINUE.to_string()
     }
 
+    // 5xx status, the server failed to serve the request
+    pub fn is_server_error(&self) -> bool {
+        self.status.trim_start().starts_with('5')
+    }
+
     pub fn get_body_len(&self) -> usize {
         self.body.len()
     }
+
+    // the connection can serve the next request only if the response body is framed
+    // and the server does not ask to close the connection
+    pub fn is_keep_alive(&self) -> bool {
+        if self.version.trim() != "HTTP/1.1" {
+            return false;
+        }
+        if let Some(connection) = self.headers.get_header(constants::CONNECTION_HEADER) {
+            if connection
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("close"))
+            {
+                return false;
+            }
+        }
+
+        self.headers.is_chunked_transfer_encoding()
+            || self
+                .headers
+                .get_header(headers::CONTENT_LENGTH_HEADER_NAME)
+                .is_some()
+    }
 }
 
 #[cfg(test)]
@@ -227,4 +266,35 @@ mod tests {
             "to_raw_string len() mismatch when body with multple empty lines"
         );
     }
+
+    #[test]
+    fn is_keep_alive_test() {
+        let response =
+            Response::from_raw_data("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_string());
+        assert!(response.is_keep_alive());
+
+        let response = Response::from_raw_data(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_string(),
+        );
+        assert!(response.is_keep_alive());
+
+        let response = Response::from_raw_data(
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: keep-alive, Close\r\n\r\n"
+                .to_string(),
+        );
+        assert!(
+            !response.is_keep_alive(),
+            "server asks to close the connection"
+        );
+
+        let response = Response::from_raw_data("HTTP/1.1 200 OK\r\n\r\n".to_string());
+        assert!(
+            !response.is_keep_alive(),
+            "body without length is delimited by closing the connection"
+        );
+
+        let response =
+            Response::from_raw_data("HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n".to_string());
+        assert!(!response.is_keep_alive());
+    }
 }
@@ -0,0 +1,141 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use crate::common::config::UpstreamTls;
+use once_cell::sync::Lazy;
+use rustls::{
+    ClientConfig, ClientConnection, OwnedTrustAnchor, RootCertStore, ServerName, StreamOwned,
+};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+// the client configs by the CA certificate file, None for the public roots
+static CLIENT_CONFIGS: Lazy<Mutex<HashMap<Option<String>, Arc<ClientConfig>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/*
+Start TLS over the connected TcpStream and complete the handshake,
+the server certificate is validated against the configured server name and root certificates.
+The handshake is bounded by the read/write timeout of the TcpStream.
+ */
+pub fn connect(mut tcp_stream: TcpStream, tls: &UpstreamTls) -> std::io::Result<TlsStream> {
+    let config = get_client_config(&tls.caCertificatePath)?;
+    let server_name = get_server_name(tls)?;
+    let mut connection = ClientConnection::new(config, server_name)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+
+    while connection.is_handshaking() {
+        connection.complete_io(&mut tcp_stream)?;
+    }
+
+    Ok(StreamOwned::new(connection, tcp_stream))
+}
+
+fn get_server_name(tls: &UpstreamTls) -> std::io::Result<ServerName> {
+    let name = tls.serverName.as_deref().unwrap_or(&tls.ip);
+    ServerName::try_from(name).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid TLS server name '{}' - {}", name, e),
+        )
+    })
+}
+
+fn get_client_config(ca_certificate_path: &Option<String>) -> std::io::Result<Arc<ClientConfig>> {
+    let mut configs = CLIENT_CONFIGS.lock().unwrap();
+    if let Some(config) = configs.get(ca_certificate_path) {
+        return Ok(config.clone());
+    }
+
+    let root_store = match ca_certificate_path {
+        Some(path) => load_root_store(path)?,
+        None => {
+            let mut root_store = RootCertStore::empty();
+            root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+                OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    ta.subject,
+                    ta.spki,
+                    ta.name_constraints,
+                )
+            }));
+            root_store
+        }
+    };
+    let config = Arc::new(
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(root_store)
+            .with_no_client_auth(),
+    );
+    configs.insert(ca_certificate_path.clone(), config.clone());
+
+    Ok(config)
+}
+
+// load the PEM encoded root certificates
+fn load_root_store(path: &str) -> std::io::Result<RootCertStore> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certificates = rustls_pemfile::certs(&mut reader)?;
+    let mut root_store = RootCertStore::empty();
+    let (added, _ignored) = root_store.add_parsable_certificates(&certificates);
+    if added == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("No valid root certificate found in '{}'.", path),
+        ));
+    }
+
+    Ok(root_store)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::config::UpstreamTls;
+    use std::env;
+    use std::fs;
+    use std::io::ErrorKind;
+
+    fn create_upstream_tls(server_name: Option<&str>, ca_path: Option<String>) -> UpstreamTls {
+        UpstreamTls {
+            ip: "127.0.0.1".to_string(),
+            port: 443,
+            serverName: server_name.map(|name| name.to_string()),
+            caCertificatePath: ca_path,
+        }
+    }
+
+    #[test]
+    fn tls_settings_test() {
+        // the server name defaults to the ip
+        assert!(super::get_server_name(&create_upstream_tls(None, None)).is_ok());
+        assert!(super::get_server_name(&create_upstream_tls(Some("wireserver"), None)).is_ok());
+        assert!(super::get_server_name(&create_upstream_tls(Some("invalid name"), None)).is_err());
+
+        // the public roots
+        let config = super::get_client_config(&None).unwrap();
+        let cached = super::get_client_config(&None).unwrap();
+        assert!(
+            std::sync::Arc::ptr_eq(&config, &cached),
+            "client config must be cached"
+        );
+
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push("tls_settings_test");
+        _ = fs::remove_dir_all(&temp_test_path);
+        fs::create_dir_all(&temp_test_path).unwrap();
+        let missing = temp_test_path.join("missing.pem");
+        let e = super::get_client_config(&Some(missing.to_string_lossy().to_string())).unwrap_err();
+        assert_eq!(ErrorKind::NotFound, e.kind());
+
+        let invalid = temp_test_path.join("invalid.pem");
+        fs::write(&invalid, "not a certificate").unwrap();
+        let e = super::get_client_config(&Some(invalid.to_string_lossy().to_string())).unwrap_err();
+        assert_eq!(ErrorKind::InvalidData, e.kind());
+
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+}
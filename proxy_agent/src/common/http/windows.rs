@@ -41,6 +41,16 @@ pub fn connect_with_redirect_record(
     port: u16,
     client_stream: &TcpStream,
 ) -> std::io::Result<TcpStream> {
+    // the redirect record is only applied to the ipv4 socket so far
+    let address: SocketAddrV4 = match format!("{ip}:{port}").parse() {
+        Ok(address) => address,
+        Err(e) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("Cannot connect to {ip}:{port} with redirect record - {e}"),
+            ));
+        }
+    };
     unsafe {
         let socket = WinSock::WSASocketW(
             WinSock::AF_INET as i32,
@@ -86,7 +96,6 @@ pub fn connect_with_redirect_record(
             "WinSock::WSAIoctl - SIO_SET_WFP_CONNECTION_REDIRECT_RECORDS",
         )?;
 
-        let address: SocketAddrV4 = format!("{ip}:{port}").parse().unwrap();
         let address = as_sockaddr_storage(address);
         let len = mem::size_of::<WinSock::SOCKADDR_IN>() as i32;
         WinSock::connect(socket, as_ptr(&address), len);
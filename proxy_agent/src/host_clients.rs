@@ -1,6 +1,8 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
 pub mod goal_state;
+pub mod identity_token;
 pub mod imds_client;
 pub mod instance_info;
+pub mod retry_policy;
 pub mod wire_server_client;
\ No newline at end of file
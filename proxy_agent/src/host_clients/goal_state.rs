@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: MIT
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 #[allow(non_snake_case)]
 pub struct GoalState {
     Version: String,
@@ -11,7 +11,7 @@ pub struct GoalState {
     Container: ContainerField,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 #[allow(non_snake_case)]
 struct MachineField {
     ExpectedState: String,
@@ -21,14 +21,14 @@ struct MachineField {
     ExpectHealthReport: String,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 #[allow(non_snake_case)]
 struct LbProbePortsField {
     #[serde(rename = "Port")]
     ports: Vec<u16>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 #[allow(non_snake_case)]
 struct ContainerField {
     ContainerId: String,
@@ -36,14 +36,14 @@ struct ContainerField {
     RoleInstanceList: RoleInstanceListField,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 #[allow(non_snake_case)]
 struct RoleInstanceListField {
     #[serde(rename = "RoleInstance")]
     RoleInstance: Vec<RoleInstanceField>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 #[allow(non_snake_case)]
 struct RoleInstanceField {
     InstanceId: String,
@@ -51,7 +51,7 @@ struct RoleInstanceField {
     Configuration: RoleConfigField,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 #[allow(non_snake_case)]
 struct RoleConfigField {
     HostingEnvironmentConfig: String,
@@ -63,6 +63,10 @@ struct RoleConfigField {
 }
 
 impl GoalState {
+    pub fn get_incarnation(&self) -> u32 {
+        self.Incarnation
+    }
+
     pub fn get_container_id(&self) -> String {
         self.Container.ContainerId.to_string()
     }
@@ -74,7 +78,7 @@ impl GoalState {
     }
 }
 
-#[derive(Deserialize, Serialize, PartialEq)]
+#[derive(Deserialize, Serialize, PartialEq, Clone)]
 #[allow(non_snake_case)]
 pub struct SharedConfig {
     Deployment: DeploymentField,
@@ -82,7 +86,7 @@ pub struct SharedConfig {
     Instances: InstancesField,
 }
 
-#[derive(Deserialize, Serialize, PartialEq)]
+#[derive(Deserialize, Serialize, PartialEq, Clone)]
 #[allow(non_snake_case)]
 struct DeploymentField {
     name: String,
@@ -90,20 +94,20 @@ struct DeploymentField {
     incarnation: String,
 }
 
-#[derive(Deserialize, Serialize, PartialEq)]
+#[derive(Deserialize, Serialize, PartialEq, Clone)]
 #[allow(non_snake_case)]
 struct RoleField {
     guid: String,
     name: String,
 }
 
-#[derive(Deserialize, Serialize, PartialEq)]
+#[derive(Deserialize, Serialize, PartialEq, Clone)]
 struct InstancesField {
     #[serde(rename = "Instance")]
     instances: Vec<SharedConfigInstance>,
 }
 
-#[derive(Deserialize, Serialize, PartialEq)]
+#[derive(Deserialize, Serialize, PartialEq, Clone)]
 #[allow(non_snake_case)]
 struct SharedConfigInstance {
     id: String,
@@ -0,0 +1,44 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use serde_derive::{Deserialize, Serialize};
+
+// the managed identity access token issued by IMDS
+#[derive(Deserialize, Serialize)]
+pub struct IdentityToken {
+    access_token: String,
+    #[serde(default)]
+    client_id: String,
+    #[serde(default)]
+    expires_in: String,
+    #[serde(default)]
+    expires_on: String,
+    #[serde(default)]
+    not_before: String,
+    #[serde(default)]
+    resource: String,
+    #[serde(default)]
+    token_type: String,
+}
+
+impl IdentityToken {
+    pub fn get_access_token(&self) -> String {
+        self.access_token.to_string()
+    }
+
+    pub fn get_client_id(&self) -> String {
+        self.client_id.to_string()
+    }
+
+    pub fn get_resource(&self) -> String {
+        self.resource.to_string()
+    }
+
+    pub fn get_token_type(&self) -> String {
+        self.token_type.to_string()
+    }
+
+    // the token expiry time in unix seconds, 0 if it is not reported
+    pub fn get_expires_on(&self) -> u64 {
+        self.expires_on.parse::<u64>().unwrap_or(0)
+    }
+}
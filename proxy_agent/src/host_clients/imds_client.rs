@@ -1,14 +1,26 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+use super::identity_token::IdentityToken;
 use super::instance_info::InstanceInfo;
-use crate::common::http::{self, http_request::HttpRequest, request::Request, response::Response};
+use super::retry_policy::RetryPolicy;
+use crate::common::config;
+use crate::common::http::{
+    http_request::{HttpRequest, RequestBuilder},
+    response::Response,
+};
 use crate::key_keeper;
 use std::io::{Error, ErrorKind};
-use url::Url;
+use std::time::Duration;
+use url::{Position, Url};
+
+const IMDS: &str = "IMDS";
+const IMDS_API_VERSION: &str = "2018-02-01";
 
 pub struct ImdsClient {
     ip: String,
     port: u16,
+    retry_policy: RetryPolicy,
+    timeout: Duration,
 }
 
 impl ImdsClient {
@@ -16,35 +28,62 @@ impl ImdsClient {
         ImdsClient {
             ip: ip.to_string(),
             port: port,
+            retry_policy: RetryPolicy::from_config(),
+            timeout: config::get_wire_server_request_timeout(),
         }
     }
 
-    pub fn get_imds_instance_info(&self) -> std::io::Result<InstanceInfo> {
-        const IMDS_URI: &str = "/metadata/instance?api-version=2018-02-01";
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // the request has the Metadata header and it is signed by the current key if present
+    fn create_http_request(&self, url: &Url) -> std::io::Result<HttpRequest> {
+        RequestBuilder::new(url.clone())
+            .sign_with(&key_keeper::get_current_key_details())
+            .build()
+    }
 
-        let req = Request::new(IMDS_URI.to_string(), "GET".to_string());
-        let url = Url::parse(&format!("http://{}:{}", self.ip.to_string(), self.port)).unwrap();
-        let url = url.join(&IMDS_URI).unwrap();
-        let mut http_request = HttpRequest::new_proxy_agent_request(
-            url,
-            req,
-            key_keeper::get_current_key_guid(),
-            key_keeper::get_current_key(),
-        )?;
+    fn create_url(&self, path: &str, query: &[(&str, &str)]) -> Url {
+        let mut url = Url::parse(&format!("http://{}:{}", self.ip, self.port)).unwrap();
+        url.set_path(path);
+        url.query_pairs_mut()
+            .append_pair("api-version", IMDS_API_VERSION)
+            .extend_pairs(query);
+        url
+    }
 
-        let response = http::get_response_in_string(&mut http_request)?;
+    fn get_response_body(&self, url: Url, name: &str) -> std::io::Result<String> {
+        let uri = url[Position::BeforePath..].to_string();
+        let response = self
+            .retry_policy
+            .get_response(IMDS, "GET", &uri, self.timeout, || {
+                self.create_http_request(&url)
+            })?;
         if response.status != Response::OK {
             return Err(Error::new(
                 ErrorKind::Other,
                 format!(
-                    "Failed to retrieve instance info {} - {}",
+                    "Failed to retrieve {} {} - {}",
+                    name,
                     response.status,
                     response.get_body_as_string()?
                 ),
             ));
         }
 
-        let instance_info_str = response.get_body_as_string()?;
+        response.get_body_as_string()
+    }
+
+    pub fn get_instance_info(&self) -> std::io::Result<InstanceInfo> {
+        let url = self.create_url("/metadata/instance", &[]);
+        let instance_info_str = self.get_response_body(url, "instance info")?;
         match serde_json::from_str::<InstanceInfo>(&instance_info_str) {
             Ok(instnce) => Ok(instnce),
             Err(e) => {
@@ -58,4 +97,91 @@ impl ImdsClient {
             }
         }
     }
+
+    // get the managed identity access token for the resource
+    pub fn get_identity_token(&self, resource: &str) -> std::io::Result<IdentityToken> {
+        let url = self.create_url("/metadata/identity/oauth2/token", &[("resource", resource)]);
+        let token_str = self.get_response_body(url, "identity token")?;
+        // never log the received token
+        serde_json::from_str::<IdentityToken>(&token_str).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("Recevied identity token is invalid, Error: {}", e),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ImdsClient;
+    use crate::common::logger;
+    use crate::host_clients::retry_policy::RetryPolicy;
+    use crate::test_mock::server_mock;
+    use proxy_agent_shared::logger_manager;
+    use std::env;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn imds_client_test() {
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push("imds_client_test");
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(),
+            temp_test_path.to_path_buf(),
+            "logger_key".to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let ip = "127.0.0.1";
+        let port = 7073u16;
+        thread::spawn(move || {
+            server_mock::start(ip.to_string(), port);
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        let retry_policy = RetryPolicy {
+            max_retries: 1,
+            initial_delay: Duration::from_millis(10),
+            max_duration: Duration::from_secs(10),
+        };
+        let client = ImdsClient::new(ip, port).with_retry_policy(retry_policy);
+
+        let instance_info = client.get_instance_info().unwrap();
+        assert_eq!(
+            "02aab8a4-74ef-476e-8182-f6d2ba4166a6",
+            instance_info.get_vm_id()
+        );
+
+        // 503 then 200
+        server_mock::set_server_error_count(port, 1);
+        let token = client
+            .get_identity_token("https://management.azure.com/")
+            .unwrap();
+        assert_eq!("eyJ0eXAi.test.token", token.get_access_token());
+        assert_eq!("Bearer", token.get_token_type());
+        assert_eq!("https://management.azure.com/", token.get_resource());
+        assert_eq!(1506484173, token.get_expires_on());
+
+        server_mock::set_server_error_count(port, 2);
+        assert!(client.get_instance_info().is_err());
+
+        server_mock::set_server_error_count(port, 0);
+        server_mock::stop(ip.to_string(), port);
+    }
+
+    #[test]
+    fn create_url_test() {
+        let client = ImdsClient::new("169.254.169.254", 80);
+        let url = client.create_url(
+            "/metadata/identity/oauth2/token",
+            &[("resource", "https://management.azure.com/")],
+        );
+        assert_eq!(
+            "http://169.254.169.254/metadata/identity/oauth2/token?api-version=2018-02-01&resource=https%3A%2F%2Fmanagement.azure.com%2F",
+            url.as_str()
+        );
+    }
 }
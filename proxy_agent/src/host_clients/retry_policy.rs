@@ -0,0 +1,347 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use crate::common::http::{self, headers, http_request::HttpRequest, response::Response};
+use crate::common::{config, logger};
+use proxy_agent_shared::misc_helpers;
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+The retry policy of the WireServer and IMDS requests.
+The delay doubles from the initial delay with each retry, with a random jitter of up to half of it,
+no more retry is made once the delay would exceed the max total duration of the request.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+    pub max_duration: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config() -> Self {
+        RetryPolicy {
+            max_retries: config::get_wire_server_retry_count(),
+            initial_delay: config::get_wire_server_retry_initial_delay(),
+            max_duration: config::get_wire_server_retry_max_duration(),
+        }
+    }
+
+    // the telemetry data is retried at most once to avoid the duplicate telemetry
+    pub fn for_telemetry(&self) -> Self {
+        RetryPolicy {
+            max_retries: self.max_retries.min(1),
+            ..*self
+        }
+    }
+
+    // the delay before the next retry, None if no more retry is allowed
+    pub fn next_delay(&self, retried: u32, start: Instant) -> Option<Duration> {
+        self.next_delay_with_retry_after(retried, start, None)
+    }
+
+    // the host asked to wait for retry_after before the next retry, it is honored if longer than the backoff
+    pub fn next_delay_with_retry_after(
+        &self,
+        retried: u32,
+        start: Instant,
+        retry_after: Option<Duration>,
+    ) -> Option<Duration> {
+        if retried >= self.max_retries {
+            return None;
+        }
+
+        let backoff = self
+            .initial_delay
+            .saturating_mul(2u32.saturating_pow(retried.min(16)));
+        let jitter = (uuid::Uuid::new_v4().as_u128() % 1000) as f64 / 2000.0;
+        let mut delay = backoff.mul_f64(1.0 - jitter);
+        if let Some(retry_after) = retry_after {
+            delay = delay.max(retry_after);
+        }
+        if start.elapsed() + delay > self.max_duration {
+            return None;
+        }
+
+        Some(delay)
+    }
+
+    /*
+    Send the request to the host and retry it on the connection errors, 5xx and throttled responses,
+    the request is created for each attempt to refresh its date and signature.
+    The throttled request is retried after the Retry-After delay of the host if it is longer than the backoff,
+    and fails with the ThrottledError once no more retry is allowed.
+    The other 4xx and responses are returned to the caller without retry.
+     */
+    pub fn get_response<F>(
+        &self,
+        host: &str,
+        method: &str,
+        uri: &str,
+        timeout: Duration,
+        create_request: F,
+    ) -> std::io::Result<Response>
+    where
+        F: Fn() -> std::io::Result<HttpRequest>,
+    {
+        let start = Instant::now();
+        let mut retried = 0;
+        loop {
+            let mut http_request = create_request()?;
+            let mut error_kind = ErrorKind::Other;
+            let mut throttled = None;
+            let error = match http::get_response_in_string_with_timeout(&mut http_request, timeout)
+            {
+                Ok(response) => {
+                    if is_throttled_response(&response) {
+                        throttled = Some(get_retry_after(&response));
+                    } else if !response.is_server_error() {
+                        return Ok(response);
+                    }
+                    format!("Host responded {}", response.status)
+                }
+                Err(e) => {
+                    let e = map_timeout_error(e, host, method, uri, timeout);
+                    error_kind = e.kind();
+                    format!("Failed to send request with error: {}", e)
+                }
+            };
+
+            match self.next_delay_with_retry_after(retried, start, throttled.flatten()) {
+                Some(delay) => {
+                    logger::write_warning(format!(
+                        "{} request {} {} failed: {}; retry {} in {:?}.",
+                        host,
+                        method,
+                        uri,
+                        error,
+                        retried + 1,
+                        delay
+                    ));
+                    thread::sleep(delay);
+                    retried += 1;
+                }
+                None => {
+                    if let Some(retry_after) = throttled {
+                        return Err(new_throttled_error(
+                            retry_after,
+                            format!(
+                                "{} request {} {} throttled after {} retries: {}",
+                                host, method, uri, retried, error
+                            ),
+                        ));
+                    }
+                    return Err(Error::new(
+                        error_kind,
+                        format!(
+                            "{} request {} {} failed after {} retries: {}",
+                            host, method, uri, retried, error
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/*
+The host throttled the request, with the delay it asked to wait before the next request.
+It is carried as the inner error of the std::io::Error, use get_throttled_error to read it.
+ */
+#[derive(Debug)]
+pub struct ThrottledError {
+    pub retry_after: Option<Duration>,
+    message: String,
+}
+
+impl std::fmt::Display for ThrottledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.retry_after {
+            Some(retry_after) => write!(f, "{}; retry after {:?}", self.message, retry_after),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ThrottledError {}
+
+pub fn new_throttled_error(retry_after: Option<Duration>, message: String) -> Error {
+    Error::new(
+        ErrorKind::Other,
+        ThrottledError {
+            retry_after,
+            message,
+        },
+    )
+}
+
+pub fn get_throttled_error(e: &Error) -> Option<&ThrottledError> {
+    e.get_ref()
+        .and_then(|inner| inner.downcast_ref::<ThrottledError>())
+}
+
+// 429, or 503 with the Retry-After header
+pub fn is_throttled_response(response: &Response) -> bool {
+    let status = response.status.trim_start();
+    status.starts_with("429")
+        || (status.starts_with("503")
+            && response
+                .headers
+                .get_header(headers::RETRY_AFTER_HEADER_NAME)
+                .is_some())
+}
+
+// the Retry-After header in delta-seconds or HTTP-date, the past date means no delay
+pub fn get_retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers
+        .get_header(headers::RETRY_AFTER_HEADER_NAME)
+        .and_then(|value| parse_retry_after(&value, misc_helpers::get_date_time_unix_nano()))
+}
+
+fn parse_retry_after(value: &str, now_unix_nano: i128) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = misc_helpers::parse_date_time_rfc1123_unix_nano(value).ok()?;
+    let delay = (date - now_unix_nano).clamp(0, u64::MAX as i128);
+    Some(Duration::from_nanos(delay as u64))
+}
+
+// report the expired request with the timeout value
+pub fn map_timeout_error(
+    e: Error,
+    host: &str,
+    method: &str,
+    uri: &str,
+    timeout: Duration,
+) -> Error {
+    if !http::is_timeout_error(&e) {
+        return e;
+    }
+
+    let message = format!(
+        "{} request {} {} timed out after {:?}",
+        host, method, uri, timeout
+    );
+    logger::write_warning(message.to_string());
+    Error::new(ErrorKind::TimedOut, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RetryPolicy, ThrottledError};
+    use crate::common::http::{headers, response::Response};
+    use std::io::{Error, ErrorKind};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn retry_policy_test() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_delay: Duration::from_millis(100),
+            max_duration: Duration::from_secs(10),
+        };
+        let start = Instant::now();
+        for retried in 0..3 {
+            let delay = policy.next_delay(retried, start).unwrap();
+            let backoff = Duration::from_millis(100 * 2u64.pow(retried));
+            assert!(
+                delay <= backoff && delay >= backoff / 2,
+                "delay {:?} must be within the jitter of the backoff {:?}",
+                delay,
+                backoff
+            );
+        }
+        assert_eq!(None, policy.next_delay(3, start), "max retries reached");
+
+        let policy = RetryPolicy {
+            max_duration: Duration::from_millis(10),
+            ..policy
+        };
+        assert_eq!(
+            None,
+            policy.next_delay(0, start),
+            "the delay must not exceed the max duration"
+        );
+        assert_eq!(1, policy.for_telemetry().max_retries);
+
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_delay: Duration::from_millis(100),
+            max_duration: Duration::from_secs(10),
+        };
+        assert_eq!(
+            Some(Duration::from_secs(2)),
+            policy.next_delay_with_retry_after(0, start, Some(Duration::from_secs(2))),
+            "the longer retry-after must be honored"
+        );
+        assert_eq!(
+            None,
+            policy.next_delay_with_retry_after(0, start, Some(Duration::from_secs(60))),
+            "the retry-after must not exceed the max duration"
+        );
+    }
+
+    #[test]
+    fn throttled_response_test() {
+        let mut response = Response::from_status(Response::TOO_MANY_REQUESTS.to_string());
+        assert!(super::is_throttled_response(&response));
+        assert_eq!(None, super::get_retry_after(&response));
+        response.headers.add_header(
+            headers::RETRY_AFTER_HEADER_NAME.to_string(),
+            "5".to_string(),
+        );
+        assert_eq!(
+            Some(Duration::from_secs(5)),
+            super::get_retry_after(&response)
+        );
+
+        let mut response = Response::from_status(Response::SERVICE_UNAVAILABLE.to_string());
+        assert!(
+            !super::is_throttled_response(&response),
+            "503 without retry-after is a server error"
+        );
+        response.headers.add_header(
+            headers::RETRY_AFTER_HEADER_NAME.to_string(),
+            "Wed, 21 Oct 2015 07:28:00 GMT".to_string(),
+        );
+        assert!(super::is_throttled_response(&response));
+        assert_eq!(
+            Some(Duration::ZERO),
+            super::get_retry_after(&response),
+            "the past date means no delay"
+        );
+
+        // the HTTP-date is counted from now
+        let now = 1445412480 * 1_000_000_000; // Wed, 21 Oct 2015 07:28:00 GMT
+        assert_eq!(
+            Some(Duration::from_secs(30)),
+            super::parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(120)),
+            super::parse_retry_after(" 120 ", now)
+        );
+        let mut response = Response::from_status(Response::TOO_MANY_REQUESTS.to_string());
+        response.headers.add_header(
+            headers::RETRY_AFTER_HEADER_NAME.to_string(),
+            "soon".to_string(),
+        );
+        assert_eq!(None, super::get_retry_after(&response));
+
+        let e = Error::new(
+            ErrorKind::Other,
+            ThrottledError {
+                retry_after: Some(Duration::from_secs(5)),
+                message: "throttled".to_string(),
+            },
+        );
+        assert_eq!(
+            Some(Duration::from_secs(5)),
+            super::get_throttled_error(&e).unwrap().retry_after
+        );
+        assert!(super::get_throttled_error(&Error::new(ErrorKind::Other, "other")).is_none());
+    }
+}
@@ -1,17 +1,61 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
 use crate::common::http::{
-    self, headers, http_request::HttpRequest, request::Request, response::Response,
+    self, headers,
+    http_request::{HttpRequest, RequestBuilder},
+    response::Response,
 };
+use crate::common::{config, logger};
 use crate::host_clients::goal_state::{GoalState, SharedConfig};
+use crate::host_clients::retry_policy::{self, RetryPolicy};
 use crate::key_keeper;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::io::prelude::*;
 use std::io::{Error, ErrorKind};
-use std::{io::prelude::*, net::TcpStream};
-use url::{Position, Url};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+const WIRE_SERVER: &str = "WireServer";
+
+// the WireServer requests whose failures are reported separately
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireServerErrorType {
+    GoalState,
+    SharedConfig,
+}
+
+impl std::fmt::Display for WireServerErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireServerErrorType::GoalState => write!(f, "GoalState"),
+            WireServerErrorType::SharedConfig => write!(f, "SharedConfig"),
+        }
+    }
+}
+
+/*
+The last goal state fetched from the WireServer endpoint and the shared config of its incarnation,
+the shared config is dropped once a goal state of another incarnation is fetched.
+ */
+#[derive(Default)]
+struct GoalStateCache {
+    goal_state: Option<(GoalState, Instant)>,
+    shared_config: Option<(String, SharedConfig)>,
+}
+
+// the goal state caches by the WireServer endpoint
+static GOAL_STATE_CACHES: Lazy<Mutex<HashMap<String, GoalStateCache>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
 pub struct WireServerClient {
     ip: String,
     port: u16,
+    retry_policy: RetryPolicy,
+    timeout: Duration,
+    goal_state_ttl: Duration,
 }
 
 impl WireServerClient {
@@ -19,7 +63,56 @@ impl WireServerClient {
         WireServerClient {
             ip: ip.to_string(),
             port: port,
+            retry_policy: RetryPolicy::from_config(),
+            timeout: config::get_wire_server_request_timeout(),
+            goal_state_ttl: config::get_goal_state_cache_ttl(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_goal_state_ttl(mut self, goal_state_ttl: Duration) -> Self {
+        self.goal_state_ttl = goal_state_ttl;
+        self
+    }
+
+    // drop the cached goal state and shared config, the next calls fetch them from the WireServer
+    pub fn invalidate_goalstate_cache(&self) {
+        GOAL_STATE_CACHES.lock().unwrap().remove(&self.endpoint());
+    }
+
+    fn get_cached_goalstate(&self) -> Option<GoalState> {
+        let caches = GOAL_STATE_CACHES.lock().unwrap();
+        match caches
+            .get(&self.endpoint())
+            .and_then(|c| c.goal_state.as_ref())
+        {
+            Some((goal_state, fetched_at)) if fetched_at.elapsed() < self.goal_state_ttl => {
+                Some(goal_state.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn cache_goalstate(&self, goal_state: &GoalState) {
+        let mut caches = GOAL_STATE_CACHES.lock().unwrap();
+        let cache = caches.entry(self.endpoint()).or_default();
+        let incarnation_changed = match &cache.goal_state {
+            Some((cached, _)) => cached.get_incarnation() != goal_state.get_incarnation(),
+            None => true,
+        };
+        if incarnation_changed {
+            cache.shared_config = None;
         }
+        cache.goal_state = Some((goal_state.clone(), Instant::now()));
     }
 
     fn endpoint(&self) -> String {
@@ -27,6 +120,11 @@ impl WireServerClient {
     }
 
     fn create_http_request(&self, method: &str, uri: String) -> std::io::Result<HttpRequest> {
+        self.create_request_builder(method, uri)?.build()
+    }
+
+    // the request is signed by the current key when it is built
+    fn create_request_builder(&self, method: &str, uri: String) -> std::io::Result<RequestBuilder> {
         let mut url;
         match Url::parse(&uri) {
             Ok(u) => url = u,
@@ -48,18 +146,17 @@ impl WireServerClient {
             }
         }
 
-        let path_para = &url[Position::BeforePath..];
-        let mut req = Request::new(path_para.to_string(), method.to_string());
-        req.headers
-            .add_header("x-ms-version".to_string(), "2012-11-30".to_string());
-        let http_request = HttpRequest::new_proxy_agent_request(
-            url,
-            req,
-            key_keeper::get_current_key_guid(),
-            key_keeper::get_current_key(),
-        )?;
+        Ok(RequestBuilder::new(url)
+            .method(method)
+            .header("x-ms-version", "2012-11-30")
+            .sign_with(&key_keeper::get_current_key_details()))
+    }
 
-        Ok(http_request)
+    fn get_response_with_retry(&self, method: &str, uri: &str) -> std::io::Result<Response> {
+        self.retry_policy
+            .get_response(WIRE_SERVER, method, uri, self.timeout, || {
+                self.create_http_request(method, uri.to_string())
+            })
     }
 
     pub fn send_telemetry_data(&self, xml_data: String) -> std::io::Result<()> {
@@ -67,54 +164,124 @@ impl WireServerClient {
             return Ok(());
         }
 
-        let data = xml_data.as_bytes();
-        let mut http_request =
-            self.create_http_request("POST", "/machine/?comp=telemetrydata".to_string())?;
-        http_request.request.headers.add_header(
-            "Content-Type".to_string(),
-            "text/xml; charset=utf-8".to_string(),
-        );
-        http_request.request.headers.add_header(
-            headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
-            data.len().to_string(),
-        );
-        http_request.request.headers.add_header(
-            headers::EXPECT_HEADER_NAME.to_string(),
-            headers::EXPECT_HEADER_VALUE.to_string(),
-        );
+        let retry_policy = self.retry_policy.for_telemetry();
+        let start = Instant::now();
+        let mut retried = 0;
+        loop {
+            let (e, data_sent) = match self.try_send_telemetry_data(xml_data.as_bytes()) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            // never resend the telemetry data the host may have received
+            if data_sent {
+                return Err(e);
+            }
+            let retry_after =
+                retry_policy::get_throttled_error(&e).and_then(|throttled| throttled.retry_after);
+            match retry_policy.next_delay_with_retry_after(retried, start, retry_after) {
+                Some(delay) => {
+                    logger::write_warning(format!(
+                        "Failed to send telemetry data with error: {}; retry in {:?}.",
+                        e, delay
+                    ));
+                    thread::sleep(delay);
+                    retried += 1;
+                }
+                None => return Err(e),
+            }
+        }
+    }
+
+    // the error is returned with whether the telemetry data has been sent to the host,
+    // the throttled response is returned as the ThrottledError with the Retry-After delay of the host
+    fn try_send_telemetry_data(&self, data: &[u8]) -> Result<(), (Error, bool)> {
+        const METHOD: &str = "POST";
+        const TELEMETRY_URI: &str = "/machine/?comp=telemetrydata";
+        // the body is sent after the host asks to continue
+        let mut http_request = self
+            .create_request_builder(METHOD, TELEMETRY_URI.to_string())
+            .and_then(|builder| {
+                builder
+                    .header(headers::CONTENT_TYPE_HEADER_NAME, "text/xml; charset=utf-8")
+                    .header(headers::CONTENT_LENGTH_HEADER_NAME, &data.len().to_string())
+                    .header(headers::EXPECT_HEADER_NAME, headers::EXPECT_HEADER_VALUE)
+                    .build()
+            })
+            .map_err(|e| (e, false))?;
 
-        let mut client = TcpStream::connect(self.endpoint())?;
+        let timed_out = |e: Error, data_sent: bool| {
+            let e = retry_policy::map_timeout_error(
+                e,
+                WIRE_SERVER,
+                METHOD,
+                TELEMETRY_URI,
+                self.timeout,
+            );
+            (e, data_sent)
+        };
+        let mut client = http::connect_with_timeout(&self.endpoint(), self.timeout)
+            .map_err(|e| timed_out(e, false))?;
         // send http request without body
         _ = client.write_all(http_request.request.to_raw_string().as_bytes());
         _ = client.flush();
-        let raw_response_data = http::receive_data_in_string(&client)?;
+        let raw_response_data =
+            http::receive_data_in_string(&client).map_err(|e| timed_out(e, false))?;
         let response = Response::from_raw_data(raw_response_data);
+        let throttled = |response: &Response, data_sent: bool| {
+            let e = retry_policy::new_throttled_error(
+                retry_policy::get_retry_after(response),
+                format!("Host throttled the telemetry data with {}", response.status),
+            );
+            (e, data_sent)
+        };
+        if retry_policy::is_throttled_response(&response) {
+            return Err(throttled(&response, false));
+        }
         if response.is_continue_response() {
-            _ = client.write_all(&data);
+            _ = client.write_all(data);
             _ = client.flush();
-            let raw_response_data = http::receive_data_in_string(&client)?;
+            let raw_response_data =
+                http::receive_data_in_string(&client).map_err(|e| timed_out(e, true))?;
             let response = Response::from_raw_data(raw_response_data);
+            if retry_policy::is_throttled_response(&response) {
+                return Err(throttled(&response, true));
+            }
             if response.status != Response::OK {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("Host resposned {}.", &response.status),
+                return Err((
+                    Error::new(
+                        ErrorKind::Other,
+                        format!("Host resposned {}.", &response.status),
+                    ),
+                    true,
                 ));
             }
         } else {
-            return Err(Error::new(
-                ErrorKind::ConnectionRefused,
-                "Host does not resposne continue to receive the reqeust body.",
+            return Err((
+                Error::new(
+                    ErrorKind::ConnectionRefused,
+                    "Host does not resposne continue to receive the reqeust body.",
+                ),
+                false,
             ));
         }
 
         Ok(())
     }
 
+    // the goal state is fetched from the WireServer only if the cached one expired
     pub fn get_goalstate(&self) -> std::io::Result<GoalState> {
-        const GOALSTATE_URI: &str = "/machine?comp=goalstate";
-        let mut http_request = self.create_http_request("GET", GOALSTATE_URI.to_string())?;
+        if let Some(goal_state) = self.get_cached_goalstate() {
+            return Ok(goal_state);
+        }
+
+        let goal_state = self.fetch_goalstate()?;
+        self.cache_goalstate(&goal_state);
+        Ok(goal_state)
+    }
 
-        let response = http::get_response_in_string(&mut http_request)?;
+    fn fetch_goalstate(&self) -> std::io::Result<GoalState> {
+        const GOALSTATE_URI: &str = "/machine?comp=goalstate";
+        let response = self.get_response_with_retry("GET", GOALSTATE_URI)?;
         if response.status != Response::OK {
             return Err(Error::new(
                 ErrorKind::Other,
@@ -139,10 +306,28 @@ impl WireServerClient {
         }
     }
 
+    // the shared config is fetched from the WireServer only if the goal state incarnation changed
     pub fn get_shared_config(&self, url: String) -> std::io::Result<SharedConfig> {
-        let mut http_request = self.create_http_request("GET", url.to_string())?;
+        if let Some(cache) = GOAL_STATE_CACHES.lock().unwrap().get(&self.endpoint()) {
+            if let Some((cached_url, shared_config)) = &cache.shared_config {
+                if *cached_url == url {
+                    return Ok(shared_config.clone());
+                }
+            }
+        }
 
-        let response = http::get_response_in_string(&mut http_request)?;
+        let shared_config = self.fetch_shared_config(&url)?;
+        if let Some(cache) = GOAL_STATE_CACHES.lock().unwrap().get_mut(&self.endpoint()) {
+            // only the shared config of the cached goal state is kept
+            if cache.goal_state.is_some() {
+                cache.shared_config = Some((url, shared_config.clone()));
+            }
+        }
+        Ok(shared_config)
+    }
+
+    fn fetch_shared_config(&self, url: &str) -> std::io::Result<SharedConfig> {
+        let response = self.get_response_with_retry("GET", url)?;
         if response.status != Response::OK {
             return Err(Error::new(
                 ErrorKind::Other,
@@ -168,3 +353,370 @@ impl WireServerClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{RetryPolicy, WireServerClient};
+    use crate::common::http::{self, headers, http_request::RequestBuilder, response::Response};
+    use crate::common::logger;
+    use crate::host_clients::retry_policy;
+    use crate::key_keeper::key::Key;
+    use crate::test_mock::server_mock;
+    use proxy_agent_shared::logger_manager;
+    use std::collections::HashMap;
+    use std::env;
+    use std::io::prelude::*;
+    use std::io::ErrorKind;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use url::Url;
+
+    #[test]
+    fn wire_server_client_retry_test() {
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push("wire_server_client_retry_test");
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(),
+            temp_test_path.to_path_buf(),
+            "logger_key".to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let ip = "127.0.0.1";
+        let port = 7072u16;
+        thread::spawn(move || {
+            server_mock::start(ip.to_string(), port);
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        let retry_policy = RetryPolicy {
+            max_retries: 2,
+            initial_delay: Duration::from_millis(10),
+            max_duration: Duration::from_secs(10),
+        };
+        let client = WireServerClient::new(ip, port)
+            .with_retry_policy(retry_policy)
+            .with_goal_state_ttl(Duration::ZERO);
+
+        // 503 then 200
+        server_mock::set_server_error_count(port, 1);
+        client.get_goalstate().unwrap();
+
+        // the retries are exhausted
+        server_mock::set_server_error_count(port, 3);
+        assert!(client.get_goalstate().is_err());
+
+        // the telemetry data is not received by the host on 503, retry it once
+        server_mock::set_server_error_count(port, 1);
+        client
+            .send_telemetry_data("<Data></Data>".to_string())
+            .unwrap();
+        server_mock::set_server_error_count(port, 2);
+        assert!(client
+            .send_telemetry_data("<Data></Data>".to_string())
+            .is_err());
+
+        server_mock::set_server_error_count(port, 0);
+        server_mock::stop(ip.to_string(), port);
+    }
+
+    #[test]
+    fn goal_state_cache_test() {
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push("goal_state_cache_test");
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(),
+            temp_test_path.to_path_buf(),
+            "logger_key".to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let ip = "127.0.0.1";
+        let port = 7074u16;
+        thread::spawn(move || {
+            server_mock::start(ip.to_string(), port);
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        let retry_policy = RetryPolicy {
+            max_retries: 0,
+            initial_delay: Duration::from_millis(10),
+            max_duration: Duration::from_secs(10),
+        };
+        let client = WireServerClient::new(ip, port)
+            .with_retry_policy(retry_policy)
+            .with_goal_state_ttl(Duration::from_secs(60));
+        client.invalidate_goalstate_cache();
+
+        let goal_state = client.get_goalstate().unwrap();
+        let url = goal_state.get_shared_config_uri();
+        let shared_config = client.get_shared_config(url.to_string()).unwrap();
+
+        // the host is not requested within the ttl and the same incarnation
+        server_mock::set_server_error_count(port, 1);
+        assert_eq!(
+            goal_state.get_incarnation(),
+            client.get_goalstate().unwrap().get_incarnation()
+        );
+        assert!(shared_config == client.get_shared_config(url.to_string()).unwrap());
+
+        // the invalidated goal state is fetched again
+        client.invalidate_goalstate_cache();
+        assert!(
+            client.get_goalstate().is_err(),
+            "the host must be requested"
+        );
+        client.get_goalstate().unwrap();
+        server_mock::set_server_error_count(port, 1);
+        assert!(
+            client.get_shared_config(url.to_string()).is_err(),
+            "the shared config must be dropped with the invalidated goal state"
+        );
+
+        // the expired goal state is fetched again
+        let client = client.with_goal_state_ttl(Duration::ZERO);
+        server_mock::set_server_error_count(port, 1);
+        assert!(
+            client.get_goalstate().is_err(),
+            "the host must be requested"
+        );
+
+        client.invalidate_goalstate_cache();
+        server_mock::set_server_error_count(port, 0);
+        server_mock::stop(ip.to_string(), port);
+    }
+
+    #[test]
+    fn wire_server_client_timeout_test() {
+        // the stalled server accepts the connection but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let retry_policy = RetryPolicy {
+            max_retries: 0,
+            initial_delay: Duration::from_millis(10),
+            max_duration: Duration::from_secs(10),
+        };
+        let client = WireServerClient::new("127.0.0.1", port)
+            .with_retry_policy(retry_policy)
+            .with_timeout(Duration::from_millis(200));
+
+        let start = Instant::now();
+        let e = match client.get_goalstate() {
+            Ok(_) => panic!("the stalled request must time out"),
+            Err(e) => e,
+        };
+        assert_eq!(ErrorKind::TimedOut, e.kind(), "{}", e);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let e = client
+            .send_telemetry_data("<Data></Data>".to_string())
+            .unwrap_err();
+        assert_eq!(ErrorKind::TimedOut, e.kind(), "{}", e);
+        drop(listener);
+    }
+
+    #[test]
+    fn wire_server_client_mock_host_test() {
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push("wire_server_client_mock_host_test");
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(),
+            temp_test_path.to_path_buf(),
+            "logger_key".to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let ip = "127.0.0.1";
+        let port = 7075u16;
+        thread::spawn(move || {
+            server_mock::start(ip.to_string(), port);
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        let retry_policy = RetryPolicy {
+            max_retries: 1,
+            initial_delay: Duration::from_millis(10),
+            max_duration: Duration::from_secs(10),
+        };
+        let client = WireServerClient::new(ip, port)
+            .with_retry_policy(retry_policy)
+            .with_goal_state_ttl(Duration::ZERO);
+
+        // the canned server error is retried, the client error is not
+        server_mock::set_canned_response(
+            port,
+            "/machine?comp=goalstate",
+            "500 Internal Server Error",
+            "",
+        );
+        assert!(client.get_goalstate().is_err());
+        assert_eq!(2, server_mock::take_received_requests(port).len());
+        server_mock::reset(port);
+        server_mock::set_canned_response(port, "/machine?comp=goalstate", "404 Not Found", "gone");
+        let e = client
+            .get_goalstate()
+            .err()
+            .expect("the client error must fail");
+        assert!(e.to_string().contains("gone"), "{}", e);
+        assert_eq!(1, server_mock::take_received_requests(port).len());
+
+        // the canned shared config which cannot be parsed
+        server_mock::reset(port);
+        let goal_state = client.get_goalstate().unwrap();
+        let url = goal_state.get_shared_config_uri();
+        server_mock::set_canned_response(port, "/machine/", "200 OK", "<SharedConfig");
+        assert!(client.get_shared_config(url.to_string()).is_err());
+        let requests = server_mock::take_received_requests(port);
+        assert_eq!(
+            vec!["GET", "GET"],
+            requests
+                .iter()
+                .map(|r| r.method.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("/machine?comp=goalstate", requests[0].url);
+        assert!(url.ends_with(&requests[1].url));
+        assert!(
+            requests.iter().all(|r| r.signature_verified != Some(false)),
+            "the requests signed by the client must be verified by the host"
+        );
+
+        // the delayed response times out
+        server_mock::reset(port);
+        server_mock::set_response_delay(port, Duration::from_millis(500));
+        let e = WireServerClient::new(ip, port)
+            .with_retry_policy(RetryPolicy {
+                max_retries: 0,
+                initial_delay: Duration::from_millis(10),
+                max_duration: Duration::from_secs(10),
+            })
+            .with_timeout(Duration::from_millis(100))
+            .with_goal_state_ttl(Duration::ZERO)
+            .get_goalstate()
+            .err()
+            .expect("the delayed response must time out");
+        assert_eq!(ErrorKind::TimedOut, e.kind(), "{}", e);
+        server_mock::reset(port);
+
+        // the request signed with the mock key is verified, the one signed with another key is not
+        let url = Url::parse(&format!("http://{}:{}/machine?comp=goalstate", ip, port)).unwrap();
+        let mut key = Key::empty();
+        for (signing_key, expected) in [(server_mock::MOCK_KEY, true), ("0A0B", false)] {
+            key.key = signing_key.to_string();
+            let mut request = RequestBuilder::new(url.clone())
+                .sign_with(&key)
+                .build()
+                .unwrap();
+            let response = http::get_response_in_string(&mut request).unwrap();
+            assert_eq!(Response::OK, response.status);
+            let requests = server_mock::take_received_requests(port);
+            assert_eq!(Some(expected), requests[0].signature_verified);
+        }
+
+        // the canned response is forwarded to the client as it is
+        server_mock::set_canned_response(port, "/forward", "201 Created", "forwarded body");
+        let mut server_stream = TcpStream::connect((ip, port)).unwrap();
+        server_stream
+            .write_all(b"GET /forward HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n")
+            .unwrap();
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (client_stream, _) = client_listener.accept().unwrap();
+        let (response, _) =
+            http::forward_response(&server_stream, &client_stream, HashMap::new(), None).unwrap();
+        assert_eq!("201 Created", response.status);
+        let response = http::receive_response_data(&client).unwrap();
+        assert_eq!("201 Created", response.status);
+        assert_eq!("forwarded body", response.get_body_as_string().unwrap());
+
+        server_mock::reset(port);
+        server_mock::stop(ip.to_string(), port);
+    }
+
+    #[test]
+    fn send_telemetry_data_throttled_test() {
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push("send_telemetry_data_throttled_test");
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(),
+            temp_test_path.to_path_buf(),
+            "logger_key".to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let ip = "127.0.0.1";
+        let port = 7076u16;
+        thread::spawn(move || {
+            server_mock::start(ip.to_string(), port);
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        let retry_policy = RetryPolicy {
+            max_retries: 1,
+            initial_delay: Duration::from_millis(10),
+            max_duration: Duration::from_secs(10),
+        };
+        let client = WireServerClient::new(ip, port).with_retry_policy(retry_policy);
+        const TELEMETRY_URI: &str = "/machine/?comp=telemetrydata";
+
+        // the retry waits for the Retry-After seconds instead of the backoff
+        server_mock::set_canned_response_with_headers(
+            port,
+            TELEMETRY_URI,
+            "429 Too Many Requests",
+            &[(headers::RETRY_AFTER_HEADER_NAME, "1")],
+            "",
+        );
+        let start = Instant::now();
+        let e = client
+            .send_telemetry_data("<Data></Data>".to_string())
+            .unwrap_err();
+        assert!(
+            start.elapsed() >= Duration::from_secs(1),
+            "{:?}",
+            start.elapsed()
+        );
+        assert_eq!(2, server_mock::take_received_requests(port).len());
+        let throttled = retry_policy::get_throttled_error(&e).expect("throttled error");
+        assert_eq!(Some(Duration::from_secs(1)), throttled.retry_after);
+        server_mock::reset(port);
+
+        // the HTTP-date beyond the max retry duration is not retried, the caller backs off
+        server_mock::set_canned_response_with_headers(
+            port,
+            TELEMETRY_URI,
+            "503 Service Unavailable",
+            &[(
+                headers::RETRY_AFTER_HEADER_NAME,
+                "Fri, 01 Jan 2100 00:00:00 GMT",
+            )],
+            "",
+        );
+        let e = client
+            .send_telemetry_data("<Data></Data>".to_string())
+            .unwrap_err();
+        assert_eq!(1, server_mock::take_received_requests(port).len());
+        let retry_after = retry_policy::get_throttled_error(&e)
+            .and_then(|throttled| throttled.retry_after)
+            .expect("retry after the date");
+        assert!(retry_after > Duration::from_secs(365 * 24 * 3600));
+        server_mock::reset(port);
+
+        // the throttled response without Retry-After is retried with the backoff
+        server_mock::set_canned_response(port, TELEMETRY_URI, "429 Too Many Requests", "");
+        let e = client
+            .send_telemetry_data("<Data></Data>".to_string())
+            .unwrap_err();
+        assert_eq!(2, server_mock::take_received_requests(port).len());
+        let throttled = retry_policy::get_throttled_error(&e).expect("throttled error");
+        assert_eq!(None, throttled.retry_after);
+
+        server_mock::reset(port);
+        server_mock::stop(ip.to_string(), port);
+    }
+}
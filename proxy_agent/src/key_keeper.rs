@@ -1,11 +1,13 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
 pub mod key;
+pub mod secure_channel_state;
 
 use self::key::Key;
-use crate::common::{constants, helpers, logger};
+use self::secure_channel_state::SecureChannelState;
+use crate::common::{config, helpers, logger};
 use crate::provision;
-use crate::proxy::proxy_authentication;
+use crate::proxy::{proxy_authentication, proxy_listener};
 use crate::{acl, redirector};
 use once_cell::sync::Lazy;
 use proxy_agent_shared::misc_helpers;
@@ -13,7 +15,8 @@ use proxy_agent_shared::proxy_agent_aggregate_status::{ModuleState, ProxyAgentDe
 use proxy_agent_shared::telemetry::event_logger;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 use std::{path::PathBuf, thread, time::Duration};
 use url::Url;
 
@@ -26,29 +29,196 @@ static FREQUENT_PULL_INTERVAL: Duration = Duration::from_secs(1); // 1 second
 const FREQUENT_PULL_TIMEOUT_IN_MILLISECONDS: u128 = 300000; // 5 minutes
 const PROVISION_TIMEUP_IN_MILLISECONDS: u128 = 120000; // 2 minute
 const DELAY_START_EVENT_THREADS_IN_MILLISECONDS: u128 = 60000; // 1 minute
+const MAX_PREVIOUS_KEYS: usize = 2; // keys held after they are replaced during the key rollout
 
-static mut CURRENT_SECURE_CHANNEL_STATE: Lazy<String> = Lazy::new(|| String::from(UNKNOWN_STATE)); // state starts from Unknown
-static mut CURRENT_KEY: Lazy<Key> = Lazy::new(|| Key::empty());
+// the secure channel state and the keys are read by every request, and only updated by the key latch thread
+static CURRENT_SECURE_CHANNEL_STATE: Lazy<RwLock<SecureChannelState>> =
+    Lazy::new(|| RwLock::new(SecureChannelState::Unknown)); // state starts from Unknown
+static KEY_RING: Lazy<RwLock<KeyRing>> = Lazy::new(|| RwLock::new(KeyRing::new()));
 static SHUT_DOWN: Lazy<Arc<AtomicBool>> = Lazy::new(|| Arc::new(AtomicBool::new(false)));
 static mut STATUS_MESSAGE: Lazy<String> =
     Lazy::new(|| String::from("Key latch thread has not started yet."));
 static mut WIRESERVER_RULE_ID: Lazy<String> = Lazy::new(|| String::from(""));
 static mut IMDS_RULE_ID: Lazy<String> = Lazy::new(|| String::from(""));
+static KEY_ABSENCE: Lazy<Mutex<KeyAbsence>> = Lazy::new(|| Mutex::new(KeyAbsence::new()));
 
-pub fn get_secure_channel_state() -> String {
-    unsafe { CURRENT_SECURE_CHANNEL_STATE.to_string() }
+// tracks how long the signing key has been absent,
+// and escalates the events from warning to critical as the duration grows
+struct KeyAbsence {
+    absent_since: Option<Instant>,
+    escalated_level: u8, // 0 - no event emitted; 1 - warning emitted; 2 - critical emitted
 }
 
+impl KeyAbsence {
+    fn new() -> Self {
+        KeyAbsence {
+            absent_since: None,
+            escalated_level: 0,
+        }
+    }
+
+    // returns the level of the event emitted by this check, if any
+    fn check(
+        &mut self,
+        key_present: bool,
+        now: Instant,
+        warning_duration: Duration,
+        critical_duration: Duration,
+    ) -> Option<&'static str> {
+        if key_present {
+            self.absent_since = None;
+            self.escalated_level = 0;
+            return None;
+        }
+
+        let absent_since = *self.absent_since.get_or_insert(now);
+        let absent_duration = now.saturating_duration_since(absent_since);
+        let (level, escalation) = if absent_duration >= critical_duration {
+            (event_logger::CRITICAL_LEVEL, 2)
+        } else if absent_duration >= warning_duration {
+            (event_logger::WARN_LEVEL, 1)
+        } else {
+            return None;
+        };
+        if self.escalated_level >= escalation {
+            return None;
+        }
+        self.escalated_level = escalation;
+
+        event_logger::write_event(
+            level,
+            format!(
+                "Key has been absent for {}s, the requests are forwarded without signature.",
+                absent_duration.as_secs()
+            ),
+            "check_key_absence",
+            "key_keeper",
+            logger::AGENT_LOGGER_KEY,
+        );
+        Some(level)
+    }
+
+    fn get_absent_duration(&self, now: Instant) -> Option<Duration> {
+        self.absent_since
+            .map(|since| now.saturating_duration_since(since))
+    }
+}
+
+// holds the designated current key used to sign the requests,
+// and the previous keys replaced during the key rollout, most recent first
+struct KeyRing {
+    current: Key,
+    previous: Vec<Key>,
+}
+
+impl KeyRing {
+    fn new() -> Self {
+        KeyRing {
+            current: Key::empty(),
+            previous: Vec::new(),
+        }
+    }
+
+    fn set_current(&mut self, key: Key) {
+        if key.guid == self.current.guid {
+            self.current = key;
+            return;
+        }
+
+        let replaced = std::mem::replace(&mut self.current, key);
+        self.previous.retain(|k| k.guid != self.current.guid);
+        if replaced.key != "" {
+            self.previous.insert(0, replaced);
+            self.previous.truncate(MAX_PREVIOUS_KEYS);
+        }
+    }
+
+    fn get(&self, guid: &str) -> Option<Key> {
+        if self.current.guid == guid {
+            return Some(self.current.clone());
+        }
+        self.previous
+            .iter()
+            .find(|k| k.guid == guid)
+            .map(|k| k.clone())
+    }
+}
+
+fn update_current_key(key: Key) {
+    KEY_RING.write().unwrap().set_current(key);
+}
+
+// the guid and the value of the designated current key, read together
+// so they always belong to the same key while the keys are rolling over
+pub fn get_current_key_details() -> Key {
+    get_current_key_from(&KEY_RING)
+}
+
+// the current key or one of the previous keys still held, by its guid
+pub fn get_key(guid: &str) -> Option<Key> {
+    get_key_from(&KEY_RING, guid)
+}
+
+// the keys are read from the given key ring, the global KEY_RING except in the tests
+fn get_current_key_from(key_ring: &RwLock<KeyRing>) -> Key {
+    key_ring.read().unwrap().current.clone()
+}
+
+fn get_key_from(key_ring: &RwLock<KeyRing>, guid: &str) -> Option<Key> {
+    key_ring.read().unwrap().get(guid)
+}
+
+pub fn is_key_present() -> bool {
+    get_current_key_details().key != ""
+}
+
+pub fn get_secure_channel_state() -> SecureChannelState {
+    *CURRENT_SECURE_CHANNEL_STATE.read().unwrap()
+}
+
+// Legacy single key getters, kept for the existing consumers.
+// They always return the designated current key even when previous keys are held.
+#[deprecated(
+    note = "use get_current_key_details() to read the guid and the key value of the same key"
+)]
 pub fn get_current_key_guid() -> String {
-    unsafe { CURRENT_KEY.guid.to_string() }
+    get_current_key_guid_from(&KEY_RING)
 }
 
+#[deprecated(
+    note = "use get_current_key_details() to read the guid and the key value of the same key"
+)]
 pub fn get_current_key() -> String {
-    unsafe { CURRENT_KEY.key.to_string() }
+    get_current_key_value_from(&KEY_RING)
 }
 
-fn get_current_key_incarnation() -> Option<u32> {
-    unsafe { CURRENT_KEY.incarnationId.clone() }
+// the legacy getters read the given key ring, the global KEY_RING except in the tests
+fn get_current_key_guid_from(key_ring: &RwLock<KeyRing>) -> String {
+    get_current_key_from(key_ring).guid
+}
+
+fn get_current_key_value_from(key_ring: &RwLock<KeyRing>) -> String {
+    get_current_key_from(key_ring).key
+}
+
+// how long the key has been absent while the secure channel is not disabled,
+// None if the key is present
+pub fn get_key_absent_duration() -> Option<Duration> {
+    KEY_ABSENCE
+        .lock()
+        .unwrap()
+        .get_absent_duration(Instant::now())
+}
+
+fn check_key_absence() {
+    let key_present = get_current_key_details().key != ""
+        || get_secure_channel_state() == SecureChannelState::Disabled;
+    KEY_ABSENCE.lock().unwrap().check(
+        key_present,
+        Instant::now(),
+        config::get_key_absent_warning_duration(),
+        config::get_key_absent_critical_duration(),
+    );
 }
 
 pub fn poll_status_async(
@@ -77,7 +247,7 @@ fn poll_secure_channel_status(
 
     // launch redirector initialization when the key keeper thread is running
     if config_start_redirector {
-        redirector::start_async(constants::PROXY_AGENT_PORT);
+        redirector::start_async(proxy_listener::get_port());
     }
 
     _ = misc_helpers::try_create_folder(key_dir.to_path_buf());
@@ -119,7 +289,7 @@ fn poll_secure_channel_status(
         if !first_iteration {
             // skip the sleep for the first loop
             let sleep;
-            if get_secure_channel_state() == UNKNOWN_STATE
+            if get_secure_channel_state() == SecureChannelState::Unknown
                 && helpers::get_elapsed_time_in_millisec() < FREQUENT_PULL_TIMEOUT_IN_MILLISECONDS
             {
                 // frequent poll the secure channel status every second for the first 5 minutes
@@ -131,6 +301,7 @@ fn poll_secure_channel_status(
             thread::sleep(sleep);
         }
         first_iteration = false;
+        check_key_absence();
 
         if !provision_timeup
             && helpers::get_elapsed_time_in_millisec() > PROVISION_TIMEUP_IN_MILLISECONDS
@@ -204,7 +375,7 @@ fn poll_secure_channel_status(
         let state = status.get_secure_channel_state();
 
         // check if need fetch the key
-        if state != DISABLE_STATE && guid != get_current_key_guid() {
+        if state != SecureChannelState::Disabled && guid != get_current_key_details().guid {
             // search the key locally first
             let mut key_found = false;
             if guid != "" {
@@ -214,9 +385,7 @@ fn poll_secure_channel_status(
                     match misc_helpers::json_read_from_file(key_file.to_path_buf()) {
                         Ok(key) => {
                             // update in memory
-                            unsafe {
-                                *CURRENT_KEY = key;
-                            }
+                            update_current_key(key);
                             let message = helpers::write_startup_event(
                                 "Found key details from local and ready to use.",
                                 "poll_secure_channel_status",
@@ -286,9 +455,7 @@ fn poll_secure_channel_status(
                     match key::attest_key(base_url.clone(), &key) {
                         Ok(()) => {
                             // update in memory
-                            unsafe {
-                                *CURRENT_KEY = key;
-                            }
+                            update_current_key(key);
                             helpers::write_startup_event(
                                 "Successfully attest the key and ready to use.",
                                 "poll_secure_channel_status",
@@ -317,12 +484,10 @@ fn poll_secure_channel_status(
 
         // update the current secure channel state if different
         if state != get_secure_channel_state() {
-            unsafe {
-                *CURRENT_SECURE_CHANNEL_STATE = state.to_string();
-            }
+            *CURRENT_SECURE_CHANNEL_STATE.write().unwrap() = state;
 
             // customer has not enforce the secure channel state
-            if state == DISABLE_STATE {
+            if state == SecureChannelState::Disabled {
                 let message = helpers::write_startup_event(
                     "Customer has not enforce the secure channel state.",
                     "poll_secure_channel_status",
@@ -373,22 +538,36 @@ pub fn get_status() -> ProxyAgentDetailStatus {
         status = ModuleState::RUNNING.to_string();
     }
 
-    let state_message = unsafe { STATUS_MESSAGE.to_string() };
+    let state_message =
+        helpers::cap_status_message(unsafe { STATUS_MESSAGE.to_string() }, "key_keeper");
     let mut states = HashMap::new();
-    states.insert("secureChannelState".to_string(), get_secure_channel_state());
-    states.insert("keyGuid".to_string(), get_current_key_guid());
+    states.insert(
+        "secureChannelState".to_string(),
+        get_secure_channel_state().to_string(),
+    );
+    let current_key = get_current_key_details();
+    states.insert("keyGuid".to_string(), current_key.guid.to_string());
     states.insert("wireServerRuleId".to_string(), unsafe {
         WIRESERVER_RULE_ID.to_string()
     });
     states.insert("imdsRuleId".to_string(), unsafe {
         IMDS_RULE_ID.to_string()
     });
-    match get_current_key_incarnation() {
+    match current_key.incarnationId {
         Some(incarnation) => {
             states.insert("keyIncarnationId".to_string(), incarnation.to_string());
         }
         None => {}
     }
+    match get_key_absent_duration() {
+        Some(duration) => {
+            states.insert(
+                "keyAbsentInSeconds".to_string(),
+                duration.as_secs().to_string(),
+            );
+        }
+        None => {}
+    }
 
     ProxyAgentDetailStatus {
         status,
@@ -403,11 +582,13 @@ mod tests {
     use crate::common::logger;
     use crate::key_keeper;
     use crate::test_mock::server_mock;
+    use proxy_agent_shared::telemetry::event_logger;
     use proxy_agent_shared::{logger_manager, misc_helpers};
     use std::env;
     use std::fs;
+    use std::sync::RwLock;
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use url::Url;
 
     #[test]
@@ -445,6 +626,156 @@ mod tests {
         _ = fs::remove_dir_all(&temp_test_path);
     }
 
+    #[test]
+    fn key_absence_test() {
+        let logger_key = "key_absence_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let warning_duration = Duration::from_secs(300);
+        let critical_duration = Duration::from_secs(1800);
+        let start = Instant::now();
+        let mut key_absence = super::KeyAbsence::new();
+
+        assert_eq!(
+            None,
+            key_absence.check(true, start, warning_duration, critical_duration),
+            "no event when the key is present"
+        );
+        assert_eq!(None, key_absence.get_absent_duration(start));
+
+        // clear the key
+        assert_eq!(
+            None,
+            key_absence.check(false, start, warning_duration, critical_duration),
+            "no event when the key just went absent"
+        );
+        let now = start + Duration::from_secs(60);
+        assert_eq!(
+            Some(Duration::from_secs(60)),
+            key_absence.get_absent_duration(now)
+        );
+
+        // advance the clock over the warning duration
+        let now = start + Duration::from_secs(301);
+        assert_eq!(
+            Some(event_logger::WARN_LEVEL),
+            key_absence.check(false, now, warning_duration, critical_duration),
+            "warning event must fire after the warning duration"
+        );
+        let now = start + Duration::from_secs(600);
+        assert_eq!(
+            None,
+            key_absence.check(false, now, warning_duration, critical_duration),
+            "warning event must fire only once"
+        );
+
+        // advance the clock over the critical duration
+        let now = start + Duration::from_secs(1801);
+        assert_eq!(
+            Some(event_logger::CRITICAL_LEVEL),
+            key_absence.check(false, now, warning_duration, critical_duration),
+            "critical event must fire after the critical duration"
+        );
+        let now = start + Duration::from_secs(3600);
+        assert_eq!(
+            None,
+            key_absence.check(false, now, warning_duration, critical_duration),
+            "critical event must fire only once"
+        );
+
+        // key latched, reset the absence
+        assert_eq!(
+            None,
+            key_absence.check(true, now, warning_duration, critical_duration)
+        );
+        assert_eq!(None, key_absence.get_absent_duration(now));
+
+        // clean up and ignore the clean up errors
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    fn create_key(guid: &str, key: &str) -> Key {
+        let key_str = format!(
+            r#"{{
+            "authorizationScheme": "Azure-HMAC-SHA256",
+            "guid": "{}",
+            "issued": "2021-05-05T 12:00:00Z",
+            "key": "{}"
+        }}"#,
+            guid, key
+        );
+        serde_json::from_str(&key_str).unwrap()
+    }
+
+    #[test]
+    fn key_ring_test() {
+        let mut key_ring = super::KeyRing::new();
+        assert_eq!("", key_ring.current.key, "key ring starts with empty key");
+
+        key_ring.set_current(create_key("guid1", "key1"));
+        assert_eq!(0, key_ring.previous.len(), "the empty key must not be held");
+
+        key_ring.set_current(create_key("guid2", "key2"));
+        key_ring.set_current(create_key("guid3", "key3"));
+        key_ring.set_current(create_key("guid4", "key4"));
+        assert_eq!("guid4", key_ring.current.guid);
+        assert_eq!(super::MAX_PREVIOUS_KEYS, key_ring.previous.len());
+        assert_eq!("key3", key_ring.get("guid3").unwrap().key);
+        assert_eq!("key2", key_ring.get("guid2").unwrap().key);
+        assert!(
+            key_ring.get("guid1").is_none(),
+            "the oldest key must be dropped"
+        );
+
+        // roll back to a previous key
+        key_ring.set_current(create_key("guid3", "key3"));
+        assert_eq!("guid3", key_ring.current.guid);
+        let previous: Vec<&str> = key_ring.previous.iter().map(|k| k.guid.as_str()).collect();
+        assert_eq!(vec!["guid4", "guid2"], previous);
+    }
+
+    #[test]
+    fn legacy_key_getters_test() {
+        // the legacy getters are tested through their helpers on a local key ring,
+        // the global KEY_RING signs the requests of the other tests in parallel
+        let key_ring = RwLock::new(super::KeyRing::new());
+        key_ring
+            .write()
+            .unwrap()
+            .set_current(create_key("guid1", "key1"));
+        assert_eq!("guid1", super::get_current_key_guid_from(&key_ring));
+        assert_eq!("key1", super::get_current_key_value_from(&key_ring));
+
+        // the legacy getters keep returning the current key while multiple keys are held
+        key_ring
+            .write()
+            .unwrap()
+            .set_current(create_key("guid2", "key2"));
+        for _ in 0..3 {
+            assert_eq!("guid2", super::get_current_key_guid_from(&key_ring));
+            assert_eq!("key2", super::get_current_key_value_from(&key_ring));
+        }
+        assert_eq!("key1", super::get_key_from(&key_ring, "guid1").unwrap().key);
+        assert!(super::get_key_from(&key_ring, "guid3").is_none());
+        let current_key = super::get_current_key_from(&key_ring);
+        assert_eq!(
+            current_key.guid,
+            super::get_current_key_guid_from(&key_ring)
+        );
+        assert_eq!(
+            current_key.key,
+            super::get_current_key_value_from(&key_ring)
+        );
+    }
+
     #[test]
     fn poll_secure_channel_status_tests() {
         let mut temp_test_path = env::temp_dir();
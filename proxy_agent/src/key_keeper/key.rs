@@ -1,31 +1,48 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+use super::secure_channel_state::{RulesMode, SecureChannelState};
 use crate::{
     common::{
+        cidr::Cidr,
         constants,
-        http::{self, headers, http_request::HttpRequest, request::Request, response::Response},
+        http::{
+            self, headers,
+            http_request::{HttpRequest, RequestBuilder},
+            request::Request,
+            response::Response,
+        },
     },
-    proxy::{proxy_connection::Connection, Claims},
+    proxy::{self, proxy_connection::Connection, Claims},
 };
 use proxy_agent_shared::misc_helpers;
 use serde_derive::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     io::{Error, ErrorKind},
+    net::IpAddr,
 };
 use url::Url;
 
-const AUDIT_MODE: &str = "audit";
-const ENFORCE_MODE: &str = "enforce";
 //const ALLOW_DEFAULT_ACCESS: &str = "allow";
 //const DENY_DEFAULT_ACCESS: &str = "deny";
+// the access of the role assignments
+pub const ALLOW_ACCESS: &str = "allow";
+pub const DENY_ACCESS: &str = "deny";
+const EXACT_MATCH: &str = "exact";
+const PREFIX_MATCH: &str = "prefix";
+const GLOB_MATCH: &str = "glob";
+const REGEX_MATCH: &str = "regex";
+// the query parameter value which only requires the key to be present
+const ANY_QUERY_VALUE: &str = "*";
+// the compiled size limit of the privilege path pattern, in bytes
+const MAX_PATH_PATTERN_SIZE: usize = 1024 * 1024;
 
 #[derive(Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct KeyStatus {
     // The authorization scheme;
     // defines what the scheme is along with what algorithms will be used.
-    // Only Azure-HMAC-SHA256 exists in V1.
+    // Azure-HMAC-SHA256 in V1, Azure-HMAC-SHA384 and Azure-HMAC-SHA512 are also supported.
     authorizationScheme: String,
     // How the guest fetches the key. Either http or vtpm.
     keyDeliveryMethod: String,
@@ -147,7 +164,9 @@ pub struct Privilege {
     pub name: String,
     pub path: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub queryParameters: Option<HashMap<String, String>>,
+    pub queryParameters: Option<HashMap<String, String>>, // all must be present in the request, value '*' matches any value
+    #[serde(skip_serializing_if = "Option::is_none", alias = "pathKind")]
+    pub matchType: Option<String>, // exact, prefix, glob or regex; default to prefix
 }
 
 #[derive(Serialize, Deserialize)]
@@ -163,12 +182,20 @@ pub struct Identity {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub userName: Option<String>,
+    // matched against the claims userId: the uid on Linux; the logon session id (LUID) on Windows,
+    // which is not a SID and is only stable for the built-in sessions, e.g. 999 (0x3e7) for SYSTEM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub userId: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub groupName: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub exePath: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub processName: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clientIpCidr: Option<String>, // the client ip range, e.g. 127.0.0.0/8 or ::1/128
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exeSha256: Option<String>, // the hex encoded sha256 of the process binary
 }
 
 #[derive(Serialize, Deserialize)]
@@ -176,6 +203,14 @@ pub struct Identity {
 pub struct RoleAssignment {
     pub role: String,
     pub identities: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notBefore: Option<String>, // RFC3339 date time, the assignment is not active before it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notAfter: Option<String>, // RFC3339 date time, the assignment expires after it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<String>, // allow or deny the identities, default to allow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>, // the lower value is evaluated first, default to 0
 }
 
 impl Privilege {
@@ -184,15 +219,72 @@ impl Privilege {
             name: self.name.to_string(),
             path: self.path.to_string(),
             queryParameters: self.queryParameters.clone(),
+            matchType: self.matchType.clone(),
+        }
+    }
+
+    fn get_match_type(&self) -> String {
+        match &self.matchType {
+            Some(match_type) => match_type.to_lowercase(),
+            None => PREFIX_MATCH.to_string(),
         }
     }
 
-    pub fn is_match(&self, connection_id: u128, request_url: url::Url) -> bool {
+    // validate the match type and the path pattern when the rules are loaded
+    fn validate(&self) -> Result<(), String> {
+        self.compile_path_matcher(false).map(|_| ())
+    }
+
+    // compile the path matcher once, the glob and regex paths are compiled to the anchored regex
+    pub fn compile_path_matcher(&self, case_insensitive: bool) -> Result<PathMatcher, String> {
+        let match_type = self.get_match_type();
+        let pattern = match match_type.as_str() {
+            EXACT_MATCH => {
+                return Ok(PathMatcher::Exact(fold_case(
+                    &normalize_request_path(&self.path),
+                    case_insensitive,
+                )))
+            }
+            PREFIX_MATCH => {
+                return Ok(PathMatcher::Prefix(fold_case(
+                    &normalize_request_path(&self.path),
+                    case_insensitive,
+                )))
+            }
+            GLOB_MATCH => glob_to_regex(&normalize_glob_path(&self.path)),
+            REGEX_MATCH => self.path.to_string(),
+            _ => {
+                return Err(format!(
+                    "privilege '{}' has unknown matchType '{}'",
+                    self.name, match_type
+                ))
+            }
+        };
+        match build_path_regex(&pattern, case_insensitive) {
+            Ok(re) if match_type == GLOB_MATCH => Ok(PathMatcher::Glob(re)),
+            Ok(re) => Ok(PathMatcher::Regex(re)),
+            Err(e) => Err(format!(
+                "privilege '{}' has invalid path pattern '{}': {}",
+                self.name, self.path, e
+            )),
+        }
+    }
+
+    // match the request with the path matcher compiled from this privilege,
+    // the request_path is the path of the request_url normalized by normalize_request_path
+    pub fn is_compiled_match(
+        &self,
+        connection_id: u128,
+        request_url: &url::Url,
+        request_path: &str,
+        path_matcher: &PathMatcher,
+        case_insensitive: bool,
+    ) -> bool {
         Connection::write_information(
             connection_id,
             format!("Start to match privilege '{}'", self.name.to_string()),
         );
-        if request_url.path().to_lowercase().starts_with(&self.path) {
+        if path_matcher.is_match(request_path, case_insensitive) {
             Connection::write_information(
                 connection_id,
                 format!("Matched privilege path '{}'", self.path.to_string()),
@@ -209,38 +301,47 @@ impl Privilege {
                     );
 
                     for (key, value) in query_parameters {
-                        match request_url.query_pairs().find(|(k, _)| k == key) {
-                            Some((_, v)) => {
-                                if v.to_lowercase() == value.to_lowercase() {
-                                    Connection::write_information(
-                                        connection_id,
-                                        format!(
-                                            "Matched query_parameters '{}:{}' from privilege '{}'",
-                                            key,
-                                            v,
-                                            self.name.to_string()
-                                        ),
-                                    );
-                                } else {
-                                    Connection::write_information(
-                                        connection_id,
-                                        format!("Not matched query_parameters value '{}' from privilege '{}'", key, self.name.to_string()),
-                                    );
-                                    return false;
-                                }
-                            }
-                            None => {
-                                Connection::write_information(
-                                    connection_id,
-                                    format!(
-                                        "Not matched query_parameters key '{}' from privilege '{}'",
-                                        key,
-                                        self.name.to_string()
-                                    ),
-                                );
-                                return false;
-                            }
+                        // all the occurrences of the key must match, so a duplicated key cannot bypass the value check
+                        let values: Vec<String> = request_url
+                            .query_pairs()
+                            .filter(|(k, _)| is_string_match(k, key, case_insensitive))
+                            .map(|(_, v)| v.to_string())
+                            .collect();
+                        if values.is_empty() {
+                            Connection::write_information(
+                                connection_id,
+                                format!(
+                                    "Not matched query_parameters key '{}' from privilege '{}'",
+                                    key,
+                                    self.name.to_string()
+                                ),
+                            );
+                            return false;
+                        }
+                        if value != ANY_QUERY_VALUE
+                            && !values
+                                .iter()
+                                .all(|v| is_string_match(v, value, case_insensitive))
+                        {
+                            Connection::write_information(
+                                connection_id,
+                                format!(
+                                    "Not matched query_parameters value '{}' from privilege '{}'",
+                                    key,
+                                    self.name.to_string()
+                                ),
+                            );
+                            return false;
                         }
+                        Connection::write_information(
+                            connection_id,
+                            format!(
+                                "Matched query_parameters '{}:{}' from privilege '{}'",
+                                key,
+                                values.join(","),
+                                self.name.to_string()
+                            ),
+                        );
                     }
                 }
                 None => {}
@@ -251,6 +352,148 @@ impl Privilege {
     }
 }
 
+// the privilege path matcher, compiled once when the authorization rules are loaded;
+// the exact and prefix paths are normalized, and lower-cased when compiled case-insensitively;
+// all the matchers match the normalized request path
+pub enum PathMatcher {
+    Exact(String),
+    Prefix(String),
+    Glob(regex::Regex),
+    Regex(regex::Regex),
+    Invalid, // never matches
+}
+
+impl PathMatcher {
+    fn is_match(&self, request_path: &str, case_insensitive: bool) -> bool {
+        match self {
+            PathMatcher::Exact(path) => fold_case(request_path, case_insensitive) == *path,
+            PathMatcher::Prefix(path) => {
+                fold_case(request_path, case_insensitive).starts_with(path.as_str())
+            }
+            PathMatcher::Glob(re) => re.is_match(trim_trailing_slash(request_path)),
+            PathMatcher::Regex(re) => re.is_match(request_path),
+            PathMatcher::Invalid => false,
+        }
+    }
+}
+
+fn fold_case(s: &str, case_insensitive: bool) -> String {
+    if case_insensitive {
+        s.to_lowercase()
+    } else {
+        s.to_string()
+    }
+}
+
+fn is_string_match(a: &str, b: &str, case_insensitive: bool) -> bool {
+    if case_insensitive {
+        a.to_lowercase() == b.to_lowercase()
+    } else {
+        a == b
+    }
+}
+
+// the pattern is always anchored to the whole request path;
+// the regex crate matches in linear time, and the compiled size is limited
+// so a huge pattern cannot exhaust the memory
+fn build_path_regex(pattern: &str, case_insensitive: bool) -> Result<regex::Regex, regex::Error> {
+    regex::RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .case_insensitive(case_insensitive)
+        .size_limit(MAX_PATH_PATTERN_SIZE)
+        .build()
+}
+
+// normalize the request path once before it is matched, as the host decodes the path the same way:
+// decode the percent-encoded characters, remove the empty and dot path segments, the trailing slash is kept;
+// the encoded slash '%2F' is kept as is, so it never splits the path segment
+pub fn normalize_request_path(path: &str) -> String {
+    let mut segments: Vec<String> = Vec::new();
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        match percent_decode_segment(segment).as_str() {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            decoded => segments.push(decoded.to_string()),
+        }
+    }
+    let mut normalized = String::new();
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if normalized.is_empty() || (path.ends_with('/') && !segments.is_empty()) {
+        normalized.push('/');
+    }
+    normalized
+}
+
+// the glob path is matched without the trailing slash
+fn normalize_glob_path(path: &str) -> String {
+    trim_trailing_slash(&normalize_request_path(path)).to_string()
+}
+
+fn trim_trailing_slash(path: &str) -> &str {
+    if path.len() > 1 {
+        path.trim_end_matches('/')
+    } else {
+        path
+    }
+}
+
+fn percent_decode_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let b = (hex_value(bytes[i + 1]) << 4) | hex_value(bytes[i + 2]);
+            if b != b'/' {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn hex_value(b: u8) -> u8 {
+    match b {
+        b'0'..=b'9' => b - b'0',
+        b'a'..=b'f' => b - b'a' + 10,
+        _ => b - b'A' + 10,
+    }
+}
+
+// convert the glob path to regex pattern,
+// '**' matches any characters, '*' and '?' match any characters and single character within a path segment
+fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    pattern.push_str(".*");
+                } else {
+                    pattern.push_str("[^/]*");
+                }
+            }
+            '?' => pattern.push_str("[^/]"),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern
+}
+
 impl Role {
     pub fn clone(&self) -> Self {
         Role {
@@ -265,125 +508,136 @@ impl Identity {
         Identity {
             name: self.name.to_string(),
             userName: self.userName.clone(),
+            userId: self.userId,
             groupName: self.groupName.clone(),
             exePath: self.exePath.clone(),
             processName: self.processName.clone(),
+            clientIpCidr: self.clientIpCidr.clone(),
+            exeSha256: self.exeSha256.clone(),
         }
     }
 
-    pub fn is_match(&self, connection_id: u128, claims: Claims) -> bool {
+    // validate the client ip range and the binary hash when the rules are loaded
+    fn validate(&self) -> Result<(), String> {
+        if let Some(client_ip_cidr) = &self.clientIpCidr {
+            if let Err(e) = Cidr::parse(client_ip_cidr) {
+                return Err(format!("identity '{}' has {}", self.name, e));
+            }
+        }
+        if let Some(exe_sha256) = &self.exeSha256 {
+            if exe_sha256.len() != 64 || !exe_sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!(
+                    "identity '{}' has exeSha256 '{}' which is not a hex encoded sha256",
+                    self.name, exe_sha256
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_match(&self, connection_id: u128, claims: Claims, case_insensitive: bool) -> bool {
         Connection::write_information(
             connection_id,
             format!("Start to match identity '{}'", self.name.to_string()),
         );
-        match self.userName {
-            Some(ref user_name) => {
-                if user_name.to_lowercase() == claims.userName.to_lowercase() {
-                    Connection::write_information(
-                        connection_id,
-                        format!(
-                            "Matched user name '{}' from identity '{}'",
-                            user_name,
-                            self.name.to_string()
-                        ),
-                    );
-                } else {
-                    Connection::write_information(
-                        connection_id,
-                        format!(
-                            "Not matched user name '{}' from identity '{}'",
-                            user_name,
-                            self.name.to_string()
-                        ),
-                    );
-                    return false;
-                }
+        match self.get_mismatch(&claims, case_insensitive) {
+            Some(mismatch) => {
+                Connection::write_information(connection_id, mismatch);
+                false
+            }
+            None => {
+                Connection::write_information(
+                    connection_id,
+                    format!("Matched identity '{}'", self.name.to_string()),
+                );
+                true
             }
-            None => {}
         }
-        match self.processName {
-            Some(ref process_name) => {
-                if process_name.to_lowercase() == claims.processName.to_lowercase() {
-                    Connection::write_information(
-                        connection_id,
-                        format!(
-                            "Matched process name '{}' from identity '{}'",
-                            process_name,
-                            self.name.to_string()
-                        ),
-                    );
-                } else {
-                    Connection::write_information(
-                        connection_id,
-                        format!(
-                            "Not matched process name '{}' from identity '{}'",
-                            process_name,
-                            self.name.to_string()
-                        ),
-                    );
-                    return false;
-                }
+    }
+
+    // the reason why the claims do not match this identity, None if matched
+    pub fn get_mismatch(&self, claims: &Claims, case_insensitive: bool) -> Option<String> {
+        if let Some(user_name) = &self.userName {
+            if !is_string_match(user_name, &claims.userName, case_insensitive) {
+                return Some(format!(
+                    "Not matched user name '{}' from identity '{}'",
+                    user_name, self.name
+                ));
             }
-            None => {}
         }
-        match self.exePath {
-            Some(ref exe_path) => {
-                if exe_path.to_lowercase() == claims.processFullPath.to_lowercase() {
-                    Connection::write_information(
-                        connection_id,
-                        format!(
-                            "Matched process full path '{}' from identity '{}'",
-                            exe_path,
-                            self.name.to_string()
-                        ),
-                    );
-                } else {
-                    Connection::write_information(
-                        connection_id,
-                        format!(
-                            "Not matched process full path '{}' from identity '{}'",
-                            exe_path,
-                            self.name.to_string()
-                        ),
-                    );
-                    return false;
-                }
+        if let Some(user_id) = self.userId {
+            if user_id != claims.userId {
+                return Some(format!(
+                    "Not matched user id '{}' from identity '{}'",
+                    user_id, self.name
+                ));
             }
-            None => {}
         }
-        match self.groupName {
-            Some(ref group_name) => {
-                let mut matched = false;
-                for claims_user_group_name in &claims.userGroups {
-                    if claims_user_group_name.to_lowercase() == group_name.to_lowercase() {
-                        Connection::write_information(
-                            connection_id,
-                            format!(
-                                "Matched user group name '{}' from identity '{}'",
-                                group_name,
-                                self.name.to_string()
-                            ),
-                        );
-                        matched = true;
-                        break;
-                    }
+        if let Some(process_name) = &self.processName {
+            if !is_string_match(process_name, &claims.processName, case_insensitive) {
+                return Some(format!(
+                    "Not matched process name '{}' from identity '{}'",
+                    process_name, self.name
+                ));
+            }
+        }
+        if let Some(exe_path) = &self.exePath {
+            if !is_string_match(exe_path, &claims.processFullPath, case_insensitive) {
+                return Some(format!(
+                    "Not matched process full path '{}' from identity '{}'",
+                    exe_path, self.name
+                ));
+            }
+        }
+        if let Some(group_name) = &self.groupName {
+            if !claims.userGroups.iter().any(|claims_group_name| {
+                is_string_match(claims_group_name, group_name, case_insensitive)
+            }) {
+                return Some(format!(
+                    "Not matched user group name '{}' from identity '{}'",
+                    group_name, self.name
+                ));
+            }
+        }
+        if let Some(client_ip_cidr) = &self.clientIpCidr {
+            let matched = match (
+                Cidr::parse(client_ip_cidr),
+                claims.clientIp.parse::<IpAddr>(),
+            ) {
+                (Ok(cidr), Ok(client_ip)) => cidr.contains(&client_ip),
+                _ => false,
+            };
+            if !matched {
+                return Some(format!(
+                    "Not matched client ip cidr '{}' from identity '{}'",
+                    client_ip_cidr, self.name
+                ));
+            }
+        }
+        // the binary is hashed last, only if the other fields match
+        if let Some(exe_sha256) = &self.exeSha256 {
+            let sha256 = match &claims.processExeSha256 {
+                Some(sha256) => Ok(sha256.to_string()),
+                None => proxy::get_process_exe_sha256(claims.processId, &claims.processFullPath),
+            };
+            match sha256 {
+                Ok(sha256) if sha256.eq_ignore_ascii_case(exe_sha256) => {}
+                Ok(sha256) => {
+                    return Some(format!(
+                        "Not matched exe sha256 '{}' from identity '{}', the process exe sha256 is '{}'",
+                        exe_sha256, self.name, sha256
+                    ));
                 }
-                if !matched {
-                    Connection::write_information(
-                        connection_id,
-                        format!(
-                            "Not matched user group name '{}' from identity '{}'",
-                            group_name,
-                            self.name.to_string()
-                        ),
-                    );
-                    return false;
+                Err(e) => {
+                    return Some(format!(
+                        "Not matched exe sha256 '{}' from identity '{}', failed to hash the process exe '{}': {}",
+                        exe_sha256, self.name, claims.processFullPath, e
+                    ));
                 }
             }
-            None => {}
         }
 
-        return true;
+        None
     }
 }
 
@@ -392,6 +646,26 @@ impl RoleAssignment {
         RoleAssignment {
             role: self.role.to_string(),
             identities: self.identities.clone(),
+            notBefore: self.notBefore.clone(),
+            notAfter: self.notAfter.clone(),
+            access: self.access.clone(),
+            priority: self.priority,
+        }
+    }
+
+    // the unknown access would be loaded as an allow rule, it fails the rules loading instead
+    fn validate(&self) -> Result<(), String> {
+        match &self.access {
+            Some(access)
+                if !access.eq_ignore_ascii_case(ALLOW_ACCESS)
+                    && !access.eq_ignore_ascii_case(DENY_ACCESS) =>
+            {
+                Err(format!(
+                    "role assignment of role '{}' has unknown access '{}', it must be {} or {}",
+                    self.role, access, ALLOW_ACCESS, DENY_ACCESS
+                ))
+            }
+            _ => Ok(()),
         }
     }
 }
@@ -403,8 +677,11 @@ impl KeyStatus {
 
         // validate authorizationScheme
         let authorization_scheme = self.authorizationScheme.to_string();
-        if authorization_scheme != constants::AUTHORIZATION_SCHEME {
-            validate_message.push_str("authorizationScheme must be 'Azure-HMAC-SHA256'; ");
+        if !constants::AUTHORIZATION_SCHEMES.contains(&authorization_scheme.as_str()) {
+            validate_message.push_str(&format!(
+                "authorizationScheme must be one of {:?}; ",
+                constants::AUTHORIZATION_SCHEMES
+            ));
         }
 
         // validate
@@ -431,17 +708,18 @@ impl KeyStatus {
 
         // validate secureChannelState, it has to be Disabled, Wireserver or wireserverandImds
         match &self.secureChannelState {
-            Some(s) => {
-                let state = s.to_lowercase();
-                if state != super::DISABLE_STATE
-                    && state != super::MUST_SIG_WIRESERVER
-                    && state != super::MUST_SIG_WIRESERVER_IMDS
-                {
-                    validate_message
-                        .push_str(&format!("secureChannelState '{}' is invalid; ", state));
+            Some(s) => match s.parse::<SecureChannelState>() {
+                Ok(SecureChannelState::Disabled)
+                | Ok(SecureChannelState::WireServer)
+                | Ok(SecureChannelState::WireServerAndImds) => {}
+                _ => {
+                    validate_message.push_str(&format!(
+                        "secureChannelState '{}' is invalid; ",
+                        s.to_lowercase()
+                    ));
                     validate_result = false;
                 }
-            }
+            },
             None => {
                 if self.version == "1.0" {
                     validate_message.push_str("secureChannelState is missing in version: 1.0");
@@ -455,6 +733,39 @@ impl KeyStatus {
             validate_result = false;
         }
 
+        // validate the privilege path patterns, the identity client ip ranges and the role assignment access,
+        // an invalid one fails the rules loading
+        if let Some(rules) = &self.authorizationRules {
+            for item in [&rules.imds, &rules.wireserver].into_iter().flatten() {
+                if let Some(privileges) = item.rules.as_ref().and_then(|r| r.privileges.as_ref()) {
+                    for privilege in privileges {
+                        if let Err(e) = privilege.validate() {
+                            validate_message.push_str(&format!("{}; ", e));
+                            validate_result = false;
+                        }
+                    }
+                }
+                if let Some(identities) = item.rules.as_ref().and_then(|r| r.identities.as_ref()) {
+                    for identity in identities {
+                        if let Err(e) = identity.validate() {
+                            validate_message.push_str(&format!("{}; ", e));
+                            validate_result = false;
+                        }
+                    }
+                }
+                if let Some(role_assignments) =
+                    item.rules.as_ref().and_then(|r| r.roleAssignments.as_ref())
+                {
+                    for role_assignment in role_assignments {
+                        if let Err(e) = role_assignment.validate() {
+                            validate_message.push_str(&format!("{}; ", e));
+                            validate_result = false;
+                        }
+                    }
+                }
+            }
+        }
+
         if !validate_result {
             return Err(Error::new(ErrorKind::InvalidData, validate_message));
         }
@@ -462,59 +773,27 @@ impl KeyStatus {
         Ok(validate_result)
     }
 
-    pub fn get_secure_channel_state(&self) -> String {
+    pub fn get_secure_channel_state(&self) -> SecureChannelState {
         if self.version == "2.0" {
-            match &self.secureChannelEnabled {
-                Some(s) => {
-                    if *s {
-                        // need read details from authorizationRules
-                        let wireserver;
-                        let imds;
-                        match &self.authorizationRules {
-                            Some(rules) => {
-                                match &rules.wireserver {
-                                    Some(item) => {
-                                        let mode = item.mode.to_lowercase();
-                                        if mode == ENFORCE_MODE {
-                                            wireserver = "WireServer Enforce";
-                                        } else if mode == AUDIT_MODE {
-                                            wireserver = "WireServer Audit";
-                                        } else {
-                                            wireserver = "WireServer Disabled";
-                                        }
-                                    }
-                                    None => wireserver = "WireServer Disabled",
-                                };
-
-                                match &rules.imds {
-                                    Some(item) => {
-                                        let mode = item.mode.to_lowercase();
-                                        if mode == ENFORCE_MODE {
-                                            imds = " IMDS Enforce";
-                                        } else if mode == AUDIT_MODE {
-                                            imds = " IMDS Audit";
-                                        } else {
-                                            imds = " IMDS Disabled";
-                                        }
-                                    }
-                                    None => imds = " IMDS Disabled",
-                                };
-                            }
-                            None => return super::DISABLE_STATE.to_string(),
-                        }
-
-                        return format!("{} - {}", wireserver, imds);
-                    } else {
-                        return super::DISABLE_STATE.to_string();
+            match (&self.secureChannelEnabled, &self.authorizationRules) {
+                // need read details from authorizationRules
+                (Some(true), Some(rules)) => {
+                    let get_mode = |item: &Option<AuthorizationItem>| match item {
+                        Some(item) => RulesMode::from_mode(&item.mode),
+                        None => RulesMode::Disabled,
+                    };
+                    SecureChannelState::Rules {
+                        wireserver: get_mode(&rules.wireserver),
+                        imds: get_mode(&rules.imds),
                     }
                 }
-                None => return super::DISABLE_STATE.to_string(),
+                _ => SecureChannelState::Disabled,
             }
         } else {
-            // version 1.0
+            // version 1.0, the state is validated when the status is fetched
             match &self.secureChannelState {
-                Some(s) => return s.to_lowercase(),
-                None => return super::DISABLE_STATE.to_string(),
+                Some(s) => s.parse().unwrap_or(SecureChannelState::Disabled),
+                None => SecureChannelState::Disabled,
             }
         }
     }
@@ -572,8 +851,8 @@ impl KeyStatus {
 pub struct Key {
     // The authorization scheme;
     // defines what the scheme is along with what algorithms will be used.
-    // Only Azure-HMAC-SHA256 exists in V1.
-    authorizationScheme: String,
+    // Azure-HMAC-SHA256, Azure-HMAC-SHA384 or Azure-HMAC-SHA512, the key is signed with its HMAC algorithm.
+    pub authorizationScheme: String,
     // An integer representing the incarnation of the key.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub incarnationId: Option<u32>,
@@ -691,22 +970,13 @@ pub fn acquire_key(base_url: Url) -> std::io::Result<Key> {
 pub fn attest_key(base_url: Url, key: &Key) -> std::io::Result<()> {
     // secure-channel/key/{key_guid}/key-attestation
     let url = base_url
-        .join(KEY_URL)
-        .unwrap()
-        .join(&key.guid)
-        .unwrap()
-        .join("key-attestation")
-        .unwrap();
-    let mut req = Request::new(
-        format!("{}/{}/key-attestation", KEY_URL, key.guid.to_string()),
-        "POST".to_string(),
-    );
-    req.headers.add_header(
-        headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
-        0.to_string(),
-    );
-    let mut http_request =
-        HttpRequest::new_proxy_agent_request(url, req, key.guid.to_string(), key.key.to_string())?;
+        .join(&format!("{}/{}/key-attestation", KEY_URL, key.guid))
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let mut http_request = RequestBuilder::new(url)
+        .method("POST")
+        .header(headers::CONTENT_LENGTH_HEADER_NAME, "0")
+        .sign_with(key)
+        .build()?;
     let mut response = http::get_response_in_string(&mut http_request)?;
     if response.status != Response::OK {
         return Err(Error::new(
@@ -724,8 +994,10 @@ mod tests {
     use super::KeyStatus;
     use crate::common::constants;
     use crate::key_keeper::key::Identity;
+    use crate::key_keeper::key::PathMatcher;
     use crate::key_keeper::key::Privilege;
     use crate::proxy::proxy_connection::Connection;
+    use std::collections::HashMap;
 
     #[test]
     fn key_status_v1_test() {
@@ -1054,6 +1326,115 @@ mod tests {
             "test", first_role_assignment.identities[0],
             "roleAssignment identities mismatch"
         );
+        assert_eq!(
+            None, first_role_assignment.access,
+            "roleAssignment access must default to None"
+        );
+    }
+
+    #[test]
+    fn key_status_privilege_validate_test() {
+        let create_status_response = |path: &str, path_kind: &str| {
+            format!(
+                r#"{{
+                "authorizationScheme": "Azure-HMAC-SHA256",
+                "keyDeliveryMethod": "http",
+                "secureChannelEnabled": true,
+                "version": "2.0",
+                "authorizationRules": {{
+                    "wireserver": {{
+                        "defaultAccess": "deny",
+                        "mode": "enforce",
+                        "id": "sigid",
+                        "rules": {{
+                            "privileges": [
+                                {{
+                                    "name": "test",
+                                    "path": "{}",
+                                    "pathKind": "{}"
+                                }}
+                            ]
+                        }}
+                    }}
+                }}
+            }}"#,
+                path, path_kind
+            )
+        };
+
+        let status: KeyStatus =
+            serde_json::from_str(&create_status_response("/machine/[a-z]+", "regex")).unwrap();
+        let wireserver_rules = status.get_wireserver_rules().unwrap();
+        let privilege = &wireserver_rules.rules.unwrap().privileges.unwrap()[0];
+        assert_eq!(
+            Some("regex".to_string()),
+            privilege.matchType,
+            "pathKind must be read as matchType"
+        );
+        assert!(status.validate().unwrap());
+
+        for (path, path_kind) in [
+            ("/machine/[a-z", "regex"),
+            ("/machine/a{1000}{1000}", "regex"),
+            ("/machine", "unknown"),
+        ] {
+            let status: KeyStatus =
+                serde_json::from_str(&create_status_response(path, path_kind)).unwrap();
+            let e = status.validate().unwrap_err();
+            assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+            assert!(
+                e.to_string().contains("privilege 'test'"),
+                "validate error must name the privilege: {}",
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn key_status_role_assignment_validate_test() {
+        let create_status_response = |access: &str| {
+            format!(
+                r#"{{
+                "authorizationScheme": "Azure-HMAC-SHA256",
+                "keyDeliveryMethod": "http",
+                "secureChannelEnabled": true,
+                "version": "2.0",
+                "authorizationRules": {{
+                    "imds": {{
+                        "defaultAccess": "allow",
+                        "mode": "enforce",
+                        "id": "sigid",
+                        "rules": {{
+                            "roleAssignments": [
+                                {{
+                                    "role": "test",
+                                    "identities": ["test"],
+                                    "access": "{}"
+                                }}
+                            ]
+                        }}
+                    }}
+                }}
+            }}"#,
+                access
+            )
+        };
+
+        for access in ["allow", "Deny", "DENY"] {
+            let status: KeyStatus = serde_json::from_str(&create_status_response(access)).unwrap();
+            assert!(status.validate().unwrap(), "access '{}' must be valid", access);
+        }
+
+        for access in ["Denied", "block", ""] {
+            let status: KeyStatus = serde_json::from_str(&create_status_response(access)).unwrap();
+            let e = status.validate().unwrap_err();
+            assert_eq!(std::io::ErrorKind::InvalidData, e.kind());
+            assert!(
+                e.to_string().contains("role 'test' has unknown access"),
+                "validate error must name the role assignment: {}",
+                e
+            );
+        }
     }
 
     #[test]
@@ -1090,6 +1471,39 @@ mod tests {
         );
     }
 
+    // match the url with the path matcher compiled from the privilege, as the authorization rules do
+    fn is_privilege_match(privilege: &Privilege, url: &url::Url, case_insensitive: bool) -> bool {
+        let path_matcher = privilege
+            .compile_path_matcher(case_insensitive)
+            .unwrap_or(PathMatcher::Invalid);
+        let request_path = super::normalize_request_path(url.path());
+        privilege.is_compiled_match(1, url, &request_path, &path_matcher, case_insensitive)
+    }
+
+    #[test]
+    fn test_normalize_request_path() {
+        for (path, normalized) in [
+            ("", "/"),
+            ("/", "/"),
+            ("/machine", "/machine"),
+            ("/machine/", "/machine/"),
+            ("//machine//a", "/machine/a"),
+            ("/%6Dachine", "/machine"),
+            ("/./machine/.", "/machine"),
+            ("/a/../machine", "/machine"),
+            ("/../machine", "/machine"),
+            ("/a%2Fb", "/a%2Fb"),
+            ("/%41%", "/A%"),
+        ] {
+            assert_eq!(
+                normalized,
+                super::normalize_request_path(path),
+                "normalized path of '{}' mismatch",
+                path
+            );
+        }
+    }
+
     #[test]
     fn test_privelege_is_match() {
         let logger_key = "test_privelege_is_match";
@@ -1108,19 +1522,19 @@ mod tests {
         let privilege: Privilege = serde_json::from_str(privilege).unwrap();
         let url = url::Url::parse("http://localhost/test?key1=value1&key2=value2").unwrap();
         assert!(
-            privilege.is_match(1, url.clone()),
+            is_privilege_match(&privilege, &url, true),
             "privilege should be matched"
         );
 
         let url = url::Url::parse("http://localhost/test?key1=value1&key2=value3").unwrap();
         assert!(
-            !privilege.is_match(1, url.clone()),
+            !is_privilege_match(&privilege, &url, true),
             "privilege should not be matched"
         );
 
         let url = url::Url::parse("http://localhost/test?key1=value1").unwrap();
         assert!(
-            !privilege.is_match(1, url.clone()),
+            !is_privilege_match(&privilege, &url, true),
             "privilege should not be matched"
         );
 
@@ -1131,7 +1545,7 @@ mod tests {
         let privilege1: Privilege = serde_json::from_str(privilege1).unwrap();
         let url = url::Url::parse("http://localhost/test?key1=value1&key2=value2").unwrap();
         assert!(
-            privilege1.is_match(1, url.clone()),
+            is_privilege_match(&privilege1, &url, true),
             "privilege should be matched"
         );
 
@@ -1146,10 +1560,181 @@ mod tests {
         let privilege2: Privilege = serde_json::from_str(privilege2).unwrap();
         let url = url::Url::parse("http://localhost/test?key1=value1&key2=value2").unwrap();
         assert!(
-            !privilege2.is_match(1, url.clone()),
+            !is_privilege_match(&privilege2, &url, true),
             "privilege should not be matched"
         );
 
+        let goal_state_privilege = r#"{
+            "name": "goalstate",
+            "path": "/machine",
+            "matchType": "exact",
+            "queryParameters": {
+                "comp": "goalstate",
+                "incarnation": "*"
+            }
+        }"#;
+        let goal_state_privilege: Privilege = serde_json::from_str(goal_state_privilege).unwrap();
+        let parse_url = |path: &str| url::Url::parse(&format!("http://localhost{}", path)).unwrap();
+        for (path, expected) in [
+            ("/machine?comp=goalstate&incarnation=1", true),
+            // extra query parameters are allowed
+            ("/machine?incarnation=&comp=goalstate&x=y", true),
+            ("/machine?comp=certificates&incarnation=1", false),
+            ("/machine?comp=goalstate", false),
+            ("/machine", false),
+            // duplicated key must not bypass the value check
+            ("/machine?comp=goalstate&comp=x&incarnation=1", false),
+        ] {
+            assert_eq!(
+                expected,
+                is_privilege_match(&goal_state_privilege, &parse_url(path), true),
+                "query parameters match mismatch for '{}'",
+                path
+            );
+        }
+
+        // clean up and ignore the clean up errors
+        _ = std::fs::remove_dir_all(temp_test_path);
+    }
+
+    #[test]
+    fn test_privilege_match_type() {
+        let logger_key = "test_privilege_match_type";
+        let mut temp_test_path = std::env::temp_dir();
+        temp_test_path.push(logger_key);
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let create_privilege = |path: &str, match_type: Option<&str>| Privilege {
+            name: "test".to_string(),
+            path: path.to_string(),
+            queryParameters: None,
+            matchType: match_type.map(|m| m.to_string()),
+        };
+        let test_url = url::Url::parse("http://localhost/testing/a/b").unwrap();
+        let exact_url = url::Url::parse("http://localhost/test").unwrap();
+
+        // default prefix match for back-compat
+        let privilege = create_privilege("/test", None);
+        assert!(is_privilege_match(&privilege, &test_url, true));
+        assert!(is_privilege_match(&privilege, &exact_url, true));
+
+        let privilege = create_privilege("/test", Some("prefix"));
+        assert!(is_privilege_match(&privilege, &test_url, true));
+        assert!(is_privilege_match(&privilege, &exact_url, true));
+
+        let privilege = create_privilege("/test", Some("Exact"));
+        assert!(
+            !is_privilege_match(&privilege, &test_url, true),
+            "exact match must not match '/testing'"
+        );
+        assert!(is_privilege_match(&privilege, &exact_url, true));
+
+        let privilege = create_privilege("/test*/*", Some("glob"));
+        assert!(
+            !is_privilege_match(&privilege, &test_url, true),
+            "'*' must not match across path segments"
+        );
+        let privilege = create_privilege("/test*/**", Some("glob"));
+        assert!(is_privilege_match(&privilege, &test_url, true));
+        assert!(!is_privilege_match(&privilege, &exact_url, true));
+        let privilege = create_privilege("/tes?", Some("glob"));
+        assert!(!is_privilege_match(&privilege, &test_url, true));
+        assert!(is_privilege_match(&privilege, &exact_url, true));
+
+        // plain path, trailing slash, empty segments and percent-encoded characters
+        let privilege = create_privilege("/testing/a/b", Some("glob"));
+        assert!(is_privilege_match(&privilege, &test_url, true));
+        for url in [
+            "http://localhost/testing/a/b/",
+            "http://localhost//testing//a/b",
+            "http://localhost/testing/%61/b",
+        ] {
+            assert!(
+                is_privilege_match(&privilege, &url::Url::parse(url).unwrap(), true),
+                "glob must match '{}'",
+                url
+            );
+        }
+        let parse_url = |path: &str| url::Url::parse(&format!("http://localhost{}", path)).unwrap();
+        let privilege = create_privilege("/machine/*/config", Some("glob"));
+        assert!(is_privilege_match(
+            &privilege,
+            &parse_url("/machine/abc/config"),
+            true
+        ));
+        assert!(
+            !is_privilege_match(&privilege, &parse_url("/machine/a/b/config"), true),
+            "'*' must not match across path segments"
+        );
+        assert!(
+            is_privilege_match(&privilege, &parse_url("/machine/a%2Fb/config"), true),
+            "encoded slash must not split the path segment"
+        );
+        assert!(
+            !is_privilege_match(&privilege, &parse_url("/machine/config"), true),
+            "'*' must not match an empty path segment"
+        );
+        let privilege = create_privilege("/metadata/**", Some("glob"));
+        assert!(is_privilege_match(
+            &privilege,
+            &parse_url("/metadata/instance/compute"),
+            true
+        ));
+        assert!(!is_privilege_match(
+            &privilege,
+            &parse_url("/metadataX/a"),
+            true
+        ));
+
+        let privilege = create_privilege("/test(ing)?/[a-z]/b", Some("regex"));
+        assert!(is_privilege_match(&privilege, &test_url, true));
+        assert!(!is_privilege_match(&privilege, &exact_url, true));
+        let privilege = create_privilege("/TEST", Some("regex"));
+        assert!(
+            is_privilege_match(&privilege, &exact_url, true),
+            "regex match must be case-insensitive"
+        );
+        assert!(
+            !is_privilege_match(&privilege, &exact_url, false),
+            "regex match must be case-sensitive"
+        );
+        let privilege = create_privilege("/test(", Some("regex"));
+        assert!(
+            !is_privilege_match(&privilege, &exact_url, true),
+            "invalid regex must not match"
+        );
+
+        // the regex is anchored to the whole path, unanchored match needs explicit '.*'
+        let privilege = create_privilege("/test", Some("regex"));
+        assert!(
+            !is_privilege_match(&privilege, &test_url, true),
+            "regex must match the whole path"
+        );
+        let privilege = create_privilege("^/test$", Some("regex"));
+        assert!(is_privilege_match(&privilege, &exact_url, true));
+        let privilege = create_privilege(".*/a/.*", Some("regex"));
+        assert!(is_privilege_match(&privilege, &test_url, true));
+
+        // pathological pattern must not backtrack on a long request path
+        let privilege = create_privilege("/(a+)+b", Some("regex"));
+        let long_path = format!("/{}c", "a".repeat(10000));
+        let start = std::time::Instant::now();
+        assert!(!is_privilege_match(
+            &privilege,
+            &parse_url(&long_path),
+            true
+        ));
+        assert!(
+            start.elapsed() < std::time::Duration::from_secs(1),
+            "regex match must run in linear time"
+        );
+
+        let privilege = create_privilege("/test", Some("unknown"));
+        assert!(
+            !is_privilege_match(&privilege, &exact_url, true),
+            "unknown match type must not match"
+        );
+
         // clean up and ignore the clean up errors
         _ = std::fs::remove_dir_all(temp_test_path);
     }
@@ -1166,6 +1751,8 @@ mod tests {
             userGroups: vec!["test".to_string()],
             processName: "test".to_string(),
             processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
             userId: 0,
             processId: 0,
             clientIp: "00.000.000".to_string(),
@@ -1182,7 +1769,7 @@ mod tests {
         }"#;
         let identity: Identity = serde_json::from_str(identity).unwrap();
         assert!(
-            identity.is_match(1, claims.clone()),
+            identity.is_match(1, claims.clone(), true),
             "identity should be matched"
         );
 
@@ -1195,7 +1782,7 @@ mod tests {
         }"#;
         let identity1: Identity = serde_json::from_str(identity1).unwrap();
         assert!(
-            !identity1.is_match(1, claims.clone()),
+            !identity1.is_match(1, claims.clone(), true),
             "identity should not be matched"
         );
 
@@ -1206,7 +1793,7 @@ mod tests {
         }"#;
         let identity2: Identity = serde_json::from_str(identity2).unwrap();
         assert!(
-            !identity2.is_match(1, claims.clone()),
+            !identity2.is_match(1, claims.clone(), true),
             "identity should not be matched"
         );
 
@@ -1216,7 +1803,7 @@ mod tests {
         }"#;
         let identity2: Identity = serde_json::from_str(identity2).unwrap();
         assert!(
-            identity2.is_match(1, claims.clone()),
+            identity2.is_match(1, claims.clone(), true),
             "identity should be matched"
         );
 
@@ -1227,7 +1814,7 @@ mod tests {
         }"#;
         let identity3: Identity = serde_json::from_str(identity3).unwrap();
         assert!(
-            !identity3.is_match(1, claims.clone()),
+            !identity3.is_match(1, claims.clone(), true),
             "identity should not be matched"
         );
         let identity3 = r#"{
@@ -1236,7 +1823,7 @@ mod tests {
         }"#;
         let identity3: Identity = serde_json::from_str(identity3).unwrap();
         assert!(
-            identity3.is_match(1, claims.clone()),
+            identity3.is_match(1, claims.clone(), true),
             "identity should be matched"
         );
 
@@ -1247,7 +1834,7 @@ mod tests {
         }"#;
         let identity4: Identity = serde_json::from_str(identity4).unwrap();
         assert!(
-            !identity4.is_match(1, claims.clone()),
+            !identity4.is_match(1, claims.clone(), true),
             "identity should not be matched"
         );
         let identity4 = r#"{
@@ -1256,7 +1843,7 @@ mod tests {
         }"#;
         let identity4: Identity = serde_json::from_str(identity4).unwrap();
         assert!(
-            identity4.is_match(1, claims.clone()),
+            identity4.is_match(1, claims.clone(), true),
             "identity should be matched"
         );
 
@@ -1267,7 +1854,7 @@ mod tests {
         }"#;
         let identity5: Identity = serde_json::from_str(identity5).unwrap();
         assert!(
-            !identity5.is_match(1, claims.clone()),
+            !identity5.is_match(1, claims.clone(), true),
             "identity should not be matched"
         );
         let identity5 = r#"{
@@ -1276,10 +1863,202 @@ mod tests {
         }"#;
         let identity5: Identity = serde_json::from_str(identity5).unwrap();
         assert!(
-            identity5.is_match(1, claims.clone()),
+            identity5.is_match(1, claims.clone(), true),
             "identity should be matched"
         );
 
+        // test userId, it is matched together with userName
+        let identity6 = r#"{
+            "name": "test",
+            "userName": "test",
+            "userId": 0
+        }"#;
+        let identity6: Identity = serde_json::from_str(identity6).unwrap();
+        assert!(
+            identity6.is_match(1, claims.clone(), true),
+            "identity should be matched"
+        );
+        let identity6 = r#"{
+            "name": "test",
+            "userName": "test",
+            "userId": 1000
+        }"#;
+        let identity6: Identity = serde_json::from_str(identity6).unwrap();
+        assert!(
+            !identity6.is_match(1, claims.clone(), true),
+            "identity should not be matched with a different user id"
+        );
+
+        // the userId is the uid on Linux, the logon session id on Windows
+        let mut platform_claims = claims.clone();
+        #[cfg(not(windows))]
+        {
+            platform_claims.userId = users::get_current_uid() as u64;
+        }
+        #[cfg(windows)]
+        {
+            // the logon session id of SYSTEM, not its SID S-1-5-18
+            platform_claims.userId = 0x3e7;
+        }
+        let identity7: Identity = serde_json::from_str(&format!(
+            r#"{{"name": "test", "userId": {}}}"#,
+            platform_claims.userId
+        ))
+        .unwrap();
+        assert!(
+            identity7.is_match(1, platform_claims, true),
+            "identity should be matched by the platform user id"
+        );
+        assert!(
+            serde_json::from_str::<Identity>(r#"{"name": "test", "userId": "S-1-5-18"}"#).is_err(),
+            "the SID is not a user id"
+        );
+
+        // test clientIpCidr
+        let create_cidr_identity = |cidr: &str| Identity {
+            name: "test".to_string(),
+            userName: None,
+            userId: None,
+            groupName: None,
+            exePath: None,
+            processName: None,
+            clientIpCidr: Some(cidr.to_string()),
+            exeSha256: None,
+        };
+        let mut claims = claims.clone();
+        for (client_ip, cidr, expected) in [
+            ("127.0.0.1", "127.0.0.0/8", true),
+            ("10.0.0.1", "127.0.0.0/8", false),
+            ("::1", "::1/128", true),
+            ("fe80::1", "fe80::/10", true),
+            ("::1", "127.0.0.0/8", false),
+            ("invalid ip", "127.0.0.0/8", false),
+        ] {
+            claims.clientIp = client_ip.to_string();
+            let identity = create_cidr_identity(cidr);
+            assert!(identity.validate().is_ok());
+            assert_eq!(
+                expected,
+                identity.is_match(1, claims.clone(), true),
+                "client ip '{}' match mismatch with cidr '{}'",
+                client_ip,
+                cidr
+            );
+        }
+        for cidr in ["127.0.0.0/33", "not a cidr"] {
+            assert!(
+                create_cidr_identity(cidr).validate().is_err(),
+                "malformed cidr '{}' must fail the validation",
+                cidr
+            );
+        }
+
+        // test exeSha256 with the binary of the test process
+        let exe_path = std::env::current_exe().unwrap();
+        let exe_sha256 = hex::encode(hmac_sha256::Hash::hash(&std::fs::read(&exe_path).unwrap()));
+        let mut claims = claims.clone();
+        claims.processId = std::process::id();
+        claims.processFullPath = exe_path.to_string_lossy().to_string();
+        let create_sha256_identity = |sha256: &str| Identity {
+            name: "test".to_string(),
+            userName: None,
+            userId: None,
+            groupName: None,
+            exePath: None,
+            processName: None,
+            clientIpCidr: None,
+            exeSha256: Some(sha256.to_string()),
+        };
+        let identity = create_sha256_identity(&exe_sha256.to_uppercase());
+        assert!(identity.validate().is_ok());
+        for _ in 0..2 {
+            assert!(
+                identity.is_match(1, claims.clone(), true),
+                "the hash of the process binary must match, the second time from the cache"
+            );
+        }
+        let identity = create_sha256_identity(&"0".repeat(64));
+        assert!(
+            !identity.is_match(1, claims.clone(), true),
+            "a renamed binary at the trusted path must not match"
+        );
+        let identity = create_sha256_identity(&exe_sha256);
+        let mut renamed_claims = claims.clone();
+        renamed_claims.processFullPath = "/usr/bin/trusted-agent".to_string();
+        assert!(
+            !identity.is_match(1, renamed_claims, true),
+            "the binary which cannot be hashed must not match"
+        );
+        claims.processFullPath = "undefined".to_string();
+        assert!(!identity.is_match(1, claims, true));
+        for sha256 in ["not hex", &"z".repeat(64), &"0".repeat(63)] {
+            assert!(
+                create_sha256_identity(sha256).validate().is_err(),
+                "malformed exeSha256 '{}' must fail the validation",
+                sha256
+            );
+        }
+
+        // clean up and ignore the clean up errors
+        _ = std::fs::remove_dir_all(temp_test_path);
+    }
+
+    #[test]
+    fn test_case_sensitivity() {
+        let logger_key = "test_case_sensitivity";
+        let mut temp_test_path = std::env::temp_dir();
+        temp_test_path.push(logger_key);
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let url = url::Url::parse("http://localhost/Machine/Config?comp=GoalState").unwrap();
+        for match_type in ["exact", "prefix", "glob", "regex"] {
+            let privilege = Privilege {
+                name: "test".to_string(),
+                path: "/machine/config".to_string(),
+                queryParameters: Some(HashMap::from([(
+                    "comp".to_string(),
+                    "goalstate".to_string(),
+                )])),
+                matchType: Some(match_type.to_string()),
+            };
+            assert!(
+                is_privilege_match(&privilege, &url, true),
+                "{} match must be case-insensitive",
+                match_type
+            );
+            assert!(
+                !is_privilege_match(&privilege, &url, false),
+                "{} match must be case-sensitive",
+                match_type
+            );
+        }
+
+        let claims = super::Claims {
+            userName: "Administrator".to_string(),
+            userGroups: vec!["Administrators".to_string()],
+            processName: "WaAppAgent.exe".to_string(),
+            processCmdLine: "WaAppAgent.exe".to_string(),
+            processExeSha256: None,
+            processSigner: None,
+            userId: 0,
+            processId: 0,
+            clientIp: "127.0.0.1".to_string(),
+            runAsElevated: true,
+            processFullPath: "c:\\windows\\waappagent.exe".to_string(),
+        };
+        let identity = Identity {
+            name: "test".to_string(),
+            userName: Some("administrator".to_string()),
+            userId: None,
+            groupName: Some("ADMINISTRATORS".to_string()),
+            exePath: Some("C:\\Windows\\WaAppAgent.exe".to_string()),
+            processName: Some("waappagent.exe".to_string()),
+            clientIpCidr: None,
+            exeSha256: None,
+        };
+        assert!(identity.is_match(1, claims.clone(), true));
+        assert!(!identity.is_match(1, claims.clone(), false));
+
         // clean up and ignore the clean up errors
         _ = std::fs::remove_dir_all(temp_test_path);
     }
@@ -0,0 +1,168 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use super::{DISABLE_STATE, MUST_SIG_WIRESERVER, MUST_SIG_WIRESERVER_IMDS, UNKNOWN_STATE};
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+const WIRESERVER_RULES_PREFIX: &str = "WireServer ";
+const IMDS_RULES_SEPARATOR: &str = " -  IMDS ";
+
+// the mode of the authorization item
+const AUDIT_MODE: &str = "audit";
+const ENFORCE_MODE: &str = "enforce";
+// the mode names in the secure channel state
+const DISABLED_MODE_NAME: &str = "Disabled";
+const AUDIT_MODE_NAME: &str = "Audit";
+const ENFORCE_MODE_NAME: &str = "Enforce";
+
+// the mode of the authorization rules of one destination
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RulesMode {
+    Disabled,
+    Audit,
+    Enforce,
+}
+
+impl RulesMode {
+    // the mode of the authorization item, anything else than audit or enforce disables the rules
+    pub fn from_mode(mode: &str) -> Self {
+        match mode.to_lowercase().as_str() {
+            ENFORCE_MODE => RulesMode::Enforce,
+            AUDIT_MODE => RulesMode::Audit,
+            _ => RulesMode::Disabled,
+        }
+    }
+}
+
+impl fmt::Display for RulesMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self {
+            RulesMode::Disabled => DISABLED_MODE_NAME,
+            RulesMode::Audit => AUDIT_MODE_NAME,
+            RulesMode::Enforce => ENFORCE_MODE_NAME,
+        };
+        write!(f, "{}", mode)
+    }
+}
+
+impl FromStr for RulesMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            DISABLED_MODE_NAME => Ok(RulesMode::Disabled),
+            AUDIT_MODE_NAME => Ok(RulesMode::Audit),
+            ENFORCE_MODE_NAME => Ok(RulesMode::Enforce),
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid authorization rules mode '{}'", s),
+            )),
+        }
+    }
+}
+
+/*
+The secure channel state.
+Version 1.0 key status reports disabled, wireserver or wireserverandimds,
+version 2.0 reports the authorization rules mode of WireServer and IMDS.
+The string form is reported in the status, it stays the same as the string states used before.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SecureChannelState {
+    Unknown,
+    Disabled,
+    WireServer,
+    WireServerAndImds,
+    Rules {
+        wireserver: RulesMode,
+        imds: RulesMode,
+    },
+}
+
+impl fmt::Display for SecureChannelState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecureChannelState::Unknown => write!(f, "{}", UNKNOWN_STATE),
+            SecureChannelState::Disabled => write!(f, "{}", DISABLE_STATE),
+            SecureChannelState::WireServer => write!(f, "{}", MUST_SIG_WIRESERVER),
+            SecureChannelState::WireServerAndImds => write!(f, "{}", MUST_SIG_WIRESERVER_IMDS),
+            SecureChannelState::Rules { wireserver, imds } => write!(
+                f,
+                "{}{}{}{}",
+                WIRESERVER_RULES_PREFIX, wireserver, IMDS_RULES_SEPARATOR, imds
+            ),
+        }
+    }
+}
+
+impl FromStr for SecureChannelState {
+    type Err = Error;
+
+    // the version 1.0 states are case-insensitive
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == UNKNOWN_STATE {
+            return Ok(SecureChannelState::Unknown);
+        }
+        match s.to_lowercase().as_str() {
+            DISABLE_STATE => return Ok(SecureChannelState::Disabled),
+            MUST_SIG_WIRESERVER => return Ok(SecureChannelState::WireServer),
+            MUST_SIG_WIRESERVER_IMDS => return Ok(SecureChannelState::WireServerAndImds),
+            _ => {}
+        }
+
+        if let Some(modes) = s.strip_prefix(WIRESERVER_RULES_PREFIX) {
+            if let Some((wireserver, imds)) = modes.split_once(IMDS_RULES_SEPARATOR) {
+                return Ok(SecureChannelState::Rules {
+                    wireserver: wireserver.parse()?,
+                    imds: imds.parse()?,
+                });
+            }
+        }
+
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid secure channel state '{}'", s),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RulesMode, SecureChannelState};
+
+    #[test]
+    fn secure_channel_state_test() {
+        for (text, state) in [
+            ("Unknown", SecureChannelState::Unknown),
+            ("disabled", SecureChannelState::Disabled),
+            ("wireserver", SecureChannelState::WireServer),
+            ("wireserverandimds", SecureChannelState::WireServerAndImds),
+            (
+                "WireServer Enforce -  IMDS Audit",
+                SecureChannelState::Rules {
+                    wireserver: RulesMode::Enforce,
+                    imds: RulesMode::Audit,
+                },
+            ),
+        ] {
+            assert_eq!(state, text.parse::<SecureChannelState>().unwrap());
+            assert_eq!(text, state.to_string(), "string form must stay stable");
+        }
+
+        assert_eq!(
+            SecureChannelState::WireServerAndImds,
+            "WireserverAndImds".parse::<SecureChannelState>().unwrap()
+        );
+        for text in ["", "wire server", "WireServer Enforce -  IMDS Invalid"] {
+            assert!(
+                text.parse::<SecureChannelState>().is_err(),
+                "'{}' must not be parsed",
+                text
+            );
+        }
+
+        assert_eq!(RulesMode::Enforce, RulesMode::from_mode("ENFORCE"));
+        assert_eq!(RulesMode::Disabled, RulesMode::from_mode("unknown"));
+    }
+}
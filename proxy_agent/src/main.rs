@@ -37,7 +37,10 @@ fn main() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 {
         if args[1].to_lowercase() == "console" {
-            service::start_service();
+            if let Err(e) = service::start_service() {
+                println!("{}", e);
+                process::exit(1);
+            }
             println!("Press Enter to end it.");
             let mut temp = String::new();
             _ = std::io::stdin().read_line(&mut temp);
@@ -64,6 +67,25 @@ fn main() {
                 // provision finished and success
                 return;
             }
+        } else if args[1].to_lowercase() == "--selftest" {
+            let mut port = common::constants::PROXY_AGENT_PORT;
+            if args.len() >= 4 && args[2].to_lowercase() == "--port" {
+                port = args[3].parse::<u16>().unwrap_or(port);
+            }
+            match service::request_self_test(port) {
+                Ok((succeeded, result)) => {
+                    println!("{}", result);
+                    if !succeeded {
+                        // exit code 1 means one of the self test stages failed.
+                        process::exit(1);
+                    }
+                }
+                Err(e) => {
+                    // exit code 2 means the self test cannot be run by the agent.
+                    println!("{}", e);
+                    process::exit(2);
+                }
+            }
         } else {
             println!("Invalid argument: {}", args[1]);
         }
@@ -1,7 +1,8 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
-use crate::common::{config, logger};
+use crate::common::{config, helpers, logger};
 use crate::key_keeper;
+use crate::key_keeper::secure_channel_state::SecureChannelState;
 use once_cell::sync::Lazy;
 use proxy_agent_shared::proxy_agent_aggregate_status::{ModuleState, ProxyAgentDetailStatus};
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -51,7 +52,7 @@ fn start(mut interval: Duration) {
 }
 
 fn redirect_should_run() -> bool {
-    if key_keeper::get_secure_channel_state() != key_keeper::DISABLE_STATE {
+    if key_keeper::get_secure_channel_state() != SecureChannelState::Disabled {
         return true;
     } else if config::get_start_redirector() {
         return true;
@@ -75,7 +76,7 @@ pub fn get_status() -> ProxyAgentDetailStatus {
 
     ProxyAgentDetailStatus {
         status,
-        message: unsafe { STATUS_MESSAGE.to_string() },
+        message: helpers::cap_status_message(unsafe { STATUS_MESSAGE.to_string() }, "monitor"),
         states: None,
     }
 }
@@ -1,12 +1,13 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
 use crate::common::{config, helpers, logger};
-use crate::proxy::proxy_listener;
+use crate::proxy::{proxy_authentication, proxy_listener};
 use crate::telemetry::event_reader;
 use crate::{key_keeper, proxy_agent_status, redirector};
 use once_cell::sync::Lazy;
 use proxy_agent_shared::misc_helpers;
 use proxy_agent_shared::telemetry::event_logger;
+use serde_derive::Serialize;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -14,6 +15,17 @@ use std::time::Duration;
 const STATUS_TAG_TMP_FILE_NAME: &str = "status.tag.tmp";
 const STATUS_TAG_FILE_NAME: &str = "status.tag";
 static mut STATE: Lazy<Arc<Mutex<u8>>> = Lazy::new(|| Arc::new(Mutex::new(0)));
+// the provision state with the readiness of each module, served by the provision state endpoint
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+pub struct ProvisionReadiness {
+    pub provisioned: bool, // the redirector, the key and the listener have all reported ready once
+    pub listenerReady: bool,
+    pub keyPresent: bool,
+    pub redirectorRunning: bool,
+    pub rulesLoaded: bool,
+}
+
 static mut LOGGER_THREADS_INITIALIZED: Lazy<Arc<Mutex<bool>>> =
     Lazy::new(|| Arc::new(Mutex::new(false)));
 
@@ -29,6 +41,42 @@ pub fn listener_started() {
     update_provision_state(4, None);
 }
 
+// the listener is moving to another port, it reports started again once rebound
+pub fn listener_rebinding() {
+    unsafe {
+        let cloned_state = Arc::clone(&*STATE);
+        let cloned_state = cloned_state.lock();
+        match cloned_state {
+            Ok(mut cloned_state) => *cloned_state &= !4,
+            Err(e) => {
+                _ = logger::write_error(format!("Failed to lock provision state with error: {e}"));
+            }
+        }
+    }
+}
+
+pub fn get_readiness() -> ProvisionReadiness {
+    let state = unsafe {
+        let cloned_state = Arc::clone(&*STATE);
+        let cloned_state = cloned_state.lock();
+        match cloned_state {
+            Ok(cloned_state) => *cloned_state,
+            Err(e) => {
+                _ = logger::write_error(format!("Failed to lock provision state with error: {e}"));
+                0
+            }
+        }
+    };
+
+    ProvisionReadiness {
+        provisioned: state == 7,
+        listenerReady: state & 4 == 4,
+        keyPresent: key_keeper::is_key_present(),
+        redirectorRunning: redirector::is_started(),
+        rulesLoaded: proxy_authentication::is_rules_loaded(),
+    }
+}
+
 fn update_provision_state(state: u8, provision_dir: Option<PathBuf>) {
     unsafe {
         let cloned_state: Arc<Mutex<u8>> = Arc::clone(&*STATE);
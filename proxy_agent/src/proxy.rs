@@ -1,22 +1,40 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
 mod authorization_rules;
+mod circuit_breaker;
+#[cfg(not(windows))]
+pub mod control_listener;
+#[cfg(feature = "fault-injection")]
+mod fault_injection;
 pub mod proxy_authentication;
 pub mod proxy_connection;
 pub mod proxy_listener;
+mod proxy_metrics;
 mod proxy_pool;
 pub mod proxy_summary;
+#[cfg(feature = "otel")]
+mod proxy_trace;
+mod rate_limiter;
+mod self_test;
 
 #[cfg(windows)]
 mod windows;
 
+use crate::common::config;
+use crate::common::http::headers;
 use crate::redirector::AuditEntry;
 use once_cell::sync::Lazy;
 use serde_derive::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 use std::{collections::HashMap, net::IpAddr, path::PathBuf};
 
 #[cfg(not(windows))]
-use std::sync::{Arc, Mutex};
+use std::net::{SocketAddr, SocketAddrV4};
+#[cfg(not(windows))]
+use std::sync::Arc;
 #[cfg(not(windows))]
 use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
 
@@ -32,13 +50,58 @@ pub struct Claims {
     pub processCmdLine: String,
     pub runAsElevated: bool,
     pub clientIp: String,
+    // collected with the collectProcessIntegrity config only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processExeSha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processSigner: Option<String>, // Windows only, the Authenticode signer subject of the process binary
+}
+
+// the claims forwarded to host in the x-ms-azure-host-claims header
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+pub struct HostClaims {
+    isRoot: String, // host reads the elevation as a string
+}
+
+impl HostClaims {
+    pub fn new(is_root: bool) -> Self {
+        HostClaims {
+            isRoot: is_root.to_string(),
+        }
+    }
+
+    pub fn from_claims(claims: &Claims) -> Self {
+        HostClaims::new(claims.runAsElevated)
+    }
+
+    // serialize the claims to the header value, fails if the value is not safe to put in a header
+    pub fn to_header_value(&self) -> std::io::Result<String> {
+        let value = serde_json::to_string(self).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Failed to serialize the host claims: {}", e),
+            )
+        })?;
+        if !headers::is_valid_header_value(&value) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "The host claims contain the characters not allowed in a header value.",
+            ));
+        }
+        Ok(value)
+    }
 }
 
+#[derive(Clone)]
 struct Process {
     pub command_line: String,
     pub name: String,
     pub exe_full_name: String,
     pub pid: u32,
+    pub start_time: Option<SystemTime>, // tells the process apart from a later process reusing the pid
+    pub exe_sha256: Option<String>,
+    pub signer: Option<String>,
 }
 
 struct User {
@@ -51,21 +114,269 @@ struct User {
 static mut CURRENT_SYSTEM: Lazy<Arc<Mutex<System>>> =
     Lazy::new(|| Arc::new(Mutex::new(System::new())));
 
-static mut USERS: Lazy<HashMap<u64, User>> = Lazy::new(|| HashMap::new());
+// the start time is read in the clock ticks after the boot time in seconds on Linux,
+// a process started within the tolerance after the connection is still trusted
+const PROCESS_START_TIME_TOLERANCE: Duration = Duration::from_secs(1);
+
+// cache the pid -> (process, cached time), the ttl is short as the pids are reused
+static PROCESSES: Lazy<Mutex<HashMap<u32, (Process, Instant)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// cache the logon_id -> (user, cached time)
+static USERS: Lazy<Mutex<HashMap<u64, (User, Instant)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+// cache the exe path -> (modified time, sha256), the binary is hashed again once it is modified
+static EXE_SHA256S: Lazy<Mutex<HashMap<String, (SystemTime, String)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// cache the exe path -> (modified time, signer subject), None if the binary is not signed
+#[cfg(windows)]
+static EXE_SIGNERS: Lazy<Mutex<HashMap<String, (SystemTime, Option<String>)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+const MAX_CACHED_EXE_SHA256S: usize = 1024;
 const UNDEFINED: &str = "undefined";
 const EMPTY: &str = "empty";
+// the claims userId of the well-known system accounts
+#[cfg(not(windows))]
+const SYSTEM_UID_MAX: u64 = 999; // root and the service accounts, the SYS_UID_MAX default of login.defs
+#[cfg(windows)]
+const SYSTEM_LOGON_IDS: [u64; 3] = [0x3e7, 0x3e4, 0x3e5]; // SYSTEM, NETWORK SERVICE and LOCAL SERVICE
 
 fn get_user(logon_id: u64) -> User {
-    unsafe {
-        // cache the logon_id -> user_name
-        if USERS.contains_key(&logon_id) {
-            return USERS[&logon_id].clone();
+    get_user_with_ttl(&USERS, logon_id, config::get_user_cache_ttl())
+}
+
+// the users are cached in the given map, the global USERS except in the tests
+fn get_user_with_ttl(
+    users: &Mutex<HashMap<u64, (User, Instant)>>,
+    logon_id: u64,
+    ttl: Option<Duration>,
+) -> User {
+    if let Some((user, cached_time)) = users.lock().unwrap().get(&logon_id) {
+        let expired = match ttl {
+            Some(ttl) => cached_time.elapsed() >= ttl,
+            None => false,
+        };
+        if !expired {
+            return user.clone();
+        }
+    }
+
+    let user = User::from_logon_id(logon_id);
+    users
+        .lock()
+        .unwrap()
+        .insert(logon_id, (user.clone(), Instant::now()));
+    user
+}
+
+// drain the cached users to force a fresh user name and groups lookup,
+// returns the count of the cleared users
+pub fn clear_user_cache() -> usize {
+    clear_users(&USERS)
+}
+
+fn clear_users(users: &Mutex<HashMap<u64, (User, Instant)>>) -> usize {
+    let mut users = users.lock().unwrap();
+    let count = users.len();
+    users.clear();
+    count
+}
+
+fn get_process(pid: u32) -> Process {
+    get_process_with_ttl(pid, config::get_process_cache_ttl())
+}
+
+fn get_process_with_ttl(pid: u32, ttl: Duration) -> Process {
+    if let Some((process, cached_time)) = PROCESSES.lock().unwrap().get(&pid) {
+        // the cached process is stale if the pid is reused by another process
+        if cached_time.elapsed() < ttl && process.start_time == get_process_start_time(pid) {
+            return process.clone();
+        }
+    }
+
+    let process = Process::from_pid(pid);
+    if !ttl.is_zero() {
+        let mut processes = PROCESSES.lock().unwrap();
+        // drop the expired processes, so the exited pids do not stay in the cache
+        processes.retain(|_, (_, cached_time)| cached_time.elapsed() < ttl);
+        processes.insert(pid, (process.clone(), Instant::now()));
+    }
+    process
+}
+
+// the start time of the process, None if the process has exited or the start time is not readable
+fn get_process_start_time(pid: u32) -> Option<SystemTime> {
+    #[cfg(windows)]
+    {
+        windows::get_process_start_time(pid).ok()
+    }
+    #[cfg(not(windows))]
+    {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        let start_ticks = parse_process_start_ticks(&stat)?;
+        let clock_ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if clock_ticks <= 0 {
+            return None;
+        }
+        let boot_time = (*BOOT_TIME)?;
+        Some(
+            SystemTime::UNIX_EPOCH
+                + Duration::from_secs(boot_time)
+                + Duration::from_millis(start_ticks * 1000 / clock_ticks as u64),
+        )
+    }
+}
+
+// the boot time in seconds since epoch, from the btime line of /proc/stat
+#[cfg(not(windows))]
+static BOOT_TIME: Lazy<Option<u64>> = Lazy::new(|| {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|btime| btime.trim().parse().ok())
+});
+
+// the starttime field of /proc/<pid>/stat, in clock ticks after the boot
+#[cfg(not(windows))]
+fn parse_process_start_ticks(stat: &str) -> Option<u64> {
+    // the command name in the 2nd field could contain spaces and ')', read the fields after its last ')'
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    // starttime is the 22nd field, the 20th after the command name
+    fields.get(19)?.parse().ok()
+}
+
+/*
+The hex encoded sha256 of the binary of the process, cached per (exe path, modified time).
+On Linux the binary is read from /proc/<pid>/exe, which is the file the process was started from
+even if another file is moved to its path afterwards.
+ */
+pub fn get_process_exe_sha256(_pid: u32, exe_full_name: &str) -> std::io::Result<String> {
+    if exe_full_name == UNDEFINED || exe_full_name == EMPTY || exe_full_name.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "The process exe path is unknown",
+        ));
+    }
+    #[cfg(windows)]
+    let exe_path = PathBuf::from(exe_full_name);
+    #[cfg(not(windows))]
+    let exe_path = {
+        let exe_path = PathBuf::from(format!("/proc/{}/exe", _pid));
+        // the pid could be reused by another process since the claims are read
+        if std::fs::read_link(&exe_path)? != PathBuf::from(exe_full_name) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("The process {} does not run '{}'", _pid, exe_full_name),
+            ));
+        }
+        exe_path
+    };
+
+    let modified = std::fs::metadata(&exe_path)?.modified()?;
+    if let Some((cached_modified, sha256)) = EXE_SHA256S.lock().unwrap().get(exe_full_name) {
+        if *cached_modified == modified {
+            return Ok(sha256.to_string());
+        }
+    }
+
+    let sha256 = compute_file_sha256(&exe_path)?;
+    let mut sha256s = EXE_SHA256S.lock().unwrap();
+    if sha256s.len() >= MAX_CACHED_EXE_SHA256S {
+        sha256s.clear();
+    }
+    sha256s.insert(exe_full_name.to_string(), (modified, sha256.to_string()));
+    Ok(sha256)
+}
+
+// the Authenticode signer subject of the binary, cached per (exe path, modified time)
+#[cfg(windows)]
+fn get_exe_signer(exe_full_name: &str) -> Option<String> {
+    let modified = std::fs::metadata(exe_full_name).ok()?.modified().ok()?;
+    if let Some((cached_modified, signer)) = EXE_SIGNERS.lock().unwrap().get(exe_full_name) {
+        if *cached_modified == modified {
+            return signer.clone();
+        }
+    }
+
+    // the unsigned binaries are cached as well, so they are not read again
+    let signer = windows::get_file_signer(exe_full_name).ok();
+    let mut signers = EXE_SIGNERS.lock().unwrap();
+    if signers.len() >= MAX_CACHED_EXE_SHA256S {
+        signers.clear();
+    }
+    signers.insert(exe_full_name.to_string(), (modified, signer.clone()));
+    signer
+}
+
+fn compute_file_sha256(path: &std::path::Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hash = hmac_sha256::Hash::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = std::io::Read::read(&mut file, &mut buffer)?;
+        if read == 0 {
+            break;
         }
+        hash.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hash.finalize()))
+}
 
-        let user = User::from_logon_id(logon_id);
-        USERS.insert(logon_id, user.clone());
-        user
+/*
+True if the process connected directly to the listener runs elevated, i.e. root on linux or an elevated token on windows.
+The direct requests are not redirected and have no audit entry, the owner of the client socket is looked up instead.
+ */
+pub fn is_loopback_client_elevated(stream: &TcpStream) -> std::io::Result<bool> {
+    let client = stream.peer_addr()?;
+    let listener = stream.local_addr()?;
+    #[cfg(windows)]
+    {
+        windows::is_loopback_client_elevated(client, listener)
     }
+    #[cfg(not(windows))]
+    {
+        let (client, listener) = match (client, listener) {
+            (SocketAddr::V4(client), SocketAddr::V4(listener)) => (client, listener),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    "Only the ipv4 loopback clients are supported",
+                ))
+            }
+        };
+        let tcp_table = std::fs::read_to_string("/proc/net/tcp")?;
+        match find_tcp_socket_uid(&tcp_table, client, listener) {
+            Some(uid) => Ok(uid == 0),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("No tcp socket found from {} to {}", client, listener),
+            )),
+        }
+    }
+}
+
+// the uid owns the tcp socket from the local to the remote address in the /proc/net/tcp table
+#[cfg(not(windows))]
+fn find_tcp_socket_uid(tcp_table: &str, local: SocketAddrV4, remote: SocketAddrV4) -> Option<u32> {
+    let local = format_proc_net_address(local);
+    let remote = format_proc_net_address(remote);
+    // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+    tcp_table.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() > 7 && fields[1] == local && fields[2] == remote {
+            fields[7].parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+// the address is the u32 in network byte order printed in the host byte order, the port in hex
+#[cfg(not(windows))]
+fn format_proc_net_address(addr: SocketAddrV4) -> String {
+    format!(
+        "{:08X}:{:04X}",
+        u32::from_ne_bytes(addr.ip().octets()),
+        addr.port()
+    )
 }
 
 #[cfg(not(windows))]
@@ -77,13 +388,18 @@ fn get_process_info(process_id: u32) -> (String, String) {
     unsafe {
         let cloned_sys = Arc::clone(&*CURRENT_SYSTEM);
         let mut sys = cloned_sys.lock().unwrap();
-        sys.refresh_processes();
-        if let Some(p) = sys.process(pid) {
+        // refresh the process of the pid only, not the whole process table,
+        // false if the process has exited
+        let refreshed = sys.refresh_process(pid);
+        if let Some(p) = sys.process(pid).filter(|_| refreshed) {
+            // exe and cmd are empty when the process exits while it is read
             match p.exe().to_str() {
-                Some(name) => process_name = name.to_string(),
-                None => process_name = UNDEFINED.to_string(),
+                Some(name) if !name.is_empty() => process_name = name.to_string(),
+                _ => process_name = UNDEFINED.to_string(),
             };
-            process_cmd_line = p.cmd().join(" ");
+            if !p.cmd().is_empty() {
+                process_cmd_line = p.cmd().join(" ");
+            }
         }
 
         (process_name, process_cmd_line)
@@ -100,13 +416,24 @@ impl Claims {
             processName: EMPTY.to_string(),
             processFullPath: EMPTY.to_string(),
             processCmdLine: EMPTY.to_string(),
+            processExeSha256: None,
+            processSigner: None,
             runAsElevated: false,
             clientIp: EMPTY.to_string(),
         }
     }
 
-    pub fn from_audit_entry(entry: &AuditEntry, client_ip: IpAddr) -> Self {
-        let p = Process::from_pid(entry.process_id);
+    // connected_at is when the connection was accepted,
+    // a process started after it reuses the pid of the process which made the connection
+    pub fn from_audit_entry(
+        entry: &AuditEntry,
+        client_ip: IpAddr,
+        connected_at: SystemTime,
+    ) -> Self {
+        let mut p = get_process(entry.process_id);
+        if p.started_after(connected_at) {
+            p = Process::undefined(entry.process_id);
+        }
         let u = get_user(entry.logon_id);
         Claims {
             userId: entry.logon_id,
@@ -116,6 +443,8 @@ impl Claims {
             processName: p.name.to_string(),
             processFullPath: p.exe_full_name.to_string(),
             processCmdLine: p.command_line.to_string(),
+            processExeSha256: p.exe_sha256.clone(),
+            processSigner: p.signer.clone(),
             runAsElevated: entry.is_admin == 1,
             clientIp: client_ip.to_string(),
         }
@@ -130,15 +459,50 @@ impl Claims {
             processName: self.processName.to_string(),
             processFullPath: self.processFullPath.to_string(),
             processCmdLine: self.processCmdLine.to_string(),
+            processExeSha256: self.processExeSha256.clone(),
+            processSigner: self.processSigner.clone(),
             runAsElevated: self.runAsElevated,
             clientIp: self.clientIp.to_string(),
         }
     }
+
+    // true if the user is a well-known system or service account,
+    // the userId is the uid on linux and the logon session id on windows
+    pub fn is_system_account(&self) -> bool {
+        #[cfg(not(windows))]
+        {
+            self.userId <= SYSTEM_UID_MAX
+        }
+        #[cfg(windows)]
+        {
+            SYSTEM_LOGON_IDS.contains(&self.userId)
+        }
+    }
 }
 
 impl Process {
+    pub fn undefined(pid: u32) -> Self {
+        Process {
+            command_line: UNDEFINED.to_string(),
+            name: UNDEFINED.to_string(),
+            exe_full_name: UNDEFINED.to_string(),
+            pid,
+            start_time: None,
+            exe_sha256: None,
+            signer: None,
+        }
+    }
+
+    pub fn started_after(&self, time: SystemTime) -> bool {
+        match self.start_time {
+            Some(start_time) => start_time > time + PROCESS_START_TIME_TOLERANCE,
+            None => false,
+        }
+    }
+
     pub fn from_pid(pid: u32) -> Self {
-        let (process_full_path, cmd);
+        let start_time = get_process_start_time(pid);
+        let (mut process_full_path, mut cmd);
         #[cfg(windows)]
         {
             let handler;
@@ -170,6 +534,26 @@ impl Process {
             cmd = process_info.1;
         }
 
+        // the process exited and the pid could be reused while the process is read
+        if start_time != get_process_start_time(pid) {
+            process_full_path = UNDEFINED.to_string();
+            cmd = UNDEFINED.to_string();
+        }
+
+        let collect_integrity =
+            config::get_collect_process_integrity() && process_full_path != UNDEFINED;
+        let exe_sha256 = match collect_integrity {
+            true => get_process_exe_sha256(pid, &process_full_path).ok(),
+            false => None,
+        };
+        #[cfg(windows)]
+        let signer = match collect_integrity {
+            true => get_exe_signer(&process_full_path),
+            false => None,
+        };
+        #[cfg(not(windows))]
+        let signer = None;
+
         let exe_path = PathBuf::from(process_full_path.to_string());
         Process {
             command_line: cmd,
@@ -180,6 +564,9 @@ impl Process {
                 .to_string(),
             exe_full_name: process_full_path,
             pid,
+            start_time,
+            exe_sha256,
+            signer,
         }
     }
 }
@@ -235,15 +622,20 @@ impl User {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::net::IpAddr;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
 
     use super::Claims;
-    use crate::proxy::USERS;
+    use super::HostClaims;
+    use crate::common::http::headers;
+    use crate::proxy::PROCESSES;
     use crate::redirector::AuditEntry;
 
     #[test]
     fn user_test() {
-        unsafe {
+        {
             let logon_id;
             let expectd_user_name;
             #[cfg(windows)]
@@ -257,13 +649,18 @@ mod tests {
                 expectd_user_name = "root";
             }
 
-            let user = super::get_user(logon_id);
+            // the global USERS is cleared by the user cache endpoint tests in parallel
+            let users = Mutex::new(HashMap::new());
+            let user = super::get_user_with_ttl(&users, logon_id, None);
             println!("UserName: {}", user.user_name);
             println!("UserGroups: {}", user.user_groups.join(", "));
             assert_eq!(expectd_user_name, user.user_name, "user name mismatch.");
             #[cfg(windows)]
             {
-                assert_eq!(0, user.user_groups.len(), "SYSTEM has no group.");
+                assert!(
+                    user.user_groups.contains(&"Administrators".to_string()),
+                    "SYSTEM must be in the Administrators group."
+                );
             }
             #[cfg(not(windows))]
             {
@@ -273,16 +670,96 @@ mod tests {
                 );
             }
 
-            // test the USERS.len will not change
-            let len = USERS.len();
-            _ = super::get_user(logon_id);
-            _ = super::get_user(logon_id);
-            _ = super::get_user(logon_id);
-            _ = super::get_user(logon_id);
-            assert_eq!(len, USERS.len(), "USERS.len() should not change")
+            // test the users.len will not change
+            let len = users.lock().unwrap().len();
+            _ = super::get_user_with_ttl(&users, logon_id, None);
+            _ = super::get_user_with_ttl(&users, logon_id, None);
+            _ = super::get_user_with_ttl(&users, logon_id, None);
+            _ = super::get_user_with_ttl(&users, logon_id, None);
+            assert_eq!(
+                len,
+                users.lock().unwrap().len(),
+                "users.len() should not change"
+            );
         }
     }
 
+    #[test]
+    fn clear_user_cache_test() {
+        let logon_id = 0u64;
+        // the global USERS is cleared by the user cache endpoint tests in parallel
+        let users: Mutex<HashMap<u64, (super::User, Instant)>> = Mutex::new(HashMap::new());
+        let get_cached_time = || users.lock().unwrap().get(&logon_id).map(|u| u.1);
+
+        _ = super::get_user_with_ttl(&users, logon_id, None);
+        let cached_time = get_cached_time().expect("user must be cached");
+        _ = super::get_user_with_ttl(&users, logon_id, None);
+        assert_eq!(
+            Some(cached_time),
+            get_cached_time(),
+            "cached user must be reused"
+        );
+
+        assert!(
+            super::clear_users(&users) > 0,
+            "cached user must be cleared"
+        );
+        assert_eq!(None, get_cached_time(), "user cache must be empty");
+        _ = super::get_user_with_ttl(&users, logon_id, None);
+        let refreshed_time = get_cached_time().expect("user must be cached again");
+        assert!(
+            refreshed_time > cached_time,
+            "fresh lookup must occur after the cache cleared"
+        );
+
+        // expired user must be looked up again
+        std::thread::sleep(Duration::from_millis(10));
+        _ = super::get_user_with_ttl(&users, logon_id, Some(Duration::from_millis(1)));
+        assert!(
+            get_cached_time().unwrap() > refreshed_time,
+            "fresh lookup must occur after the ttl expired"
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn get_process_info_test() {
+        let (path, cmd) = super::get_process_info(std::process::id());
+        assert_ne!(super::UNDEFINED, path, "current process path must be read");
+        assert_ne!(super::UNDEFINED, cmd, "current process cmd must be read");
+
+        // the pid above the linux pid_max is never a running process
+        let (path, cmd) = super::get_process_info(u32::MAX);
+        assert_eq!(super::UNDEFINED, path);
+        assert_eq!(super::UNDEFINED, cmd);
+    }
+
+    #[test]
+    fn process_cache_test() {
+        let pid = std::process::id();
+        let get_cached_time = || PROCESSES.lock().unwrap().get(&pid).map(|p| p.1);
+
+        let process = super::get_process_with_ttl(pid, Duration::from_secs(60));
+        let cached_time = get_cached_time().expect("process must be cached");
+        for _ in 0..3 {
+            let cached = super::get_process_with_ttl(pid, Duration::from_secs(60));
+            assert_eq!(process.exe_full_name, cached.exe_full_name);
+        }
+        assert_eq!(
+            Some(cached_time),
+            get_cached_time(),
+            "the repeated lookups must reuse the cached process"
+        );
+
+        // expired process must be looked up again
+        std::thread::sleep(Duration::from_millis(10));
+        _ = super::get_process_with_ttl(pid, Duration::from_millis(1));
+        assert!(
+            get_cached_time().unwrap() > cached_time,
+            "fresh lookup must occur after the ttl expired"
+        );
+    }
+
     #[test]
     fn entry_to_claims() {
         let mut entry = AuditEntry::empty();
@@ -292,7 +769,11 @@ mod tests {
         entry.destination_port = 80;
         entry.is_admin = 1;
 
-        let claims = Claims::from_audit_entry(&entry, IpAddr::from([127, 0, 0, 1]));
+        let claims = Claims::from_audit_entry(
+            &entry,
+            IpAddr::from([127, 0, 0, 1]),
+            std::time::SystemTime::now(),
+        );
         println!("{}", serde_json::to_string(&claims).unwrap());
 
         assert!(claims.runAsElevated, "runAsElevated must be true");
@@ -316,5 +797,117 @@ mod tests {
             claims.processCmdLine,
             "processCmdLine cannot be empty."
         );
+
+        // the process integrity is collected with the config only, and the older claims still parse
+        assert_eq!(None, claims.processExeSha256);
+        assert_eq!(None, claims.processSigner);
+        let json = serde_json::to_string(&claims).unwrap();
+        assert!(!json.contains("processExeSha256"));
+        let claims: Claims = serde_json::from_str(&json).unwrap();
+        assert_eq!(None, claims.processExeSha256);
+
+        // the hash of the binary is cached by its path
+        let sha256 = super::get_process_exe_sha256(claims.processId, &claims.processFullPath)
+            .expect("the binary of the current process must be hashed");
+        assert_eq!(64, sha256.len());
+        assert_eq!(
+            Some(sha256),
+            super::EXE_SHA256S
+                .lock()
+                .unwrap()
+                .get(&claims.processFullPath)
+                .map(|(_, sha256)| sha256.to_string())
+        );
+    }
+
+    #[test]
+    fn pid_reuse_test() {
+        let pid = std::process::id();
+        let start_time = super::get_process_start_time(pid).expect("start time must be readable");
+        assert!(start_time <= std::time::SystemTime::now());
+
+        #[cfg(not(windows))]
+        assert_eq!(
+            Some(42),
+            super::parse_process_start_ticks(
+                "1 (a) (b) S 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 42 0"
+            )
+        );
+
+        // the connection was accepted before the process with the pid started
+        let mut entry = AuditEntry::empty();
+        entry.process_id = pid;
+        let connected_at = start_time - Duration::from_secs(60);
+        let claims = Claims::from_audit_entry(&entry, IpAddr::from([127, 0, 0, 1]), connected_at);
+        assert_eq!(super::UNDEFINED, claims.processFullPath);
+        assert_eq!(super::UNDEFINED, claims.processCmdLine);
+    }
+
+    #[test]
+    fn loopback_client_elevated_test() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let elevated = super::is_loopback_client_elevated(&stream).unwrap();
+
+        #[cfg(not(windows))]
+        {
+            assert_eq!(unsafe { libc::geteuid() } == 0, elevated);
+
+            let tcp_table = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 0100007F:0C08 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 100 1 0 100 0 0 10 0
+   1: 0100007F:D431 0100007F:0C08 01 00000000:00000000 00:00000000 00000000  1000        0 101 1 0 20 4 30 10 -1";
+            let client = "127.0.0.1:54321".parse().unwrap();
+            let listener = "127.0.0.1:3080".parse().unwrap();
+            assert_eq!(
+                Some(1000),
+                super::find_tcp_socket_uid(tcp_table, client, listener)
+            );
+            assert_eq!(
+                None,
+                super::find_tcp_socket_uid(tcp_table, listener, client)
+            );
+        }
+        #[cfg(windows)]
+        let _ = elevated;
+    }
+
+    #[test]
+    fn is_system_account_test() {
+        let mut claims = Claims::empty();
+        #[cfg(not(windows))]
+        {
+            claims.userId = 0; // root
+            assert!(claims.is_system_account());
+            claims.userId = 999; // the last system uid
+            assert!(claims.is_system_account());
+            claims.userId = 1000; // the first regular user
+            assert!(!claims.is_system_account());
+        }
+        #[cfg(windows)]
+        {
+            for logon_id in [0x3e7, 0x3e4, 0x3e5] {
+                claims.userId = logon_id;
+                assert!(claims.is_system_account());
+            }
+            claims.userId = 0x5c2f1; // an interactive logon session
+            assert!(!claims.is_system_account());
+        }
+    }
+
+    #[test]
+    fn host_claims_test() {
+        let mut claims = Claims::empty();
+        claims.runAsElevated = true;
+        let value = HostClaims::from_claims(&claims).to_header_value().unwrap();
+        assert_eq!(r#"{"isRoot":"true"}"#, value);
+        assert!(headers::is_valid_header_value(&value));
+
+        assert!(!headers::is_valid_header_value(
+            "{\"userName\":\"a\r\nx-injected: 1\"}"
+        ));
+        assert!(!headers::is_valid_header_value(
+            "{\"userName\":\"\u{00e9}\"}"
+        ));
     }
 }
@@ -1,17 +1,148 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
-use crate::key_keeper::key::{AuthorizationItem, Identity, Privilege};
+use crate::common::{config, logger};
+use crate::key_keeper::key::{self, AuthorizationItem, Identity, PathMatcher, Privilege};
 use proxy_agent_shared::misc_helpers;
 use serde_derive::{Deserialize, Serialize};
 
 use super::{proxy_connection::Connection, Claims};
 
+pub const ALLOW_ACCESS: &str = key::ALLOW_ACCESS;
+pub const DENY_ACCESS: &str = key::DENY_ACCESS;
+pub const AUDIT_MODE: &str = "audit";
+
 #[derive(Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Rule {
     pub roleName: String,
     pub privileges: Vec<Privilege>,
     pub identities: Vec<Identity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notBefore: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notAfter: Option<String>,
+    // allow or deny the matched identities, the deny rules take precedence over the allow rules of the same priority
+    pub access: String,
+    // the rules are evaluated from the lowest priority value, None is 0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    // compiled from the privileges in the same order when the rules are loaded
+    #[serde(skip)]
+    pathMatchers: Vec<PathMatcher>,
+}
+
+impl Rule {
+    fn compile(&mut self, case_insensitive: bool) {
+        self.pathMatchers = self
+            .privileges
+            .iter()
+            .map(
+                |privilege| match privilege.compile_path_matcher(case_insensitive) {
+                    Ok(path_matcher) => path_matcher,
+                    Err(e) => {
+                        logger::write_warning(format!("Rule '{}': {}", self.roleName, e));
+                        PathMatcher::Invalid
+                    }
+                },
+            )
+            .collect();
+    }
+
+    pub fn get_priority(&self) -> i32 {
+        self.priority.unwrap_or(0)
+    }
+
+    pub fn is_deny(&self) -> bool {
+        self.access == DENY_ACCESS
+    }
+
+    // check the rule time window against the current time in unix nanoseconds,
+    // a rule with invalid time window never matches
+    pub fn is_active(&self, connection_id: u128, now: i128) -> bool {
+        if let Some(not_before) = &self.notBefore {
+            match misc_helpers::parse_date_time_rfc3339_unix_nano(not_before) {
+                Ok(not_before_time) => {
+                    if now < not_before_time {
+                        Connection::write_information(
+                            connection_id,
+                            format!(
+                                "Rule '{}' is skipped as it is not active before {}.",
+                                self.roleName, not_before
+                            ),
+                        );
+                        return false;
+                    }
+                }
+                Err(e) => {
+                    Connection::write_warning(
+                        connection_id,
+                        format!("Rule '{}' is skipped: {}", self.roleName, e),
+                    );
+                    return false;
+                }
+            }
+        }
+
+        if let Some(not_after) = &self.notAfter {
+            match misc_helpers::parse_date_time_rfc3339_unix_nano(not_after) {
+                Ok(not_after_time) => {
+                    if now > not_after_time {
+                        Connection::write_information(
+                            connection_id,
+                            format!(
+                                "Rule '{}' is skipped as it expired at {}.",
+                                self.roleName, not_after
+                            ),
+                        );
+                        return false;
+                    }
+                }
+                Err(e) => {
+                    Connection::write_warning(
+                        connection_id,
+                        format!("Rule '{}' is skipped: {}", self.roleName, e),
+                    );
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+// the result of the authorization rules evaluation, explains why the request is allowed or denied
+#[derive(Serialize, Deserialize, Clone)]
+#[allow(non_snake_case)]
+pub struct AuthorizationDecision {
+    pub allowed: bool,
+    // the rule decided the access, None if it falls back to the default access
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ruleName: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privilegeName: Option<String>,
+    pub reason: String,
+    // the identities evaluated for the matched privileges, and why each one did not match
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub evaluations: Vec<String>,
+}
+
+impl AuthorizationDecision {
+    fn new(allowed: bool, reason: &str) -> Self {
+        AuthorizationDecision {
+            allowed,
+            ruleName: None,
+            privilegeName: None,
+            reason: reason.to_string(),
+            evaluations: Vec::new(),
+        }
+    }
+
+    fn with_rule(mut self, rule_name: &str, privilege_name: &str) -> Self {
+        self.ruleName = Some(rule_name.to_string());
+        self.privilegeName = Some(privilege_name.to_string());
+        self
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,7 +152,12 @@ pub struct AuthorizationRules {
     pub defaultAllowed: bool,
     // disabled, audit, enforce
     pub mode: String,
+    // the id of the authorization item the rules are built from
+    #[serde(default)]
+    pub id: String,
     pub rules: Option<Vec<Rule>>,
+    // compare the privilege paths and the identity names case-insensitively
+    pub caseInsensitive: bool,
 }
 
 #[allow(dead_code)]
@@ -30,7 +166,9 @@ impl AuthorizationRules {
         AuthorizationRules {
             defaultAllowed: false,
             mode: "disabled".to_string(),
+            id: String::new(),
             rules: None,
+            caseInsensitive: config::get_case_insensitive_match(),
         }
     }
 
@@ -41,6 +179,22 @@ impl AuthorizationRules {
                     let mut rules = Vec::new();
                     for role_assignment in role_assignments {
                         let role_name = role_assignment.role.to_string();
+                        let not_before = role_assignment.notBefore.clone();
+                        let not_after = role_assignment.notAfter.clone();
+                        let priority = role_assignment.priority;
+                        let mut access = role_assignment
+                            .access
+                            .as_deref()
+                            .unwrap_or(ALLOW_ACCESS)
+                            .to_lowercase();
+                        if access != ALLOW_ACCESS && access != DENY_ACCESS {
+                            // rejected by the key status validation, the rules built from an unvalidated item fail closed
+                            logger::write_warning(format!(
+                                "Role assignment of role '{}' has unknown access '{}', it is treated as {}.",
+                                role_name, access, DENY_ACCESS
+                            ));
+                            access = DENY_ACCESS.to_string();
+                        }
 
                         let mut privileges = Vec::new();
                         match &access_control_rules.privileges {
@@ -82,6 +236,11 @@ impl AuthorizationRules {
                             roleName: role_name,
                             privileges: privileges,
                             identities: identities,
+                            notBefore: not_before,
+                            notAfter: not_after,
+                            access: access,
+                            priority: priority,
+                            pathMatchers: Vec::new(),
                         });
                     }
                     Some(rules)
@@ -91,67 +250,206 @@ impl AuthorizationRules {
             None => None,
         };
 
-        AuthorizationRules {
+        let mut authorization_rules = AuthorizationRules {
             defaultAllowed: authorization_item.defaultAccess.to_lowercase() == "allow",
             mode: authorization_item.mode.to_lowercase(),
+            id: authorization_item.id,
             rules: rules,
+            caseInsensitive: config::get_case_insensitive_match(),
+        };
+        authorization_rules.compile();
+        authorization_rules
+    }
+
+    // compile the privilege path matchers once, they are reused by all the requests,
+    // and order the rules by priority, the rules of the same priority keep their order in the authorization item
+    pub fn compile(&mut self) {
+        let case_insensitive = self.caseInsensitive;
+        if let Some(rules) = &mut self.rules {
+            rules.sort_by_key(|rule| rule.get_priority());
+            for rule in rules {
+                rule.compile(case_insensitive);
+            }
         }
     }
 
     pub fn clone(&self) -> AuthorizationRules {
         match misc_helpers::json_clone(self) {
-            Ok(rules) => rules,
+            Ok(mut rules) => {
+                rules.compile();
+                rules
+            }
             Err(_) => AuthorizationRules::new(),
         }
     }
 
     pub fn is_allowed(&self, connection_id: u128, request_url: String, claims: Claims) -> bool {
+        self.explain(connection_id, request_url, claims).allowed
+    }
+
+    // evaluate the rules at the given time in unix nanoseconds
+    pub fn is_allowed_at(
+        &self,
+        connection_id: u128,
+        request_url: String,
+        claims: Claims,
+        now: i128,
+    ) -> bool {
+        self.explain_at(connection_id, request_url, claims, now)
+            .allowed
+    }
+
+    pub fn is_audit_mode(&self) -> bool {
+        self.mode.to_lowercase() == AUDIT_MODE
+    }
+
+    // evaluate the rules and explain the decision, it is returned as is in any mode,
+    // the caller decides whether to enforce it
+    pub fn explain(
+        &self,
+        connection_id: u128,
+        request_url: String,
+        claims: Claims,
+    ) -> AuthorizationDecision {
+        self.explain_at(
+            connection_id,
+            request_url,
+            claims,
+            misc_helpers::get_date_time_unix_nano(),
+        )
+    }
+
+    pub fn explain_at(
+        &self,
+        connection_id: u128,
+        request_url: String,
+        claims: Claims,
+        now: i128,
+    ) -> AuthorizationDecision {
         if self.mode.to_lowercase() == "disabled" {
-            return true;
+            return AuthorizationDecision::new(true, "Authorization is disabled.");
         }
 
-        let url = request_url.to_lowercase();
-        let url = match url::Url::parse(&url) {
+        let url = match url::Url::parse(&request_url) {
             Ok(u) => u,
             Err(_) => {
                 Connection::write_error(
                     connection_id,
                     format!("Failed to parse the request url: {}", request_url),
                 );
-                return false;
+                return AuthorizationDecision::new(false, "Invalid request url.");
             }
         };
 
+        // normalize the path once, so the encoded or dot segments cannot bypass any path matcher
+        let request_path = key::normalize_request_path(url.path());
+
+        /*
+        The rules are evaluated in priority order, from the lowest value, see compile.
+        The first priority with a matched identity decides the access:
+        a matched deny rule of that priority denies the request, otherwise the first matched allow rule allows it.
+        Without priorities all the rules are of priority 0, so any matched deny rule takes precedence.
+         */
+        let mut evaluations = Vec::new();
         if let Some(rules) = &self.rules {
             let mut role_privilege_matched = false;
+            let mut allowed_by: Option<(&str, &str)> = None;
+            let mut allowed_by_priority = None;
             for rule in rules {
+                // the allow rule of a higher priority decides before the lower priority deny rules are evaluated
+                if let Some((role_name, privilege_name)) = allowed_by {
+                    if allowed_by_priority != Some(rule.get_priority()) {
+                        return Self::allowed_by(
+                            connection_id,
+                            role_name,
+                            privilege_name,
+                            evaluations,
+                        );
+                    }
+                }
+                if !rule.is_active(connection_id, now) {
+                    evaluations.push(format!("Rule '{}' is not active.", rule.roleName));
+                    continue;
+                }
+
                 // is privilege match
-                for privilege in &rule.privileges {
-                    if privilege.is_match(connection_id, url.clone()) {
-                        role_privilege_matched = true;
+                for (privilege, path_matcher) in rule.privileges.iter().zip(&rule.pathMatchers) {
+                    if privilege.is_compiled_match(
+                        connection_id,
+                        &url,
+                        &request_path,
+                        path_matcher,
+                        self.caseInsensitive,
+                    ) {
+                        // a deny rule does not apply to the identities it does not match
+                        if !rule.is_deny() {
+                            role_privilege_matched = true;
+                        }
                         for identity in &rule.identities {
-                            if identity.is_match(connection_id, claims.clone()) {
-                                return true;
+                            if let Some(mismatch) =
+                                identity.get_mismatch(&claims, self.caseInsensitive)
+                            {
+                                Connection::write_information(connection_id, mismatch.to_string());
+                                evaluations.push(format!(
+                                    "Rule '{}' privilege '{}': {}",
+                                    rule.roleName, privilege.name, mismatch
+                                ));
+                                continue;
+                            }
+
+                            if rule.is_deny() {
+                                let reason = format!(
+                                    "Denied by the deny rule '{}' with identity '{}'.",
+                                    rule.roleName, identity.name
+                                );
+                                Connection::write_warning(connection_id, reason.to_string());
+                                let mut decision = AuthorizationDecision::new(false, &reason)
+                                    .with_rule(&rule.roleName, &privilege.name);
+                                decision.evaluations = evaluations;
+                                return decision;
+                            }
+                            // keep evaluating the other rules of the same priority as a deny rule could still match
+                            if allowed_by.is_none() {
+                                allowed_by = Some((&rule.roleName, &privilege.name));
+                                allowed_by_priority = Some(rule.get_priority());
                             }
                         }
                     }
                 }
             }
 
+            if let Some((role_name, privilege_name)) = allowed_by {
+                return Self::allowed_by(connection_id, role_name, privilege_name, evaluations);
+            }
+
             if role_privilege_matched {
-                Connection::write_information(
-                    connection_id,
-                    "Privilege matched once, but no identity matches.".to_string(),
-                );
-                return false;
+                let reason = "Privilege matched once, but no identity matches.";
+                Connection::write_information(connection_id, reason.to_string());
+                let mut decision = AuthorizationDecision::new(false, reason);
+                decision.evaluations = evaluations;
+                return decision;
             }
         }
 
-        Connection::write_information(
-            connection_id,
-            "No privilege matched, fall back to default access.".to_string(),
-        );
-        self.defaultAllowed
+        let reason = "No privilege matched, fall back to default access.";
+        Connection::write_information(connection_id, reason.to_string());
+        let mut decision = AuthorizationDecision::new(self.defaultAllowed, reason);
+        decision.evaluations = evaluations;
+        decision
+    }
+
+    fn allowed_by(
+        connection_id: u128,
+        role_name: &str,
+        privilege_name: &str,
+        evaluations: Vec<String>,
+    ) -> AuthorizationDecision {
+        let reason = format!("Allowed by the rule '{}'.", role_name);
+        Connection::write_information(connection_id, reason.to_string());
+        let mut decision =
+            AuthorizationDecision::new(true, &reason).with_rule(role_name, privilege_name);
+        decision.evaluations = evaluations;
+        decision
     }
 }
 
@@ -180,17 +478,25 @@ mod tests {
                 name: "test".to_string(),
                 path: "/test".to_string(),
                 queryParameters: None,
+                matchType: None,
             }]),
             identities: Some(vec![Identity {
                 name: "test".to_string(),
                 exePath: Some("test".to_string()),
                 groupName: Some("test".to_string()),
                 processName: Some("test".to_string()),
+                clientIpCidr: None,
+                exeSha256: None,
                 userName: Some("test".to_string()),
+                userId: None,
             }]),
             roleAssignments: Some(vec![RoleAssignment {
                 role: "test".to_string(),
                 identities: vec!["test".to_string()],
+                notBefore: None,
+                notAfter: None,
+                access: None,
+                priority: None,
             }]),
         };
         let authorization_item: AuthorizationItem = AuthorizationItem {
@@ -200,7 +506,17 @@ mod tests {
             id: "0".to_string(),
         };
         let rules = AuthorizationRules::from_authorization_item(authorization_item);
-        let _clone_rules = rules.clone();
+        let clone_rules = rules.clone();
+        #[cfg(windows)]
+        assert!(
+            rules.caseInsensitive,
+            "match must be case-insensitive on Windows"
+        );
+        #[cfg(not(windows))]
+        assert!(
+            !rules.caseInsensitive,
+            "match must be case-sensitive on Linux"
+        );
         assert_eq!(rules.defaultAllowed, false);
         assert_eq!(rules.mode, "enforce");
         assert_eq!(rules.rules.is_some(), true);
@@ -214,11 +530,18 @@ mod tests {
             clientIp: "0".to_string(),
             processName: "test".to_string(),
             processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
             runAsElevated: true,
         };
         // assert the claim is allowed given the rules above
         let url = url::Url::parse("http://localhost/test?").unwrap();
         assert_eq!(rules.is_allowed(0, url.to_string(), claims.clone()), true);
+        // the cloned rules are compiled again
+        assert_eq!(
+            clone_rules.is_allowed(0, url.to_string(), claims.clone()),
+            true
+        );
         claims.userName = "test1".to_string();
         assert_eq!(rules.is_allowed(0, url.to_string(), claims.clone()), false);
 
@@ -232,17 +555,25 @@ mod tests {
                 name: "test".to_string(),
                 path: "/test".to_string(),
                 queryParameters: None,
+                matchType: None,
             }]),
             identities: Some(vec![Identity {
                 name: "test".to_string(),
                 exePath: Some("test".to_string()),
                 groupName: Some("test".to_string()),
                 processName: Some("test".to_string()),
+                clientIpCidr: None,
+                exeSha256: None,
                 userName: Some("test".to_string()),
+                userId: None,
             }]),
             roleAssignments: Some(vec![RoleAssignment {
                 role: "test".to_string(),
                 identities: vec!["test".to_string()],
+                notBefore: None,
+                notAfter: None,
+                access: None,
+                priority: None,
             }]),
         };
         let authorization_item: AuthorizationItem = AuthorizationItem {
@@ -266,17 +597,25 @@ mod tests {
                 name: "test".to_string(),
                 path: "/test".to_string(),
                 queryParameters: None,
+                matchType: None,
             }]),
             identities: Some(vec![Identity {
                 name: "test".to_string(),
                 exePath: Some("test".to_string()),
                 groupName: Some("test".to_string()),
                 processName: Some("test".to_string()),
+                clientIpCidr: None,
+                exeSha256: None,
                 userName: Some("test".to_string()),
+                userId: None,
             }]),
             roleAssignments: Some(vec![RoleAssignment {
                 role: "test".to_string(),
                 identities: vec!["test".to_string()],
+                notBefore: None,
+                notAfter: None,
+                access: None,
+                priority: None,
             }]),
         };
         let authorization_item: AuthorizationItem = AuthorizationItem {
@@ -303,17 +642,25 @@ mod tests {
                 name: "test".to_string(),
                 path: "/test".to_string(),
                 queryParameters: None,
+                matchType: None,
             }]),
             identities: Some(vec![Identity {
                 name: "test1".to_string(),
                 exePath: Some("test".to_string()),
                 groupName: Some("test".to_string()),
                 processName: Some("test".to_string()),
+                clientIpCidr: None,
+                exeSha256: None,
                 userName: Some("test".to_string()),
+                userId: None,
             }]),
             roleAssignments: Some(vec![RoleAssignment {
                 role: "test".to_string(),
                 identities: vec!["test1".to_string()],
+                notBefore: None,
+                notAfter: None,
+                access: None,
+                priority: None,
             }]),
         };
         let authorization_item: AuthorizationItem = AuthorizationItem {
@@ -330,4 +677,514 @@ mod tests {
         let url = url::Url::parse("http://localhost/test?").unwrap();
         assert_eq!(rules.is_allowed(0, url.to_string(), claims.clone()), false);
     }
+
+    #[test]
+    fn test_authorization_rules_deny() {
+        let logger_key = "test_authorization_rules_deny";
+        let mut temp_test_path = std::env::temp_dir();
+        temp_test_path.push(logger_key);
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let create_identity =
+            |name: &str, user_name: Option<&str>, group_name: Option<&str>| Identity {
+                name: name.to_string(),
+                exePath: None,
+                groupName: group_name.map(|s| s.to_string()),
+                processName: None,
+                clientIpCidr: None,
+                exeSha256: None,
+                userName: user_name.map(|s| s.to_string()),
+                userId: None,
+            };
+        let access_control_rules = AccessControlRules {
+            roles: Some(vec![
+                Role {
+                    name: "reader".to_string(),
+                    privileges: vec!["test".to_string()],
+                },
+                Role {
+                    name: "blocked".to_string(),
+                    privileges: vec!["test".to_string(), "deny".to_string()],
+                },
+            ]),
+            privileges: Some(vec![
+                Privilege {
+                    name: "test".to_string(),
+                    path: "/test".to_string(),
+                    queryParameters: None,
+                    matchType: None,
+                },
+                Privilege {
+                    name: "deny".to_string(),
+                    path: "/deny".to_string(),
+                    queryParameters: None,
+                    matchType: None,
+                },
+            ]),
+            identities: Some(vec![
+                create_identity("test", Some("test"), None),
+                create_identity("admins", None, Some("admins")),
+            ]),
+            roleAssignments: Some(vec![
+                RoleAssignment {
+                    role: "reader".to_string(),
+                    identities: vec!["test".to_string()],
+                    notBefore: None,
+                    notAfter: None,
+                    access: None,
+                    priority: None,
+                },
+                RoleAssignment {
+                    role: "blocked".to_string(),
+                    identities: vec!["admins".to_string()],
+                    notBefore: None,
+                    notAfter: None,
+                    access: Some("Deny".to_string()),
+                    priority: None,
+                },
+            ]),
+        };
+        let rules = AuthorizationRules::from_authorization_item(AuthorizationItem {
+            defaultAccess: "allow".to_string(),
+            mode: "enforce".to_string(),
+            rules: Some(access_control_rules),
+            id: "0".to_string(),
+        });
+        let rule_access: Vec<&str> = rules
+            .rules
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|rule| rule.access.as_str())
+            .collect();
+        assert_eq!(vec![super::ALLOW_ACCESS, super::DENY_ACCESS], rule_access);
+
+        let create_claims = |user_name: &str, groups: Vec<&str>| Claims {
+            userId: 0,
+            userName: user_name.to_string(),
+            userGroups: groups.iter().map(|g| g.to_string()).collect(),
+            processId: 0,
+            processFullPath: "test".to_string(),
+            clientIp: "0".to_string(),
+            processName: "test".to_string(),
+            processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
+            runAsElevated: false,
+        };
+        let url = "http://localhost/test?".to_string();
+        assert!(rules.is_allowed(0, url.clone(), create_claims("test", vec![])));
+        assert!(
+            !rules.is_allowed(0, url.clone(), create_claims("test", vec!["admins"])),
+            "deny rule takes precedence over the allow rule"
+        );
+        assert!(
+            !rules.is_allowed(0, url.clone(), create_claims("other", vec!["admins"])),
+            "deny rule overrides the default allow access"
+        );
+
+        let url = "http://localhost/deny?".to_string();
+        assert!(!rules.is_allowed(0, url.clone(), create_claims("other", vec!["admins"])));
+        assert!(
+            rules.is_allowed(0, url.clone(), create_claims("other", vec![])),
+            "unmatched deny rule falls back to the default access"
+        );
+
+        // explain the decisions
+        let url = "http://localhost/test?".to_string();
+        let decision = rules.explain(0, url.clone(), create_claims("test", vec!["admins"]));
+        assert!(!decision.allowed);
+        assert_eq!(Some("blocked".to_string()), decision.ruleName);
+        assert_eq!(Some("test".to_string()), decision.privilegeName);
+
+        let decision = rules.explain(0, url.clone(), create_claims("test", vec![]));
+        assert!(decision.allowed);
+        assert_eq!(Some("reader".to_string()), decision.ruleName);
+        assert_eq!(
+            vec!["Rule 'blocked' privilege 'test': Not matched user group name 'admins' from identity 'admins'"],
+            decision.evaluations,
+            "the unmatched identities must be explained"
+        );
+
+        let decision = rules.explain(0, url.clone(), create_claims("other", vec![]));
+        assert!(
+            !decision.allowed,
+            "privilege matched but no identity matches"
+        );
+        assert_eq!(None, decision.ruleName);
+        assert_eq!(
+            2,
+            decision.evaluations.len(),
+            "both rules evaluated the identities"
+        );
+    }
+
+    #[test]
+    fn test_authorization_rules_deny_bypass() {
+        let logger_key = "test_authorization_rules_deny_bypass";
+        let mut temp_test_path = std::env::temp_dir();
+        temp_test_path.push(logger_key);
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let claims = Claims {
+            userId: 0,
+            userName: "test".to_string(),
+            userGroups: vec![],
+            processId: 0,
+            processFullPath: "test".to_string(),
+            clientIp: "0".to_string(),
+            processName: "test".to_string(),
+            processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
+            runAsElevated: false,
+        };
+        for (path, match_type) in [
+            ("/machine", Some("exact")),
+            ("/machine", Some("prefix")),
+            ("/machine", None),
+            ("/machine", Some("glob")),
+            ("/mach.*", Some("regex")),
+        ] {
+            let access_control_rules = AccessControlRules {
+                roles: Some(vec![Role {
+                    name: "blocked".to_string(),
+                    privileges: vec!["machine".to_string()],
+                }]),
+                privileges: Some(vec![Privilege {
+                    name: "machine".to_string(),
+                    path: path.to_string(),
+                    queryParameters: None,
+                    matchType: match_type.map(|s| s.to_string()),
+                }]),
+                identities: Some(vec![Identity {
+                    name: "test".to_string(),
+                    exePath: None,
+                    groupName: None,
+                    processName: None,
+                    clientIpCidr: None,
+                    exeSha256: None,
+                    userName: Some("test".to_string()),
+                    userId: None,
+                }]),
+                roleAssignments: Some(vec![RoleAssignment {
+                    role: "blocked".to_string(),
+                    identities: vec!["test".to_string()],
+                    notBefore: None,
+                    notAfter: None,
+                    access: Some("Deny".to_string()),
+                    priority: None,
+                }]),
+            };
+            let rules = AuthorizationRules::from_authorization_item(AuthorizationItem {
+                defaultAccess: "allow".to_string(),
+                mode: "enforce".to_string(),
+                rules: Some(access_control_rules),
+                id: "0".to_string(),
+            });
+
+            // the host decodes all these paths to '/machine'
+            for url in [
+                "http://localhost/machine",
+                "http://localhost/%6Dachine",
+                "http://localhost/%6d%61chine",
+                "http://localhost/./machine",
+                "http://localhost/%2E/machine",
+                "http://localhost/other/../machine",
+                "http://localhost//machine",
+            ] {
+                assert!(
+                    !rules.is_allowed(0, url.to_string(), claims.clone()),
+                    "deny rule '{}' ({:?}) must not be bypassed by '{}'",
+                    path,
+                    match_type,
+                    url
+                );
+            }
+            assert!(
+                rules.is_allowed(0, "http://localhost/other".to_string(), claims.clone()),
+                "unmatched path falls back to the default access"
+            );
+            assert!(
+                rules.is_allowed(0, "http://localhost/ma%2Fchine".to_string(), claims.clone()),
+                "encoded slash is not decoded"
+            );
+        }
+    }
+
+    #[test]
+    fn test_authorization_rules_unknown_access() {
+        let logger_key = "test_authorization_rules_unknown_access";
+        let mut temp_test_path = std::env::temp_dir();
+        temp_test_path.push(logger_key);
+        proxy_agent_shared::logger_manager::init_logger(
+            crate::common::logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let access_control_rules = AccessControlRules {
+            roles: Some(vec![Role {
+                name: "blocked".to_string(),
+                privileges: vec!["test".to_string()],
+            }]),
+            privileges: Some(vec![Privilege {
+                name: "test".to_string(),
+                path: "/test".to_string(),
+                queryParameters: None,
+                matchType: None,
+            }]),
+            identities: Some(vec![Identity {
+                name: "test".to_string(),
+                exePath: None,
+                groupName: None,
+                processName: None,
+                clientIpCidr: None,
+                exeSha256: None,
+                userName: Some("test".to_string()),
+                userId: None,
+            }]),
+            roleAssignments: Some(vec![RoleAssignment {
+                role: "blocked".to_string(),
+                identities: vec!["test".to_string()],
+                notBefore: None,
+                notAfter: None,
+                access: Some("Denied".to_string()),
+                priority: None,
+            }]),
+        };
+        let rules = AuthorizationRules::from_authorization_item(AuthorizationItem {
+            defaultAccess: "allow".to_string(),
+            mode: "enforce".to_string(),
+            rules: Some(access_control_rules),
+            id: "0".to_string(),
+        });
+
+        // the unknown access fails closed instead of allowing the identities
+        let rule = &rules.rules.as_ref().unwrap()[0];
+        assert!(rule.is_deny(), "unknown access must be treated as deny");
+        let mut claims = Claims::empty();
+        claims.userName = "test".to_string();
+        assert!(!rules.is_allowed(0, "http://localhost/test".to_string(), claims));
+
+        _ = std::fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn test_authorization_rules_time_window() {
+        let logger_key = "test_authorization_rules_time_window";
+        let mut temp_test_path = std::env::temp_dir();
+        temp_test_path.push(logger_key);
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let create_rules = |not_before: Option<&str>, not_after: Option<&str>| {
+            let access_control_rules = AccessControlRules {
+                roles: Some(vec![Role {
+                    name: "test".to_string(),
+                    privileges: vec!["test".to_string()],
+                }]),
+                privileges: Some(vec![Privilege {
+                    name: "test".to_string(),
+                    path: "/test".to_string(),
+                    queryParameters: None,
+                    matchType: None,
+                }]),
+                identities: Some(vec![Identity {
+                    name: "test".to_string(),
+                    exePath: None,
+                    groupName: None,
+                    processName: None,
+                    clientIpCidr: None,
+                    exeSha256: None,
+                    userName: Some("test".to_string()),
+                    userId: None,
+                }]),
+                roleAssignments: Some(vec![RoleAssignment {
+                    role: "test".to_string(),
+                    identities: vec!["test".to_string()],
+                    notBefore: not_before.map(|s| s.to_string()),
+                    notAfter: not_after.map(|s| s.to_string()),
+                    access: None,
+                    priority: None,
+                }]),
+            };
+            AuthorizationRules::from_authorization_item(AuthorizationItem {
+                defaultAccess: "deny".to_string(),
+                mode: "enforce".to_string(),
+                rules: Some(access_control_rules),
+                id: "0".to_string(),
+            })
+        };
+
+        let claims = Claims {
+            userId: 0,
+            userName: "test".to_string(),
+            userGroups: vec!["test".to_string()],
+            processId: 0,
+            processFullPath: "test".to_string(),
+            clientIp: "0".to_string(),
+            processName: "test".to_string(),
+            processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
+            runAsElevated: true,
+        };
+        let url = "http://localhost/test?".to_string();
+        // 2024-06-01T00:00:00Z
+        let now: i128 = 1_717_200_000 * 1_000_000_000;
+
+        // active window
+        let rules = create_rules(Some("2024-01-01T00:00:00Z"), Some("2025-01-01T00:00:00Z"));
+        assert!(rules.is_allowed_at(0, url.clone(), claims.clone(), now));
+
+        // expired window
+        let rules = create_rules(Some("2024-01-01T00:00:00Z"), Some("2024-05-31T23:59:59Z"));
+        assert!(
+            !rules.is_allowed_at(0, url.clone(), claims.clone(), now),
+            "expired rule must not match"
+        );
+
+        // future window
+        let rules = create_rules(Some("2024-06-01T00:00:01+00:00"), None);
+        assert!(
+            !rules.is_allowed_at(0, url.clone(), claims.clone(), now),
+            "not yet active rule must not match"
+        );
+
+        // open ended windows
+        let rules = create_rules(None, Some("2024-06-01T08:00:00+08:00"));
+        assert!(rules.is_allowed_at(0, url.clone(), claims.clone(), now));
+        let rules = create_rules(None, None);
+        assert!(rules.is_allowed_at(0, url.clone(), claims.clone(), now));
+
+        // invalid window never matches
+        let rules = create_rules(Some("not a date"), None);
+        assert!(!rules.is_allowed_at(0, url.clone(), claims.clone(), now));
+    }
+
+    #[test]
+    fn test_authorization_rules_priority() {
+        let logger_key = "test_authorization_rules_priority";
+        let mut temp_test_path = std::env::temp_dir();
+        temp_test_path.push(logger_key);
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let create_identity =
+            |name: &str, user_name: Option<&str>, group_name: Option<&str>| Identity {
+                name: name.to_string(),
+                exePath: None,
+                groupName: group_name.map(|s| s.to_string()),
+                processName: None,
+                clientIpCidr: None,
+                exeSha256: None,
+                userName: user_name.map(|s| s.to_string()),
+                userId: None,
+            };
+        let create_assignment =
+            |role: &str, identity: &str, access: &str, priority: Option<i32>| RoleAssignment {
+                role: role.to_string(),
+                identities: vec![identity.to_string()],
+                notBefore: None,
+                notAfter: None,
+                access: Some(access.to_string()),
+                priority,
+            };
+        let create_role = |name: &str| Role {
+            name: name.to_string(),
+            privileges: vec!["test".to_string()],
+        };
+        let access_control_rules = AccessControlRules {
+            roles: Some(vec![
+                create_role("reader"),
+                create_role("blocked"),
+                create_role("operators"),
+                create_role("suspended"),
+            ]),
+            privileges: Some(vec![Privilege {
+                name: "test".to_string(),
+                path: "/test".to_string(),
+                queryParameters: None,
+                matchType: None,
+            }]),
+            identities: Some(vec![
+                create_identity("test", Some("test"), None),
+                create_identity("other", Some("other"), None),
+                create_identity("admins", None, Some("admins")),
+            ]),
+            roleAssignments: Some(vec![
+                create_assignment("reader", "other", "allow", Some(5)),
+                create_assignment("blocked", "admins", "deny", None),
+                create_assignment("operators", "test", "allow", Some(-1)),
+                create_assignment("suspended", "other", "deny", Some(5)),
+            ]),
+        };
+        let rules = AuthorizationRules::from_authorization_item(AuthorizationItem {
+            defaultAccess: "allow".to_string(),
+            mode: "enforce".to_string(),
+            rules: Some(access_control_rules),
+            id: "0".to_string(),
+        });
+        let rule_names: Vec<&str> = rules
+            .rules
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|rule| rule.roleName.as_str())
+            .collect();
+        assert_eq!(
+            vec!["operators", "blocked", "reader", "suspended"],
+            rule_names,
+            "the rules are ordered by priority, the equal priorities keep their order"
+        );
+        assert_eq!(
+            rule_names,
+            rules
+                .clone()
+                .rules
+                .unwrap()
+                .iter()
+                .map(|rule| rule.roleName.as_str())
+                .collect::<Vec<&str>>(),
+            "the cloned rules keep the order"
+        );
+
+        let create_claims = |user_name: &str, groups: Vec<&str>| Claims {
+            userId: 0,
+            userName: user_name.to_string(),
+            userGroups: groups.iter().map(|g| g.to_string()).collect(),
+            processId: 0,
+            processFullPath: "test".to_string(),
+            clientIp: "0".to_string(),
+            processName: "test".to_string(),
+            processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
+            runAsElevated: false,
+        };
+        let url = "http://localhost/test?".to_string();
+
+        let decision = rules.explain(0, url.clone(), create_claims("test", vec!["admins"]));
+        assert!(
+            decision.allowed,
+            "the allow rule of a higher priority overrides the deny rule"
+        );
+        assert_eq!(Some("operators".to_string()), decision.ruleName);
+
+        let decision = rules.explain(0, url.clone(), create_claims("other", vec!["admins"]));
+        assert!(
+            !decision.allowed,
+            "the deny rule of a higher priority overrides the allow rule"
+        );
+        assert_eq!(Some("blocked".to_string()), decision.ruleName);
+
+        let decision = rules.explain(0, url.clone(), create_claims("other", vec![]));
+        assert!(
+            !decision.allowed,
+            "the deny rule takes precedence over the allow rule of the same priority"
+        );
+        assert_eq!(Some("suspended".to_string()), decision.ruleName);
+    }
 }
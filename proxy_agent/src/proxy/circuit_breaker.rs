@@ -0,0 +1,247 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "halfOpen",
+        }
+    }
+}
+
+struct Circuit {
+    state: CircuitState,
+    failures: u32,         // consecutive failures in the current window
+    window_start: Instant, // start of the failure counting window
+    state_since: Instant,  // when the circuit is opened or the last probe is let through
+}
+
+/*
+Circuit breaker per upstream destination (ip, port).
+'failure_threshold' consecutive failures within 'window' open the circuit of the destination,
+the requests to an open circuit are rejected for 'cool_down'. After that one probe request is let through
+every 'cool_down' (half open), its success closes the circuit and its failure opens it again.
+Any success resets the consecutive failures, the breaker is disabled if the threshold is 0.
+ */
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    window: Duration,
+    cool_down: Duration,
+    circuits: HashMap<SocketAddr, Circuit>, // only the destinations with failures are tracked
+    opened_count: u64,
+    rejected_count: u64,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, window: Duration, cool_down: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold,
+            window,
+            cool_down,
+            circuits: HashMap::new(),
+            opened_count: 0,
+            rejected_count: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.failure_threshold > 0
+    }
+
+    // returns false if the circuit of the destination is open
+    pub fn try_acquire(&mut self, destination: SocketAddr) -> bool {
+        self.try_acquire_at(destination, Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, destination: SocketAddr, now: Instant) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        let cool_down = self.cool_down;
+        let circuit = match self.circuits.get_mut(&destination) {
+            Some(circuit) => circuit,
+            None => return true,
+        };
+        if circuit.state == CircuitState::Closed {
+            return true;
+        }
+        // let a probe through after the cool down, or again if the last probe has not reported back
+        if now.saturating_duration_since(circuit.state_since) >= cool_down {
+            circuit.state = CircuitState::HalfOpen;
+            circuit.state_since = now;
+            return true;
+        }
+
+        self.rejected_count += 1;
+        false
+    }
+
+    // returns true if the circuit is closed by this success
+    pub fn record_success(&mut self, destination: SocketAddr) -> bool {
+        match self.circuits.remove(&destination) {
+            Some(circuit) => circuit.state != CircuitState::Closed,
+            None => false,
+        }
+    }
+
+    // returns true if the circuit is opened by this failure
+    pub fn record_failure(&mut self, destination: SocketAddr) -> bool {
+        self.record_failure_at(destination, Instant::now())
+    }
+
+    fn record_failure_at(&mut self, destination: SocketAddr, now: Instant) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+
+        let circuit = self.circuits.entry(destination).or_insert(Circuit {
+            state: CircuitState::Closed,
+            failures: 0,
+            window_start: now,
+            state_since: now,
+        });
+        match circuit.state {
+            // a request let through before the circuit is opened
+            CircuitState::Open => return false,
+            CircuitState::HalfOpen => {}
+            CircuitState::Closed => {
+                if now.saturating_duration_since(circuit.window_start) >= self.window {
+                    circuit.failures = 0;
+                    circuit.window_start = now;
+                }
+                circuit.failures += 1;
+                if circuit.failures < self.failure_threshold {
+                    return false;
+                }
+            }
+        }
+
+        circuit.state = CircuitState::Open;
+        circuit.state_since = now;
+        self.opened_count += 1;
+        true
+    }
+
+    pub fn get_state(&self, destination: SocketAddr) -> CircuitState {
+        self.circuits
+            .get(&destination)
+            .map_or(CircuitState::Closed, |circuit| circuit.state)
+    }
+
+    // the number of times the circuits are opened
+    pub fn get_opened_count(&self) -> u64 {
+        self.opened_count
+    }
+
+    // the number of requests rejected by the open circuits
+    pub fn get_rejected_count(&self) -> u64 {
+        self.rejected_count
+    }
+
+    // the tracked destinations with their state and consecutive failures, e.g. '168.63.129.16:80 open (5)'
+    pub fn get_states(&self) -> Vec<String> {
+        let mut states: Vec<String> = self
+            .circuits
+            .iter()
+            .map(|(destination, circuit)| {
+                format!(
+                    "{} {} ({})",
+                    destination,
+                    circuit.state.as_str(),
+                    circuit.failures
+                )
+            })
+            .collect();
+        states.sort();
+        states
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CircuitBreaker, CircuitState};
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn circuit_breaker_test() {
+        let wire_server = "168.63.129.16:80".parse::<SocketAddr>().unwrap();
+        let imds = "169.254.169.254:80".parse::<SocketAddr>().unwrap();
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(10), Duration::from_secs(30));
+        assert!(breaker.is_enabled());
+
+        // the failures out of the window are not consecutive
+        assert!(!breaker.record_failure_at(wire_server, start));
+        assert!(!breaker.record_failure_at(wire_server, start));
+        let later = start + Duration::from_secs(10);
+        assert!(!breaker.record_failure_at(wire_server, later));
+        assert!(!breaker.record_failure_at(wire_server, later));
+        assert!(breaker.try_acquire_at(wire_server, later));
+        assert!(
+            breaker.record_failure_at(wire_server, later),
+            "3 failures within the window"
+        );
+        assert_eq!(CircuitState::Open, breaker.get_state(wire_server));
+        assert_eq!(1, breaker.get_opened_count());
+        assert!(!breaker.try_acquire_at(wire_server, later));
+        assert!(
+            breaker.try_acquire_at(imds, later),
+            "the destinations have their own circuits"
+        );
+        assert_eq!(1, breaker.get_rejected_count());
+        assert_eq!(vec!["168.63.129.16:80 open (3)"], breaker.get_states());
+
+        // a single probe after the cool down, its failure opens the circuit again
+        let probe = later + Duration::from_secs(30);
+        assert!(breaker.try_acquire_at(wire_server, probe));
+        assert_eq!(CircuitState::HalfOpen, breaker.get_state(wire_server));
+        assert!(!breaker.try_acquire_at(wire_server, probe));
+        assert!(breaker.record_failure_at(wire_server, probe));
+        assert_eq!(CircuitState::Open, breaker.get_state(wire_server));
+        assert_eq!(2, breaker.get_opened_count());
+
+        // another probe if the last one has not reported back
+        let probe = probe + Duration::from_secs(30);
+        assert!(breaker.try_acquire_at(wire_server, probe));
+        let probe = probe + Duration::from_secs(30);
+        assert!(breaker.try_acquire_at(wire_server, probe));
+        assert!(
+            breaker.record_success(wire_server),
+            "the probe success closes the circuit"
+        );
+        assert_eq!(CircuitState::Closed, breaker.get_state(wire_server));
+        assert!(breaker.try_acquire_at(wire_server, probe));
+        assert!(breaker.get_states().is_empty());
+
+        // a success resets the consecutive failures
+        assert!(!breaker.record_failure_at(imds, probe));
+        assert!(!breaker.record_failure_at(imds, probe));
+        assert!(!breaker.record_success(imds));
+        assert!(!breaker.record_failure_at(imds, probe));
+        assert_eq!(CircuitState::Closed, breaker.get_state(imds));
+
+        let mut breaker = CircuitBreaker::new(0, Duration::from_secs(10), Duration::from_secs(30));
+        assert!(!breaker.is_enabled());
+        for _ in 0..10 {
+            assert!(!breaker.record_failure_at(wire_server, start));
+            assert!(
+                breaker.try_acquire_at(wire_server, start),
+                "circuit breaker is disabled"
+            );
+        }
+    }
+}
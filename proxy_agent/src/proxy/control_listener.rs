@@ -0,0 +1,264 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+
+/*
+Serve the internal endpoints on a unix domain socket for the local tooling,
+so they can be queried without a TCP port and are never subject to the redirection.
+The control connections are not looked up in the eBPF audit map and only the endpoints
+which do not change the agent state are exposed, the usercache clearing is not.
+The socket file is only accessible by its owner, so the callers are as elevated as the agent itself,
+the proxy listener port rebinding is only served here for that reason.
+Windows has no control socket, the elevated loopback clients of the proxy listener rebind its port instead.
+ */
+use super::proxy_connection::Connection;
+use super::proxy_listener;
+use crate::common::http;
+use crate::common::http::headers;
+use crate::common::http::request::Request;
+use crate::common::http::response::Response;
+use crate::common::{constants, logger};
+use crate::service;
+use once_cell::sync::Lazy;
+use proxy_agent_shared::misc_helpers;
+use std::fs;
+use std::io::prelude::*;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const MAX_CONTROL_REQUEST_BODY_SIZE: usize = 64 * 1024;
+static SHUT_DOWN: AtomicBool = AtomicBool::new(false);
+static SOCKET_PATH: Lazy<Mutex<Option<PathBuf>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn start_async(path: PathBuf) {
+    _ = thread::Builder::new()
+        .name("control_listener".to_string())
+        .spawn(move || {
+            if let Ok(listener) = bind(&path) {
+                serve(listener);
+            }
+        });
+}
+
+fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    let path_string = misc_helpers::path_to_string(path.to_path_buf());
+    logger::write(format!("Start control listener at '{}'.", path_string));
+
+    // remove the socket file left by the last run, never the other files
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            _ = fs::remove_file(path);
+        }
+    }
+    let listener = UnixListener::bind(path)
+        .and_then(|listener| {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+            Ok(listener)
+        })
+        .map_err(|e| {
+            let message = format!(
+                "Failed to bind control listener '{}' with error {}.",
+                path_string, e
+            );
+            logger::write_error(message.to_string());
+            std::io::Error::new(e.kind(), message)
+        })?;
+
+    SHUT_DOWN.store(false, Ordering::Relaxed);
+    *SOCKET_PATH.lock().unwrap() = Some(path.to_path_buf());
+    Ok(listener)
+}
+
+// the control requests are rare and small, they are handled one by one
+fn serve(listener: UnixListener) {
+    for stream in listener.incoming() {
+        if SHUT_DOWN.load(Ordering::Relaxed) {
+            break;
+        }
+        match stream {
+            Ok(stream) => handle_connection(proxy_listener::next_connection_id(), &stream),
+            Err(e) => {
+                logger::write_warning(format!(
+                    "Incoming control connection with error {e}; ignore it."
+                ));
+            }
+        }
+    }
+
+    logger::write("Control listener stopped accepting new request.".to_string());
+}
+
+fn handle_connection(connection_id: u128, mut stream: &UnixStream) {
+    _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+    let request = match http::receive_request_data_from(stream, |_| MAX_CONTROL_REQUEST_BODY_SIZE) {
+        Ok(request) => request,
+        Err(e) => {
+            Connection::write_warning(
+                connection_id,
+                format!("Failed to received control request: {}", e),
+            );
+            return;
+        }
+    };
+
+    let mut response =
+        if request.headers.get_content_length().unwrap_or(0) > MAX_CONTROL_REQUEST_BODY_SIZE {
+            Response::from_status(Response::PAYLOAD_TOO_LARGE.to_string())
+        } else if let Some(response) = get_rebind_response(&request) {
+            response
+        } else {
+            match proxy_listener::get_control_response(connection_id, &request) {
+                Some(response) => response,
+                None => Response::from_status(Response::NOT_FOUND.to_string()),
+            }
+        };
+    if response
+        .headers
+        .get_header(headers::CONTENT_LENGTH_HEADER_NAME)
+        .is_none()
+    {
+        response.headers.add_header(
+            headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
+            "0".to_string(),
+        );
+    }
+    Connection::write(
+        connection_id,
+        format!(
+            "Control request {} responded with '{}'.",
+            request.description(),
+            response.status
+        ),
+    );
+    _ = stream.write_all(&response.to_raw_bytes());
+    _ = stream.flush();
+}
+
+// move the proxy listener to the port in the query, e.g. POST /proxyagent/rebind?port=3081
+// it returns after the listener serves the new port or has failed to, returns None for the other requests
+fn get_rebind_response(request: &Request) -> Option<Response> {
+    let (path, query) = proxy_listener::get_internal_path(request);
+    if path != constants::REBIND_ENDPOINT || request.method.to_uppercase() != "POST" {
+        return None;
+    }
+
+    let mut response = match proxy_listener::get_rebind_port(&query) {
+        Some(port) => match service::rebind_proxy_port(port) {
+            Ok(()) => Response::from_status(Response::OK.to_string()),
+            Err(e) => Response::new(Response::INTERNAL_SERVER_ERROR.to_string(), e.to_string()),
+        },
+        None => Response::new(
+            Response::BAD_REQUEST.to_string(),
+            "The port query is missing or invalid.".to_string(),
+        ),
+    };
+    response.headers.add_header(
+        headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
+        response.get_body_len().to_string(),
+    );
+    Some(response)
+}
+
+pub fn stop() {
+    let path = SOCKET_PATH.lock().unwrap().take();
+    if let Some(path) = path {
+        SHUT_DOWN.store(true, Ordering::Relaxed);
+        // wake up the listener to see the stop signal
+        _ = UnixStream::connect(&path);
+        _ = fs::remove_file(&path);
+        logger::write_warning("Sending stop signal to control listener.".to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::http::response::Response;
+    use std::env;
+    use std::fs;
+    use std::io::prelude::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    fn send_control_request(path: &std::path::Path, raw_request: &str) -> Response {
+        let mut stream = UnixStream::connect(path).unwrap();
+        stream.write_all(raw_request.as_bytes()).unwrap();
+        // the control connection is closed after the response
+        let mut raw_response = String::new();
+        stream.read_to_string(&mut raw_response).unwrap();
+        let response = Response::from_raw_data(raw_response);
+        assert!(response.headers.get_content_length().is_ok());
+        response
+    }
+
+    #[test]
+    fn control_listener_test() {
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push("control_listener_test");
+        _ = fs::remove_dir_all(&temp_test_path);
+        fs::create_dir_all(&temp_test_path).unwrap();
+        let socket_path = temp_test_path.join("control.sock");
+
+        // the stale socket file is replaced
+        drop(std::os::unix::net::UnixListener::bind(&socket_path).unwrap());
+        let listener = super::bind(&socket_path).unwrap();
+        let mode = fs::metadata(&socket_path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777, "only the owner can connect");
+        let handle = thread::spawn(move || super::serve(listener));
+
+        let response = send_control_request(
+            &socket_path,
+            "GET /proxyagent/provisionstate HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(Response::OK, response.status);
+        assert_eq!(
+            Some("application/json".to_string()),
+            response.headers.get_header("Content-Type")
+        );
+
+        // the state changing and the proxied requests are not served
+        let response = send_control_request(
+            &socket_path,
+            "DELETE /proxyagent/usercache HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(Response::NOT_FOUND, response.status);
+        let response = send_control_request(
+            &socket_path,
+            "GET /machine?comp=goalstate HTTP/1.1\r\nHost: 168.63.129.16\r\n\r\n",
+        );
+        assert_eq!(Response::NOT_FOUND, response.status);
+
+        // the listener port is rebound on the control socket only
+        let response = send_control_request(
+            &socket_path,
+            "POST /proxyagent/rebind?port=0 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+        );
+        assert_eq!(Response::BAD_REQUEST, response.status);
+        let response = send_control_request(
+            &socket_path,
+            &format!(
+                "POST /proxyagent/rebind?port={} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+                crate::proxy::proxy_listener::get_port()
+            ),
+        );
+        assert_eq!(
+            Response::OK,
+            response.status,
+            "rebinding to the current port is a no-op"
+        );
+
+        super::stop();
+        handle.join().unwrap();
+        assert!(!socket_path.exists(), "the socket file is removed");
+
+        // the other files are not replaced
+        fs::write(&socket_path, "not a socket").unwrap();
+        assert!(super::bind(&socket_path).is_err());
+
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+}
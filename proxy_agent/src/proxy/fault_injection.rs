@@ -0,0 +1,247 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+#![cfg(feature = "fault-injection")]
+
+// Inject faults to the upstream requests for resilience testing.
+// It is compiled only with the 'fault-injection' feature and enabled only with the 'faultInjection' config.
+use crate::common::config::{self, FaultInjection};
+use crate::common::http::response::Response;
+use crate::common::logger;
+use once_cell::sync::Lazy;
+use proxy_agent_shared::telemetry::event_logger;
+#[cfg(test)]
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const FAIL_FAULT: &str = "fail";
+pub const DELAY_FAULT: &str = "delay";
+pub const STATUS_FAULT: &str = "status";
+
+static FAULT_INJECTOR: Lazy<Mutex<Option<FaultInjector>>> =
+    Lazy::new(|| Mutex::new(create_fault_injector()));
+
+#[cfg(test)]
+thread_local! {
+    // the injector of the current test thread, so a test does not inject the faults to the other tests
+    static TEST_FAULT_INJECTOR: RefCell<Option<FaultInjector>> = const { RefCell::new(None) };
+}
+
+#[cfg(test)]
+pub fn set_test_fault_injector(injector: Option<FaultInjector>) {
+    TEST_FAULT_INJECTOR.with(|test_injector| *test_injector.borrow_mut() = injector);
+}
+
+fn create_fault_injector() -> Option<FaultInjector> {
+    match config::get_fault_injection() {
+        Some(settings) => {
+            event_logger::write_event(
+                event_logger::CRITICAL_LEVEL,
+                format!(
+                    "FAULT INJECTION IS ENABLED: '{}' fault will be injected to {}% of the upstream requests. It must not be enabled in production!",
+                    settings.faultType,
+                    settings.rate * 100.0
+                ),
+                "create_fault_injector",
+                "fault_injection",
+                logger::AGENT_LOGGER_KEY,
+            );
+            Some(FaultInjector::new(settings))
+        }
+        None => None,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Fault {
+    Fail,
+    Delay(Duration),
+    Status(String),
+}
+
+pub struct FaultInjector {
+    settings: FaultInjection,
+    count: u64,
+}
+
+impl FaultInjector {
+    pub fn new(settings: FaultInjection) -> Self {
+        FaultInjector { settings, count: 0 }
+    }
+
+    // the faults are spread evenly by the rate over the requests,
+    // e.g. rate 0.25 injects the fault to every 4th request
+    pub fn next_fault(&mut self) -> Option<Fault> {
+        let rate = self.settings.rate.clamp(0.0, 1.0);
+        let previous = (self.count as f64 * rate).floor();
+        self.count += 1;
+        let current = (self.count as f64 * rate).floor();
+        if current <= previous {
+            return None;
+        }
+
+        match self.settings.faultType.to_lowercase().as_str() {
+            FAIL_FAULT => Some(Fault::Fail),
+            DELAY_FAULT => Some(Fault::Delay(Duration::from_millis(
+                self.settings.delayInMilliseconds.unwrap_or(0),
+            ))),
+            STATUS_FAULT => Some(Fault::Status(
+                self.settings
+                    .status
+                    .clone()
+                    .unwrap_or(Response::BAD_GATEWAY.to_string()),
+            )),
+            _ => None,
+        }
+    }
+}
+
+fn next_fault() -> Option<Fault> {
+    #[cfg(test)]
+    {
+        let fault = TEST_FAULT_INJECTOR.with(|test_injector| {
+            test_injector
+                .borrow_mut()
+                .as_mut()
+                .map(|injector| injector.next_fault())
+        });
+        if let Some(fault) = fault {
+            return fault;
+        }
+    }
+    match FAULT_INJECTOR.lock().unwrap().as_mut() {
+        Some(injector) => injector.next_fault(),
+        None => None,
+    }
+}
+
+// apply the configured fault to the upstream request sent to host, before waiting for host to respond;
+// the fail fault fails the request with a connection error and the delay fault counts against the deadline,
+// so the injected faults go through the retry, the timeout and the circuit breaker as the real upstream failures;
+// the status fault fails the request with the configured status, see get_injected_status
+pub fn apply_fault(deadline: Instant) -> std::io::Result<()> {
+    match next_fault() {
+        Some(Fault::Fail) => Err(Error::new(
+            ErrorKind::ConnectionReset,
+            "injected fail fault",
+        )),
+        Some(Fault::Delay(delay)) => {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if delay >= remaining {
+                std::thread::sleep(remaining);
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "injected delay fault exceeded the upstream timeout",
+                ));
+            }
+            std::thread::sleep(delay);
+            Ok(())
+        }
+        Some(Fault::Status(status)) => Err(Error::new(ErrorKind::Other, InjectedStatus(status))),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug)]
+struct InjectedStatus(String);
+
+impl fmt::Display for InjectedStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "injected status fault '{}'", self.0)
+    }
+}
+
+impl std::error::Error for InjectedStatus {}
+
+// the status of the injected status fault, it is responded instead of the status of the upstream error
+pub fn get_injected_status(e: &Error) -> Option<String> {
+    e.get_ref()?
+        .downcast_ref::<InjectedStatus>()
+        .map(|status| status.0.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fault, FaultInjector};
+    use crate::common::config::FaultInjection;
+    use std::io::ErrorKind;
+    use std::time::{Duration, Instant};
+
+    fn create_injector(fault_type: &str, rate: f64) -> FaultInjector {
+        FaultInjector::new(FaultInjection {
+            faultType: fault_type.to_string(),
+            rate,
+            delayInMilliseconds: Some(20),
+            status: Some("503 Service Unavailable".to_string()),
+        })
+    }
+
+    #[test]
+    fn fault_injector_test() {
+        let mut injector = create_injector(super::FAIL_FAULT, 0.25);
+        let faults: Vec<Option<Fault>> = (0..8).map(|_| injector.next_fault()).collect();
+        assert_eq!(
+            2,
+            faults.iter().filter(|f| f.is_some()).count(),
+            "rate 0.25 must inject the fault to 2 of 8 requests"
+        );
+        assert_eq!(Some(Fault::Fail), faults[3]);
+        assert_eq!(Some(Fault::Fail), faults[7]);
+
+        let mut injector = create_injector(super::DELAY_FAULT, 1.0);
+        for _ in 0..3 {
+            assert_eq!(
+                Some(Fault::Delay(Duration::from_millis(20))),
+                injector.next_fault()
+            );
+        }
+
+        let mut injector = create_injector(super::STATUS_FAULT, 0.5);
+        assert_eq!(None, injector.next_fault());
+        assert_eq!(
+            Some(Fault::Status("503 Service Unavailable".to_string())),
+            injector.next_fault()
+        );
+
+        let mut injector = create_injector(super::FAIL_FAULT, 0.0);
+        for _ in 0..10 {
+            assert_eq!(None, injector.next_fault(), "rate 0 must not inject fault");
+        }
+
+        let mut injector = create_injector("unknown", 1.0);
+        assert_eq!(None, injector.next_fault(), "unknown fault type is ignored");
+    }
+
+    #[test]
+    fn apply_fault_test() {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        super::set_test_fault_injector(Some(create_injector(super::FAIL_FAULT, 1.0)));
+        let e = super::apply_fault(deadline).unwrap_err();
+        assert_eq!(ErrorKind::ConnectionReset, e.kind());
+        assert_eq!(None, super::get_injected_status(&e));
+
+        super::set_test_fault_injector(Some(create_injector(super::STATUS_FAULT, 1.0)));
+        let e = super::apply_fault(deadline).unwrap_err();
+        assert_eq!(
+            Some("503 Service Unavailable".to_string()),
+            super::get_injected_status(&e)
+        );
+
+        super::set_test_fault_injector(Some(create_injector(super::DELAY_FAULT, 1.0)));
+        let start = Instant::now();
+        super::apply_fault(deadline).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let e = super::apply_fault(Instant::now() + Duration::from_millis(5)).unwrap_err();
+        assert_eq!(
+            ErrorKind::TimedOut,
+            e.kind(),
+            "the delay beyond the deadline times out the request"
+        );
+
+        super::set_test_fault_injector(Some(create_injector(super::FAIL_FAULT, 0.0)));
+        super::apply_fault(deadline).unwrap();
+        super::set_test_fault_injector(None);
+    }
+}
@@ -1,39 +1,213 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
-use super::authorization_rules::AuthorizationRules;
+use super::authorization_rules::{AuthorizationDecision, AuthorizationRules};
 use super::proxy_connection::Connection;
+use super::proxy_listener;
 use super::proxy_summary::ProxySummary;
 use crate::common::http::response::Response;
+use crate::common::logger;
 use crate::key_keeper::key::AuthorizationItem;
 use crate::proxy_agent_status;
 use crate::{common::config, common::constants, proxy::Claims};
 use once_cell::sync::Lazy;
-use std::sync::Mutex;
-
-static mut WIRESERVER_RULES: Lazy<Mutex<Option<AuthorizationRules>>> =
-    Lazy::new(|| Mutex::new(None));
-static mut IMDS_RULES: Lazy<Mutex<Option<AuthorizationRules>>> = Lazy::new(|| Mutex::new(None));
+use proxy_agent_shared::telemetry::event_logger;
+use serde_derive::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+// the event name of the authorization decisions recorded in audit mode
+const AUDIT_EVENT_NAME: &str = "AuthorizationAudit";
+
+/*
+The rules are read by every request, and only replaced when the key status is updated.
+The new rules are built before the lock is taken and swapped in as a whole,
+each request takes a snapshot of the rules and evaluates it without holding the lock,
+so the in-flight requests keep the rules they started with and never see a partial update.
+ */
+type RulesSnapshot = Option<Arc<AuthorizationRules>>;
+static WIRESERVER_RULES: Lazy<RwLock<RulesSnapshot>> = Lazy::new(|| RwLock::new(None));
+static IMDS_RULES: Lazy<RwLock<RulesSnapshot>> = Lazy::new(|| RwLock::new(None));
+// bumped on every rules update, the authorization decisions made with the older rules are stale
+static RULES_VERSION: AtomicU64 = AtomicU64::new(0);
 
 pub fn set_wireserver_rules(authorization_item: Option<AuthorizationItem>) {
-    let rules = match authorization_item {
-        Some(item) => Some(AuthorizationRules::from_authorization_item(item)),
-        None => None,
-    };
-    unsafe {
-        *WIRESERVER_RULES.lock().unwrap() = rules;
-    }
+    install_rules(&WIRESERVER_RULES, "WireServer", authorization_item);
 }
 
 pub fn set_imds_rules(authorization_item: Option<AuthorizationItem>) {
-    let rules = match authorization_item {
-        Some(item) => Some(AuthorizationRules::from_authorization_item(item)),
-        None => None,
+    install_rules(&IMDS_RULES, "IMDS", authorization_item);
+}
+
+pub fn get_wireserver_rules() -> RulesSnapshot {
+    WIRESERVER_RULES.read().unwrap().clone()
+}
+
+pub fn get_imds_rules() -> RulesSnapshot {
+    IMDS_RULES.read().unwrap().clone()
+}
+
+pub fn get_rules_version() -> u64 {
+    RULES_VERSION.load(Ordering::Relaxed)
+}
+
+// swap in the rules built from the authorization item, and log the transition if the rules id or mode changed
+fn install_rules(
+    current: &RwLock<RulesSnapshot>,
+    destination: &str,
+    authorization_item: Option<AuthorizationItem>,
+) {
+    let rules =
+        authorization_item.map(|item| Arc::new(AuthorizationRules::from_authorization_item(item)));
+    let new_state = get_rules_state(&rules);
+    let old_rules = std::mem::replace(&mut *current.write().unwrap(), rules);
+    RULES_VERSION.fetch_add(1, Ordering::Relaxed);
+
+    let old_state = get_rules_state(&old_rules);
+    if old_state != new_state {
+        let message = format!(
+            "{} authorization rules changed from {} to {}",
+            destination, old_state, new_state
+        );
+        logger::write_information(message.to_string());
+        event_logger::write_event(
+            event_logger::INFO_LEVEL,
+            message,
+            "install_rules",
+            "proxy_authentication",
+            logger::AGENT_LOGGER_KEY,
+        );
+    }
+}
+
+fn get_rules_state(rules: &RulesSnapshot) -> String {
+    match rules {
+        Some(rules) => format!("'{}' ({})", rules.id, rules.mode),
+        None => "none".to_string(),
+    }
+}
+
+// true once the authorization rules of any destination are set from the key status
+pub fn is_rules_loaded() -> bool {
+    WIRESERVER_RULES.read().unwrap().is_some() || IMDS_RULES.read().unwrap().is_some()
+}
+
+// the synthetic request to evaluate the authorization rules against, nothing is forwarded to host
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+pub struct SimulateRequest {
+    pub destination: String, // WireServer or IMDS
+    pub url: String,
+    pub claims: Claims,
+}
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+pub struct SimulateResult {
+    pub destination: String,
+    // the effective access, the denied request is still allowed in audit mode
+    pub allowed: bool,
+    // the id and the mode of the rules evaluated, None if no rules are set for the destination
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rulesId: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decision: Option<AuthorizationDecision>,
+}
+
+/*
+Evaluate the current authorization rules of the destination for the synthetic claims and url,
+the platform processes and the elevation checks of the destination are not simulated.
+ */
+pub fn simulate(connection_id: u128, request: SimulateRequest) -> std::io::Result<SimulateResult> {
+    let rules = match request.destination.to_lowercase().as_str() {
+        "wireserver" => get_wireserver_rules(),
+        "imds" => get_imds_rules(),
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown destination '{}'", request.destination),
+            ))
+        }
     };
-    unsafe {
-        *IMDS_RULES.lock().unwrap() = rules;
+    Ok(simulate_rules(connection_id, request, &rules))
+}
+
+fn simulate_rules(
+    connection_id: u128,
+    request: SimulateRequest,
+    rules: &RulesSnapshot,
+) -> SimulateResult {
+    match rules {
+        Some(rules) => {
+            let decision = rules.explain(connection_id, request.url, request.claims);
+            SimulateResult {
+                destination: request.destination,
+                allowed: decision.allowed || rules.is_audit_mode(),
+                rulesId: Some(rules.id.to_string()),
+                mode: Some(rules.mode.to_string()),
+                decision: Some(decision),
+            }
+        }
+        None => SimulateResult {
+            destination: request.destination,
+            allowed: true,
+            rulesId: None,
+            mode: None,
+            decision: None,
+        },
     }
 }
 
+// the decision the enforce mode would make, recorded when the rules are in audit mode
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct AuthorizationAuditRecord<'a> {
+    connectionId: u128,
+    destination: &'a str,
+    url: &'a str,
+    decision: &'a str, // allow or deny
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ruleName: &'a Option<String>,
+    reason: &'a str,
+    claims: &'a Claims,
+}
+
+fn create_audit_record(
+    connection_id: u128,
+    destination: &str,
+    request_url: &str,
+    claims: &Claims,
+    decision: &AuthorizationDecision,
+) -> String {
+    let record = AuthorizationAuditRecord {
+        connectionId: connection_id,
+        destination,
+        url: request_url,
+        decision: if decision.allowed { "allow" } else { "deny" },
+        ruleName: &decision.ruleName,
+        reason: &decision.reason,
+        claims,
+    };
+    serde_json::to_string(&record).unwrap_or_default()
+}
+
+fn write_audit_record(
+    connection_id: u128,
+    destination: &str,
+    request_url: &str,
+    claims: &Claims,
+    decision: &AuthorizationDecision,
+) {
+    event_logger::write_event(
+        event_logger::INFO_LEVEL,
+        create_audit_record(connection_id, destination, request_url, claims, decision),
+        AUDIT_EVENT_NAME,
+        "proxy_authentication",
+        logger::AGENT_LOGGER_KEY,
+    );
+}
+
 #[cfg(windows)]
 mod default {
     use crate::proxy::Claims;
@@ -114,14 +288,20 @@ impl Authenticate for WireServer {
         }
 
         if config::get_wire_server_support() == 2 {
-            let wireserver_rules = unsafe { WIRESERVER_RULES.lock().unwrap() };
-            match &*wireserver_rules {
+            match get_wireserver_rules() {
                 Some(rules) => {
-                    let allowed = rules.is_allowed(
-                        connection_id,
-                        request_url.to_string(),
-                        self.claims.clone(),
-                    );
+                    let decision =
+                        rules.explain(connection_id, request_url.to_string(), self.claims.clone());
+                    if rules.is_audit_mode() {
+                        write_audit_record(
+                            connection_id,
+                            "WireServer",
+                            &request_url,
+                            &self.claims,
+                            &decision,
+                        );
+                    }
+                    let allowed = decision.allowed;
                     if !allowed {
                         let summary = ProxySummary {
                             userId: self.claims.userId,
@@ -130,17 +310,24 @@ impl Authenticate for WireServer {
                             clientIp: self.claims.clientIp.to_string(),
                             processFullPath: self.claims.processFullPath.to_string(),
                             processCmdLine: self.claims.processCmdLine.to_string(),
+                            processExeSha256: self.claims.processExeSha256.clone(),
+                            processSigner: self.claims.processSigner.clone(),
                             runAsElevated: self.claims.runAsElevated,
+                            isSystemAccount: self.claims.is_system_account(),
                             method: String::new(),
                             url: request_url.to_string(),
                             ip: constants::WIRE_SERVER_IP.to_string(),
                             port: constants::WIRE_SERVER_PORT,
                             responseStatus: Response::FORBIDDEN.to_string(),
                             elapsedTime: 0,
+                            tunnelBytesSent: None,
+                            tunnelBytesReceived: None,
+                            authorization: Some(decision),
+                            upstreamError: None,
                         };
                         proxy_agent_status::add_connection_summary(summary, true);
 
-                        if rules.mode.to_lowercase() == "audit" {
+                        if rules.is_audit_mode() {
                             Connection::write_information(connection_id, format!("WireServer request {} denied in audit mode, continue forward the request", request_url.to_string()));
                             return true;
                         }
@@ -169,15 +356,20 @@ struct IMDS {
 impl Authenticate for IMDS {
     fn authenticate(&self, connection_id: u128, request_url: String) -> bool {
         if config::get_imds_support() == 2 {
-            let imds_rules = unsafe { IMDS_RULES.lock().unwrap() };
-            match &*imds_rules {
+            match get_imds_rules() {
                 Some(rules) => {
-                    let allowed = rules.is_allowed(
-                        connection_id,
-                        request_url.to_string(),
-                        self.claims.clone(),
-                    );
-
+                    let decision =
+                        rules.explain(connection_id, request_url.to_string(), self.claims.clone());
+                    if rules.is_audit_mode() {
+                        write_audit_record(
+                            connection_id,
+                            "IMDS",
+                            &request_url,
+                            &self.claims,
+                            &decision,
+                        );
+                    }
+                    let allowed = decision.allowed;
                     if !allowed {
                         let summary = ProxySummary {
                             userId: self.claims.userId,
@@ -186,17 +378,24 @@ impl Authenticate for IMDS {
                             clientIp: self.claims.clientIp.to_string(),
                             processFullPath: self.claims.processFullPath.to_string(),
                             processCmdLine: self.claims.processCmdLine.to_string(),
+                            processExeSha256: self.claims.processExeSha256.clone(),
+                            processSigner: self.claims.processSigner.clone(),
                             runAsElevated: self.claims.runAsElevated,
+                            isSystemAccount: self.claims.is_system_account(),
                             method: String::new(),
                             url: request_url.to_string(),
                             ip: constants::IMDS_IP.to_string(),
                             port: constants::IMDS_PORT,
                             responseStatus: Response::FORBIDDEN.to_string(),
                             elapsedTime: 0,
+                            tunnelBytesSent: None,
+                            tunnelBytesReceived: None,
+                            authorization: Some(decision),
+                            upstreamError: None,
                         };
                         proxy_agent_status::add_connection_summary(summary, true);
 
-                        if rules.mode.to_lowercase() == "audit" {
+                        if rules.is_audit_mode() {
                             Connection::write_information(connection_id, format!("IMDS request {} denied in audit mode, continue forward the request", request_url.to_string()));
                             return true;
                         }
@@ -270,7 +469,9 @@ pub fn get_authenticate(ip: String, port: u16, claims: Claims) -> Box<dyn Authen
         return Box::new(GAPlugin { claims });
     } else if ip == constants::IMDS_IP && port == constants::IMDS_PORT {
         return Box::new(IMDS { claims });
-    } else if ip == constants::PROXY_AGENT_IP && port == constants::PROXY_AGENT_PORT {
+    } else if (ip == constants::PROXY_AGENT_IP || ip == proxy_listener::get_local_ip())
+        && port == proxy_listener::get_port()
+    {
         return Box::new(ProxyAgent {});
     } else {
         Box::new(Default {})
@@ -291,6 +492,8 @@ mod tests {
             processName: "test".to_string(),
             processFullPath: "test".to_string(),
             processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
             runAsElevated: true,
             clientIp: "127.0.0.1".to_string(),
         };
@@ -362,6 +565,8 @@ mod tests {
             processName: "test".to_string(),
             processFullPath: "test".to_string(),
             processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
             runAsElevated: true,
             clientIp: "127.0.0.1".to_string(),
         };
@@ -444,6 +649,8 @@ mod tests {
             processName: "test".to_string(),
             processFullPath: "test".to_string(),
             processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
             runAsElevated: true,
             clientIp: "127.0.0.1".to_string(),
         };
@@ -516,6 +723,101 @@ mod tests {
         );
     }
 
+    #[test]
+    fn install_rules_test() {
+        let claims = crate::proxy::Claims {
+            userId: 0,
+            userName: "test".to_string(),
+            userGroups: vec!["test".to_string()],
+            processId: std::process::id(),
+            processName: "test".to_string(),
+            processFullPath: "test".to_string(),
+            processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
+            runAsElevated: true,
+            clientIp: "127.0.0.1".to_string(),
+        };
+        let url = "http://localhost/test?";
+        let current = std::sync::RwLock::new(None);
+        assert_eq!("none", super::get_rules_state(&current.read().unwrap()));
+
+        super::install_rules(
+            &current,
+            "test",
+            Some(AuthorizationItem {
+                defaultAccess: "allow".to_string(),
+                mode: "enforce".to_string(),
+                id: "old".to_string(),
+                rules: None,
+            }),
+        );
+        // the in-flight request holds the snapshot of the old rules
+        let snapshot = current.read().unwrap().clone().unwrap();
+
+        super::install_rules(
+            &current,
+            "test",
+            Some(AuthorizationItem {
+                defaultAccess: "deny".to_string(),
+                mode: "enforce".to_string(),
+                id: "new".to_string(),
+                rules: None,
+            }),
+        );
+        assert!(
+            snapshot.is_allowed(1, url.to_string(), claims.clone()),
+            "the in-flight request must keep evaluating the old rules"
+        );
+        assert_eq!("old", snapshot.id);
+
+        let latest = current.read().unwrap().clone().unwrap();
+        assert!(
+            !latest.is_allowed(2, url.to_string(), claims.clone()),
+            "the new request must evaluate the new rules"
+        );
+        assert_eq!(
+            "'new' (enforce)",
+            super::get_rules_state(&current.read().unwrap())
+        );
+
+        super::install_rules(&current, "test", None);
+        assert!(current.read().unwrap().is_none());
+        assert_eq!("old", snapshot.id, "the snapshot must outlive the removal");
+    }
+
+    #[test]
+    fn simulate_test() {
+        let request = || super::SimulateRequest {
+            destination: "WireServer".to_string(),
+            url: "http://localhost/test?".to_string(),
+            claims: crate::proxy::Claims::empty(),
+        };
+        let result = super::simulate_rules(1, request(), &None);
+        assert!(result.allowed, "no rules must allow the request");
+        assert!(result.decision.is_none());
+
+        let rules = std::sync::Arc::new(
+            crate::proxy::authorization_rules::AuthorizationRules::from_authorization_item(
+                AuthorizationItem {
+                    defaultAccess: "deny".to_string(),
+                    mode: "audit".to_string(),
+                    id: "audit".to_string(),
+                    rules: None,
+                },
+            ),
+        );
+        let result = super::simulate_rules(1, request(), &Some(rules));
+        assert!(result.allowed, "audit mode must not deny the request");
+        assert!(!result.decision.unwrap().allowed);
+        assert_eq!(Some("audit".to_string()), result.rulesId);
+        assert_eq!(Some("audit".to_string()), result.mode);
+
+        let mut unknown = request();
+        unknown.destination = "GAPlugin".to_string();
+        assert!(super::simulate(1, unknown).is_err());
+    }
+
     #[test]
     fn is_platform_process_test() {
         let mut claims = crate::proxy::Claims {
@@ -526,6 +828,8 @@ mod tests {
             processName: "test".to_string(),
             processFullPath: "test".to_string(),
             processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
             runAsElevated: true,
             clientIp: "127.0.0.1".to_string(),
         };
@@ -569,4 +873,41 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn audit_record_test() {
+        let claims = crate::proxy::Claims {
+            userId: 0,
+            userName: "test".to_string(),
+            userGroups: vec!["test".to_string()],
+            processId: 100,
+            processName: "test".to_string(),
+            processFullPath: "/usr/bin/test".to_string(),
+            processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
+            runAsElevated: false,
+            clientIp: "127.0.0.1".to_string(),
+        };
+        let decision = crate::proxy::authorization_rules::AuthorizationDecision {
+            allowed: false,
+            ruleName: Some("role".to_string()),
+            privilegeName: Some("privilege".to_string()),
+            reason: "Denied by the deny rule 'role' with identity 'test'.".to_string(),
+            evaluations: Vec::new(),
+        };
+        let record = super::create_audit_record(
+            1,
+            "WireServer",
+            "http://localhost/machine",
+            &claims,
+            &decision,
+        );
+        let record: serde_json::Value = serde_json::from_str(&record).unwrap();
+        assert_eq!("WireServer", record["destination"]);
+        assert_eq!("http://localhost/machine", record["url"]);
+        assert_eq!("deny", record["decision"], "would-be decision mismatch");
+        assert_eq!("role", record["ruleName"]);
+        assert_eq!("/usr/bin/test", record["claims"]["processFullPath"]);
+    }
 }
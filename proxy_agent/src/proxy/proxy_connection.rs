@@ -1,22 +1,48 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+use crate::common::{config, constants};
 use crate::proxy::Claims;
+use proxy_agent_shared::misc_helpers;
 use proxy_agent_shared::{logger_manager, rolling_logger::RollingLogger};
+use serde_derive::Serialize;
 use std::net::TcpStream;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
 pub struct Connection {
     pub stream: TcpStream,
     pub id: u128,
 
     pub now: Instant,
+    // when the connection was accepted, the process making it must have started before
+    pub connected_at: SystemTime,
     pub cliams: Option<Claims>,
     pub ip: String,
     pub port: u16,
+    // the response of the current request is fully forwarded, the client connection can serve the next request
+    pub keep_alive: bool,
+    // the claims, destination, url, rules version, key and time bucket authorized on this connection
+    pub authorized_request: Option<String>,
+    #[cfg(feature = "otel")]
+    pub span: Option<crate::proxy::proxy_trace::Span>,
 }
 
+// a line of the per-connection log in the json format
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+struct ConnectionLogEntry {
+    connectionId: u128,
+    timestamp: String,
+    level: String,
+    message: String,
+}
+
+const VERBOSE_LEVEL: &str = "Verbose";
+const INFORMATION_LEVEL: &str = "Information";
+const WARNING_LEVEL: &str = "Warning";
+const ERROR_LEVEL: &str = "Error";
+
 impl Connection {
     pub const CONNECTION_LOGGER_KEY: &'static str = "Connection_Logger";
     pub fn init_logger(log_folder: PathBuf) {
@@ -33,31 +59,74 @@ impl Connection {
         logger_manager::get_logger(Connection::CONNECTION_LOGGER_KEY)
     }
 
-    pub fn write(connection_id:u128, message: String) {
-        _ = Connection::get_connection_logger()
-            .lock()
-            .unwrap()
-            .write(format!("Connection:{} - {}", connection_id, message));
+    pub fn write(connection_id: u128, message: String) {
+        Connection::write_log(VERBOSE_LEVEL, connection_id, message);
+    }
+
+    pub fn write_information(connection_id: u128, message: String) {
+        Connection::write_log(INFORMATION_LEVEL, connection_id, message);
     }
 
-    pub fn write_information(connection_id:u128, message: String) {
-        _ = Connection::get_connection_logger()
-            .lock()
-            .unwrap()
-            .write_information(format!("Connection:{} - {}", connection_id, message));
+    pub fn write_warning(connection_id: u128, message: String) {
+        Connection::write_log(WARNING_LEVEL, connection_id, message);
     }
 
-    pub fn write_warning(connection_id:u128, message: String) {
-        _ = Connection::get_connection_logger()
-            .lock()
-            .unwrap()
-            .write_warning(format!("Connection:{} - {}", connection_id, message));
+    pub fn write_error(connection_id: u128, message: String) {
+        Connection::write_log(ERROR_LEVEL, connection_id, message);
     }
 
-    pub fn write_error(connection_id:u128, message: String) {
-        _ = Connection::get_connection_logger()
-            .lock()
-            .unwrap()
-            .write_error(format!("Connection:{} - {}", connection_id, message));
+    fn write_log(level: &str, connection_id: u128, message: String) {
+        let logger = Connection::get_connection_logger();
+        let mut logger = logger.lock().unwrap();
+        if config::get_connection_log_format() == constants::JSON_LOG_FORMAT {
+            _ = logger.write_line(format_json_log(level, connection_id, message));
+            return;
+        }
+
+        let message = format!("Connection:{} - {}", connection_id, message);
+        _ = match level {
+            INFORMATION_LEVEL => logger.write_information(message),
+            WARNING_LEVEL => logger.write_warning(message),
+            ERROR_LEVEL => logger.write_error(message),
+            _ => logger.write(message),
+        };
+    }
+}
+
+fn format_json_log(level: &str, connection_id: u128, message: String) -> String {
+    let entry = ConnectionLogEntry {
+        connectionId: connection_id,
+        timestamp: misc_helpers::get_date_time_string_with_miliseconds(),
+        level: level.to_string(),
+        message,
+    };
+    match serde_json::to_string(&entry) {
+        Ok(line) => line,
+        Err(e) => format!(
+            "Connection:{} - Failed to serialize the log entry: {}",
+            connection_id, e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn format_json_log_test() {
+        let line = super::format_json_log(
+            super::WARNING_LEVEL,
+            u128::MAX,
+            "Failed to \"connect\".".to_string(),
+        );
+        assert!(!line.contains('\n'), "json log must be a single line");
+
+        let entry: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(super::WARNING_LEVEL, entry["level"]);
+        assert_eq!("Failed to \"connect\".", entry["message"]);
+        assert!(!entry["timestamp"].as_str().unwrap().is_empty());
+        assert!(
+            line.contains(&format!("\"connectionId\":{}", u128::MAX)),
+            "connectionId must be a number field"
+        );
     }
 }
@@ -1,38 +1,166 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+use super::circuit_breaker::{CircuitBreaker, CircuitState};
 use super::proxy_authentication;
+use super::proxy_metrics;
 use super::proxy_pool::ProxyPool;
+#[cfg(feature = "otel")]
+use super::proxy_trace::{self, Span};
+use super::rate_limiter::RateLimiter;
+use super::self_test;
+use crate::common::cidr::Cidr;
 use crate::common::config;
+use crate::common::config::{SkipSignatureDestination, UpstreamTls};
 use crate::common::constants;
 use crate::common::helpers;
 use crate::common::http;
+use crate::common::http::connection_pool::ConnectionPool;
+use crate::common::http::headers;
+use crate::common::http::http_request;
 use crate::common::http::request::Request;
 use crate::common::http::response::Response;
+use crate::common::http::tls;
 use crate::common::logger;
 use crate::key_keeper;
+use crate::key_keeper::key::Key;
 use crate::provision;
+use crate::proxy;
 use crate::proxy::proxy_connection::Connection;
 use crate::proxy::proxy_summary::ProxySummary;
-use crate::proxy::Claims;
+use crate::proxy::{Claims, HostClaims};
 use crate::proxy_agent_status;
 use crate::redirector;
+use crate::redirector::{AuditEntry, DestinationResolver};
+use crate::service;
 use once_cell::sync::Lazy;
 use proxy_agent_shared::misc_helpers;
 use proxy_agent_shared::proxy_agent_aggregate_status::{ModuleState, ProxyAgentDetailStatus};
 use proxy_agent_shared::telemetry::event_logger;
 use std::collections::HashMap;
 use std::io::prelude::*;
-use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
+use url::Url;
 
+const INVALID_AUDIT_ENTRY_RETRY_COUNT: u32 = 3;
+const INVALID_AUDIT_ENTRY_RETRY_DELAY: Duration = Duration::from_millis(10);
+// the allowed client CIDRs of the listener bound to a non-loopback address if they are not configured, the listener address is added to them
+const LOOPBACK_CLIENT_CIDRS: [&str; 2] = ["127.0.0.0/8", "::1/128"];
+// log the signature input only when the body is small enough to be readable
+const MAX_LOGGED_SIG_INPUT_BODY_SIZE: usize = 4 * 1024;
+// warn if the request body is allowed to be larger than this
+const REQUEST_BODY_LIMIT_WARNING_SIZE: usize = 1024 * 1024 * 1024; // 1GB
+// the reused authorization decisions are made again every second, as the rule time windows may pass
+const AUTHORIZATION_TIME_BUCKET_IN_NANOSECONDS: i128 = 1_000_000_000;
 static SHUT_DOWN: Lazy<Arc<AtomicBool>> = Lazy::new(|| Arc::new(AtomicBool::new(false)));
-static mut CONNECTION_COUNT: Lazy<Mutex<u128>> = Lazy::new(|| Mutex::new(0));
+static LISTENER_RUNNING: AtomicBool = AtomicBool::new(false);
+// the port the listener serves, it changes when the listener is rebound
+static LISTENER_PORT: AtomicU16 = AtomicU16::new(constants::PROXY_AGENT_PORT);
+// true when the connection limit event has been emitted for the current limit crossing
+static CONNECTION_LIMIT_REACHED: AtomicBool = AtomicBool::new(false);
+// true while the requests expected to be signed are forwarded unsigned as the current key is empty
+static FORWARDING_UNSIGNED: AtomicBool = AtomicBool::new(false);
+// the 2xx connection summaries seen by the summary sampling
+static SUCCESS_SUMMARY_COUNT: AtomicU64 = AtomicU64::new(0);
+// the connection ids allocated so far, read by the status without a lock
+static CONNECTION_COUNT: AtomicU64 = AtomicU64::new(0);
+// the pending job count of the running listener pool
+static ACTIVE_CONNECTIONS: Lazy<Mutex<Option<Arc<AtomicUsize>>>> = Lazy::new(|| Mutex::new(None));
+// (low, large) request body size limits, validated when the listener starts
+static REQUEST_BODY_LIMITS: Lazy<(usize, usize)> = Lazy::new(|| {
+    validate_request_body_limits(
+        config::get_request_body_low_limit_size(),
+        config::get_request_body_large_limit_size(),
+    )
+});
+// the client streams of the connections being handled, to abort them at shutdown
+static IN_FLIGHT_STREAMS: Lazy<Mutex<HashMap<u128, TcpStream>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 static mut STATUS_MESSAGE: Lazy<String> =
     Lazy::new(|| String::from("Proxy listner has not started yet."));
+static ALLOWED_CLIENT_CIDRS: Lazy<Option<Vec<Cidr>>> = Lazy::new(|| {
+    get_allowed_client_cidrs(
+        config::get_allowed_client_cidrs(),
+        config::get_listener_address(),
+    )
+});
+static DENIED_PROCESS_PATHS: Lazy<Vec<(String, regex::Regex)>> =
+    Lazy::new(|| parse_denied_process_paths(config::get_denied_process_paths()));
+static SKIP_SIGNATURE_DESTINATIONS: Lazy<Vec<(Cidr, Option<u16>)>> =
+    Lazy::new(|| parse_skip_signature_destinations(config::get_skip_signature_destinations()));
+// the headers set by the agent are always removed from the client request, so a client cannot impersonate the agent
+static REQUEST_HEADER_DENY_LIST: Lazy<Vec<String>> = Lazy::new(|| {
+    let mut deny_list: Vec<String> = [
+        constants::CLAIMS_HEADER,
+        constants::AUTHORIZATION_HEADER,
+        constants::DATE_HEADER,
+    ]
+    .iter()
+    .map(|name| name.to_string())
+    .collect();
+    deny_list.extend(config::get_request_header_deny_list());
+    deny_list
+});
+// the headers framing the request are always forwarded with the allow list
+static REQUEST_HEADER_ALLOW_LIST: Lazy<Option<Vec<String>>> = Lazy::new(|| {
+    config::get_request_header_allow_list().map(|mut allow_list| {
+        allow_list.extend(
+            [
+                headers::HOST_HEADER_NAME,
+                headers::CONTENT_LENGTH_HEADER_NAME,
+                headers::TRANSFER_ENCODING_HEADER_NAME,
+                headers::EXPECT_HEADER_NAME,
+                constants::CONNECTION_HEADER,
+            ]
+            .iter()
+            .map(|name| name.to_string()),
+        );
+        allow_list
+    })
+});
+static ALLOWED_METHODS: Lazy<Option<Vec<String>>> = Lazy::new(|| {
+    config::get_allowed_methods()
+        .map(|methods| methods.iter().map(|method| method.to_uppercase()).collect())
+});
+static RATE_LIMITER: Lazy<Mutex<RateLimiter>> = Lazy::new(|| {
+    Mutex::new(RateLimiter::new(
+        config::get_rate_limit_requests_per_second(),
+        config::get_rate_limit_burst(),
+    ))
+});
+static CIRCUIT_BREAKER: Lazy<Mutex<CircuitBreaker>> = Lazy::new(|| {
+    Mutex::new(CircuitBreaker::new(
+        config::get_circuit_breaker_failure_threshold(),
+        config::get_circuit_breaker_window(),
+        config::get_circuit_breaker_cool_down(),
+    ))
+});
+// the circuit breaker states of the status, published after the breaker changes,
+// so the status polling never waits on the breaker lock taken by every request;
+// it starts with the states of an untouched breaker, built without taking the lock
+static CIRCUIT_BREAKER_STATUS: Lazy<RwLock<Arc<HashMap<String, String>>>> = Lazy::new(|| {
+    RwLock::new(Arc::new(get_circuit_breaker_states(&CircuitBreaker::new(
+        0,
+        Duration::ZERO,
+        Duration::ZERO,
+    ))))
+});
+static UPSTREAM_POOL: Lazy<ConnectionPool> = Lazy::new(|| {
+    // the upstream connection carries the redirect record of its client on Windows,
+    // it must not be reused for other clients
+    let max_idle_connections = if cfg!(windows) {
+        0
+    } else {
+        config::get_upstream_max_idle_connections()
+    };
+    ConnectionPool::new(config::get_upstream_idle_timeout(), max_idle_connections)
+});
 
 pub fn start_async(port: u16, pool_size: u16) {
     _ = thread::Builder::new()
@@ -45,24 +173,112 @@ pub fn start_async(port: u16, pool_size: u16) {
 fn start(port: u16, pool_size: u16) {
     Connection::init_logger(config::get_logs_dir());
 
-    let shutdown = SHUT_DOWN.clone();
-    // listen to wildcard ip address to accept request from
-    // loopback address and local ip addresses
-    let addr = format!("{}:{}", Ipv4Addr::UNSPECIFIED, port);
-    logger::write(format!("Start proxy listener at '{}'.", &addr));
-    let listener;
-    match TcpListener::bind(&addr) {
-        Ok(l) => listener = l,
+    LISTENER_PORT.store(port, Ordering::Relaxed);
+    #[cfg(not(windows))]
+    if let Some(path) = config::get_control_socket_path() {
+        super::control_listener::start_async(path);
+    }
+    if let Ok(listener) = bind(port) {
+        serve(listener, pool_size);
+    }
+}
+
+pub fn get_port() -> u16 {
+    LISTENER_PORT.load(Ordering::Relaxed)
+}
+
+// true until the listener has drained or aborted its connections after the stop signal
+pub fn is_running() -> bool {
+    LISTENER_RUNNING.load(Ordering::Relaxed)
+}
+
+/*
+Stop the current listener gracefully and serve on the new port.
+The current port is served again if the new port cannot be bound.
+Fails without serving any port if the current listener does not stop before the deadline.
+ */
+pub fn rebind(new_port: u16, pool_size: u16) -> std::io::Result<()> {
+    let old_port = get_port();
+    stop(old_port);
+    if is_running() {
+        // serving again would clear the stop signal, and the old listener would keep accepting
+        return Err(std::io::Error::new(
+            ErrorKind::TimedOut,
+            format!(
+                "Proxy listener on port {} did not stop before the deadline.",
+                old_port
+            ),
+        ));
+    }
+
+    match bind(new_port) {
+        Ok(listener) => {
+            serve_async(listener, new_port, pool_size);
+            Ok(())
+        }
         Err(e) => {
-            let message = format!("Failed to bind TcpListener '{}' with error {}.", addr, e);
-            unsafe {
-                *STATUS_MESSAGE = message.to_string();
+            if let Ok(listener) = bind(old_port) {
+                serve_async(listener, old_port, pool_size);
             }
-            logger::write_error(message);
-            return;
+            Err(e)
         }
     }
+}
+
+fn serve_async(listener: TcpListener, port: u16, pool_size: u16) {
+    SHUT_DOWN.store(false, Ordering::Relaxed);
+    LISTENER_PORT.store(port, Ordering::Relaxed);
+    // the port is bound already, it is running before the thread is scheduled
+    LISTENER_RUNNING.store(true, Ordering::Relaxed);
+    _ = thread::Builder::new()
+        .name("proxy_listener".to_string())
+        .spawn(move || {
+            serve(listener, pool_size);
+        });
+}
+
+// the ip to reach the listener from the local machine
+pub fn get_local_ip() -> String {
+    match config::get_listener_address() {
+        Some(ip) => ip.to_string(),
+        None => constants::PROXY_AGENT_IP.to_string(),
+    }
+}
+
+fn bind(port: u16) -> std::io::Result<TcpListener> {
+    let addr = match config::get_listener_address() {
+        Some(ip) => {
+            if !ip.is_loopback() {
+                event_logger::write_event(
+                    event_logger::WARN_LEVEL,
+                    format!(
+                        "Proxy listener is bound to the non-loopback address {}, it may be reachable from the other hosts.",
+                        ip
+                    ),
+                    "bind",
+                    "proxy_listener",
+                    logger::AGENT_LOGGER_KEY,
+                );
+            }
+            format!("{}:{}", ip, port)
+        }
+        // listen to wildcard ip address to accept request from
+        // loopback address and local ip addresses
+        None => format!("{}:{}", Ipv4Addr::UNSPECIFIED, port),
+    };
+    logger::write(format!("Start proxy listener at '{}'.", &addr));
+    TcpListener::bind(&addr).map_err(|e| {
+        let message = format!("Failed to bind TcpListener '{}' with error {}.", addr, e);
+        unsafe {
+            *STATUS_MESSAGE = message.to_string();
+        }
+        logger::write_error(message.to_string());
+        std::io::Error::new(e.kind(), message)
+    })
+}
 
+fn serve(listener: TcpListener, pool_size: u16) {
+    let shutdown = SHUT_DOWN.clone();
     let message = helpers::write_startup_event(
         "Started proxy listener, ready to accept request",
         "start",
@@ -73,8 +289,24 @@ fn start(port: u16, pool_size: u16) {
         *STATUS_MESSAGE = message.to_string();
     }
     provision::listener_started();
+    LISTENER_RUNNING.store(true, Ordering::Relaxed);
+    event_logger::write_event(
+        event_logger::INFO_LEVEL,
+        format!(
+            "Emit 1 in {} successful connection summary events, the others are always emitted.",
+            config::get_connection_summary_sample_rate()
+        ),
+        "start",
+        "proxy_listener",
+        logger::AGENT_LOGGER_KEY,
+    );
 
+    Lazy::force(&REQUEST_BODY_LIMITS);
     let pool = ProxyPool::new(pool_size as usize);
+    let max_active_connections = config::get_max_active_connections();
+    let wait_for_connections =
+        config::get_connection_limit_policy() == constants::CONNECTION_LIMIT_WAIT;
+    *ACTIVE_CONNECTIONS.lock().unwrap() = Some(pool.pending_counter());
 
     for connection in listener.incoming() {
         if shutdown.load(Ordering::Relaxed) {
@@ -85,30 +317,41 @@ fn start(port: u16, pool_size: u16) {
             logger::write_warning(message.to_string());
             break;
         }
-        let mut connection_count_clone: u128 = 0;
-
-        if let Ok(mut connection_count_lock) = unsafe { CONNECTION_COUNT.lock() } {
-            if *connection_count_lock == u128::MAX {
-                // reset connection id
-                *connection_count_lock = 0;
-            }
-            *connection_count_lock += 1;
-
-            connection_count_clone = connection_count_lock.clone();
-        }
+        let connection_count_clone = next_connection_id();
         match connection {
             Ok(stream) => {
+                if is_connection_limit_reached(pool.pending(), max_active_connections) {
+                    send_response(&stream, None, Response::SERVICE_UNAVAILABLE);
+                    continue;
+                }
+                // the connection waits in the pool queue before it is handled
+                let connected_at = SystemTime::now();
                 pool.execute(move || {
+                    let _in_flight = InFlightStream::register(connection_count_clone, &stream);
                     let mut connection = Connection {
                         stream,
                         id: connection_count_clone,
                         now: Instant::now(),
+                        connected_at,
                         cliams: None,
                         ip: String::new(),
                         port: 0,
+                        keep_alive: false,
+                        authorized_request: None,
+                        #[cfg(feature = "otel")]
+                        span: None,
                     };
-                    handle_connection(&mut connection);
+                    handle_connection(&mut connection, &redirector::AuditMapResolver);
                 });
+                if wait_for_connections
+                    && is_connection_limit_reached(pool.pending(), max_active_connections)
+                {
+                    // hold the next accept until a connection finishes,
+                    // the new connections wait in the listen backlog instead of the pool queue
+                    pool.wait_pending_below(max_active_connections, || {
+                        shutdown.load(Ordering::Relaxed)
+                    });
+                }
             }
             Err(e) => {
                 logger::write_warning(format!("Incoming connection with error {e}; ignore it."));
@@ -118,37 +361,397 @@ fn start(port: u16, pool_size: u16) {
     }
 
     logger::write("ProxyListener stopped accepting new request.".to_string());
+
+    // drain the in-flight connections, abort the ones still running after the grace period
+    let mut aborted = 0;
+    let (drained, unfinished) = pool.shutdown(
+        config::get_shutdown_grace_period(),
+        config::get_shutdown_hard_deadline(),
+        || aborted = abort_in_flight_streams(),
+    );
+    event_logger::write_event(
+        event_logger::INFO_LEVEL,
+        format!(
+            "Proxy listener stopped: {} connections finished, {} connections aborted after the grace period, {} connections left running at the hard deadline.",
+            drained, aborted, unfinished
+        ),
+        "start",
+        "proxy_listener",
+        logger::AGENT_LOGGER_KEY,
+    );
+    *ACTIVE_CONNECTIONS.lock().unwrap() = None;
+    LISTENER_RUNNING.store(false, Ordering::Relaxed);
+}
+
+// removes the client stream from the in-flight streams when the connection is handled, even if the handler panics
+struct InFlightStream(u128);
+
+impl InFlightStream {
+    fn register(connection_id: u128, stream: &TcpStream) -> Self {
+        if let Ok(stream) = stream.try_clone() {
+            IN_FLIGHT_STREAMS
+                .lock()
+                .unwrap()
+                .insert(connection_id, stream);
+        }
+        InFlightStream(connection_id)
+    }
+}
+
+impl Drop for InFlightStream {
+    fn drop(&mut self) {
+        if let Ok(mut streams) = IN_FLIGHT_STREAMS.lock() {
+            streams.remove(&self.0);
+        }
+    }
 }
 
+/*
+Shut down the client streams of the in-flight connections, returns the number of connections aborted.
+The handlers blocked on the client fail right away; the ones waiting on the host fail
+when they send the response, or at the upstream timeout, whichever comes first.
+ */
+fn abort_in_flight_streams() -> usize {
+    let streams: Vec<(u128, TcpStream)> = IN_FLIGHT_STREAMS.lock().unwrap().drain().collect();
+    for (connection_id, stream) in &streams {
+        _ = stream.shutdown(std::net::Shutdown::Both);
+        Connection::write_warning(
+            *connection_id,
+            "Connection aborted as the listener is shutting down.".to_string(),
+        );
+    }
+    streams.len()
+}
+
+// the connection ids are shared by the proxy and the control connections
+pub(super) fn next_connection_id() -> u128 {
+    let count = CONNECTION_COUNT.fetch_add(1, Ordering::Relaxed);
+    count.wrapping_add(1) as u128
+}
+
+// the redirector and the listener disagree about the source port of the redirected connection
+fn report_audit_lookup_miss(client_source_ip: &IpAddr, client_source_port: u16) {
+    let misses = proxy_metrics::record_audit_lookup_miss();
+    event_logger::write_event(
+        event_logger::WARN_LEVEL,
+        format!(
+            "Audit entry not found for client {}:{}, {} audit lookup misses so far.",
+            client_source_ip, client_source_port, misses
+        ),
+        "audit_lookup_miss",
+        "proxy_listener",
+        Connection::CONNECTION_LOGGER_KEY,
+    );
+}
+
+// the request is forwarded unsigned as the current key is empty,
+// the event is emitted once when the agent starts forwarding the requests unsigned
+fn report_unsigned_request(connection_id: u128) {
+    let count = proxy_metrics::record_unsigned_request();
+    Connection::write_warning(
+        connection_id,
+        "Current key is empty, forward the request without signature.".to_string(),
+    );
+    if !FORWARDING_UNSIGNED.swap(true, Ordering::Relaxed) {
+        event_logger::write_event(
+            event_logger::WARN_LEVEL,
+            format!(
+                "Current key is empty, the requests expected to be signed are forwarded without signature, {} unsigned requests so far.",
+                count
+            ),
+            "report_unsigned_request",
+            "proxy_listener",
+            logger::AGENT_LOGGER_KEY,
+        );
+    }
+}
+
+// the request is signed, ends the period of forwarding the requests unsigned
+fn report_signed_request() {
+    if FORWARDING_UNSIGNED.swap(false, Ordering::Relaxed) {
+        event_logger::write_event(
+            event_logger::INFO_LEVEL,
+            format!(
+                "Current key is available, the requests are signed again after {} unsigned requests so far.",
+                proxy_metrics::get_unsigned_request_count()
+            ),
+            "report_signed_request",
+            "proxy_listener",
+            logger::AGENT_LOGGER_KEY,
+        );
+    }
+}
+
+fn report_denied_process(connection_id: u128, claims: &Claims, pattern: &str) {
+    event_logger::write_event(
+        event_logger::WARN_LEVEL,
+        format!(
+            "Connection {} from process {} ({}) is denied by the process path '{}'.",
+            connection_id, claims.processFullPath, claims.processId, pattern
+        ),
+        "denied_process",
+        "proxy_listener",
+        Connection::CONNECTION_LOGGER_KEY,
+    );
+}
+
+// the signed requests are buffered to compute the signature, so they have the lower limit
+fn get_request_body_limit(request: &Request) -> usize {
+    let (low, large) = *REQUEST_BODY_LIMITS;
+    if is_streamed_request(request) {
+        // only the body read ahead with the headers is buffered
+        0
+    } else if request.need_skip_sig() {
+        large
+    } else {
+        low
+    }
+}
+
+// the unsigned request body is streamed to host when configured, so it is not capped by the large limit
+fn is_streamed_request(request: &Request) -> bool {
+    request.need_skip_sig() && config::get_stream_unsigned_request_body()
+}
+
+// the chunked request body is streamed to host as-is, only the unsigned streamed request could have it
+fn is_streamed_chunked_request(request: &Request) -> bool {
+    let chunked_only = request
+        .headers
+        .get_header(headers::TRANSFER_ENCODING_HEADER_NAME)
+        .map_or(false, |value| {
+            value
+                .trim()
+                .eq_ignore_ascii_case(headers::CHUNKED_TRANSFER_ENCODING)
+        });
+    chunked_only
+        && request
+            .headers
+            .get_header(headers::CONTENT_LENGTH_HEADER_NAME)
+            .is_none()
+        && is_streamed_request(request)
+}
+
+// returns the (low, large) limits to use, the low limit is capped by the large one
+fn validate_request_body_limits(low: usize, large: usize) -> (usize, usize) {
+    if large > REQUEST_BODY_LIMIT_WARNING_SIZE {
+        event_logger::write_event(
+            event_logger::WARN_LEVEL,
+            format!(
+                "Request body large limit {} bytes is over {} bytes, the requests could exhaust the memory.",
+                large, REQUEST_BODY_LIMIT_WARNING_SIZE
+            ),
+            "validate_request_body_limits",
+            "proxy_listener",
+            logger::AGENT_LOGGER_KEY,
+        );
+    }
+    if low > large {
+        event_logger::write_event(
+            event_logger::WARN_LEVEL,
+            format!(
+                "Request body low limit {} bytes is greater than the large limit {} bytes, use the large limit for both.",
+                low, large
+            ),
+            "validate_request_body_limits",
+            "proxy_listener",
+            logger::AGENT_LOGGER_KEY,
+        );
+        return (large, large);
+    }
+    (low, large)
+}
+
+// returns true if the new connection must be rejected,
+// the event is emitted once when the active connections reach the limit
+fn is_connection_limit_reached(active_connections: usize, max_active_connections: usize) -> bool {
+    if max_active_connections == 0 || active_connections < max_active_connections {
+        CONNECTION_LIMIT_REACHED.store(false, Ordering::Relaxed);
+        return false;
+    }
+
+    if !CONNECTION_LIMIT_REACHED.swap(true, Ordering::Relaxed) {
+        event_logger::write_event(
+            event_logger::WARN_LEVEL,
+            format!(
+                "Active connections reached the limit {}, apply the '{}' connection limit policy to the new connections.",
+                max_active_connections,
+                config::get_connection_limit_policy()
+            ),
+            "is_connection_limit_reached",
+            "proxy_listener",
+            logger::AGENT_LOGGER_KEY,
+        );
+    }
+    true
+}
+
+// the connections queued or handled by the listener, 0 if the listener is not running
+pub fn get_active_connection_count() -> usize {
+    match ACTIVE_CONNECTIONS.lock().unwrap().as_ref() {
+        Some(pending) => pending.load(Ordering::SeqCst),
+        None => 0,
+    }
+}
+
+// the connection ids allocated so far, it only grows and is not the count of the open connections
 pub fn get_proxy_connection_count() -> u128 {
-    unsafe { *CONNECTION_COUNT.lock().unwrap() }
+    CONNECTION_COUNT.load(Ordering::Relaxed) as u128
 }
 
 pub fn stop(port: u16) {
     SHUT_DOWN.store(true, Ordering::Relaxed);
-    let _ = TcpStream::connect(format!("127.0.0.1:{}", port));
+    let _ = TcpStream::connect(format!("{}:{}", get_local_ip(), port));
     logger::write_warning("Sending stop signal.".to_string());
+
+    // wait for the listener to drain or abort the in-flight connections
+    let deadline = Instant::now() + config::get_shutdown_hard_deadline() + Duration::from_secs(1);
+    while is_running() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
 }
 
-fn handle_connection(connection: &mut Connection) {
-    let mut stream = &connection.stream;
+fn handle_connection(connection: &mut Connection, resolver: &dyn DestinationResolver) {
+    handle_connection_with(
+        connection,
+        resolver,
+        config::get_authorize_once_per_connection(),
+    );
+}
+
+/*
+Handle the requests of the client connection, only one request unless authorize_once is set.
+With authorize_once, the connection serves the next request while the responses are fully forwarded,
+and the authorization decision is reused for the requests with the same claims, destination and path.
+ */
+fn handle_connection_with(
+    connection: &mut Connection,
+    resolver: &dyn DestinationResolver,
+    authorize_once: bool,
+) {
     Connection::write_information(connection.id, "Received connection.".to_string());
 
     // set read timeout to handle the case
     // when the actual body content is less than
-    // Content-Length in request header
-    _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+    // Content-Length in request header,
+    // the idle client connection is closed at the timeout as well
+    _ = connection
+        .stream
+        .set_read_timeout(Some(Duration::from_secs(10)));
 
-    // received data from original client
-    let mut request: Request;
-    match http::receive_request_data(&mut stream) {
-        Ok(data) => request = data,
-        Err(e) => {
-            Connection::write_warning(connection.id, format!("Failed to received data from client: {}", e));
+    loop {
+        // received data from original client
+        let request = match http::receive_request_data_with_body_limit(
+            &connection.stream,
+            get_request_body_limit,
+        ) {
+            Ok(data) => data,
+            Err(e) => {
+                Connection::write_warning(
+                    connection.id,
+                    format!("Failed to received data from client: {}", e),
+                );
+                return;
+            }
+        };
+        let keep_alive = authorize_once && request.is_keep_alive();
+        connection.keep_alive = false;
+        handle_request(connection, resolver, request);
+        if !keep_alive || !connection.keep_alive || SHUT_DOWN.load(Ordering::Relaxed) {
             return;
         }
-    };
+
+        // wait for the next request, the client closes the connection or it is idle until the read timeout
+        match connection.stream.peek(&mut [0u8; 1]) {
+            Ok(len) if len > 0 => {}
+            _ => return,
+        }
+        connection.now = Instant::now();
+        connection.cliams = None;
+        connection.ip = String::new();
+        connection.port = 0;
+        Connection::write_information(
+            connection.id,
+            "Received the next request on the connection.".to_string(),
+        );
+    }
+}
+
+fn handle_request(
+    connection: &mut Connection,
+    resolver: &dyn DestinationResolver,
+    mut request: Request,
+) {
+    let stream = &connection.stream;
     Connection::write_warning(connection.id, format!("Got request: {}", request.description()));
+    #[cfg(feature = "otel")]
+    {
+        let start_time = SystemTime::now() - connection.now.elapsed();
+        connection.span = Some(Span::start(connection.id, start_time, &request));
+    }
+    let body_limit = get_request_body_limit(&request);
+    if !is_streamed_request(&request)
+        && request.headers.get_content_length().unwrap_or(0) > body_limit
+    {
+        Connection::write_warning(
+            connection.id,
+            format!("Request body exceeds the limit {} bytes.", body_limit),
+        );
+        send_response(&stream, Some(&request), Response::PAYLOAD_TOO_LARGE);
+        log_connection_summary(
+            connection,
+            &request,
+            Response::PAYLOAD_TOO_LARGE.to_string(),
+        );
+        return;
+    }
+    if request
+        .headers
+        .get_header(headers::TRANSFER_ENCODING_HEADER_NAME)
+        .is_some()
+        && !is_streamed_chunked_request(&request)
+    {
+        // the buffered request body is only framed by Content-Length, a chunked body would desync the host connection
+        Connection::write_warning(
+            connection.id,
+            "The request with Transfer-Encoding is not supported.".to_string(),
+        );
+        send_response(&stream, Some(&request), Response::NOT_IMPLEMENTED);
+        log_connection_summary(connection, &request, Response::NOT_IMPLEMENTED.to_string());
+        return;
+    }
+    if request.is_http2_preface() {
+        // only HTTP/1.1 is supported, the clients should fall back to it
+        Connection::write_warning(
+            connection.id,
+            "HTTP/2 with prior knowledge is not supported.".to_string(),
+        );
+        send_response(
+            &stream,
+            Some(&request),
+            Response::HTTP_VERSION_NOT_SUPPORTED,
+        );
+        log_connection_summary(
+            connection,
+            &request,
+            Response::HTTP_VERSION_NOT_SUPPORTED.to_string(),
+        );
+        return;
+    }
+    if let Some(allowed_methods) = ALLOWED_METHODS.as_ref() {
+        if !is_method_allowed(&request.method, allowed_methods) {
+            Connection::write_warning(
+                connection.id,
+                format!("Request method {} is not allowed.", request.method),
+            );
+            send_method_not_allowed_response(&stream, &request, allowed_methods);
+            log_connection_summary(
+                connection,
+                &request,
+                Response::METHOD_NOT_ALLOWED.to_string(),
+            );
+            return;
+        }
+    }
 
     // lookup the eBPF audit_map
     let client_source_ip: IpAddr;
@@ -168,8 +771,21 @@ fn handle_connection(connection: &mut Connection) {
             return;
         }
     };
+    if !is_client_allowed(&client_source_ip, &ALLOWED_CLIENT_CIDRS) {
+        Connection::write_warning(
+            connection.id,
+            format!(
+                "Client {} is not in the allowed client CIDRs.",
+                client_source_ip
+            ),
+        );
+        send_response(&stream, Some(&request), Response::FORBIDDEN);
+        log_connection_summary(connection, &request, Response::FORBIDDEN.to_string());
+        return;
+    }
+
     let entry;
-    match redirector::lookup_audit(client_source_port) {
+    match resolver.lookup_audit(client_source_port) {
         Ok(data) => entry = data,
         Err(e) => {
             let err = format!("Failed to get lookup_audit: {}", e);
@@ -182,7 +798,7 @@ fn handle_connection(connection: &mut Connection) {
             );
 
             Connection::write_information(connection.id, "Try to get audit entry from socket stream".to_string());
-            match redirector::get_audit_from_stream(&stream) {
+            match resolver.get_audit_from_stream(&stream) {
                 Ok(data) => entry = data,
                 Err(e) => {
                     if e.kind() != std::io::ErrorKind::Unsupported {
@@ -195,20 +811,38 @@ fn handle_connection(connection: &mut Connection) {
                             Connection::CONNECTION_LOGGER_KEY,
                         );
                     }
-                    send_response(&stream, Response::MISDIRECTED);
+                    if handle_internal_request(connection, &request, &client_source_ip) {
+                        return;
+                    }
+                    report_audit_lookup_miss(&client_source_ip, client_source_port);
+                    send_response(&stream, Some(&request), Response::MISDIRECTED);
                     log_connection_summary(connection, &request, Response::MISDIRECTED.to_string());
                     return;
                 }
             }
         }
     }
-    let claims = Claims::from_audit_entry(&entry, client_source_ip);
+    let entry = match resolve_audit_entry(
+        connection.id,
+        entry,
+        &config::get_invalid_audit_entry_policy(),
+        &request,
+        || resolver.lookup_audit(client_source_port),
+    ) {
+        Some(entry) => entry,
+        None => {
+            send_response(&stream, Some(&request), Response::BAD_GATEWAY);
+            log_connection_summary(connection, &request, Response::BAD_GATEWAY.to_string());
+            return;
+        }
+    };
+    let claims = Claims::from_audit_entry(&entry, client_source_ip, connection.connected_at);
     let claim_details: String;
     match serde_json::to_string(&claims) {
         Ok(json) => claim_details = json,
         Err(e) => {
             Connection::write_warning(connection.id, format!("Failed to get claim json string: {}", e));
-            send_response(&stream, Response::MISDIRECTED);
+            send_response(&stream, Some(&request), Response::MISDIRECTED);
             log_connection_summary(connection, &request, Response::MISDIRECTED.to_string());
             return;
         }
@@ -216,129 +850,806 @@ fn handle_connection(connection: &mut Connection) {
     Connection::write(connection.id, claim_details.to_string());
     connection.cliams = Some(claims.clone());
 
+    // the denied processes are rejected regardless of the authorization rules and mode
+    if let Some(pattern) = get_denied_process_path(&claims.processFullPath, &DENIED_PROCESS_PATHS) {
+        report_denied_process(connection.id, &claims, pattern);
+        send_response(&stream, Some(&request), Response::FORBIDDEN);
+        log_connection_summary(connection, &request, Response::FORBIDDEN.to_string());
+        return;
+    }
+
     // Get the dst ip and port to remote server
     let (ip, port);
-    ip = redirector::ip_to_string(entry.destination_ipv4);
+    ip = entry.destination_addr().to_string();
     port = http::ntohs(entry.destination_port);
-    Connection::write(connection.id, format!("Use lookup value:{ip}:{port}."));
+    Connection::write(
+        connection.id,
+        format!(
+            "Use lookup value:{}.",
+            SocketAddr::new(entry.destination_addr(), port)
+        ),
+    );
     connection.ip = ip.to_string();
     connection.port = port;
+    let destination = SocketAddr::new(entry.destination_addr(), port);
+
+    if !RATE_LIMITER.lock().unwrap().try_acquire(client_source_ip) {
+        Connection::write_warning(
+            connection.id,
+            format!("Client {} exceeded the rate limit.", client_source_ip),
+        );
+        send_response(
+            &connection.stream,
+            Some(&request),
+            Response::TOO_MANY_REQUESTS,
+        );
+        log_connection_summary(
+            connection,
+            &request,
+            Response::TOO_MANY_REQUESTS.to_string(),
+        );
+        return;
+    }
 
     // authenticate the connection
     let auth = proxy_authentication::get_authenticate(ip.to_string(), port, claims.clone());
     Connection::write(connection.id, format!("Got auth: {}", auth.to_string()));
-    if !auth.authenticate(connection.id, request.url.to_string()) {
-        Connection::write_warning(connection.id, format!(
-            "Denied unauthorize request: {}",
-            claim_details.to_string()
-        ));
-        send_response(&stream, Response::FORBIDDEN);
-        log_connection_summary(connection, &request, Response::FORBIDDEN.to_string());
+    let authorized_request = get_authorized_request(
+        connection,
+        &request,
+        &claim_details,
+        proxy_authentication::get_rules_version(),
+        &key_keeper::get_current_key_details().guid,
+        misc_helpers::get_date_time_unix_nano(),
+    );
+    if !authorize_request(
+        connection,
+        auth.as_ref(),
+        &request,
+        &claim_details,
+        authorized_request,
+    ) {
         return;
     }
+    let stream = &connection.stream;
 
-    // start new request to the Host endpoint
-    let mut server_stream;
-    match http::connect_to_server(ip.to_string(), port, stream) {
-        Ok(data) => server_stream = data,
-        Err(e) => {
-            Connection::write_warning(connection.id, format!("Failed to start new request to host: {}", e));
-            send_response(&stream, Response::MISDIRECTED);
-            log_connection_summary(connection, &request, Response::MISDIRECTED.to_string());
-            return;
-        }
+    if !try_acquire_circuit(connection.id, destination) {
+        Connection::write_warning(
+            connection.id,
+            format!(
+                "The circuit of {} is open, reject the request.",
+                destination
+            ),
+        );
+        send_response(&stream, Some(&request), Response::SERVICE_UNAVAILABLE);
+        log_connection_summary(
+            connection,
+            &request,
+            Response::SERVICE_UNAVAILABLE.to_string(),
+        );
+        return;
+    }
+
+    if request.is_connect_request() {
+        return handle_tunnel_request(connection, &request);
+    }
+
+    if request.headers.remove_h2c_upgrade() {
+        Connection::write(
+            connection.id,
+            "Removed the h2c upgrade, forward the request over HTTP/1.1.".to_string(),
+        );
+    }
+
+    let mut removed_headers = request.headers.remove_hop_by_hop_headers();
+    removed_headers.extend(request.headers.filter(
+        &REQUEST_HEADER_DENY_LIST,
+        REQUEST_HEADER_ALLOW_LIST.as_deref(),
+    ));
+    if !removed_headers.is_empty() {
+        Connection::write(
+            connection.id,
+            format!(
+                "Removed the request headers: {}.",
+                removed_headers.join(", ")
+            ),
+        );
     }
 
     // Add required headers
-    let host_claims = format!(
-        "{{ \"{}\": \"{}\"}}",
-        constants::CLAIMS_IS_ROOT,
-        claims.runAsElevated
-    );
-    request.headers.add_header(
-        constants::CLAIMS_HEADER.to_string(),
-        host_claims.to_string(),
-    );
+    let host_claims = match HostClaims::from_claims(&claims).to_header_value() {
+        Ok(value) => value,
+        Err(e) => {
+            Connection::write_error(
+                connection.id,
+                format!("Failed to build the claims header: {}", e),
+            );
+            send_response(&stream, Some(&request), Response::BAD_GATEWAY);
+            log_connection_summary(connection, &request, Response::BAD_GATEWAY.to_string());
+            return;
+        }
+    };
+    request
+        .headers
+        .add_header(constants::CLAIMS_HEADER.to_string(), host_claims);
     request.headers.add_header(
         constants::DATE_HEADER.to_string(),
         misc_helpers::get_date_time_rfc1123_string(),
     );
-
-    if request.need_skip_sig() {
-        // skip the signature and send the request headers to host now
-        return handle_connection_without_signature(connection, request, &mut server_stream);
+    // added before the request is signed, so the header is covered by the signature
+    if config::get_forward_client_ip() {
+        let dropped = request.headers.append_forwarded_for(&claims.clientIp);
+        if !dropped.is_empty() {
+            Connection::write_warning(
+                connection.id,
+                format!(
+                    "Dropped the X-Forwarded-For values which are not ip addresses: {}.",
+                    dropped.join(", ")
+                ),
+            );
+        }
+    }
+    // rewritten before the request is signed, so the signature covers the Host header actually sent
+    if let Some(host) = get_upstream_host(
+        &config::get_host_header_policy(),
+        config::get_host_header_override(),
+        &destination,
+    ) {
+        let original_host = request.headers.rewrite_host(&host);
+        Connection::write(
+            connection.id,
+            format!(
+                "Rewrote the Host header '{}' to '{}'.",
+                original_host.unwrap_or_default(),
+                host
+            ),
+        );
+    }
+    // the upstream request continues the trace as a child of the connection span
+    #[cfg(feature = "otel")]
+    if let Some(span) = &connection.span {
+        request.headers.add_header(
+            proxy_trace::TRACEPARENT_HEADER.to_string(),
+            span.context.to_traceparent(),
+        );
     }
 
-    handle_connection_with_signature(connection, request, &mut server_stream);
-}
-
-fn handle_connection_with_signature(
-    connection: &mut Connection,
-    mut request: Request,
-    server_stream: &mut TcpStream,
-) {
-    let client_stream = &connection.stream;
-    if request.expect_continue_request() {
-        handle_expect_continue_request(connection, client_stream, &mut request);
+    let skip_reason =
+        get_skip_signature_reason(&request, &destination, &SKIP_SIGNATURE_DESTINATIONS);
+    if let Some(reason) = &skip_reason {
+        Connection::write_information(connection.id, format!("Skip the signature as {}.", reason));
     }
+    let skip_sig = skip_reason.is_some();
 
-    // Add header x-ms-azure-host-authorization
-    let key = key_keeper::get_current_key();
-    if key != "" {
-        let input_to_sign = request.as_sig_input();
-        match helpers::compute_signature(key.to_string(), &input_to_sign.as_slice()) {
-            Ok(sig) => {
-                match String::from_utf8(input_to_sign) {
-                    Ok(data) => {
-                        Connection::write(connection.id, format!("Computed the signature with input: {}", data))
-                    }
-                    Err(e) => {
-                        Connection::write_warning(connection.id, format!(
-                            "Failed convert the input_to_sign to string, error {}",
-                            e
-                        ));
-                    }
-                }
+    let upstream_timeout = config::get_proxy_upstream_timeout();
+    if let Some(tls) = config::get_upstream_tls(&ip, port) {
+        return handle_connection_with_tls(connection, request, &tls, skip_sig, upstream_timeout);
+    }
 
-                let authorization_value = format!(
-                    "{} {} {}",
-                    constants::AUTHORIZATION_SCHEME,
-                    key_keeper::get_current_key_guid(),
-                    sig
-                );
-                request.headers.add_header(
-                    constants::AUTHORIZATION_HEADER.to_string(),
-                    authorization_value.to_string(),
-                );
-                Connection::write(connection.id, format!(
-                    "Added authorization header {}",
-                    authorization_value.to_string()
-                ))
-            }
-            Err(e) => {
-                Connection::write_error(connection.id, format!("compute_signature failed with error: {}", e));
-            }
-        }
+    // start new request to the Host endpoint,
+    // only the signed requests reuse the idle connection as they can be resent if it is closed by the host
+    let pooled_stream = if skip_sig {
+        None
     } else {
-        Connection::write(connection.id, "current key is empty, skip compute signature for testing.".to_string());
+        UPSTREAM_POOL.checkout(&ip.to_string(), port)
+    };
+    let reused = pooled_stream.is_some();
+    let mut server_stream;
+    match pooled_stream.map_or_else(
+        || http::connect_to_server(ip.to_string(), port, stream, upstream_timeout),
+        Ok,
+    ) {
+        Ok(data) => server_stream = data,
+        Err(e) => {
+            Connection::write_warning(connection.id, format!("Failed to start new request to host: {}", e));
+            return send_upstream_error_response(connection, &request, e);
+        }
+    }
+    if reused {
+        Connection::write(
+            connection.id,
+            "Reused the idle connection to host.".to_string(),
+        );
+    }
+
+    if skip_sig {
+        // skip the signature and send the request headers to host now
+        return handle_connection_without_signature(
+            connection,
+            request,
+            &mut server_stream,
+            upstream_timeout,
+        );
+    }
+
+    if handle_connection_with_signature(
+        connection,
+        request,
+        &mut server_stream,
+        reused,
+        upstream_timeout,
+    ) && UPSTREAM_POOL.checkin(&ip.to_string(), port, server_stream)
+    {
+        Connection::write(
+            connection.id,
+            "Kept the connection to host alive for reuse.".to_string(),
+        );
+    }
+}
+
+// serve the internal endpoints for the requests sent to this listener directly from loopback,
+// returns true if the request is handled
+fn handle_internal_request(connection: &Connection, request: &Request, client_ip: &IpAddr) -> bool {
+    if !client_ip.is_loopback() {
+        return false;
+    }
+
+    let (path, query) = get_internal_path(request);
+    let method = request.method.to_uppercase();
+    let is_clear_user_cache_request = path == constants::USER_CACHE_ENDPOINT && method == "DELETE";
+    // windows has no control socket, the listener port is rebound here instead
+    let is_rebind_request = cfg!(windows) && path == constants::REBIND_ENDPOINT && method == "POST";
+    let is_logged_request = (path == constants::AUTHORIZATION_SIMULATE_ENDPOINT
+        || path == constants::SELF_TEST_ENDPOINT)
+        && method == "POST";
+
+    // the user cache clearing, the listener port rebinding, the recent connections of all the callers,
+    // the authorization simulation and the self test are only for the elevated callers
+    let is_elevated_request = is_clear_user_cache_request
+        || is_rebind_request
+        || is_logged_request
+        || (path == constants::RECENT_CONNECTIONS_ENDPOINT && method == "GET");
+    if is_elevated_request {
+        match proxy::is_loopback_client_elevated(&connection.stream) {
+            Ok(true) => {}
+            Ok(false) => {
+                Connection::write_warning(
+                    connection.id,
+                    format!("{} is only allowed for the elevated callers.", path),
+                );
+                send_response(&connection.stream, Some(request), Response::FORBIDDEN);
+                log_connection_summary(connection, request, Response::FORBIDDEN.to_string());
+                return true;
+            }
+            Err(e) => {
+                Connection::write_warning(
+                    connection.id,
+                    format!("Failed to get the elevation of the caller: {}", e),
+                );
+                send_response(&connection.stream, Some(request), Response::FORBIDDEN);
+                log_connection_summary(connection, request, Response::FORBIDDEN.to_string());
+                return true;
+            }
+        }
+    }
+
+    if is_clear_user_cache_request {
+        let count = proxy::clear_user_cache();
+        event_logger::write_event(
+            event_logger::INFO_LEVEL,
+            format!("Cleared {} cached users on demand.", count),
+            "handle_internal_request",
+            "proxy_listener",
+            logger::AGENT_LOGGER_KEY,
+        );
+        send_response(&connection.stream, Some(request), Response::OK);
+        return true;
+    }
+    if is_rebind_request {
+        handle_rebind_request(connection, request, &query);
+        return true;
+    }
+
+    let response = match get_control_response(connection.id, request) {
+        Some(response) => response,
+        None => return false,
+    };
+    let mut stream = &connection.stream;
+    _ = stream.write_all(&response.to_raw_bytes());
+    _ = stream.flush();
+    // the scrapes and the state queries are not logged nor counted as the proxied requests
+    if is_logged_request {
+        log_connection_summary(connection, request, response.status.to_string());
+    }
+    true
+}
+
+// the lowercased path without the trailing '/' and the query of the internal request
+// the new port in the query of the rebind request, e.g. POST /proxyagent/rebind?port=3081
+pub(super) fn get_rebind_port(query: &str) -> Option<u16> {
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key.eq_ignore_ascii_case("port"))
+        .and_then(|(_, value)| value.parse::<u16>().ok())
+        .filter(|port| *port != 0)
+}
+
+// the rebind waits for the in-flight connections to drain, this one included,
+// so it is accepted here and moves the listener after the response is sent
+fn handle_rebind_request(connection: &Connection, request: &Request, query: &str) {
+    let status = match get_rebind_port(query) {
+        Some(port) => {
+            _ = thread::Builder::new()
+                .name("rebind".to_string())
+                .spawn(move || {
+                    if let Err(e) = service::rebind_proxy_port(port) {
+                        logger::write_error(format!(
+                            "Failed to rebind proxy listener to port {}: {}",
+                            port, e
+                        ));
+                    }
+                });
+            Response::ACCEPTED
+        }
+        None => Response::BAD_REQUEST,
+    };
+    send_response(&connection.stream, Some(request), status);
+    log_connection_summary(connection, request, status.to_string());
+}
+
+pub(super) fn get_internal_path(request: &Request) -> (String, String) {
+    let (path, query) = match Url::parse(&request.url) {
+        Ok(url) => (
+            url.path().to_string(),
+            url.query().unwrap_or_default().to_string(),
+        ),
+        Err(_) => match request.url.split_once('?') {
+            Some((path, query)) => (path.to_string(), query.to_string()),
+            None => (request.url.to_string(), String::new()),
+        },
+    };
+    (path.trim_end_matches('/').to_lowercase(), query)
+}
+
+/*
+Build the response of the internal endpoints which do not change the agent state,
+they are served to the loopback clients of this listener and on the control socket.
+Returns None for the other requests, the caller must check the elevation of the recent connections, the simulation and the self test requests.
+ */
+pub(super) fn get_control_response(connection_id: u128, request: &Request) -> Option<Response> {
+    let (path, query) = get_internal_path(request);
+    let method = request.method.to_uppercase();
+    let mut response = if path == constants::METRICS_ENDPOINT && method == "GET" {
+        let body =
+            proxy_metrics::render(get_proxy_connection_count(), get_active_connection_count());
+        new_internal_response(body, "text/plain; version=0.0.4")
+    } else if path == constants::PROVISION_STATE_ENDPOINT && method == "GET" {
+        let body = serde_json::to_string(&provision::get_readiness()).unwrap_or_default();
+        new_internal_response(body, "application/json")
+    } else if path == constants::RECENT_CONNECTIONS_ENDPOINT && method == "GET" {
+        // the latest connection summaries, e.g. /proxyagent/recent?count=10&status=403&failedOnly=true
+        let mut count = usize::MAX;
+        let mut response_status = None;
+        let mut failed_only = false;
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            match key.to_lowercase().as_str() {
+                "count" => count = value.parse::<usize>().unwrap_or(count),
+                "status" => response_status = Some(value.to_string()),
+                "failedonly" => failed_only = value.eq_ignore_ascii_case("true"),
+                _ => {}
+            }
+        }
+        // the summaries are copied out of the buffer before the serialization
+        let summaries = proxy_agent_status::get_recent_connection_summaries(
+            count,
+            response_status.as_deref(),
+            failed_only,
+        );
+        let body = serde_json::to_string(&summaries).unwrap_or_default();
+        new_internal_response(body, "application/json")
+    } else if path == constants::AUTHORIZATION_SIMULATE_ENDPOINT && method == "POST" {
+        // evaluate the authorization rules for the synthetic claims and url in the body
+        let result = serde_json::from_slice(request.get_body())
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+            .and_then(|simulate_request| {
+                proxy_authentication::simulate(connection_id, simulate_request)
+            });
+        match result {
+            Ok(result) => new_internal_response(
+                serde_json::to_string(&result).unwrap_or_default(),
+                "application/json",
+            ),
+            Err(e) => Response::new(Response::BAD_REQUEST.to_string(), e.to_string()),
+        }
+    } else if path == constants::SELF_TEST_ENDPOINT && method == "POST" {
+        // the failed stage is reported in the body, the caller checks the succeeded flag
+        let result = self_test::run(connection_id);
+        new_internal_response(
+            serde_json::to_string(&result).unwrap_or_default(),
+            "application/json",
+        )
+    } else {
+        return None;
+    };
+    response.headers.add_header(
+        headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
+        response.get_body_len().to_string(),
+    );
+    Some(response)
+}
+
+fn new_internal_response(body: String, content_type: &str) -> Response {
+    let mut response = Response::new(Response::OK.to_string(), body);
+    response.headers.add_header(
+        headers::CONTENT_TYPE_HEADER_NAME.to_string(),
+        content_type.to_string(),
+    );
+    response
+}
+
+// only the loopback clients and the listener address itself are allowed by default if the listener is bound to a non-loopback address,
+// the redirector sends the traffic to the listener address, so the redirected connections come from that address;
+// the wildcard address is not defaulted as it serves the redirected clients from the local ip addresses too
+fn get_allowed_client_cidrs(
+    cidrs: Option<Vec<String>>,
+    listener_address: Option<Ipv4Addr>,
+) -> Option<Vec<Cidr>> {
+    match (cidrs, listener_address) {
+        (Some(cidrs), _) => Some(parse_client_cidrs(cidrs)),
+        (None, Some(ip)) if !ip.is_loopback() => {
+            let mut cidrs: Vec<String> = LOOPBACK_CLIENT_CIDRS
+                .iter()
+                .map(|cidr| cidr.to_string())
+                .collect();
+            cidrs.push(format!("{}/32", ip));
+            Some(parse_client_cidrs(cidrs))
+        }
+        _ => None,
+    }
+}
+
+fn parse_client_cidrs(cidrs: Vec<String>) -> Vec<Cidr> {
+    let mut allowed = Vec::new();
+    for cidr in cidrs {
+        match Cidr::parse(&cidr) {
+            Ok(c) => allowed.push(c),
+            Err(e) => {
+                event_logger::write_event(
+                    event_logger::WARN_LEVEL,
+                    format!("Ignore the invalid allowed client CIDR: {}", e),
+                    "parse_client_cidrs",
+                    "proxy_listener",
+                    logger::AGENT_LOGGER_KEY,
+                );
+            }
+        }
+    }
+    allowed
+}
+
+// '*' matches any characters including the path separators, the paths are case-insensitive on Windows
+fn parse_denied_process_paths(paths: Vec<String>) -> Vec<(String, regex::Regex)> {
+    let mut denied_paths = Vec::new();
+    for path in paths {
+        let pattern = path
+            .split('*')
+            .map(regex::escape)
+            .collect::<Vec<String>>()
+            .join(".*");
+        match regex::RegexBuilder::new(&format!("^{}$", pattern))
+            .case_insensitive(cfg!(windows))
+            .build()
+        {
+            Ok(re) => denied_paths.push((path, re)),
+            Err(e) => {
+                logger::write_warning(format!("Ignore the denied process path '{}': {}", path, e))
+            }
+        }
+    }
+    denied_paths
+}
+
+// returns the denied path pattern matched by the process full path
+fn get_denied_process_path<'a>(
+    process_full_path: &str,
+    denied_paths: &'a [(String, regex::Regex)],
+) -> Option<&'a str> {
+    denied_paths
+        .iter()
+        .find(|(_, re)| re.is_match(process_full_path))
+        .map(|(path, _)| path.as_str())
+}
+
+fn parse_skip_signature_destinations(
+    destinations: Vec<SkipSignatureDestination>,
+) -> Vec<(Cidr, Option<u16>)> {
+    let mut parsed = Vec::new();
+    for destination in destinations {
+        match Cidr::parse(&destination.cidr) {
+            Ok(cidr) => parsed.push((cidr, destination.port)),
+            Err(e) => {
+                event_logger::write_event(
+                    event_logger::WARN_LEVEL,
+                    format!("Ignore the invalid skip signature destination: {}", e),
+                    "parse_skip_signature_destinations",
+                    "proxy_listener",
+                    logger::AGENT_LOGGER_KEY,
+                );
+            }
+        }
+    }
+    parsed
+}
+
+// the reason to forward the request without the signature, None means the request is signed;
+// the unsigned requests are skipped by their method and url, or by their destination in the configured ranges
+fn get_skip_signature_reason(
+    request: &Request,
+    destination: &SocketAddr,
+    skip_destinations: &[(Cidr, Option<u16>)],
+) -> Option<String> {
+    if request.need_skip_sig() {
+        return Some(format!(
+            "{} {} is an unsigned request",
+            request.method, request.url
+        ));
+    }
+
+    let skipped = skip_destinations.iter().any(|(cidr, port)| {
+        cidr.contains(&destination.ip()) && port.map_or(true, |p| p == destination.port())
+    });
+    if skipped {
+        return Some(format!(
+            "destination {} is in skipSignatureDestinations",
+            destination
+        ));
+    }
+    None
+}
+
+// the Host header sent to the host by the policy, None to keep the client one
+fn get_upstream_host(
+    policy: &str,
+    host_override: Option<String>,
+    destination: &SocketAddr,
+) -> Option<String> {
+    match policy {
+        constants::HOST_HEADER_DESTINATION => {
+            if destination.port() == 80 {
+                // the default port is omitted, and the ipv6 address is bracketed as in the url
+                match destination.ip() {
+                    IpAddr::V4(ip) => Some(ip.to_string()),
+                    IpAddr::V6(ip) => Some(format!("[{}]", ip)),
+                }
+            } else {
+                Some(destination.to_string())
+            }
+        }
+        constants::HOST_HEADER_OVERRIDE => host_override,
+        _ => None,
+    }
+}
+
+// all the clients are allowed if the allowed client CIDRs are not configured
+fn is_client_allowed(client_ip: &IpAddr, allowed_cidrs: &Option<Vec<Cidr>>) -> bool {
+    match allowed_cidrs {
+        Some(cidrs) => cidrs.iter().any(|cidr| cidr.contains(client_ip)),
+        None => true,
+    }
+}
+
+fn is_method_allowed(method: &str, allowed_methods: &[String]) -> bool {
+    allowed_methods
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(method))
+}
+
+fn send_method_not_allowed_response(
+    mut client_stream: &TcpStream,
+    request: &Request,
+    allowed_methods: &[String],
+) {
+    let mut response = Response::from_status(Response::METHOD_NOT_ALLOWED.to_string());
+    for (name, value) in get_response_authorization_headers(Some(request)) {
+        response.headers.add_header(name.to_string(), value);
+    }
+    response.headers.add_header(
+        headers::ALLOW_HEADER_NAME.to_string(),
+        allowed_methods.join(", "),
+    );
+    _ = client_stream.write_all(response.to_raw_string().as_bytes());
+    _ = client_stream.flush();
+}
+
+// the decision is kept on the connection and reused for its next requests with the same authorized request,
+// the next requests are only served on the connection with authorizeOncePerConnection,
+// the denied request is responded with 403 and returns false
+fn authorize_request(
+    connection: &mut Connection,
+    auth: &dyn proxy_authentication::Authenticate,
+    request: &Request,
+    claim_details: &str,
+    authorized_request: String,
+) -> bool {
+    if connection.authorized_request.as_deref() == Some(authorized_request.as_str()) {
+        Connection::write(
+            connection.id,
+            "Reused the authorization decision of the connection.".to_string(),
+        );
+        return true;
+    }
+
+    if auth.authenticate(connection.id, get_authorization_url(request)) {
+        connection.authorized_request = Some(authorized_request);
+        return true;
+    }
+
+    proxy_metrics::record_authorization_denial();
+    Connection::write_warning(
+        connection.id,
+        format!("Denied unauthorize request: {}", claim_details),
+    );
+    send_response(&connection.stream, Some(request), Response::FORBIDDEN);
+    log_connection_summary(connection, request, Response::FORBIDDEN.to_string());
+    false
+}
+
+// the claims, the destination and the full url with the query of the request,
+// along with the rules version, the key guid and the time bucket the decision is made in
+fn get_authorized_request(
+    connection: &Connection,
+    request: &Request,
+    claim_details: &str,
+    rules_version: u64,
+    key_guid: &str,
+    now: i128,
+) -> String {
+    format!(
+        "{} {}:{} {} rules:{} key:{} time:{}",
+        claim_details,
+        connection.ip,
+        connection.port,
+        request.url,
+        rules_version,
+        key_guid,
+        now / AUTHORIZATION_TIME_BUCKET_IN_NANOSECONDS
+    )
+}
+
+// the client connection can serve the next request only if the whole response body is framed and forwarded
+fn is_response_complete(response: &Response, forwarded_body_len: usize) -> bool {
+    if response.headers.is_chunked_transfer_encoding() {
+        return true;
+    }
+    response
+        .headers
+        .get_header(headers::CONTENT_LENGTH_HEADER_NAME)
+        .is_some()
+        && response.headers.get_content_length().ok() == Some(forwarded_body_len)
+}
+
+// the url the authorization rules are evaluated against,
+// the CONNECT request url is in the authority-form 'host:port', which cannot be parsed as a url
+fn get_authorization_url(request: &Request) -> String {
+    if request.is_connect_request() {
+        let scheme = if request.url.ends_with(":443") {
+            "https"
+        } else {
+            "http"
+        };
+        return format!("{}://{}/", scheme, request.url);
+    }
+    request.url.to_string()
+}
+
+// relay the bytes between the client and host for the authorized CONNECT request,
+// the tunneled data is not signed as it is opaque to the proxy
+fn handle_tunnel_request(connection: &Connection, request: &Request) {
+    let client_stream = &connection.stream;
+    let server_stream = match http::connect_to_server(
+        connection.ip.to_string(),
+        connection.port,
+        client_stream,
+        config::get_proxy_upstream_timeout(),
+    ) {
+        Ok(stream) => stream,
+        Err(e) => {
+            Connection::write_warning(
+                connection.id,
+                format!("Failed to start the tunnel to host: {}", e),
+            );
+            return send_upstream_error_response(connection, request, e);
+        }
+    };
+    record_upstream_outcome(connection, true);
+
+    // the tunnel stays open until either side closes it or it is idle for the tunnel idle timeout
+    send_response(
+        client_stream,
+        Some(request),
+        Response::CONNECTION_ESTABLISHED,
+    );
+    Connection::write(
+        connection.id,
+        "Tunnel established, start to relay the data.".to_string(),
+    );
+
+    let (sent, received) = http::tunnel(
+        client_stream,
+        &server_stream,
+        config::get_tunnel_idle_timeout(),
+    );
+    Connection::write_information(
+        connection.id,
+        format!(
+            "Tunnel closed, relayed {} bytes to host and {} bytes to the client.",
+            sent, received
+        ),
+    );
+    write_connection_summary(
+        connection,
+        request,
+        Response::CONNECTION_ESTABLISHED.to_string(),
+        Some((sent, received)),
+        None,
+    );
+}
+
+// returns true if the connection to host can be reused for the next request
+fn handle_connection_with_signature(
+    connection: &mut Connection,
+    mut request: Request,
+    server_stream: &mut TcpStream,
+    reused: bool,
+    upstream_timeout: Duration,
+) -> bool {
+    let client_stream = &connection.stream;
+    if request.expect_continue_request() {
+        handle_expect_continue_request(connection, client_stream, &mut request);
+    }
+
+    if !add_authorization_header(connection, &mut request) {
+        send_signature_error_response(connection, &request);
+        return false;
     }
 
     // send to remote server
-    _ = server_stream.write_all(request.to_raw_string().as_bytes());
-    _ = server_stream.flush();
+    let retry_count = if is_idempotent_method(&request.method) {
+        config::get_upstream_retry_count()
+    } else {
+        0
+    };
+    // send the headers and the body as they are, without assembling a copy of the whole request
+    let raw_headers = request.get_raw_string_without_body();
+    let body: &[u8] = if request.expect_continue_request() {
+        // the body is sent after host responds with 'continue'
+        &[]
+    } else {
+        request.get_body()
+    };
+    if let Err(e) = send_request_to_host(
+        connection,
+        server_stream,
+        reused,
+        &[raw_headers.as_bytes(), body],
+        upstream_timeout,
+        retry_count,
+    ) {
+        send_upstream_error_response(connection, &request, e);
+        return false;
+    }
 
-    // insert default x-ms-azure-host-authorization header to let the client know it is through proxy agent
-    let mut extra_response_headers: HashMap<&str, &str> = HashMap::new();
-    extra_response_headers.insert(constants::AUTHORIZATION_HEADER, "value");
+    // insert x-ms-azure-host-authorization header to let the client verify it is through proxy agent
+    let authorization_headers = get_response_authorization_headers(Some(&request));
+    let extra_response_headers: HashMap<&str, &str> = authorization_headers
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
 
     let mut response_without_body;
+    let mut forwarded_body_len;
+    if let Err(e) = http::set_deadline(server_stream, Instant::now() + upstream_timeout) {
+        send_upstream_error_response(connection, &request, e);
+        return false;
+    }
     match http::forward_response(
         &server_stream,
         &client_stream,
         extra_response_headers.clone(),
+        config::get_max_response_body_size(),
     ) {
         Ok(data) => {
             response_without_body = data.0;
+            forwarded_body_len = data.1;
              Connection::write(connection.id, format!(
                 "Forwarded host response: {}, streamed body length: {}",
                 response_without_body.description(),
@@ -347,22 +1658,38 @@ fn handle_connection_with_signature(
         }
         Err(e) => {
             Connection::write_warning(connection.id, format!("Failed to forward response from host: {}", e));
-            return;
+            send_upstream_error_response(connection, &request, e);
+            return false;
         }
     };
 
     if response_without_body.is_continue_response() {
         Connection::write(connection.id, "Current response expect sending original request body now.".to_string());
+        let deadline = Instant::now() + upstream_timeout;
+        if let Err(e) = http::set_deadline(server_stream, deadline) {
+            send_upstream_error_response(connection, &request, e);
+            return false;
+        }
         _ = server_stream.write_all(&request.get_body());
         _ = server_stream.flush();
+        if let Err(e) = http::wait_for_response(server_stream, deadline) {
+            send_upstream_error_response(connection, &request, e);
+            return false;
+        }
+        if let Err(e) = http::set_deadline(server_stream, Instant::now() + upstream_timeout) {
+            send_upstream_error_response(connection, &request, e);
+            return false;
+        }
 
         match http::forward_response(
             &server_stream,
             &client_stream,
             extra_response_headers.clone(),
+            config::get_max_response_body_size(),
         ) {
             Ok(data) => {
                 response_without_body = data.0;
+                forwarded_body_len = data.1;
                  Connection::write(connection.id, format!(
                     "Forwarded host response: {}, streamed body length: {}",
                     response_without_body.description(),
@@ -371,123 +1698,719 @@ fn handle_connection_with_signature(
             }
             Err(e) => {
                  Connection::write_warning(connection.id, format!("Failed to forward response from host: {}", e));
-                return;
+                send_upstream_error_response(connection, &request, e);
+                return false;
             }
         };
     }
 
+    record_upstream_outcome(connection, true);
     log_connection_summary(
         connection,
         &request,
         response_without_body.status.to_string(),
     );
+
+    // the connection is reusable only after the whole response body is read from it
+    connection.keep_alive = is_response_complete(&response_without_body, forwarded_body_len);
+    response_without_body.is_keep_alive() && connection.keep_alive
 }
 
-fn handle_expect_continue_request(
-    connection: &Connection,
-    client_stream: &TcpStream,
-    request: &mut Request,
-) {
-    // send 'continue' response to the original client
-    send_response(client_stream, Response::CONTINUE);
+// Add header x-ms-azure-host-authorization,
+// returns false if the request cannot be signed and must not be forwarded
+fn add_authorization_header(connection: &Connection, request: &mut Request) -> bool {
+    sign_request(
+        connection.id,
+        request,
+        &config::get_signature_failure_policy(),
+        key_keeper::get_current_key_details,
+    )
+}
 
-    let content_length;
-    match request.headers.get_content_length() {
-        Ok(len) => content_length = len,
-        Err(e) => {
-             Connection::write_warning(connection.id, format!(" {}", e));
-            send_response(client_stream, Response::BAD_REQUEST);
-            log_connection_summary(connection, &request, Response::BAD_REQUEST.to_string());
-            return;
+// sign the request with the current key and apply the signature failure policy if it fails
+fn sign_request<F>(connection_id: u128, request: &mut Request, policy: &str, get_key: F) -> bool
+where
+    F: Fn() -> Key,
+{
+    let key = get_key();
+    if key.key == "" {
+        report_unsigned_request(connection_id);
+        return true;
+    }
+    let e = match compute_authorization_header(connection_id, request, &key) {
+        Ok(()) => {
+            report_signed_request();
+            return true;
+        }
+        Err(e) => e,
+    };
+
+    Connection::write_error(
+        connection_id,
+        format!(
+            "compute_signature failed with error: {}, apply the '{}' policy.",
+            e, policy
+        ),
+    );
+    match policy {
+        constants::SIGNATURE_FAILURE_RETRY => {
+            // the key could be rolled over or latched again in between
+            let key = get_key();
+            match compute_authorization_header(connection_id, request, &key) {
+                Ok(()) => true,
+                Err(e) => {
+                    Connection::write_error(
+                        connection_id,
+                        format!("compute_signature failed again with error: {}", e),
+                    );
+                    false
+                }
+            }
         }
+        constants::SIGNATURE_FAILURE_FORWARD => {
+            Connection::write_warning(
+                connection_id,
+                "Forward the request without the authorization header.".to_string(),
+            );
+            true
+        }
+        _ => false,
     }
+}
 
-    // receive body content from client
-    let data;
-    match http::receive_body(&client_stream, content_length) {
-        Ok(d) => data = d,
-        Err(e) => {
-             Connection::write_warning(connection.id, format!("Failed to received body from client: {}", e));
-            send_response(client_stream, Response::BAD_REQUEST);
-            log_connection_summary(connection, &request, Response::BAD_REQUEST.to_string());
-            return;
+fn compute_authorization_header(
+    connection_id: u128,
+    request: &mut Request,
+    key: &Key,
+) -> std::io::Result<()> {
+    let authorization_value = http_request::add_authorization_header(request, key)?;
+    if request.get_body_len() <= MAX_LOGGED_SIG_INPUT_BODY_SIZE {
+        match String::from_utf8(request.as_sig_input()) {
+            Ok(data) => Connection::write(
+                connection_id,
+                format!("Computed the signature with input: {}", data),
+            ),
+            Err(e) => {
+                Connection::write_warning(
+                    connection_id,
+                    format!("Failed convert the input_to_sign to string, error {}", e),
+                );
+            }
         }
+    } else {
+        Connection::write(
+            connection_id,
+            format!(
+                "Computed the signature with the body of {} bytes.",
+                request.get_body_len()
+            ),
+        );
     }
-    request.set_body(data);
+
+    Connection::write(
+        connection_id,
+        format!("Added authorization header {}", authorization_value),
+    );
+    Ok(())
 }
 
-fn handle_connection_without_signature(
+// send the request over TLS to host and forward the response,
+// the TLS connections are not kept in the idle pool, which is for the plain TCP connections only
+fn handle_connection_with_tls(
     connection: &mut Connection,
     mut request: Request,
-    server_stream: &mut TcpStream,
+    tls: &UpstreamTls,
+    skip_sig: bool,
+    upstream_timeout: Duration,
 ) {
-     Connection::write_information(connection.id, format!(
-        "Current request {} could send to host without signature.",
-        request.description()
-    ));
-    let mut client_stream = &connection.stream;
+    let streamed = is_streamed_request(&request);
+    if request.expect_continue_request() {
+        if streamed {
+            // the body is streamed to host after the headers
+            send_response(&connection.stream, Some(&request), Response::CONTINUE);
+        } else {
+            // receive the body from the client now, so host is not asked to 'continue'
+            handle_expect_continue_request(connection, &connection.stream, &mut request);
+        }
+        request.headers.remove_header(headers::EXPECT_HEADER_NAME);
+    }
+    if !skip_sig && !add_authorization_header(connection, &mut request) {
+        return send_signature_error_response(connection, &request);
+    }
 
-    // send the request without signature to host
-    _ = server_stream.write_all(request.to_raw_string().as_bytes());
-    _ = server_stream.flush();
-    let mut response;
-    match http::receive_response_data(server_stream) {
-        Ok(data) => response = data,
+    let mut server_stream = match connect_with_tls(connection, tls, upstream_timeout) {
+        Ok(stream) => stream,
         Err(e) => {
-             Connection::write_warning(connection.id, format!("Failed to receive data from host: {}", e));
-            send_response(&client_stream, Response::BAD_GATEWAY);
-            log_connection_summary(connection, &request, Response::BAD_GATEWAY.to_string());
-            return;
+            Connection::write_warning(
+                connection.id,
+                format!("Failed to start new TLS request to host: {}", e),
+            );
+            return send_upstream_error_response(connection, &request, e);
         }
     };
-     Connection::write(connection.id, format!(
-        "Received host response: {}",
-        response.description()
-    ));
+    Connection::write(
+        connection.id,
+        format!(
+            "Connected to host over TLS with server name {}.",
+            tls.serverName.as_deref().unwrap_or(&tls.ip)
+        ),
+    );
 
-    if response.is_continue_response() {
-        let content_length;
-        match request.headers.get_content_length() {
-            Ok(len) => content_length = len,
-            Err(e) => {
-                 Connection::write_warning(connection.id, format!(" {}", e));
-                send_response(&client_stream, Response::BAD_REQUEST);
-                log_connection_summary(connection, &request, Response::BAD_REQUEST.to_string());
-                return;
-            }
+    if let Err(e) = server_stream.write_all(request.get_raw_string_without_body().as_bytes()) {
+        return send_upstream_error_response(connection, &request, e);
+    }
+    if streamed {
+        if !forward_streamed_request_body(connection, &request, &mut server_stream) {
+            return;
         }
+    } else if let Err(e) = server_stream.write_all(request.get_body()) {
+        return send_upstream_error_response(connection, &request, e);
+    }
+    let sent = server_stream.flush().and_then(|_| {
+        wait_for_upstream_response(&server_stream.sock, Instant::now() + upstream_timeout)
+    });
+    if let Err(e) = sent {
+        return send_upstream_error_response(connection, &request, e);
+    }
 
-        // send 'continue' response to the original client
-        send_response(&client_stream, Response::CONTINUE);
-
-        Connection::write(connection.id, "Current response expect streaming original body now.".to_string());
-        match http::stream_body(&mut client_stream, server_stream, content_length) {
-            Ok(l) => {
-                if l < content_length {
-                     Connection::write_warning(connection.id, format!(
-                        "Streamed data {} from request body is less than Content-Length {}",
-                        l, content_length
-                    ));
-                    send_response(&client_stream, Response::BAD_REQUEST);
-                    log_connection_summary(connection, &request, Response::BAD_REQUEST.to_string());
-                    return;
-                }
-            }
-            Err(e) => {
-                 Connection::write_warning(connection.id, format!("Failed streaming the request body, error {}", e));
-                send_response(&client_stream, Response::BAD_GATEWAY);
-                log_connection_summary(connection, &request, Response::BAD_GATEWAY.to_string());
-                return;
+    // insert x-ms-azure-host-authorization header to let the client verify it is through proxy agent
+    let authorization_headers = get_response_authorization_headers(Some(&request));
+    let extra_response_headers: HashMap<&str, &str> = authorization_headers
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    match http::forward_response_from(
+        &mut server_stream,
+        &connection.stream,
+        extra_response_headers,
+        config::get_max_response_body_size(),
+    ) {
+        Ok((response, forwarded_body_len)) => {
+            Connection::write(
+                connection.id,
+                format!(
+                    "Forwarded host response over TLS: {}, streamed body length: {}",
+                    response.description(),
+                    forwarded_body_len
+                ),
+            );
+            record_upstream_outcome(connection, true);
+            log_connection_summary(connection, &request, response.status.to_string());
+            connection.keep_alive = is_response_complete(&response, forwarded_body_len);
+        }
+        Err(e) => {
+            Connection::write_warning(
+                connection.id,
+                format!("Failed to forward the TLS response from host: {}", e),
+            );
+            send_upstream_error_response(connection, &request, e);
+        }
+    }
+}
+
+// the handshake is bounded by the upstream timeout as well
+fn connect_with_tls(
+    connection: &Connection,
+    tls: &UpstreamTls,
+    upstream_timeout: Duration,
+) -> std::io::Result<tls::TlsStream> {
+    let tcp_stream = http::connect_to_server(
+        connection.ip.to_string(),
+        connection.port,
+        &connection.stream,
+        upstream_timeout,
+    )?;
+    tcp_stream.set_read_timeout(Some(upstream_timeout))?;
+    tcp_stream.set_write_timeout(Some(upstream_timeout))?;
+    tls::connect(tcp_stream, tls)
+}
+
+// send the request to host and wait for host to start responding within the timeout,
+// resend the request on a new connection if the connection fails before host responds:
+//   the reused connection could be closed by host while it was idle, the request is resent once if it failed to be written,
+//   otherwise it is retried up to retry_count times
+fn send_request_to_host(
+    connection: &Connection,
+    server_stream: &mut TcpStream,
+    mut reused: bool,
+    request_parts: &[&[u8]],
+    upstream_timeout: Duration,
+    retry_count: u32,
+) -> std::io::Result<()> {
+    let mut retried = 0;
+    loop {
+        let deadline = Instant::now() + upstream_timeout;
+        http::set_deadline(server_stream, deadline)?;
+        let written = request_parts
+            .iter()
+            .try_for_each(|part| server_stream.write_all(part))
+            .and_then(|_| server_stream.flush());
+        let write_failed = written.is_err();
+        let e = match written.and_then(|_| wait_for_upstream_response(server_stream, deadline)) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if !is_connection_error(&e) {
+            return Err(e);
+        }
+
+        // host could have processed the request once it is written,
+        // so the request written to the reused connection is not resent unless it is allowed to retry
+        if reused && write_failed {
+            Connection::write_warning(
+                connection.id,
+                "The reused connection is closed by host, resend the request on a new connection."
+                    .to_string(),
+            );
+        } else if retried < retry_count {
+            retried += 1;
+            event_logger::write_event(
+                event_logger::WARN_LEVEL,
+                format!(
+                    "Connection {}: retry {} of the request to {}:{} after the connection error: {}",
+                    connection.id, retried, connection.ip, connection.port, e
+                ),
+                "send_request_to_host",
+                "proxy_listener",
+                logger::AGENT_LOGGER_KEY,
+            );
+        } else {
+            return Err(e);
+        }
+
+        reused = false;
+        *server_stream = http::connect_to_server(
+            connection.ip.to_string(),
+            connection.port,
+            &connection.stream,
+            upstream_timeout,
+        )?;
+    }
+}
+
+// wait for host to start responding to the request sent,
+// the injected faults are applied here, so they fail the request as the real upstream failures
+fn wait_for_upstream_response(server_stream: &TcpStream, deadline: Instant) -> std::io::Result<()> {
+    #[cfg(feature = "fault-injection")]
+    super::fault_injection::apply_fault(deadline)?;
+    http::wait_for_response(server_stream, deadline)
+}
+
+// the connection failed before any response is received, so the request can be safely resent
+fn is_connection_error(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::BrokenPipe
+            | ErrorKind::NotConnected
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+// the idempotent methods could be retried without changing the result on host
+fn is_idempotent_method(method: &str) -> bool {
+    matches!(
+        method.to_uppercase().as_str(),
+        "GET" | "HEAD" | "PUT" | "DELETE"
+    )
+}
+
+// returns false if the circuit of the destination is open and the request is rejected
+fn try_acquire_circuit(connection_id: u128, destination: SocketAddr) -> bool {
+    let mut breaker = CIRCUIT_BREAKER.lock().unwrap();
+    let acquired = breaker.try_acquire(destination);
+    // the request let through a half open circuit is its probe,
+    // the circuit turns half open when its cool down is over
+    let probing = acquired && breaker.get_state(destination) == CircuitState::HalfOpen;
+    if !acquired || probing {
+        publish_circuit_breaker_status(&breaker);
+    }
+    drop(breaker);
+    if probing {
+        Connection::write_information(
+            connection_id,
+            format!(
+                "The circuit of {} is half open, let the request through as its probe.",
+                destination
+            ),
+        );
+    }
+    acquired
+}
+
+// swap in the states of the breaker for the status, the disabled breaker never changes
+fn publish_circuit_breaker_status(breaker: &CircuitBreaker) {
+    if breaker.is_enabled() {
+        let states = Arc::new(get_circuit_breaker_states(breaker));
+        *CIRCUIT_BREAKER_STATUS.write().unwrap() = states;
+    }
+}
+
+fn get_circuit_breaker_states(breaker: &CircuitBreaker) -> HashMap<String, String> {
+    let mut states = HashMap::new();
+    states.insert(
+        "circuitBreakerOpenedCount".to_string(),
+        breaker.get_opened_count().to_string(),
+    );
+    states.insert(
+        "circuitBreakerRejectedCount".to_string(),
+        breaker.get_rejected_count().to_string(),
+    );
+    states.insert(
+        "circuitBreakerStates".to_string(),
+        breaker.get_states().join(", "),
+    );
+    states
+}
+
+// the upstream responses close the circuit of the destination and the upstream errors could open it
+fn record_upstream_outcome(connection: &Connection, responded: bool) {
+    let ip = match connection.ip.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => return,
+    };
+    let destination = SocketAddr::new(ip, connection.port);
+    let mut breaker = CIRCUIT_BREAKER.lock().unwrap();
+    let closed = responded && breaker.record_success(destination);
+    let opened = !responded && breaker.record_failure(destination);
+    publish_circuit_breaker_status(&breaker);
+    drop(breaker);
+    if responded {
+        if closed {
+            Connection::write_information(
+                connection.id,
+                format!("Closed the circuit of {} as host responded.", destination),
+            );
+        }
+    } else if opened {
+        let message = format!(
+            "Opened the circuit of {} after consecutive failures, reject its requests for {:?}.",
+            destination,
+            config::get_circuit_breaker_cool_down()
+        );
+        Connection::write_warning(connection.id, message.to_string());
+        event_logger::write_event(
+            event_logger::WARN_LEVEL,
+            message,
+            "record_upstream_outcome",
+            "proxy_listener",
+            logger::AGENT_LOGGER_KEY,
+        );
+    }
+}
+
+// respond 504 if host did not respond in time, otherwise 502,
+// the failure class is returned in the x-ms-proxy-error header and recorded in the connection summary
+fn send_upstream_error_response(connection: &Connection, request: &Request, e: std::io::Error) {
+    #[cfg(feature = "fault-injection")]
+    if let Some(status) = super::fault_injection::get_injected_status(&e) {
+        Connection::write_warning(
+            connection.id,
+            format!("Injected fault response: {}", status),
+        );
+        record_upstream_outcome(connection, false);
+        return send_proxy_error_response(connection, request, &status, INJECTED_FAULT);
+    }
+
+    let error_class = classify_upstream_error(&e);
+    let status = match error_class {
+        UPSTREAM_TIMEOUT => Response::GATEWAY_TIMEOUT,
+        CLIENT_ERROR => Response::BAD_REQUEST,
+        _ => Response::BAD_GATEWAY,
+    };
+    Connection::write_warning(
+        connection.id,
+        format!("Request to host failed with {}: {}", error_class, e),
+    );
+    // the client stream failures are not failures of the host, they do not open its circuit
+    if error_class != CLIENT_ERROR {
+        record_upstream_outcome(connection, false);
+    }
+
+    send_proxy_error_response(connection, request, status, error_class);
+}
+
+// respond 502 to the request failed to be signed, it is not sent to host
+fn send_signature_error_response(connection: &Connection, request: &Request) {
+    send_proxy_error_response(connection, request, Response::BAD_GATEWAY, SIGNATURE_ERROR);
+}
+
+fn send_proxy_error_response(
+    connection: &Connection,
+    request: &Request,
+    status: &str,
+    error_class: &'static str,
+) {
+    let mut response = Response::from_status(status.to_string());
+    for (name, value) in get_response_authorization_headers(Some(request)) {
+        response.headers.add_header(name.to_string(), value);
+    }
+    response.headers.add_header(
+        constants::PROXY_ERROR_HEADER.to_string(),
+        error_class.to_string(),
+    );
+    let mut client_stream = &connection.stream;
+    _ = client_stream.write_all(response.to_raw_string().as_bytes());
+    _ = client_stream.flush();
+
+    write_connection_summary(
+        connection,
+        request,
+        status.to_string(),
+        None,
+        Some(error_class),
+    );
+}
+
+const UPSTREAM_TIMEOUT: &str = "timeout";
+const SIGNATURE_ERROR: &str = "signature_error";
+const CLIENT_ERROR: &str = "client_error";
+#[cfg(feature = "fault-injection")]
+const INJECTED_FAULT: &str = "injected_fault";
+
+// the failure class names the kind of the failure only, the error details are not returned to the client
+fn classify_upstream_error(e: &std::io::Error) -> &'static str {
+    if http::is_client_stream_error(e) {
+        return CLIENT_ERROR;
+    }
+    let is_tls_error = e
+        .get_ref()
+        .map_or(false, |inner| inner.is::<rustls::Error>());
+    if is_tls_error {
+        return "tls_error";
+    }
+
+    match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => UPSTREAM_TIMEOUT,
+        ErrorKind::ConnectionRefused => "connection_refused",
+        ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::BrokenPipe
+        | ErrorKind::NotConnected
+        | ErrorKind::UnexpectedEof => "connection_closed",
+        ErrorKind::InvalidData | ErrorKind::InvalidInput => "protocol_error",
+        _ => "upstream_error",
+    }
+}
+
+fn handle_expect_continue_request(
+    connection: &Connection,
+    client_stream: &TcpStream,
+    request: &mut Request,
+) {
+    // send 'continue' response to the original client
+    send_response(client_stream, Some(request), Response::CONTINUE);
+
+    let content_length;
+    match request.headers.get_content_length() {
+        Ok(len) => content_length = len,
+        Err(e) => {
+             Connection::write_warning(connection.id, format!(" {}", e));
+            send_response(client_stream, Some(request), Response::BAD_REQUEST);
+            log_connection_summary(connection, &request, Response::BAD_REQUEST.to_string());
+            return;
+        }
+    }
+
+    // receive body content from client
+    let data;
+    match http::receive_body(&client_stream, content_length) {
+        Ok(d) => data = d,
+        Err(e) => {
+             Connection::write_warning(connection.id, format!("Failed to received body from client: {}", e));
+            send_response(client_stream, Some(request), Response::BAD_REQUEST);
+            log_connection_summary(connection, &request, Response::BAD_REQUEST.to_string());
+            return;
+        }
+    }
+    request.set_body(data);
+}
+
+// stream the request body to host, the client is responded if it fails
+fn forward_streamed_request_body<W: Write>(
+    connection: &Connection,
+    request: &Request,
+    dest_stream: W,
+) -> bool {
+    if request.headers.is_chunked_transfer_encoding() {
+        return match http::stream_chunked_body(&connection.stream, request.get_body(), dest_stream)
+        {
+            Ok(len) => {
+                Connection::write(
+                    connection.id,
+                    format!("Streamed chunked request body to host, length: {}", len),
+                );
+                true
+            }
+            Err(e) => {
+                send_upstream_error_response(connection, request, e);
+                false
             }
         };
+    }
+
+    let content_length = match request.headers.get_content_length() {
+        Ok(len) => len,
+        Err(e) => {
+            Connection::write_warning(connection.id, format!(" {}", e));
+            send_response(&connection.stream, Some(request), Response::BAD_REQUEST);
+            log_connection_summary(connection, request, Response::BAD_REQUEST.to_string());
+            return false;
+        }
+    };
+    match stream_request_body(&connection.stream, request, content_length, dest_stream) {
+        Ok(len) if len < content_length => {
+            Connection::write_warning(
+                connection.id,
+                format!(
+                    "Streamed data {} from request body is less than Content-Length {}",
+                    len, content_length
+                ),
+            );
+            send_response(&connection.stream, Some(request), Response::BAD_REQUEST);
+            log_connection_summary(connection, request, Response::BAD_REQUEST.to_string());
+            false
+        }
+        Ok(len) => {
+            Connection::write(
+                connection.id,
+                format!("Streamed request body to host, length: {}", len),
+            );
+            true
+        }
+        Err(e) => {
+            send_upstream_error_response(connection, request, e);
+            false
+        }
+    }
+}
+
+// send the body buffered with the request, then the rest of the body still in the client stream,
+// returns the body length sent
+fn stream_request_body<W: Write>(
+    client_stream: &TcpStream,
+    request: &Request,
+    content_length: usize,
+    mut dest_stream: W,
+) -> std::io::Result<usize> {
+    dest_stream.write_all(request.get_body())?;
+    let remaining = content_length.saturating_sub(request.get_body_len());
+    let streamed = http::stream_body(client_stream, &mut dest_stream, remaining)?;
+    Ok(request.get_body_len() + streamed)
+}
+
+fn handle_connection_without_signature(
+    connection: &mut Connection,
+    request: Request,
+    server_stream: &mut TcpStream,
+    upstream_timeout: Duration,
+) {
+     Connection::write_information(connection.id, format!(
+        "Current request {} could send to host without signature.",
+        request.description()
+    ));
+    let mut client_stream = &connection.stream;
+
+    // send the request without signature to host
+    let deadline = Instant::now() + upstream_timeout;
+    if let Err(e) = http::set_deadline(server_stream, deadline) {
+        return send_upstream_error_response(connection, &request, e);
+    }
+    _ = server_stream.write_all(request.get_raw_string_without_body().as_bytes());
+    if request.expect_continue_request() {
+        // the body is streamed after host asks to 'continue'
+    } else if is_streamed_request(&request) {
+        if !forward_streamed_request_body(connection, &request, &mut *server_stream) {
+            return;
+        }
+    } else {
+        _ = server_stream.write_all(request.get_body());
+    }
+    _ = server_stream.flush();
+    if let Err(e) = wait_for_upstream_response(server_stream, deadline) {
+        return send_upstream_error_response(connection, &request, e);
+    }
+    if let Err(e) = http::set_deadline(server_stream, Instant::now() + upstream_timeout) {
+        return send_upstream_error_response(connection, &request, e);
+    }
+    let mut response;
+    match http::receive_response_data_with_limit(
+        server_stream,
+        config::get_max_response_body_size(),
+    ) {
+        Ok(data) => response = data,
+        Err(e) => {
+             Connection::write_warning(connection.id, format!("Failed to receive data from host: {}", e));
+            return send_upstream_error_response(connection, &request, e);
+        }
+    };
+     Connection::write(connection.id, format!(
+        "Received host response: {}",
+        response.description()
+    ));
+
+    if response.is_continue_response() {
+        let content_length;
+        match request.headers.get_content_length() {
+            Ok(len) => content_length = len,
+            Err(e) => {
+                 Connection::write_warning(connection.id, format!(" {}", e));
+                send_response(&client_stream, Some(&request), Response::BAD_REQUEST);
+                log_connection_summary(connection, &request, Response::BAD_REQUEST.to_string());
+                return;
+            }
+        }
+
+        // send 'continue' response to the original client
+        send_response(&client_stream, Some(&request), Response::CONTINUE);
+
+        Connection::write(connection.id, "Current response expect streaming original body now.".to_string());
+        let deadline = Instant::now() + upstream_timeout;
+        if let Err(e) = http::set_deadline(server_stream, deadline) {
+            return send_upstream_error_response(connection, &request, e);
+        }
+        if request.headers.is_chunked_transfer_encoding() {
+            if !forward_streamed_request_body(connection, &request, &mut *server_stream) {
+                return;
+            }
+        } else {
+            match http::stream_body(&mut client_stream, server_stream, content_length) {
+                Ok(l) => {
+                    if l < content_length {
+                         Connection::write_warning(connection.id, format!(
+                            "Streamed data {} from request body is less than Content-Length {}",
+                            l, content_length
+                        ));
+                        send_response(&client_stream, Some(&request), Response::BAD_REQUEST);
+                        log_connection_summary(connection, &request, Response::BAD_REQUEST.to_string());
+                        return;
+                    }
+                }
+                Err(e) => {
+                     Connection::write_warning(connection.id, format!("Failed streaming the request body, error {}", e));
+                    send_response(&client_stream, Some(&request), Response::BAD_GATEWAY);
+                    log_connection_summary(connection, &request, Response::BAD_GATEWAY.to_string());
+                    return;
+                }
+            };
+        }
 
-        match http::receive_response_data(server_stream) {
+        if let Err(e) = http::wait_for_response(server_stream, deadline) {
+            return send_upstream_error_response(connection, &request, e);
+        }
+        if let Err(e) = http::set_deadline(server_stream, Instant::now() + upstream_timeout) {
+            return send_upstream_error_response(connection, &request, e);
+        }
+        match http::receive_response_data_with_limit(
+            server_stream,
+            config::get_max_response_body_size(),
+        ) {
             Ok(data) => response = data,
             Err(e) => {
                  Connection::write_warning(connection.id, format!("Failed to receive data from host: {}", e));
-                send_response(&client_stream, Response::BAD_GATEWAY);
-                log_connection_summary(connection, &request, Response::BAD_GATEWAY.to_string());
-                return;
+                return send_upstream_error_response(connection, &request, e);
             }
         };
          Connection::write(connection.id, format!(
@@ -496,20 +2419,111 @@ fn handle_connection_without_signature(
         ));
     }
 
-    // insert default x-ms-azure-host-authorization header to let the client know it is through proxy agent
-    response.headers.add_header(
-        constants::AUTHORIZATION_HEADER.to_string(),
-        "value".to_string(),
-    );
+    // insert x-ms-azure-host-authorization header to let the client verify it is through proxy agent
+    for (name, value) in get_response_authorization_headers(Some(&request)) {
+        response.headers.add_header(name.to_string(), value);
+    }
+    response.headers.remove_hop_by_hop_headers();
 
     // response to original client
     _ = client_stream.write_all(&response.to_raw_bytes());
     _ = client_stream.flush();
 
+    record_upstream_outcome(connection, true);
     log_connection_summary(connection, &request, response.status.to_string());
+    // the chunked response body is not buffered on this path
+    connection.keep_alive = !response.headers.is_chunked_transfer_encoding()
+        && is_response_complete(&response, response.get_body_len());
+}
+
+// validate the audit entry and apply the invalid audit entry policy to it,
+// returns None if the request must be rejected
+fn resolve_audit_entry<F>(
+    connection_id: u128,
+    mut entry: AuditEntry,
+    policy: &str,
+    request: &Request,
+    lookup: F,
+) -> Option<AuditEntry>
+where
+    F: Fn() -> std::io::Result<AuditEntry>,
+{
+    if entry.is_valid() {
+        return Some(entry);
+    }
+
+    Connection::write_warning(
+        connection_id,
+        format!(
+            "Invalid audit entry: {}, apply the '{}' policy.",
+            serde_json::to_string(&entry).unwrap_or_default(),
+            policy
+        ),
+    );
+    match policy {
+        constants::INVALID_AUDIT_ENTRY_RETRY => {
+            for _ in 0..INVALID_AUDIT_ENTRY_RETRY_COUNT {
+                thread::sleep(INVALID_AUDIT_ENTRY_RETRY_DELAY);
+                match lookup() {
+                    Ok(data) if data.is_valid() => return Some(data),
+                    _ => {}
+                }
+            }
+            Connection::write_warning(
+                connection_id,
+                "The audit entry is still invalid after retries.".to_string(),
+            );
+            None
+        }
+        constants::INVALID_AUDIT_ENTRY_FORWARD => {
+            if entry.destination_addr().is_unspecified() || entry.destination_port == 0 {
+                // fall back to the destination requested by the client
+                match get_request_destination(request) {
+                    Some((ip, port)) => entry.set_destination(ip, port),
+                    None => {
+                        Connection::write_warning(
+                            connection_id,
+                            "Failed to get the destination from the request.".to_string(),
+                        );
+                        return None;
+                    }
+                }
+            }
+            Some(entry)
+        }
+        _ => None,
+    }
+}
+
+// the ip destination from the absolute request url or the Host header
+fn get_request_destination(request: &Request) -> Option<(IpAddr, u16)> {
+    let url = match request.get_url() {
+        Some(url) => url,
+        None => {
+            let host = request.headers.get_header(headers::HOST_HEADER_NAME)?;
+            Url::parse(&format!("http://{}", host)).ok()?
+        }
+    };
+    match url.host() {
+        Some(url::Host::Ipv4(ip)) => Some((IpAddr::V4(ip), url.port_or_known_default()?)),
+        Some(url::Host::Ipv6(ip)) => Some((IpAddr::V6(ip), url.port_or_known_default()?)),
+        _ => None,
+    }
 }
 
 fn log_connection_summary(connection: &Connection, request: &Request, response_status: String) {
+    write_connection_summary(connection, request, response_status, None, None)
+}
+
+// tunnel_bytes is the bytes relayed by the CONNECT tunnel, (from client, from host)
+// upstream_error is the failure class when the request to host failed
+fn write_connection_summary(
+    connection: &Connection,
+    request: &Request,
+    response_status: String,
+    tunnel_bytes: Option<(u64, u64)>,
+    upstream_error: Option<&str>,
+) {
     let elapsed_time = connection.now.elapsed();
     let claims = match &connection.cliams {
         Some(c) => c.clone(),
@@ -523,43 +2537,153 @@ fn log_connection_summary(connection: &Connection, request: &Request, response_s
         clientIp: claims.clientIp.to_string(),
         processFullPath: claims.processFullPath.to_string(),        
         processCmdLine: claims.processCmdLine.to_string(),
+        processExeSha256: claims.processExeSha256.clone(),
+        processSigner: claims.processSigner.clone(),
         runAsElevated: claims.runAsElevated,
+        isSystemAccount: connection
+            .cliams
+            .as_ref()
+            .map_or(false, |c| c.is_system_account()),
         method: request.method.to_string(),
         url: request.url.to_string(),
         ip: connection.ip.to_string(),
         port: connection.port,
         responseStatus: response_status.to_string(),
         elapsedTime: elapsed_time.as_millis(),
+        tunnelBytesSent: tunnel_bytes.map(|bytes| bytes.0),
+        tunnelBytesReceived: tunnel_bytes.map(|bytes| bytes.1),
+        authorization: None,
+        upstreamError: upstream_error.map(|class| class.to_string()),
     };
     match serde_json::to_string(&summary) {
         Ok(json) => {
-            event_logger::write_event(
-                event_logger::INFO_LEVEL,
-                json,
-                "log_connection_summary",
-                "proxy_listener",
-                Connection::CONNECTION_LOGGER_KEY,
-            );
+            if should_emit_summary(
+                &summary.responseStatus,
+                &SUCCESS_SUMMARY_COUNT,
+                config::get_connection_summary_sample_rate(),
+            ) {
+                event_logger::write_event(
+                    event_logger::INFO_LEVEL,
+                    json,
+                    "log_connection_summary",
+                    "proxy_listener",
+                    Connection::CONNECTION_LOGGER_KEY,
+                );
+            } else {
+                // sampled out of the telemetry, it is still in the connection log
+                Connection::write(connection.id, json);
+            }
         }
         Err(_) => {}
     };
+    #[cfg(feature = "otel")]
+    if let Some(span) = &connection.span {
+        span.end(&summary);
+    }
+    proxy_metrics::record_request(&summary.responseStatus, elapsed_time);
+    log_slow_request(&summary, config::get_slow_request_threshold());
     proxy_agent_status::add_connection_summary(summary, false);
 }
 
-fn send_response(mut client_stream: &TcpStream, status: &str) {
-    let mut response = Response::from_status(status.to_string());
+// the failed and denied summaries are always emitted, 1 in 'sample_rate' 2xx summaries is emitted
+fn should_emit_summary(response_status: &str, success_count: &AtomicU64, sample_rate: u32) -> bool {
+    if !response_status.starts_with('2') || sample_rate <= 1 {
+        return true;
+    }
+    success_count.fetch_add(1, Ordering::Relaxed) % sample_rate as u64 == 0
+}
 
-    // insert default x-ms-azure-host-authorization header to let the client know it is through proxy agent
-    response.headers.add_header(
-        constants::AUTHORIZATION_HEADER.to_string(),
-        "value".to_string(),
+// emit the warning event if the request took longer than the threshold,
+// returns true if the slow request event is emitted
+fn log_slow_request(summary: &ProxySummary, threshold: Duration) -> bool {
+    if summary.elapsedTime <= threshold.as_millis() {
+        return false;
+    }
+
+    let json = serde_json::to_string(summary).unwrap_or_default();
+    event_logger::write_event(
+        event_logger::WARN_LEVEL,
+        format!(
+            "Slow request: took {}ms which exceeds the threshold {}ms. {}",
+            summary.elapsedTime,
+            threshold.as_millis(),
+            json
+        ),
+        "log_slow_request",
+        "proxy_listener",
+        logger::AGENT_LOGGER_KEY,
     );
+    true
+}
+
+/*
+    The x-ms-azure-host-authorization header of the response lets the client verify the response is through proxy agent:
+        <authorization scheme of the current key> <current key guid> <hex encoded HMAC signature>
+    The signed input is "<request method>\n<request url>\n<x-ms-azure-host-date>",
+    the signed date is returned in the x-ms-azure-host-date header of the same response,
+    the method and url are empty if the response is sent before the request is read.
+    The header is UNSIGNED_RESPONSE_AUTHORIZATION before the key is latched, it only tells the response is from proxy agent.
+    A client holding the key treats a response without a verified header as not through proxy agent.
+*/
+fn get_response_authorization_headers(request: Option<&Request>) -> Vec<(&'static str, String)> {
+    let date = misc_helpers::get_date_time_rfc1123_string();
+    let key = key_keeper::get_current_key_details();
+    let (method, url) = request.map_or(("", ""), |r| (r.method.as_str(), r.url.as_str()));
+    let authorization = build_response_authorization(
+        &key.authorizationScheme,
+        &key.key,
+        &key.guid,
+        method,
+        url,
+        &date,
+    );
+    vec![
+        (constants::AUTHORIZATION_HEADER, authorization),
+        (constants::DATE_HEADER, date),
+    ]
+}
+
+fn build_response_authorization(
+    authorization_scheme: &str,
+    key: &str,
+    key_guid: &str,
+    method: &str,
+    url: &str,
+    date: &str,
+) -> String {
+    if key.is_empty() {
+        return constants::UNSIGNED_RESPONSE_AUTHORIZATION.to_string();
+    }
+
+    let input = get_response_sig_input(method, url, date);
+    let authorization =
+        helpers::build_authorization_header(authorization_scheme, key, key_guid, input.as_bytes());
+    match authorization {
+        Ok(authorization) => authorization,
+        Err(e) => {
+            logger::write_warning(format!("Failed to sign the response authorization: {}", e));
+            constants::UNSIGNED_RESPONSE_AUTHORIZATION.to_string()
+        }
+    }
+}
+
+fn get_response_sig_input(method: &str, url: &str, date: &str) -> String {
+    format!("{}\n{}\n{}", method, url, date)
+}
+
+fn send_response(mut client_stream: &TcpStream, request: Option<&Request>, status: &str) {
+    let mut response = Response::from_status(status.to_string());
+    for (name, value) in get_response_authorization_headers(request) {
+        response.headers.add_header(name.to_string(), value);
+    }
 
     // response to original client
     _ = client_stream.write_all(response.to_raw_string().as_bytes());
     _ = client_stream.flush();
 }
 
+// the status is polled by the provision check and the status reporting,
+// it only reads the counters and the published snapshots, never the locks taken by the requests
 pub fn get_status() -> ProxyAgentDetailStatus {
     let shutdown = SHUT_DOWN.clone();
     let status;
@@ -569,39 +2693,75 @@ pub fn get_status() -> ProxyAgentDetailStatus {
         status = ModuleState::RUNNING.to_string();
     }
 
+    let mut states = HashMap::new();
+    states.insert(
+        "activeConnections".to_string(),
+        get_active_connection_count().to_string(),
+    );
+    states.insert(
+        "totalConnections".to_string(),
+        get_proxy_connection_count().to_string(),
+    );
+    states.insert(
+        "auditLookupMisses".to_string(),
+        proxy_metrics::get_audit_lookup_miss_count().to_string(),
+    );
+    // the snapshot is cloned out of the lock, the states are copied after it is released
+    let breaker_states = CIRCUIT_BREAKER_STATUS.read().unwrap().clone();
+    states.extend(
+        breaker_states
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+    );
+
     ProxyAgentDetailStatus {
         status,
-        message: unsafe { STATUS_MESSAGE.to_string() },
-        states: None,
+        message: helpers::cap_status_message(
+            unsafe { STATUS_MESSAGE.to_string() },
+            "proxy_listener",
+        ),
+        states: Some(states),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::common::config;
     use crate::common::constants;
+    use crate::common::helpers;
     use crate::common::http;
     use crate::common::http::headers;
     use crate::common::http::request::Request;
     use crate::common::http::response::Response;
     use crate::common::logger;
+    use crate::key_keeper::key::Key;
+    use crate::proxy::proxy_authentication;
     use crate::proxy::proxy_listener;
     use crate::proxy::proxy_listener::Connection;
+    use crate::proxy::proxy_metrics;
+    use crate::proxy::proxy_summary::ProxySummary;
     use crate::proxy::Claims;
+    use crate::redirector::AuditEntry;
+    use crate::test_mock::redirector_mock::FixedDestinationResolver;
+    use crate::test_mock::server_mock;
     use proxy_agent_shared::logger_manager;
     use std::env;
     use std::fs;
-    use std::io::Write;
+    use std::io::{Read, Write};
+    use std::net::IpAddr;
+    use std::net::SocketAddr;
     use std::net::TcpListener;
     use std::net::TcpStream;
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
     use std::sync::Arc;
     use std::time::Duration;
     use std::time::Instant;
+    use std::time::SystemTime;
     use std::{thread, time};
 
-    #[test]
-    fn direct_request_test() {
-        let logger_key = "direct_request_test";
+    // start the listener on the port for the requests sent to it directly from loopback
+    fn start_direct_listener(logger_key: &str, port: u16) -> (PathBuf, thread::JoinHandle<()>) {
         let mut temp_test_path = env::temp_dir();
         temp_test_path.push(logger_key);
         logger_manager::init_logger(
@@ -613,39 +2773,611 @@ mod tests {
         );
         Connection::init_logger(temp_test_path.to_path_buf());
 
-        // start listener, the port must different from the one used in production code
-        let port: u16 = 8091;
+        // the stop signal of the listener in the previous test is still set
+        super::SHUT_DOWN.store(false, Ordering::Relaxed);
         let handle = thread::spawn(move || {
             proxy_listener::start(port, 1);
         });
-
         // give some time to let the listener started
-        let sleep_duration = time::Duration::from_millis(100);
-        thread::sleep(sleep_duration);
+        thread::sleep(time::Duration::from_millis(100));
+        (temp_test_path, handle)
+    }
+
+    fn stop_direct_listener(port: u16, handle: thread::JoinHandle<()>, temp_test_path: PathBuf) {
+        proxy_listener::stop(port);
+        handle.join().unwrap();
+
+        // clean up and ignore the clean up errors
+        _ = fs::remove_dir_all(temp_test_path);
+    }
 
+    fn send_direct_request(port: u16, request: &mut Request) -> Response {
         let mut client = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
-        let mut request = Request::new(format!("http://127.0.0.1:{}", port), "GET".to_string());
         client
             .write_all(request.to_raw_string().as_bytes())
             .unwrap();
         client.flush().unwrap();
+        http::receive_response_data(&mut client).unwrap()
+    }
 
-        let response = http::receive_response_data(&mut client).unwrap();
-
-        // stop listener
-        proxy_listener::stop(port);
-        handle.join().unwrap();
+    #[test]
+    fn direct_request_test() {
+        // start listener, the port must different from the one used in production code
+        let port: u16 = 8091;
+        let (temp_test_path, handle) = start_direct_listener("direct_request_test", port);
 
+        let mut request = Request::new(format!("http://127.0.0.1:{}", port), "GET".to_string());
+        let response = send_direct_request(port, &mut request);
         assert_eq!(
             Response::MISDIRECTED,
             response.status,
             "response.status mismatched."
         );
 
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn transfer_encoding_request_test() {
+        let port: u16 = 8101;
+        let (temp_test_path, handle) =
+            start_direct_listener("transfer_encoding_request_test", port);
+
+        // the request body framed by Transfer-Encoding cannot be forwarded
+        for (transfer_encoding, body) in [
+            (headers::CHUNKED_TRANSFER_ENCODING, "0\r\n\r\n"),
+            ("gzip", ""),
+        ] {
+            let mut client = TcpStream::connect(format!("127.0.0.1:{}", port)).unwrap();
+            let mut request =
+                Request::new(format!("http://127.0.0.1:{}", port), "POST".to_string());
+            request.headers.add_header(
+                headers::TRANSFER_ENCODING_HEADER_NAME.to_string(),
+                transfer_encoding.to_string(),
+            );
+            client
+                .write_all(request.to_raw_string().as_bytes())
+                .unwrap();
+            client.write_all(body.as_bytes()).unwrap();
+            client.flush().unwrap();
+            let response = http::receive_response_data(&mut client).unwrap();
+            assert_eq!(
+                Response::NOT_IMPLEMENTED,
+                response.status,
+                "Transfer-Encoding '{}' must not be forwarded",
+                transfer_encoding
+            );
+        }
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn user_cache_endpoint_test() {
+        let port: u16 = 8102;
+        let (temp_test_path, handle) = start_direct_listener("user_cache_endpoint_test", port);
+
+        // the other tests may cache the same user again, but never with an older cached time
+        let logon_id = 0u64;
+        let get_cached_time = || {
+            crate::proxy::USERS
+                .lock()
+                .unwrap()
+                .get(&logon_id)
+                .map(|u| u.1)
+        };
+        _ = crate::proxy::get_user(logon_id);
+        let cached_time = get_cached_time().expect("user must be cached");
+
+        // clear the user cache from the internal endpoint
+        let mut request = Request::new(
+            constants::USER_CACHE_ENDPOINT.to_string(),
+            "DELETE".to_string(),
+        );
+        let response = send_direct_request(port, &mut request);
+        // only the elevated callers are allowed, the test process is the caller
+        #[cfg(not(windows))]
+        let elevated = unsafe { libc::geteuid() } == 0;
+        #[cfg(windows)]
+        let elevated = crate::proxy::windows::is_process_elevated(std::process::id()).unwrap();
+        if elevated {
+            assert_eq!(Response::OK, response.status, "response.status mismatched.");
+            assert!(
+                get_cached_time()
+                    .filter(|time| *time <= cached_time)
+                    .is_none(),
+                "the cached user must be cleared"
+            );
+        } else {
+            assert_eq!(Response::FORBIDDEN, response.status);
+            assert_eq!(
+                Some(cached_time),
+                get_cached_time(),
+                "the cached user must be kept"
+            );
+        }
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn rebind_endpoint_test() {
+        assert_eq!(Some(3081), proxy_listener::get_rebind_port("port=3081"));
+        assert_eq!(None, proxy_listener::get_rebind_port("port=0"));
+        assert_eq!(None, proxy_listener::get_rebind_port("other=3081"));
+
+        let port: u16 = 8112;
+        let (temp_test_path, handle) = start_direct_listener("rebind_endpoint_test", port);
+
+        // rebinding to the current port is a no-op
+        let mut request = Request::new(
+            format!("{}?port={}", constants::REBIND_ENDPOINT, port),
+            "POST".to_string(),
+        );
+        let response = send_direct_request(port, &mut request);
+        // the port is rebound on the control socket only, except on windows which has none
+        #[cfg(not(windows))]
+        assert_ne!(Response::ACCEPTED, response.status);
+        #[cfg(windows)]
+        if response.status != Response::FORBIDDEN {
+            assert_eq!(Response::ACCEPTED, response.status);
+        }
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn metrics_endpoint_test() {
+        let port: u16 = 8103;
+        let (temp_test_path, handle) = start_direct_listener("metrics_endpoint_test", port);
+        let mut request = Request::new(format!("http://127.0.0.1:{}", port), "GET".to_string());
+        let response = send_direct_request(port, &mut request);
+        assert_eq!(Response::MISDIRECTED, response.status);
+
+        // scrape the metrics from the internal endpoint
+        let mut request = Request::new(constants::METRICS_ENDPOINT.to_string(), "GET".to_string());
+        let response = send_direct_request(port, &mut request);
+        assert_eq!(Response::OK, response.status, "response.status mismatched.");
+        let metrics = response.get_body_as_string().unwrap();
+        assert!(
+            metrics.contains("azure_proxy_agent_requests_total{status=\"421\"}"),
+            "the misdirected request must be counted"
+        );
+        assert!(metrics.contains("azure_proxy_agent_active_connections 1"));
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn connection_status_test() {
+        let port: u16 = 8104;
+        let (temp_test_path, handle) = start_direct_listener("connection_status_test", port);
+        let mut request = Request::new(format!("http://127.0.0.1:{}", port), "GET".to_string());
+        _ = send_direct_request(port, &mut request);
+
+        let states = proxy_listener::get_status().states.unwrap();
+        assert!(states.contains_key("activeConnections"));
+        assert_eq!(
+            proxy_listener::get_proxy_connection_count().to_string(),
+            states["totalConnections"]
+        );
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn misdirected_request_test() {
+        let port: u16 = 8105;
+        let (temp_test_path, handle) = start_direct_listener("misdirected_request_test", port);
+        let mut request = Request::new(format!("http://127.0.0.1:{}", port), "GET".to_string());
+        let response = send_direct_request(port, &mut request);
+        assert_eq!(Response::MISDIRECTED, response.status);
+
+        let states = proxy_listener::get_status().states.unwrap();
+        assert_ne!(
+            "0", states["auditLookupMisses"],
+            "the misdirected request must be counted"
+        );
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn circuit_breaker_status_test() {
+        let port: u16 = 8106;
+        let (temp_test_path, handle) = start_direct_listener("circuit_breaker_status_test", port);
+        let mut request = Request::new(format!("http://127.0.0.1:{}", port), "GET".to_string());
+        _ = send_direct_request(port, &mut request);
+
+        let states = proxy_listener::get_status().states.unwrap();
+        assert_eq!(
+            "0", states["circuitBreakerOpenedCount"],
+            "the circuit breaker is disabled by default"
+        );
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn provision_state_endpoint_test() {
+        let port: u16 = 8107;
+        let (temp_test_path, handle) = start_direct_listener("provision_state_endpoint_test", port);
+
+        // get the provision state with the readiness flags from the internal endpoint
+        let mut request = Request::new(
+            constants::PROVISION_STATE_ENDPOINT.to_string(),
+            "GET".to_string(),
+        );
+        let response = send_direct_request(port, &mut request);
+        assert_eq!(Response::OK, response.status, "response.status mismatched.");
+        let readiness: serde_json::Value =
+            serde_json::from_str(&response.get_body_as_string().unwrap()).unwrap();
+        assert_eq!(true, readiness["listenerReady"]);
+        for flag in [
+            "provisioned",
+            "keyPresent",
+            "redirectorRunning",
+            "rulesLoaded",
+        ] {
+            assert!(
+                readiness[flag].is_boolean(),
+                "missing readiness flag {flag}"
+            );
+        }
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn recent_connections_endpoint_test() {
+        let port: u16 = 8108;
+        let (temp_test_path, handle) =
+            start_direct_listener("recent_connections_endpoint_test", port);
+
+        // query the latest failed connections from the internal endpoint
+        let mut request = Request::new(
+            format!(
+                "{}?count=5&failedOnly=true",
+                constants::RECENT_CONNECTIONS_ENDPOINT
+            ),
+            "GET".to_string(),
+        );
+        let response = send_direct_request(port, &mut request);
+        // only the elevated callers are allowed
+        #[cfg(not(windows))]
+        let elevated = unsafe { libc::geteuid() } == 0;
+        #[cfg(windows)]
+        let elevated = response.status != Response::FORBIDDEN;
+        if elevated {
+            assert_eq!(Response::OK, response.status, "response.status mismatched.");
+            let recent: Vec<serde_json::Value> =
+                serde_json::from_str(&response.get_body_as_string().unwrap()).unwrap();
+            assert!(recent.len() <= 5);
+            assert!(recent
+                .iter()
+                .all(|summary| !summary["responseStatus"].as_str().unwrap().starts_with('2')));
+        } else {
+            assert_eq!(Response::FORBIDDEN, response.status);
+        }
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn authorization_simulate_endpoint_test() {
+        let port: u16 = 8109;
+        let (temp_test_path, handle) =
+            start_direct_listener("authorization_simulate_endpoint_test", port);
+
+        // simulate the authorization decision, only the elevated callers are allowed
+        let mut request = Request::new(
+            constants::AUTHORIZATION_SIMULATE_ENDPOINT.to_string(),
+            "POST".to_string(),
+        );
+        let mut claims = Claims::empty();
+        claims.runAsElevated = true;
+        request.set_body_as_string(
+            serde_json::json!({
+                "destination": "IMDS",
+                "url": "http://169.254.169.254/metadata/instance",
+                "claims": claims,
+            })
+            .to_string(),
+        );
+        request.headers.add_header(
+            headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
+            request.get_body_len().to_string(),
+        );
+        let response = send_direct_request(port, &mut request);
+        #[cfg(not(windows))]
+        let elevated = unsafe { libc::geteuid() } == 0;
+        #[cfg(windows)]
+        let elevated = response.status != Response::FORBIDDEN;
+        if elevated {
+            assert_eq!(Response::OK, response.status, "response.status mismatched.");
+            let result: serde_json::Value =
+                serde_json::from_str(&response.get_body_as_string().unwrap()).unwrap();
+            assert_eq!("IMDS", result["destination"]);
+            assert!(result["allowed"].is_boolean());
+        } else {
+            assert_eq!(Response::FORBIDDEN, response.status);
+        }
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn self_test_endpoint_test() {
+        let port: u16 = 8110;
+        let (temp_test_path, handle) = start_direct_listener("self_test_endpoint_test", port);
+
+        // run the self test, only the elevated callers are allowed
+        let mut request = Request::new(
+            constants::SELF_TEST_ENDPOINT.to_string(),
+            "POST".to_string(),
+        );
+        request.headers.add_header(
+            headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
+            "0".to_string(),
+        );
+        let response = send_direct_request(port, &mut request);
+        #[cfg(not(windows))]
+        let elevated = unsafe { libc::geteuid() } == 0;
+        #[cfg(windows)]
+        let elevated = response.status != Response::FORBIDDEN;
+        if elevated {
+            assert_eq!(Response::OK, response.status, "response.status mismatched.");
+            let result: serde_json::Value =
+                serde_json::from_str(&response.get_body_as_string().unwrap()).unwrap();
+            assert!(result["succeeded"].is_boolean());
+            assert_eq!("bind", result["stages"][0]["name"]);
+        } else {
+            assert_eq!(Response::FORBIDDEN, response.status);
+        }
+
+        stop_direct_listener(port, handle, temp_test_path);
+    }
+
+    #[test]
+    fn rebind_test() {
+        let port: u16 = 8111;
+        let (temp_test_path, handle) = start_direct_listener("rebind_test", port);
+
+        // rebind the listener to another port
+        let new_port: u16 = 8092;
+        proxy_listener::rebind(new_port, 1).unwrap();
+        handle.join().unwrap();
+        assert_eq!(new_port, proxy_listener::get_port());
+        thread::sleep(time::Duration::from_millis(100));
+        let mut request = Request::new(format!("http://127.0.0.1:{}", new_port), "GET".to_string());
+        let response = send_direct_request(new_port, &mut request);
+        assert_eq!(
+            Response::MISDIRECTED,
+            response.status,
+            "the rebound listener must serve the request."
+        );
+        assert!(
+            TcpStream::connect(format!("127.0.0.1:{}", port)).is_err(),
+            "the previous port must be released"
+        );
+
+        // stop listener
+        proxy_listener::stop(new_port);
+
         // clean up and ignore the clean up errors
         _ = fs::remove_dir_all(temp_test_path);
     }
 
+    #[test]
+    fn denied_process_path_test() {
+        let denied_paths = super::parse_denied_process_paths(vec![
+            "/usr/bin/python*".to_string(),
+            "*/curl".to_string(),
+            "/usr/bin/perl".to_string(),
+        ]);
+        assert_eq!(3, denied_paths.len());
+
+        assert_eq!(
+            Some("/usr/bin/python*"),
+            super::get_denied_process_path("/usr/bin/python3.10", &denied_paths)
+        );
+        assert_eq!(
+            Some("*/curl"),
+            super::get_denied_process_path("/opt/tools/bin/curl", &denied_paths)
+        );
+        assert_eq!(
+            Some("/usr/bin/perl"),
+            super::get_denied_process_path("/usr/bin/perl", &denied_paths)
+        );
+        assert_eq!(
+            None,
+            super::get_denied_process_path("/usr/bin/perl5", &denied_paths),
+            "the path without '*' must match exactly"
+        );
+        assert_eq!(
+            None,
+            super::get_denied_process_path("/usr/bin/curl.sh", &denied_paths)
+        );
+        assert_eq!(
+            None,
+            super::get_denied_process_path("/usr/bin/python3", &[]),
+            "no process is denied by default"
+        );
+
+        // the regex characters in the path are literal
+        let denied_paths = super::parse_denied_process_paths(vec!["/opt/a+b/[x].sh".to_string()]);
+        assert!(super::get_denied_process_path("/opt/a+b/[x].sh", &denied_paths).is_some());
+        assert!(super::get_denied_process_path("/opt/aab/x.sh", &denied_paths).is_none());
+    }
+
+    #[test]
+    fn is_client_allowed_test() {
+        let loopback: IpAddr = "127.0.0.1".parse().unwrap();
+        let local_ip: IpAddr = "10.0.0.4".parse().unwrap();
+        let remote_ip: IpAddr = "192.168.1.10".parse().unwrap();
+
+        assert!(
+            super::is_client_allowed(&remote_ip, &None),
+            "all clients are allowed when the allowed client CIDRs are not configured"
+        );
+
+        let allowed_cidrs = Some(super::parse_client_cidrs(vec![
+            "127.0.0.0/8".to_string(),
+            "10.0.0.0/24".to_string(),
+            "invalid".to_string(),
+        ]));
+        assert!(super::is_client_allowed(&loopback, &allowed_cidrs));
+        assert!(super::is_client_allowed(&local_ip, &allowed_cidrs));
+        assert!(
+            !super::is_client_allowed(&remote_ip, &allowed_cidrs),
+            "client outside the allowed client CIDRs must not be allowed"
+        );
+
+        let allowed_cidrs = Some(Vec::new());
+        assert!(
+            !super::is_client_allowed(&loopback, &allowed_cidrs),
+            "no client is allowed with an empty allowed client CIDRs"
+        );
+
+        // only the loopback clients are allowed by default on the non-loopback listener
+        let allowed_cidrs =
+            super::get_allowed_client_cidrs(None, Some("10.0.0.4".parse().unwrap()));
+        assert!(super::is_client_allowed(&loopback, &allowed_cidrs));
+        assert!(super::is_client_allowed(
+            &"::1".parse().unwrap(),
+            &allowed_cidrs
+        ));
+        assert!(
+            super::is_client_allowed(&local_ip, &allowed_cidrs),
+            "the redirected client from the listener address must be allowed by default"
+        );
+        assert!(
+            !super::is_client_allowed(&"10.0.0.5".parse().unwrap(), &allowed_cidrs),
+            "only the listener address itself is allowed by default"
+        );
+        assert!(
+            !super::is_client_allowed(&remote_ip, &allowed_cidrs),
+            "the remote client must not be allowed on the non-loopback listener by default"
+        );
+        assert!(super::get_allowed_client_cidrs(None, None).is_none());
+        assert!(
+            super::get_allowed_client_cidrs(None, Some("127.0.0.1".parse().unwrap())).is_none()
+        );
+        let allowed_cidrs = super::get_allowed_client_cidrs(
+            Some(vec!["192.168.1.0/24".to_string()]),
+            Some("10.0.0.4".parse().unwrap()),
+        );
+        assert!(
+            super::is_client_allowed(&remote_ip, &allowed_cidrs),
+            "the configured allowed client CIDRs are kept"
+        );
+    }
+
+    #[test]
+    fn get_authorization_url_test() {
+        use crate::key_keeper::key::AuthorizationItem;
+        use crate::proxy::authorization_rules::AuthorizationRules;
+
+        let request = Request::new("/machine?comp=goalstate".to_string(), "GET".to_string());
+        assert_eq!(
+            "/machine?comp=goalstate",
+            super::get_authorization_url(&request)
+        );
+
+        let request = Request::new("169.254.169.254:443".to_string(), "CONNECT".to_string());
+        assert_eq!(
+            "https://169.254.169.254:443/",
+            super::get_authorization_url(&request)
+        );
+        let request = Request::new("168.63.129.16:80".to_string(), "CONNECT".to_string());
+        let url = super::get_authorization_url(&request);
+        assert_eq!(
+            "http://168.63.129.16/",
+            url::Url::parse(&url).unwrap().as_str()
+        );
+
+        // the enforced rules decide the CONNECT request instead of denying it as an invalid url
+        let rules = AuthorizationRules::from_authorization_item(AuthorizationItem {
+            defaultAccess: "allow".to_string(),
+            mode: "enforce".to_string(),
+            rules: None,
+            id: "0".to_string(),
+        });
+        let decision = rules.explain(0, url, Claims::empty());
+        assert!(decision.allowed, "{:?}", decision.evaluations);
+    }
+
+    #[test]
+    fn is_method_allowed_test() {
+        let allowed_methods = vec!["GET".to_string(), "POST".to_string()];
+        assert!(super::is_method_allowed("GET", &allowed_methods));
+        assert!(super::is_method_allowed("post", &allowed_methods));
+        assert!(!super::is_method_allowed("TRACE", &allowed_methods));
+        assert!(!super::is_method_allowed("CONNECT", &allowed_methods));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let request = Request::new("/".to_string(), "TRACE".to_string());
+        super::send_method_not_allowed_response(&server, &request, &allowed_methods);
+        drop(server);
+        let mut raw_response = String::new();
+        client.read_to_string(&mut raw_response).unwrap();
+        let response = Response::from_raw_data(raw_response);
+        assert_eq!(Response::METHOD_NOT_ALLOWED, response.status);
+        assert_eq!(
+            Some("GET, POST".to_string()),
+            response.headers.get_header(headers::ALLOW_HEADER_NAME)
+        );
+    }
+
+    #[test]
+    fn get_skip_signature_reason_test() {
+        let skip_destinations = super::parse_skip_signature_destinations(vec![
+            config::SkipSignatureDestination {
+                cidr: "10.0.0.0/24".to_string(),
+                port: Some(8080),
+            },
+            config::SkipSignatureDestination {
+                cidr: "10.0.1.4".to_string(),
+                port: None,
+            },
+            config::SkipSignatureDestination {
+                cidr: "invalid".to_string(),
+                port: None,
+            },
+        ]);
+        assert_eq!(2, skip_destinations.len(), "invalid range is ignored");
+
+        let request = Request::new("/machine?comp=goalstate".to_string(), "GET".to_string());
+        let wire_server: SocketAddr = "168.63.129.16:80".parse().unwrap();
+        assert_eq!(
+            None,
+            super::get_skip_signature_reason(&request, &wire_server, &skip_destinations),
+            "the request is signed by default"
+        );
+        for destination in ["10.0.0.4:8080", "10.0.1.4:80", "10.0.1.4:443"] {
+            let destination: SocketAddr = destination.parse().unwrap();
+            assert!(
+                super::get_skip_signature_reason(&request, &destination, &skip_destinations)
+                    .is_some(),
+                "{destination} is in the skip signature destinations"
+            );
+        }
+        let destination: SocketAddr = "10.0.0.4:80".parse().unwrap();
+        assert_eq!(
+            None,
+            super::get_skip_signature_reason(&request, &destination, &skip_destinations),
+            "the port does not match"
+        );
+
+        let request = Request::new("/vmAgentLog".to_string(), "PUT".to_string());
+        assert!(
+            super::get_skip_signature_reason(&request, &wire_server, &[]).is_some(),
+            "the unsigned request is skipped regardless of the destination"
+        );
+    }
+
     const PROXY_ENDPOINT_ADDRESS: &str = "127.0.0.1:8083";
     const SERVER_ENDPOINT_ADDRESS: &str = "127.0.0.1:9093";
     #[test]
@@ -694,9 +3426,14 @@ mod tests {
                         stream: stream,
                         id: id,
                         now: Instant::now(),
+                        connected_at: SystemTime::now(),
                         cliams: None,
                         ip: String::new(),
                         port: 0,
+                        keep_alive: false,
+                        authorized_request: None,
+                        #[cfg(feature = "otel")]
+                        span: None,
                     };
                     id = id + 1;
                     proxy_connection_stream(&mut connection);
@@ -745,7 +3482,7 @@ mod tests {
 
             if request.expect_continue_request() {
                 if request.get_body_len() != 0 {
-                    super::send_response(&stream, Response::BAD_REQUEST);
+                    super::send_response(&stream, None, Response::BAD_REQUEST);
                     return;
                 }
 
@@ -758,11 +3495,11 @@ mod tests {
 
             // check actual body length against content-length
             if request.get_body_len() != content_length {
-                super::send_response(&stream, Response::BAD_REQUEST);
+                super::send_response(&stream, None, Response::BAD_REQUEST);
                 return;
             }
 
-            return super::send_response(&stream, Response::OK);
+            return super::send_response(&stream, None, Response::OK);
         }
     }
 
@@ -779,6 +3516,8 @@ mod tests {
             processName: "proxy_connection_stream".to_string(),
             processFullPath: "proxy_connection_stream_full".to_string(),
             processCmdLine: "proxy_connection_stream_cmd".to_string(),
+            processExeSha256: None,
+            processSigner: None,
             runAsElevated: true,
             clientIp: "127.0.0.1".to_string(),
         };
@@ -791,6 +3530,7 @@ mod tests {
         connection.ip = "127.0.0.1".to_string();
         connection.port = 8084;
         let mut server_stream = TcpStream::connect(SERVER_ENDPOINT_ADDRESS).unwrap();
+        let upstream_timeout = config::get_proxy_upstream_timeout();
 
         if request.need_skip_sig() {
             // skip the signature and send the request headers to host now
@@ -798,10 +3538,17 @@ mod tests {
                 connection,
                 request,
                 &mut server_stream,
+                upstream_timeout,
             );
         }
 
-        super::handle_connection_with_signature(connection, request, &mut server_stream);
+        super::handle_connection_with_signature(
+            connection,
+            request,
+            &mut server_stream,
+            false,
+            upstream_timeout,
+        );
     }
 
     fn test_get_response() {
@@ -837,16 +3584,16 @@ mod tests {
             body.len().to_string(),
         );
 
-        // post request with full body directly
+        // post request with full body directly, the binary body must be forwarded as it is
         request.set_body(body);
         let mut client_stream = TcpStream::connect(PROXY_ENDPOINT_ADDRESS).unwrap();
         _ = client_stream.write_all(&request.to_raw_bytes());
         _ = client_stream.flush();
         let response = http::receive_response_data(&client_stream).unwrap();
         assert_eq!(
-            Response::BAD_REQUEST,
+            Response::OK,
             response.status,
-            "response.status must be 400 Bad Request"
+            "response.status must be 200 OK"
         );
         assert_eq!(
             "",
@@ -885,4 +3632,1308 @@ mod tests {
             "response body must be empty"
         );
     }
+
+    #[test]
+    fn log_slow_request_test() {
+        let logger_key = "log_slow_request_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        // simulate a delayed request handler
+        let start = Instant::now();
+        thread::sleep(Duration::from_millis(50));
+        let summary = ProxySummary {
+            method: "GET".to_string(),
+            url: "/test".to_string(),
+            clientIp: "127.0.0.1".to_string(),
+            ip: "127.0.0.1".to_string(),
+            port: 80,
+            userId: 0,
+            userName: "test".to_string(),
+            userGroups: vec![],
+            processFullPath: "test".to_string(),
+            processCmdLine: "test".to_string(),
+            processExeSha256: None,
+            processSigner: None,
+            runAsElevated: false,
+            isSystemAccount: false,
+            responseStatus: Response::OK.to_string(),
+            elapsedTime: start.elapsed().as_millis(),
+            tunnelBytesSent: None,
+            tunnelBytesReceived: None,
+            authorization: None,
+            upstreamError: None,
+        };
+
+        assert!(
+            proxy_listener::log_slow_request(&summary, Duration::from_millis(20)),
+            "slow request event must fire when the request exceeds the threshold"
+        );
+        assert!(
+            !proxy_listener::log_slow_request(&summary, Duration::from_secs(10)),
+            "slow request event must not fire when the request is within the threshold"
+        );
+
+        // clean up and ignore the clean up errors
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn should_emit_summary_test() {
+        let success_count = AtomicU64::new(0);
+        let emitted = (0..10)
+            .filter(|_| super::should_emit_summary(Response::OK, &success_count, 4))
+            .count();
+        assert_eq!(3, emitted, "the 1st, 5th and 9th 2xx summaries");
+        for status in [
+            Response::FORBIDDEN,
+            Response::BAD_GATEWAY,
+            Response::MISDIRECTED,
+        ] {
+            assert!(
+                super::should_emit_summary(status, &success_count, 4),
+                "the non-2xx summaries are never sampled out"
+            );
+        }
+        assert_eq!(10, success_count.load(Ordering::Relaxed));
+
+        let success_count = AtomicU64::new(0);
+        assert!((0..10).all(|_| super::should_emit_summary(Response::OK, &success_count, 1)));
+    }
+
+    #[test]
+    fn validate_request_body_limits_test() {
+        assert_eq!(
+            (100, 1000),
+            proxy_listener::validate_request_body_limits(100, 1000)
+        );
+        assert_eq!(
+            (1000, 1000),
+            proxy_listener::validate_request_body_limits(1000, 1000)
+        );
+        assert_eq!(
+            (100, 100),
+            proxy_listener::validate_request_body_limits(1000, 100),
+            "low limit is capped by the large limit"
+        );
+    }
+
+    #[test]
+    fn is_connection_limit_reached_test() {
+        assert!(!proxy_listener::is_connection_limit_reached(0, 2));
+        assert!(!proxy_listener::is_connection_limit_reached(1, 2));
+        assert!(proxy_listener::is_connection_limit_reached(2, 2));
+        assert!(proxy_listener::is_connection_limit_reached(3, 2));
+        assert!(
+            !proxy_listener::is_connection_limit_reached(100, 0),
+            "0 means no limit"
+        );
+    }
+
+    #[test]
+    fn get_upstream_host_test() {
+        let wire_server = "168.63.129.16:80".parse::<SocketAddr>().unwrap();
+        let host_ga_plugin = "168.63.129.16:32526".parse::<SocketAddr>().unwrap();
+        let ipv6 = "[fd00::1]:80".parse::<SocketAddr>().unwrap();
+        let host_override = Some("metadata.azure.internal".to_string());
+
+        assert_eq!(
+            None,
+            proxy_listener::get_upstream_host(
+                constants::HOST_HEADER_PRESERVE,
+                host_override.clone(),
+                &wire_server
+            ),
+            "the client Host header is kept by default"
+        );
+        assert_eq!(
+            Some("168.63.129.16".to_string()),
+            proxy_listener::get_upstream_host(
+                constants::HOST_HEADER_DESTINATION,
+                host_override.clone(),
+                &wire_server
+            )
+        );
+        assert_eq!(
+            Some("168.63.129.16:32526".to_string()),
+            proxy_listener::get_upstream_host(
+                constants::HOST_HEADER_DESTINATION,
+                None,
+                &host_ga_plugin
+            )
+        );
+        assert_eq!(
+            Some("[fd00::1]".to_string()),
+            proxy_listener::get_upstream_host(constants::HOST_HEADER_DESTINATION, None, &ipv6)
+        );
+        assert_eq!(
+            host_override,
+            proxy_listener::get_upstream_host(
+                constants::HOST_HEADER_OVERRIDE,
+                host_override.clone(),
+                &host_ga_plugin
+            )
+        );
+    }
+
+    #[test]
+    fn abort_in_flight_streams_test() {
+        let logger_key = "abort_in_flight_streams_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        // the handled connection is not aborted
+        drop(proxy_listener::InFlightStream::register(
+            u128::MAX - 1,
+            &stream,
+        ));
+        assert_eq!(0, proxy_listener::abort_in_flight_streams());
+
+        // a stuck handler waits for the client until it is aborted
+        let in_flight = proxy_listener::InFlightStream::register(u128::MAX, &stream);
+        let handler = thread::spawn(move || {
+            let _in_flight = in_flight;
+            let mut buffer = [0u8; 16];
+            (&stream).read(&mut buffer)
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(1, proxy_listener::abort_in_flight_streams());
+        assert_eq!(
+            0,
+            handler.join().unwrap().unwrap(),
+            "the aborted stream reads to the end"
+        );
+        let mut buffer = [0u8; 16];
+        assert_eq!(
+            0,
+            client.read(&mut buffer).unwrap_or(0),
+            "the client sees the connection closed"
+        );
+
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn send_request_to_host_retry_test() {
+        let logger_key = "send_request_to_host_retry_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        assert!(proxy_listener::is_idempotent_method("get"));
+        assert!(proxy_listener::is_idempotent_method("DELETE"));
+        assert!(!proxy_listener::is_idempotent_method("POST"));
+
+        // the host closes the first connection after receiving the request
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        let upstream_thread = thread::spawn(move || {
+            for i in 0..2 {
+                let (mut stream, _) = upstream.accept().unwrap();
+                let mut buf = [0u8; 1024];
+                _ = stream.read(&mut buf);
+                if i == 1 {
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .unwrap();
+                }
+            }
+            upstream
+        });
+
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = client_listener.accept().unwrap();
+        let connection = Connection {
+            stream,
+            id: 1,
+            now: Instant::now(),
+            connected_at: SystemTime::now(),
+            cliams: None,
+            ip: "127.0.0.1".to_string(),
+            port,
+            keep_alive: false,
+            authorized_request: None,
+            #[cfg(feature = "otel")]
+            span: None,
+        };
+        let raw_request = b"GET / HTTP/1.1\r\n\r\n";
+        let mut server_stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        proxy_listener::send_request_to_host(
+            &connection,
+            &mut server_stream,
+            false,
+            &[raw_request],
+            Duration::from_secs(5),
+            1,
+        )
+        .unwrap();
+        let response = http::receive_response_data(&server_stream).unwrap();
+        assert_eq!(Response::OK, response.status);
+        let upstream = upstream_thread.join().unwrap();
+
+        // no retry
+        let upstream_thread = thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            _ = stream.read(&mut buf);
+            upstream
+        });
+        let mut server_stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let e = proxy_listener::send_request_to_host(
+            &connection,
+            &mut server_stream,
+            false,
+            &[raw_request],
+            Duration::from_secs(5),
+            0,
+        )
+        .unwrap_err();
+        assert!(proxy_listener::is_connection_error(&e));
+        let upstream = upstream_thread.join().unwrap();
+
+        // the POST request is not resent after host closed the reused connection it read the request from
+        let upstream_thread = thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            _ = stream.read(&mut buf);
+            drop(stream);
+            thread::sleep(Duration::from_millis(200));
+            upstream.set_nonblocking(true).unwrap();
+            upstream.accept().is_err()
+        });
+        let mut server_stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let e = proxy_listener::send_request_to_host(
+            &connection,
+            &mut server_stream,
+            true,
+            &[b"POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n"],
+            Duration::from_secs(5),
+            0,
+        )
+        .unwrap_err();
+        assert!(proxy_listener::is_connection_error(&e));
+        assert!(
+            upstream_thread.join().unwrap(),
+            "no new connection to resend the request"
+        );
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn injected_fault_test() {
+        use crate::common::config::FaultInjection;
+        use crate::proxy::circuit_breaker::{CircuitBreaker, CircuitState};
+        use crate::proxy::fault_injection::{self, FaultInjector};
+
+        let logger_key = "injected_fault_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+        Connection::init_logger(temp_test_path.to_path_buf());
+        let create_injector = |fault_type: &str, rate: f64| {
+            FaultInjector::new(FaultInjection {
+                faultType: fault_type.to_string(),
+                rate,
+                delayInMilliseconds: None,
+                status: Some("503 Service Unavailable".to_string()),
+            })
+        };
+
+        // host responds to every request it receives
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicU64::new(0));
+        let upstream_accepted = accepted.clone();
+        thread::spawn(move || {
+            for stream in upstream.incoming() {
+                let mut stream = stream.unwrap();
+                upstream_accepted.fetch_add(1, Ordering::Relaxed);
+                let mut buf = [0u8; 1024];
+                _ = stream.read(&mut buf);
+                _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+            }
+        });
+
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        _ = client.set_read_timeout(Some(Duration::from_secs(10)));
+        let (stream, _) = client_listener.accept().unwrap();
+        let connection = Connection {
+            stream,
+            id: 1,
+            now: Instant::now(),
+            connected_at: SystemTime::now(),
+            cliams: None,
+            ip: "127.0.0.1".to_string(),
+            port,
+            keep_alive: false,
+            authorized_request: None,
+            #[cfg(feature = "otel")]
+            span: None,
+        };
+        let raw_request = b"GET / HTTP/1.1\r\n\r\n";
+
+        // the injected connection error is retried on a new connection
+        let mut injector = create_injector(fault_injection::FAIL_FAULT, 0.5);
+        assert!(injector.next_fault().is_none());
+        fault_injection::set_test_fault_injector(Some(injector));
+        let mut server_stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        proxy_listener::send_request_to_host(
+            &connection,
+            &mut server_stream,
+            false,
+            &[raw_request],
+            Duration::from_secs(5),
+            1,
+        )
+        .unwrap();
+        let response = http::receive_response_data(&server_stream).unwrap();
+        assert_eq!(Response::OK, response.status);
+        assert_eq!(
+            2,
+            accepted.load(Ordering::Relaxed),
+            "the request must be retried on a new connection"
+        );
+
+        // the injected failures open the circuit of the destination
+        let destination = SocketAddr::new("127.0.0.1".parse().unwrap(), port);
+        let breaker = std::mem::replace(
+            &mut *proxy_listener::CIRCUIT_BREAKER.lock().unwrap(),
+            CircuitBreaker::new(2, Duration::from_secs(30), Duration::from_secs(30)),
+        );
+        fault_injection::set_test_fault_injector(Some(create_injector(
+            fault_injection::FAIL_FAULT,
+            1.0,
+        )));
+        let request = Request::new("/".to_string(), "GET".to_string());
+        for _ in 0..2 {
+            assert!(proxy_listener::try_acquire_circuit(
+                connection.id,
+                destination
+            ));
+            let mut server_stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let e = proxy_listener::send_request_to_host(
+                &connection,
+                &mut server_stream,
+                false,
+                &[raw_request],
+                Duration::from_secs(5),
+                0,
+            )
+            .unwrap_err();
+            assert!(proxy_listener::is_connection_error(&e));
+            proxy_listener::send_upstream_error_response(&connection, &request, e);
+            let response = http::receive_response_data(&client).unwrap();
+            assert_eq!(Response::BAD_GATEWAY, response.status);
+        }
+        let state = proxy_listener::CIRCUIT_BREAKER
+            .lock()
+            .unwrap()
+            .get_state(destination);
+        assert_eq!(CircuitState::Open, state);
+        assert!(
+            !proxy_listener::try_acquire_circuit(connection.id, destination),
+            "the open circuit must reject the request"
+        );
+
+        // the injected status is responded as the upstream failure
+        *proxy_listener::CIRCUIT_BREAKER.lock().unwrap() =
+            CircuitBreaker::new(1, Duration::from_secs(30), Duration::from_secs(30));
+        fault_injection::set_test_fault_injector(Some(create_injector(
+            fault_injection::STATUS_FAULT,
+            1.0,
+        )));
+        let mut server_stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let e = proxy_listener::send_request_to_host(
+            &connection,
+            &mut server_stream,
+            false,
+            &[raw_request],
+            Duration::from_secs(5),
+            1,
+        )
+        .unwrap_err();
+        proxy_listener::send_upstream_error_response(&connection, &request, e);
+        let response = http::receive_response_data(&client).unwrap();
+        assert_eq!("503 Service Unavailable", response.status);
+        assert_eq!(
+            Some("injected_fault".to_string()),
+            response.headers.get_header(constants::PROXY_ERROR_HEADER)
+        );
+        let state = proxy_listener::CIRCUIT_BREAKER
+            .lock()
+            .unwrap()
+            .get_state(destination);
+        assert_eq!(CircuitState::Open, state);
+
+        fault_injection::set_test_fault_injector(None);
+        *proxy_listener::CIRCUIT_BREAKER.lock().unwrap() = breaker;
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn upstream_stall_test() {
+        let logger_key = "upstream_stall_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        // the host sends a partial status line and then stalls
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        let upstream_thread = thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            _ = stream.read(&mut buf);
+            stream.write_all(b"HTTP/1.1 2").unwrap();
+            thread::sleep(Duration::from_secs(3));
+        });
+
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = client_listener.accept().unwrap();
+        let mut connection = Connection {
+            stream,
+            id: proxy_listener::next_connection_id(),
+            now: Instant::now(),
+            connected_at: SystemTime::now(),
+            cliams: None,
+            ip: "127.0.0.1".to_string(),
+            port,
+            keep_alive: false,
+            authorized_request: None,
+            #[cfg(feature = "otel")]
+            span: None,
+        };
+        let request =
+            Request::from_raw_request("GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n".to_string())
+                .unwrap();
+        let mut server_stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let start = Instant::now();
+        let reusable = super::handle_connection_with_signature(
+            &mut connection,
+            request,
+            &mut server_stream,
+            false,
+            Duration::from_secs(1),
+        );
+        assert!(!reusable, "the stalled connection is not reused");
+        assert!(
+            start.elapsed() < Duration::from_secs(3),
+            "the stalled response is timed out"
+        );
+        let response = http::receive_response_data(&client).unwrap();
+        assert_eq!(Response::GATEWAY_TIMEOUT, response.status);
+
+        upstream_thread.join().unwrap();
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn handle_connection_with_resolver_test() {
+        let logger_key = "handle_connection_with_resolver_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let ip = "127.0.0.1";
+        let port = 7077u16;
+        thread::spawn(move || {
+            server_mock::start(ip.to_string(), port);
+        });
+        thread::sleep(Duration::from_millis(100));
+        server_mock::set_canned_response(port, "/resolved", "200 OK", "resolved");
+
+        // the request is sent to the client stream before the connection is handled in this thread
+        let handle = |resolver: &FixedDestinationResolver| {
+            let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+            let (stream, _) = client_listener.accept().unwrap();
+            client
+                .write_all(
+                    b"GET /resolved HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: 0\r\n\r\n",
+                )
+                .unwrap();
+            let mut connection = Connection {
+                stream,
+                id: proxy_listener::next_connection_id(),
+                now: Instant::now(),
+                connected_at: SystemTime::now(),
+                cliams: None,
+                ip: String::new(),
+                port: 0,
+                keep_alive: false,
+                authorized_request: None,
+                #[cfg(feature = "otel")]
+                span: None,
+            };
+            super::handle_connection(&mut connection, resolver);
+            http::receive_response_data(&client).unwrap()
+        };
+
+        // the fixed audit entry of this process resolves the destination to the mock host
+        let mut entry = AuditEntry::empty();
+        entry.process_id = std::process::id();
+        entry.set_destination(ip.parse().unwrap(), port);
+        let response = handle(&FixedDestinationResolver::new(entry));
+        assert_eq!("200 OK", response.status);
+        assert_eq!("resolved", response.get_body_as_string().unwrap());
+        let received = server_mock::take_received_requests(port);
+        assert_eq!(1, received.len());
+        assert_eq!("/resolved", received[0].url);
+
+        // the connection without the audit entry is not forwarded
+        let response = handle(&FixedDestinationResolver::unresolved());
+        assert_eq!(Response::MISDIRECTED, response.status);
+        assert!(server_mock::take_received_requests(port).is_empty());
+
+        server_mock::reset(port);
+        server_mock::stop(ip.to_string(), port);
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    // allows the requests with the given url only, and counts the authorization calls
+    struct UrlAuthenticate {
+        allowed_url: String,
+        calls: AtomicU64,
+    }
+    impl proxy_authentication::Authenticate for UrlAuthenticate {
+        fn authenticate(&self, _connection_id: u128, request_url: String) -> bool {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            request_url == self.allowed_url
+        }
+
+        fn to_string(&self) -> String {
+            "UrlAuthenticate".to_string()
+        }
+    }
+
+    #[test]
+    fn authorize_request_test() {
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = client_listener.accept().unwrap();
+        let mut connection = Connection {
+            stream,
+            id: proxy_listener::next_connection_id(),
+            now: Instant::now(),
+            connected_at: SystemTime::now(),
+            cliams: None,
+            ip: constants::IMDS_IP.to_string(),
+            port: constants::IMDS_PORT,
+            keep_alive: false,
+            authorized_request: None,
+            #[cfg(feature = "otel")]
+            span: None,
+        };
+        let auth = UrlAuthenticate {
+            allowed_url: "/metadata/instance?api-version=A".to_string(),
+            calls: AtomicU64::new(0),
+        };
+        let authorize = |connection: &mut Connection, url: &str| {
+            let request = Request::new(url.to_string(), "GET".to_string());
+            let authorized_request =
+                super::get_authorized_request(connection, &request, "claims", 1, "guid", 0);
+            super::authorize_request(connection, &auth, &request, "claims", authorized_request)
+        };
+
+        // the second request with the same url reuses the decision of the connection
+        assert!(authorize(
+            &mut connection,
+            "/metadata/instance?api-version=A"
+        ));
+        let authorized_request = connection.authorized_request.clone();
+        assert!(authorized_request.is_some());
+        assert!(authorize(
+            &mut connection,
+            "/metadata/instance?api-version=A"
+        ));
+        assert_eq!(1, auth.calls.load(Ordering::Relaxed));
+
+        // the request differing only in the query is authorized again and denied
+        assert!(!authorize(
+            &mut connection,
+            "/metadata/instance?api-version=B"
+        ));
+        assert_eq!(2, auth.calls.load(Ordering::Relaxed));
+        assert_eq!(authorized_request, connection.authorized_request);
+        _ = client.set_read_timeout(Some(Duration::from_secs(10)));
+        let mut buffer = [0u8; 1024];
+        let len = client.read(&mut buffer).unwrap();
+        let response = Response::from_raw_data(String::from_utf8_lossy(&buffer[..len]).to_string());
+        assert_eq!(Response::FORBIDDEN, response.status);
+    }
+
+    #[test]
+    fn get_authorized_request_test() {
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = client_listener.accept().unwrap();
+        let connection = Connection {
+            stream,
+            id: proxy_listener::next_connection_id(),
+            now: Instant::now(),
+            connected_at: SystemTime::now(),
+            cliams: None,
+            ip: constants::IMDS_IP.to_string(),
+            port: constants::IMDS_PORT,
+            keep_alive: false,
+            authorized_request: None,
+            #[cfg(feature = "otel")]
+            span: None,
+        };
+        let request = Request::new(
+            "/metadata/instance?api-version=A".to_string(),
+            "GET".to_string(),
+        );
+        let second = 1_000_000_000i128;
+        let authorized_request =
+            super::get_authorized_request(&connection, &request, "claims", 1, "guid", 10 * second);
+        assert_eq!(
+            authorized_request,
+            super::get_authorized_request(
+                &connection,
+                &request,
+                "claims",
+                1,
+                "guid",
+                11 * second - 1
+            ),
+            "the decision is reused within the same time bucket"
+        );
+
+        // any change of the request, the rules, the key or the time bucket makes the decision stale
+        let other_request = Request::new(
+            "/metadata/instance?api-version=B".to_string(),
+            "GET".to_string(),
+        );
+        for other in [
+            super::get_authorized_request(
+                &connection,
+                &other_request,
+                "claims",
+                1,
+                "guid",
+                10 * second,
+            ),
+            super::get_authorized_request(&connection, &request, "other", 1, "guid", 10 * second),
+            super::get_authorized_request(&connection, &request, "claims", 2, "guid", 10 * second),
+            super::get_authorized_request(&connection, &request, "claims", 1, "guid2", 10 * second),
+            super::get_authorized_request(&connection, &request, "claims", 1, "guid", 11 * second),
+        ] {
+            assert_ne!(authorized_request, other);
+        }
+    }
+
+    #[test]
+    fn authorize_once_per_connection_test() {
+        let logger_key = "authorize_once_per_connection_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        // the stop signal of the listener in the previous test is still set
+        super::SHUT_DOWN.store(false, Ordering::Relaxed);
+        let ip = "127.0.0.1";
+        let port = 7078u16;
+        thread::spawn(move || {
+            server_mock::start(ip.to_string(), port);
+        });
+        thread::sleep(Duration::from_millis(100));
+        server_mock::set_canned_response(port, "/resolved", "200 OK", "resolved");
+        let mut entry = AuditEntry::empty();
+        entry.process_id = std::process::id();
+        entry.set_destination(ip.parse().unwrap(), port);
+        let resolver = FixedDestinationResolver::new(entry);
+
+        // both requests are written before the connection is handled in this thread
+        let handle = |authorize_once: bool| {
+            let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+            let (stream, _) = client_listener.accept().unwrap();
+            let request = b"GET /resolved HTTP/1.1\r\nHost: 127.0.0.1\r\nContent-Length: 0\r\n\r\n";
+            client.write_all(request).unwrap();
+            let second_request = client.try_clone().unwrap();
+            let writer = thread::spawn(move || {
+                // the second request is sent after the first one is read by the connection
+                thread::sleep(Duration::from_millis(500));
+                let mut second_request = second_request;
+                _ = second_request.write_all(request);
+                _ = second_request.shutdown(std::net::Shutdown::Write);
+            });
+            let mut connection = Connection {
+                stream,
+                id: proxy_listener::next_connection_id(),
+                now: Instant::now(),
+                connected_at: SystemTime::now(),
+                cliams: None,
+                ip: String::new(),
+                port: 0,
+                keep_alive: false,
+                authorized_request: None,
+                #[cfg(feature = "otel")]
+                span: None,
+            };
+            super::handle_connection_with(&mut connection, &resolver, authorize_once);
+            writer.join().unwrap();
+            _ = connection.stream.shutdown(std::net::Shutdown::Both);
+            (client, connection.authorized_request)
+        };
+
+        // the second request on the same connection reuses the authorization decision
+        let (mut client, authorized_request) = handle(true);
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        assert_eq!(2, responses.matches("HTTP/1.1 200 OK\r\n").count());
+        assert_eq!(2, responses.matches("\r\n\r\nresolved").count());
+        assert_eq!(2, server_mock::take_received_requests(port).len());
+        assert!(authorized_request
+            .unwrap()
+            .contains(&format!(" {}:{} /resolved ", ip, port)));
+
+        // without the option, only the first request is served
+        let (mut client, _) = handle(false);
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).unwrap();
+        assert_eq!(1, responses.matches("HTTP/1.1 200 OK\r\n").count());
+        assert_eq!(1, server_mock::take_received_requests(port).len());
+
+        server_mock::reset(port);
+        server_mock::stop(ip.to_string(), port);
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn stream_request_body_test() {
+        // the client writes the request in a thread as the body is larger than the socket buffers
+        let send = |content_length: usize, body: Vec<u8>| {
+            let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+            let (stream, _) = client_listener.accept().unwrap();
+            _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+            let client = thread::spawn(move || {
+                let head = format!(
+                    "POST /machine/?comp=telemetrydata HTTP/1.1\r\nHost: 168.63.129.16\r\nContent-Length: {}\r\n\r\n",
+                    content_length
+                );
+                client.write_all(head.as_bytes()).unwrap();
+                client.write_all(&body).unwrap();
+                client.shutdown(std::net::Shutdown::Write).unwrap();
+                client
+            });
+            (stream, client)
+        };
+
+        // the body is not buffered, the part read ahead with the headers is kept
+        let body: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let (stream, client) = send(body.len(), body.clone());
+        let request = http::receive_request_data_with_body_limit(&stream, |_| 0).unwrap();
+        assert!(request.need_skip_sig());
+        assert!(request.get_body_len() < body.len());
+        assert_eq!(&body[..request.get_body_len()], &request.get_body()[..]);
+        let mut host = Vec::new();
+        let len = super::stream_request_body(&stream, &request, body.len(), &mut host).unwrap();
+        assert_eq!(body.len(), len);
+        assert!(body == host, "the streamed body must match the client body");
+        _ = client.join();
+
+        // the client sends less than its Content-Length
+        let (stream, client) = send(100, b"short".to_vec());
+        let request = http::receive_request_data_with_body_limit(&stream, |_| 0).unwrap();
+        let mut host = Vec::new();
+        let len = super::stream_request_body(&stream, &request, 100, &mut host).unwrap();
+        assert_eq!(5, len);
+        assert_eq!(b"short".to_vec(), host);
+        _ = client.join();
+    }
+
+    #[test]
+    fn stream_chunked_request_body_test() {
+        let logger_key = "stream_chunked_request_body_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let send = |body: &'static [u8]| {
+            let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+            let (stream, _) = client_listener.accept().unwrap();
+            _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+            let head = "POST /machine/?comp=telemetrydata HTTP/1.1\r\nHost: 168.63.129.16\r\nTransfer-Encoding: chunked\r\n\r\n";
+            client.write_all(head.as_bytes()).unwrap();
+            client.write_all(body).unwrap();
+            client.shutdown(std::net::Shutdown::Write).unwrap();
+            let request = http::receive_request_data_with_body_limit(&stream, |_| 0).unwrap();
+            let connection = Connection {
+                stream,
+                id: 1,
+                now: Instant::now(),
+                connected_at: SystemTime::now(),
+                cliams: None,
+                ip: "127.0.0.1".to_string(),
+                port: 65002,
+                keep_alive: false,
+                authorized_request: None,
+                #[cfg(feature = "otel")]
+                span: None,
+            };
+            (connection, request, client)
+        };
+
+        // the chunked body is streamed as-is, including the part read ahead with the headers
+        let body = b"4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nx-trailer: 1\r\n\r\n";
+        let (connection, request, _client) = send(body);
+        assert!(request.need_skip_sig());
+        assert!(request.headers.is_chunked_transfer_encoding());
+        let mut host = Vec::new();
+        assert!(super::forward_streamed_request_body(
+            &connection,
+            &request,
+            &mut host
+        ));
+        assert_eq!(body.to_vec(), host);
+
+        // the malformed chunked body is rejected as the client error
+        let (connection, request, client) = send(b"zz\r\nWiki\r\n0\r\n\r\n");
+        let mut host = Vec::new();
+        assert!(!super::forward_streamed_request_body(
+            &connection,
+            &request,
+            &mut host
+        ));
+        let response = http::receive_response_data(&client).unwrap();
+        assert_eq!(Response::BAD_REQUEST, response.status);
+        assert_eq!(
+            Some("client_error".to_string()),
+            response.headers.get_header(constants::PROXY_ERROR_HEADER)
+        );
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn resolve_audit_entry_test() {
+        let logger_key = "resolve_audit_entry_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        let valid_entry = || AuditEntry {
+            logon_id: 999,
+            process_id: 1234,
+            is_admin: 0,
+            destination_ipv4: u32::from_le_bytes([168, 63, 129, 16]),
+            destination_port: 80u16.to_be(),
+            address_family: crate::redirector::AF_INET,
+            destination_ipv6: [0; 16],
+        };
+        let mut request = Request::new("/machine?comp=goalstate".to_string(), "GET".to_string());
+        request
+            .headers
+            .add_header("Host".to_string(), "168.63.129.16:32526".to_string());
+
+        // valid entry is used under any policy
+        let entry = proxy_listener::resolve_audit_entry(
+            0,
+            valid_entry(),
+            constants::INVALID_AUDIT_ENTRY_REJECT,
+            &request,
+            || panic!("valid entry must not be looked up again"),
+        )
+        .unwrap();
+        assert_eq!(valid_entry().destination_ipv4, entry.destination_ipv4);
+
+        // reject
+        let entry = proxy_listener::resolve_audit_entry(
+            0,
+            AuditEntry::empty(),
+            constants::INVALID_AUDIT_ENTRY_REJECT,
+            &request,
+            || Ok(valid_entry()),
+        );
+        assert!(entry.is_none(), "zeroed entry must be rejected");
+
+        // retry
+        let lookup_count = std::cell::Cell::new(0);
+        let entry = proxy_listener::resolve_audit_entry(
+            0,
+            AuditEntry::empty(),
+            constants::INVALID_AUDIT_ENTRY_RETRY,
+            &request,
+            || {
+                lookup_count.set(lookup_count.get() + 1);
+                if lookup_count.get() < 2 {
+                    Ok(AuditEntry::empty())
+                } else {
+                    Ok(valid_entry())
+                }
+            },
+        )
+        .unwrap();
+        assert_eq!(2, lookup_count.get());
+        assert_eq!(80, http::ntohs(entry.destination_port));
+        let entry = proxy_listener::resolve_audit_entry(
+            0,
+            AuditEntry::empty(),
+            constants::INVALID_AUDIT_ENTRY_RETRY,
+            &request,
+            || Ok(AuditEntry::empty()),
+        );
+        assert!(entry.is_none(), "entry still invalid after retries");
+
+        // forward
+        let entry = proxy_listener::resolve_audit_entry(
+            0,
+            AuditEntry::empty(),
+            constants::INVALID_AUDIT_ENTRY_FORWARD,
+            &request,
+            || Ok(AuditEntry::empty()),
+        )
+        .unwrap();
+        assert_eq!(
+            "168.63.129.16",
+            crate::redirector::ip_to_string(entry.destination_ipv4)
+        );
+        assert_eq!(32526, http::ntohs(entry.destination_port));
+        let mut request = Request::new("/".to_string(), "GET".to_string());
+        request
+            .headers
+            .add_header("Host".to_string(), "[fd00::1]:8080".to_string());
+        let entry = proxy_listener::resolve_audit_entry(
+            0,
+            AuditEntry::empty(),
+            constants::INVALID_AUDIT_ENTRY_FORWARD,
+            &request,
+            || Ok(AuditEntry::empty()),
+        )
+        .unwrap();
+        assert_eq!("fd00::1", entry.destination_addr().to_string());
+        assert_eq!(8080, http::ntohs(entry.destination_port));
+        let request = Request::new("/".to_string(), "GET".to_string());
+        let entry = proxy_listener::resolve_audit_entry(
+            0,
+            AuditEntry::empty(),
+            constants::INVALID_AUDIT_ENTRY_FORWARD,
+            &request,
+            || Ok(AuditEntry::empty()),
+        );
+        assert!(entry.is_none(), "no destination to forward to");
+    }
+
+    #[test]
+    fn get_status_test() {
+        // the status must not wait for the breaker lock held by a request
+        let breaker = proxy_listener::CIRCUIT_BREAKER.lock().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            _ = sender.send(proxy_listener::get_status());
+        });
+        let status = receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("get_status must not block on the circuit breaker lock");
+        drop(breaker);
+        let states = status.states.unwrap();
+        assert_eq!(
+            proxy_listener::get_proxy_connection_count().to_string(),
+            states["totalConnections"]
+        );
+        assert_eq!(
+            "0", states["circuitBreakerOpenedCount"],
+            "the status starts with the states of an untouched breaker"
+        );
+        assert!(states.contains_key("circuitBreakerStates"));
+
+        // the published snapshot follows the breaker
+        let mut breaker =
+            super::CircuitBreaker::new(1, Duration::from_secs(30), Duration::from_secs(30));
+        breaker.record_failure("127.0.0.1:8080".parse().unwrap());
+        let states = proxy_listener::get_circuit_breaker_states(&breaker);
+        assert_eq!("1", states["circuitBreakerOpenedCount"]);
+        assert_eq!("127.0.0.1:8080 open (1)", states["circuitBreakerStates"]);
+    }
+
+    #[test]
+    fn sign_request_test() {
+        let valid_key = || {
+            let mut key = Key::empty();
+            key.key =
+                "4A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59".to_string();
+            key
+        };
+        // the hex decoding of the key fails
+        let invalid_key = || {
+            let mut key = Key::empty();
+            key.key = "zz".to_string();
+            key
+        };
+        let new_request = || Request::new("/machine?comp=goalstate".to_string(), "GET".to_string());
+
+        let mut request = new_request();
+        assert!(proxy_listener::sign_request(
+            0,
+            &mut request,
+            constants::SIGNATURE_FAILURE_FAIL,
+            valid_key
+        ));
+        assert!(request
+            .headers
+            .get_header(constants::AUTHORIZATION_HEADER)
+            .is_some());
+
+        // fail
+        let mut request = new_request();
+        assert!(!proxy_listener::sign_request(
+            0,
+            &mut request,
+            constants::SIGNATURE_FAILURE_FAIL,
+            invalid_key
+        ));
+
+        // retry with the key latched again
+        let key_count = std::cell::Cell::new(0);
+        let mut request = new_request();
+        assert!(proxy_listener::sign_request(
+            0,
+            &mut request,
+            constants::SIGNATURE_FAILURE_RETRY,
+            || {
+                key_count.set(key_count.get() + 1);
+                if key_count.get() < 2 {
+                    invalid_key()
+                } else {
+                    valid_key()
+                }
+            }
+        ));
+        assert_eq!(2, key_count.get());
+        assert!(request
+            .headers
+            .get_header(constants::AUTHORIZATION_HEADER)
+            .is_some());
+        let mut request = new_request();
+        assert!(
+            !proxy_listener::sign_request(
+                0,
+                &mut request,
+                constants::SIGNATURE_FAILURE_RETRY,
+                invalid_key
+            ),
+            "the key is still invalid after retry"
+        );
+
+        // forward unsigned
+        let mut request = new_request();
+        assert!(proxy_listener::sign_request(
+            0,
+            &mut request,
+            constants::SIGNATURE_FAILURE_FORWARD,
+            invalid_key
+        ));
+        assert!(request
+            .headers
+            .get_header(constants::AUTHORIZATION_HEADER)
+            .is_none());
+
+        // forward unsigned without the key, it is counted until a request is signed
+        let unsigned_count = proxy_metrics::get_unsigned_request_count();
+        let mut request = new_request();
+        assert!(proxy_listener::sign_request(
+            0,
+            &mut request,
+            constants::SIGNATURE_FAILURE_FAIL,
+            Key::empty
+        ));
+        assert!(request
+            .headers
+            .get_header(constants::AUTHORIZATION_HEADER)
+            .is_none());
+        assert!(proxy_metrics::get_unsigned_request_count() > unsigned_count);
+        assert!(proxy_listener::FORWARDING_UNSIGNED.load(Ordering::Relaxed));
+        let mut request = new_request();
+        assert!(proxy_listener::sign_request(
+            0,
+            &mut request,
+            constants::SIGNATURE_FAILURE_FAIL,
+            valid_key
+        ));
+        assert!(!proxy_listener::FORWARDING_UNSIGNED.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn classify_upstream_error_test() {
+        for (kind, expected) in [
+            (std::io::ErrorKind::TimedOut, "timeout"),
+            (std::io::ErrorKind::ConnectionRefused, "connection_refused"),
+            (std::io::ErrorKind::ConnectionReset, "connection_closed"),
+            (std::io::ErrorKind::InvalidData, "protocol_error"),
+            (std::io::ErrorKind::Other, "upstream_error"),
+        ] {
+            let e = std::io::Error::new(kind, "details not returned to the client");
+            assert_eq!(expected, proxy_listener::classify_upstream_error(&e));
+        }
+
+        let e = std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            rustls::Error::General("handshake".to_string()),
+        );
+        assert_eq!("tls_error", proxy_listener::classify_upstream_error(&e));
+    }
+
+    #[test]
+    fn client_stream_error_test() {
+        use crate::proxy::circuit_breaker::{CircuitBreaker, CircuitState};
+
+        let logger_key = "client_stream_error_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        Connection::init_logger(temp_test_path.to_path_buf());
+
+        // the client closes its connection before the response of host is forwarded
+        let client_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(client_listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = client_listener.accept().unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let port = 65001;
+        let connection = Connection {
+            stream,
+            id: 1,
+            now: Instant::now(),
+            connected_at: SystemTime::now(),
+            cliams: None,
+            ip: "127.0.0.1".to_string(),
+            port,
+            keep_alive: false,
+            authorized_request: None,
+            #[cfg(feature = "otel")]
+            span: None,
+        };
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+        let e = http::forward_response_from(
+            &response[..],
+            &connection.stream,
+            std::collections::HashMap::new(),
+            None,
+        )
+        .err()
+        .unwrap();
+        assert_eq!("client_error", proxy_listener::classify_upstream_error(&e));
+
+        // the client stream error does not open the circuit of host
+        let destination = SocketAddr::new("127.0.0.1".parse().unwrap(), port);
+        let breaker = std::mem::replace(
+            &mut *proxy_listener::CIRCUIT_BREAKER.lock().unwrap(),
+            CircuitBreaker::new(1, Duration::from_secs(30), Duration::from_secs(30)),
+        );
+        let request = Request::new("/".to_string(), "GET".to_string());
+        proxy_listener::send_upstream_error_response(&connection, &request, e);
+        let state = proxy_listener::CIRCUIT_BREAKER
+            .lock()
+            .unwrap()
+            .get_state(destination);
+        *proxy_listener::CIRCUIT_BREAKER.lock().unwrap() = breaker;
+        assert_eq!(CircuitState::Closed, state);
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn build_response_authorization_test() {
+        let key = "4A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59";
+        let key_guid = "key-guid";
+        let date = "Thu, 01 Jan 2026 00:00:00 GMT";
+        let authorization = proxy_listener::build_response_authorization(
+            constants::AUTHORIZATION_SCHEME,
+            key,
+            key_guid,
+            "GET",
+            "/machine?comp=goalstate",
+            date,
+        );
+        let parts: Vec<&str> = authorization.split_whitespace().collect();
+        assert_eq!(constants::AUTHORIZATION_SCHEME, parts[0]);
+        assert_eq!(key_guid, parts[1]);
+
+        let input = proxy_listener::get_response_sig_input("GET", "/machine?comp=goalstate", date);
+        assert!(helpers::verify_authorization_header(
+            &authorization,
+            constants::AUTHORIZATION_SCHEME,
+            key,
+            input.as_bytes()
+        ));
+        let input =
+            proxy_listener::get_response_sig_input("GET", "/machine?comp=certificates", date);
+        assert!(
+            !helpers::verify_authorization_header(
+                &authorization,
+                constants::AUTHORIZATION_SCHEME,
+                key,
+                input.as_bytes()
+            ),
+            "the signature is tied to the request"
+        );
+
+        assert_eq!(
+            constants::UNSIGNED_RESPONSE_AUTHORIZATION,
+            proxy_listener::build_response_authorization(
+                constants::AUTHORIZATION_SCHEME,
+                "",
+                "",
+                "GET",
+                "/",
+                date
+            ),
+            "no key is latched"
+        );
+    }
 }
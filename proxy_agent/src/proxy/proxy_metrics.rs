@@ -0,0 +1,274 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const METRIC_PREFIX: &str = "azure_proxy_agent";
+// upper bounds of the request duration histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+// the response status which is not a 3-digit code is counted under this label
+const OTHER_STATUS: &str = "other";
+
+static METRICS: Lazy<Mutex<ProxyMetrics>> = Lazy::new(|| Mutex::new(ProxyMetrics::new()));
+// the audit lookup misses read by the status polling, which must not wait on the metrics lock of the request path
+static AUDIT_LOOKUP_MISSES: AtomicU64 = AtomicU64::new(0);
+
+/*
+Aggregated counters of the proxied requests, rendered in the Prometheus text exposition format.
+The requests are labeled by the response status code only, so the number of the series is bounded.
+ */
+pub struct ProxyMetrics {
+    requests: BTreeMap<String, u64>, // response status code -> count
+    authorization_denials: u64,
+    audit_lookup_misses: u64, // requests misdirected as their source port is not in the audit map
+    unsigned_requests: u64, // requests expected to be signed but forwarded unsigned as the key is empty
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: Duration,
+    latency_count: u64,
+}
+
+impl ProxyMetrics {
+    pub fn new() -> Self {
+        ProxyMetrics {
+            requests: BTreeMap::new(),
+            authorization_denials: 0,
+            audit_lookup_misses: 0,
+            unsigned_requests: 0,
+            latency_buckets: [0; LATENCY_BUCKETS.len()],
+            latency_sum: Duration::ZERO,
+            latency_count: 0,
+        }
+    }
+
+    pub fn record_request(&mut self, response_status: &str, elapsed: Duration) {
+        *self
+            .requests
+            .entry(get_status_code(response_status))
+            .or_insert(0) += 1;
+
+        let seconds = elapsed.as_secs_f64();
+        for (i, upper_bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if seconds <= *upper_bound {
+                self.latency_buckets[i] += 1;
+            }
+        }
+        self.latency_sum += elapsed;
+        self.latency_count += 1;
+    }
+
+    pub fn record_authorization_denial(&mut self) {
+        self.authorization_denials += 1;
+    }
+
+    pub fn record_audit_lookup_miss(&mut self) -> u64 {
+        self.audit_lookup_misses += 1;
+        self.audit_lookup_misses
+    }
+
+    pub fn record_unsigned_request(&mut self) -> u64 {
+        self.unsigned_requests += 1;
+        self.unsigned_requests
+    }
+
+    pub fn render(&self, total_connections: u128, active_connections: usize) -> String {
+        let mut text = String::new();
+
+        _ = writeln!(
+            text,
+            "# HELP {METRIC_PREFIX}_connections_total Connections accepted by the proxy listener."
+        );
+        _ = writeln!(text, "# TYPE {METRIC_PREFIX}_connections_total counter");
+        _ = writeln!(
+            text,
+            "{METRIC_PREFIX}_connections_total {total_connections}"
+        );
+
+        _ = writeln!(
+            text,
+            "# HELP {METRIC_PREFIX}_active_connections Connections queued or handled by the proxy listener."
+        );
+        _ = writeln!(text, "# TYPE {METRIC_PREFIX}_active_connections gauge");
+        _ = writeln!(
+            text,
+            "{METRIC_PREFIX}_active_connections {active_connections}"
+        );
+
+        _ = writeln!(
+            text,
+            "# HELP {METRIC_PREFIX}_requests_total Proxied requests by the response status code."
+        );
+        _ = writeln!(text, "# TYPE {METRIC_PREFIX}_requests_total counter");
+        for (status, count) in &self.requests {
+            _ = writeln!(
+                text,
+                "{METRIC_PREFIX}_requests_total{{status=\"{status}\"}} {count}"
+            );
+        }
+
+        _ = writeln!(
+            text,
+            "# HELP {METRIC_PREFIX}_authorization_denials_total Requests denied by the authorization."
+        );
+        _ = writeln!(
+            text,
+            "# TYPE {METRIC_PREFIX}_authorization_denials_total counter"
+        );
+        _ = writeln!(
+            text,
+            "{METRIC_PREFIX}_authorization_denials_total {}",
+            self.authorization_denials
+        );
+
+        _ = writeln!(
+            text,
+            "# HELP {METRIC_PREFIX}_audit_lookup_misses_total Requests misdirected as the audit entry is not found."
+        );
+        _ = writeln!(
+            text,
+            "# TYPE {METRIC_PREFIX}_audit_lookup_misses_total counter"
+        );
+        _ = writeln!(
+            text,
+            "{METRIC_PREFIX}_audit_lookup_misses_total {}",
+            self.audit_lookup_misses
+        );
+
+        _ = writeln!(
+            text,
+            "# HELP {METRIC_PREFIX}_unsigned_requests_total Requests forwarded unsigned as the current key is empty."
+        );
+        _ = writeln!(
+            text,
+            "# TYPE {METRIC_PREFIX}_unsigned_requests_total counter"
+        );
+        _ = writeln!(
+            text,
+            "{METRIC_PREFIX}_unsigned_requests_total {}",
+            self.unsigned_requests
+        );
+
+        _ = writeln!(
+            text,
+            "# HELP {METRIC_PREFIX}_request_duration_seconds Time to handle the proxied requests."
+        );
+        _ = writeln!(
+            text,
+            "# TYPE {METRIC_PREFIX}_request_duration_seconds histogram"
+        );
+        for (upper_bound, count) in LATENCY_BUCKETS.iter().zip(self.latency_buckets.iter()) {
+            _ = writeln!(
+                text,
+                "{METRIC_PREFIX}_request_duration_seconds_bucket{{le=\"{upper_bound}\"}} {count}"
+            );
+        }
+        _ = writeln!(
+            text,
+            "{METRIC_PREFIX}_request_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            self.latency_count
+        );
+        _ = writeln!(
+            text,
+            "{METRIC_PREFIX}_request_duration_seconds_sum {}",
+            self.latency_sum.as_secs_f64()
+        );
+        _ = writeln!(
+            text,
+            "{METRIC_PREFIX}_request_duration_seconds_count {}",
+            self.latency_count
+        );
+
+        text
+    }
+}
+
+// the status code of the response status, e.g. "200" of "200 OK"
+fn get_status_code(response_status: &str) -> String {
+    match response_status.split_whitespace().next() {
+        Some(code) if code.len() == 3 && code.bytes().all(|b| b.is_ascii_digit()) => {
+            code.to_string()
+        }
+        _ => OTHER_STATUS.to_string(),
+    }
+}
+
+pub fn record_request(response_status: &str, elapsed: Duration) {
+    METRICS
+        .lock()
+        .unwrap()
+        .record_request(response_status, elapsed);
+}
+
+pub fn record_authorization_denial() {
+    METRICS.lock().unwrap().record_authorization_denial();
+}
+
+// returns the misses counted so far
+pub fn record_audit_lookup_miss() -> u64 {
+    let misses = METRICS.lock().unwrap().record_audit_lookup_miss();
+    AUDIT_LOOKUP_MISSES.fetch_max(misses, Ordering::Relaxed);
+    misses
+}
+
+pub fn get_audit_lookup_miss_count() -> u64 {
+    AUDIT_LOOKUP_MISSES.load(Ordering::Relaxed)
+}
+
+// returns the unsigned requests counted so far
+pub fn record_unsigned_request() -> u64 {
+    METRICS.lock().unwrap().record_unsigned_request()
+}
+
+pub fn get_unsigned_request_count() -> u64 {
+    METRICS.lock().unwrap().unsigned_requests
+}
+
+pub fn render(total_connections: u128, active_connections: usize) -> String {
+    METRICS
+        .lock()
+        .unwrap()
+        .render(total_connections, active_connections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProxyMetrics;
+    use std::time::Duration;
+
+    #[test]
+    fn proxy_metrics_test() {
+        let mut metrics = ProxyMetrics::new();
+        metrics.record_request("200 OK", Duration::from_millis(3));
+        metrics.record_request("200 OK", Duration::from_millis(300));
+        metrics.record_request("403 Forbidden", Duration::from_secs(60));
+        metrics.record_request("invalid status", Duration::from_millis(3));
+        metrics.record_authorization_denial();
+        metrics.record_audit_lookup_miss();
+        metrics.record_unsigned_request();
+
+        let text = metrics.render(10, 2);
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            "azure_proxy_agent_connections_total 10",
+            "azure_proxy_agent_active_connections 2",
+            "azure_proxy_agent_requests_total{status=\"200\"} 2",
+            "azure_proxy_agent_requests_total{status=\"403\"} 1",
+            "azure_proxy_agent_requests_total{status=\"other\"} 1",
+            "azure_proxy_agent_authorization_denials_total 1",
+            "azure_proxy_agent_audit_lookup_misses_total 1",
+            "azure_proxy_agent_unsigned_requests_total 1",
+            "azure_proxy_agent_request_duration_seconds_bucket{le=\"0.005\"} 2",
+            "azure_proxy_agent_request_duration_seconds_bucket{le=\"0.5\"} 3",
+            "azure_proxy_agent_request_duration_seconds_bucket{le=\"30\"} 3",
+            "azure_proxy_agent_request_duration_seconds_bucket{le=\"+Inf\"} 4",
+            "azure_proxy_agent_request_duration_seconds_count 4",
+        ] {
+            assert!(lines.contains(&expected), "missing metric line: {expected}");
+        }
+    }
+}
@@ -1,8 +1,13 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
 use crate::common::logger;
@@ -12,6 +17,7 @@ type Job = Box<dyn FnOnce() + Send + 'static>;
 pub struct ProxyPool {
     workers: Vec<Worker>,
     sender: Option<mpsc::Sender<Job>>,
+    pending: Arc<AtomicUsize>, // the jobs queued or running
 }
 
 impl ProxyPool {
@@ -20,15 +26,17 @@ impl ProxyPool {
 
         let (sender, receiver) = mpsc::channel();
         let receiver = Arc::new(Mutex::new(receiver));
+        let pending = Arc::new(AtomicUsize::new(0));
         let mut workers = Vec::with_capacity(size);
 
         for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)))
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&pending)))
         }
 
         ProxyPool {
             workers: workers,
             sender: Some(sender),
+            pending,
         }
     }
 
@@ -37,7 +45,69 @@ impl ProxyPool {
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
-        _ = self.sender.as_ref().unwrap().send(job);
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        if self.sender.as_ref().unwrap().send(job).is_err() {
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    // the number of the jobs queued or running
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    // the shared count of the jobs queued or running, to read it without the pool
+    pub fn pending_counter(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.pending)
+    }
+
+    // stop taking new jobs and wait up to the grace period for the queued and running jobs to finish,
+    // then call 'abort' to make the remaining jobs return and wait for them up to the hard deadline;
+    // both are counted from the shutdown, returns the number of the jobs finished and the ones still running
+    // at the hard deadline, the workers of the unfinished jobs are left behind and end with the process
+    pub fn shutdown<F>(
+        mut self,
+        grace_period: Duration,
+        hard_deadline: Duration,
+        abort: F,
+    ) -> (usize, usize)
+    where
+        F: FnOnce(),
+    {
+        drop(self.sender.take());
+
+        let start = Instant::now();
+        let outstanding = self.pending.load(Ordering::SeqCst);
+        self.wait_until(start + grace_period);
+        if self.pending.load(Ordering::SeqCst) > 0 {
+            abort();
+            self.wait_until(start + hard_deadline);
+        }
+
+        let unfinished = self.pending.load(Ordering::SeqCst);
+        if unfinished > 0 {
+            // do not join the busy workers in drop
+            for worker in &mut self.workers {
+                worker.thread.take();
+            }
+        }
+        (outstanding.saturating_sub(unfinished), unfinished)
+    }
+
+    // block until fewer than 'limit' jobs are queued or running, or 'stop' returns true
+    pub fn wait_pending_below<F>(&self, limit: usize, stop: F)
+    where
+        F: Fn() -> bool,
+    {
+        while self.pending.load(Ordering::SeqCst) >= limit && !stop() {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn wait_until(&self, deadline: Instant) {
+        while self.pending.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
     }
 }
 
@@ -60,13 +130,21 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Job>>>,
+        pending: Arc<AtomicUsize>,
+    ) -> Self {
         let thread = thread::spawn(move || loop {
             let message = receiver.lock().unwrap().recv();
 
             match message {
                 Ok(job) => {
-                    job();
+                    // keep the worker and the pending count right if the job panics
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        logger::write_warning(format!("Worker {id} job panicked."));
+                    }
+                    pending.fetch_sub(1, Ordering::SeqCst);
                 }
                 Err(e) => {
                     logger::write_warning(format!(
@@ -83,3 +161,121 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ProxyPool;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn proxy_pool_shutdown_test() {
+        let pool = ProxyPool::new(3);
+        for _ in 0..2 {
+            pool.execute(|| thread::sleep(Duration::from_millis(100)));
+        }
+        pool.execute(|| thread::sleep(Duration::from_secs(5)));
+        thread::sleep(Duration::from_millis(20));
+
+        let start = Instant::now();
+        let (drained, unfinished) = pool.shutdown(
+            Duration::from_millis(300),
+            Duration::from_millis(500),
+            || {},
+        );
+        assert!(
+            start.elapsed() < Duration::from_secs(2),
+            "shutdown must not wait beyond the hard deadline"
+        );
+        assert_eq!(2, drained, "the short jobs must be drained");
+        assert_eq!(
+            1, unfinished,
+            "the long job must not finish before the hard deadline"
+        );
+
+        let pool = ProxyPool::new(1);
+        pool.execute(|| thread::sleep(Duration::from_millis(50)));
+        pool.execute(|| thread::sleep(Duration::from_millis(50)));
+        let (drained, unfinished) =
+            pool.shutdown(Duration::from_secs(5), Duration::from_secs(10), || {
+                panic!("no abort if all the jobs finish in the grace period")
+            });
+        assert_eq!((2, 0), (drained, unfinished));
+    }
+
+    #[test]
+    fn proxy_pool_abort_test() {
+        let pool = ProxyPool::new(2);
+        let aborted = Arc::new(AtomicBool::new(false));
+        let aborted_clone = aborted.clone();
+        // a stuck job which only returns when it is aborted
+        pool.execute(move || {
+            while !aborted_clone.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        thread::sleep(Duration::from_millis(20));
+
+        let start = Instant::now();
+        let (drained, unfinished) =
+            pool.shutdown(Duration::from_millis(100), Duration::from_secs(5), || {
+                aborted.store(true, Ordering::SeqCst)
+            });
+        assert!(
+            start.elapsed() < Duration::from_secs(2),
+            "shutdown must return once the aborted job finishes"
+        );
+        assert_eq!((1, 0), (drained, unfinished));
+    }
+
+    #[test]
+    fn proxy_pool_pending_test() {
+        let pool = ProxyPool::new(1);
+        assert_eq!(0, pool.pending());
+        pool.execute(|| panic!("job panics"));
+        pool.execute(|| thread::sleep(Duration::from_millis(200)));
+        pool.execute(|| thread::sleep(Duration::from_millis(10)));
+        assert!(pool.pending() >= 2, "the jobs are queued or running");
+
+        let start = Instant::now();
+        while pool.pending() > 0 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            0,
+            pool.pending(),
+            "the worker keeps running after the job panics"
+        );
+    }
+
+    #[test]
+    fn proxy_pool_wait_pending_test() {
+        let pool = ProxyPool::new(2);
+        pool.execute(|| thread::sleep(Duration::from_millis(100)));
+        pool.execute(|| thread::sleep(Duration::from_secs(1)));
+
+        let start = Instant::now();
+        pool.wait_pending_below(2, || false);
+        assert!(
+            start.elapsed() < Duration::from_millis(900),
+            "the wait must end once the short job finishes"
+        );
+        assert_eq!(1, pool.pending());
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stop_clone.store(true, Ordering::SeqCst);
+        });
+        let start = Instant::now();
+        pool.wait_pending_below(1, || stop.load(Ordering::SeqCst));
+        assert!(
+            start.elapsed() < Duration::from_millis(900),
+            "the wait must end when it is stopped"
+        );
+        assert_eq!(1, pool.pending(), "the long job is still running");
+    }
+}
@@ -1,8 +1,9 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+use super::authorization_rules::AuthorizationDecision;
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[allow(non_snake_case)]
 pub struct ProxySummary {
     pub method: String,
@@ -15,9 +16,23 @@ pub struct ProxySummary {
     pub userGroups: Vec<String>,
     pub processFullPath: String,
     pub processCmdLine: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processExeSha256: Option<String>, // collected with the collectProcessIntegrity config only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processSigner: Option<String>, // Windows only, the Authenticode signer subject of the process binary
     pub runAsElevated: bool,
+    #[serde(default)]
+    pub isSystemAccount: bool, // the user is a well-known system or service account, see Claims::is_system_account
     pub responseStatus: String,
     pub elapsedTime: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnelBytesSent: Option<u64>, // CONNECT tunnel only, bytes relayed from the client to host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnelBytesReceived: Option<u64>, // CONNECT tunnel only, bytes relayed from host to the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization: Option<AuthorizationDecision>, // denied by the authorization rules only, explains the decision
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstreamError: Option<String>, // the failure class when the request to host failed or it cannot be signed
 }
 
 impl ProxySummary {
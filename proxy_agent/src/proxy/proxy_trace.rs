@@ -0,0 +1,350 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+#![cfg(feature = "otel")]
+
+// Trace the proxied requests with the W3C trace context, https://www.w3.org/TR/trace-context/.
+// It is compiled only with the 'otel' feature, a span covers a connection from the request received
+// to the connection summary, and is exported to the 'otlpTracesEndpoint' config in the OTLP/HTTP json encoding.
+use super::proxy_summary::ProxySummary;
+use crate::common::config;
+use crate::common::http;
+use crate::common::http::http_request::HttpRequest;
+use crate::common::http::request::Request;
+use crate::common::logger;
+use once_cell::sync::Lazy;
+use proxy_agent_shared::misc_helpers;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+const SERVICE_NAME: &str = "azure-proxy-agent";
+const SPAN_KIND_SERVER: u8 = 2;
+const STATUS_CODE_ERROR: u8 = 2;
+// the spans not exported yet, the new spans are dropped when the queue is full
+const MAX_QUEUED_SPANS: usize = 1024;
+const MAX_EXPORT_BATCH: usize = 64;
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+static EXPORTER: Lazy<Mutex<Option<SyncSender<serde_json::Value>>>> =
+    Lazy::new(|| Mutex::new(start_exporter()));
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: String, // 32 lowercase hex
+    pub span_id: String,  // 16 lowercase hex
+    pub flags: u8,
+}
+
+impl TraceContext {
+    // parse the version 00 traceparent header, returns None if it is not valid
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let parts: Vec<&str> = traceparent.trim().split('-').collect();
+        if parts.len() != 4 || parts[0] != "00" {
+            return None;
+        }
+        let (trace_id, span_id, flags) = (parts[1], parts[2], parts[3]);
+        if !is_hex_id(trace_id, 32) || !is_hex_id(span_id, 16) || !is_hex_id(flags, 2) {
+            return None;
+        }
+
+        Some(TraceContext {
+            trace_id: trace_id.to_string(),
+            span_id: span_id.to_string(),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        })
+    }
+
+    pub fn to_traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+}
+
+// lowercase hex of the length, all zeros is not a valid id
+fn is_hex_id(id: &str, len: usize) -> bool {
+    id.len() == len
+        && id
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+        && (len == 2 || id.chars().any(|c| c != '0'))
+}
+
+fn new_id(len: usize) -> String {
+    uuid::Uuid::new_v4().simple().to_string()[..len].to_string()
+}
+
+pub struct Span {
+    pub context: TraceContext, // injected to the upstream request as its parent
+    parent_span_id: Option<String>,
+    connection_id: u128,
+    start_time: SystemTime,
+}
+
+impl Span {
+    // start the span of the connection, it continues the trace of the client traceparent if it is valid
+    pub fn start(connection_id: u128, start_time: SystemTime, request: &Request) -> Self {
+        let parent = request
+            .headers
+            .get_header(TRACEPARENT_HEADER)
+            .and_then(|value| TraceContext::parse(&value));
+        let (trace_id, flags, parent_span_id) = match parent {
+            Some(parent) => (parent.trace_id, parent.flags, Some(parent.span_id)),
+            None => (new_id(32), 1, None),
+        };
+
+        Span {
+            context: TraceContext {
+                trace_id,
+                span_id: new_id(16),
+                flags,
+            },
+            parent_span_id,
+            connection_id,
+            start_time,
+        }
+    }
+
+    // end the span with the connection summary and queue it to export
+    pub fn end(&self, summary: &ProxySummary) {
+        let end_time = SystemTime::now();
+        if let Some(sender) = EXPORTER.lock().unwrap().as_ref() {
+            match sender.try_send(self.to_otlp_json(summary, end_time)) {
+                Ok(_) | Err(TrySendError::Disconnected(_)) => {}
+                Err(TrySendError::Full(_)) => {
+                    logger::write_warning("Span export queue is full, drop the span.".to_string())
+                }
+            }
+        }
+    }
+
+    // the span in the OTLP json encoding
+    fn to_otlp_json(&self, summary: &ProxySummary, end_time: SystemTime) -> serde_json::Value {
+        let status_code: u16 = summary
+            .responseStatus
+            .split_whitespace()
+            .next()
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0);
+        let mut attributes = vec![
+            string_attribute("http.request.method", &summary.method),
+            string_attribute("url.full", &summary.url),
+            string_attribute("server.address", &summary.ip),
+            int_attribute("server.port", summary.port as u128),
+            int_attribute("http.response.status_code", status_code as u128),
+            string_attribute("client.address", &summary.clientIp),
+            int_attribute("proxy_agent.elapsed_ms", summary.elapsedTime),
+            string_attribute("proxy_agent.connection_id", &self.connection_id.to_string()),
+        ];
+        if let Some(upstream_error) = &summary.upstreamError {
+            attributes.push(string_attribute("error.type", upstream_error));
+        }
+
+        let mut span = serde_json::json!({
+            "traceId": self.context.trace_id,
+            "spanId": self.context.span_id,
+            "name": summary.method,
+            "kind": SPAN_KIND_SERVER,
+            "startTimeUnixNano": unix_nanos(self.start_time),
+            "endTimeUnixNano": unix_nanos(end_time),
+            "attributes": attributes,
+        });
+        if let Some(parent_span_id) = &self.parent_span_id {
+            span["parentSpanId"] = serde_json::json!(parent_span_id);
+        }
+        if status_code == 0 || status_code >= 500 {
+            span["status"] = serde_json::json!({ "code": STATUS_CODE_ERROR });
+        }
+        span
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> serde_json::Value {
+    serde_json::json!({ "key": key, "value": { "stringValue": value } })
+}
+
+// the 64-bit integers are encoded as strings in the OTLP json encoding
+fn int_attribute(key: &str, value: u128) -> serde_json::Value {
+    serde_json::json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+fn to_export_request_json(spans: Vec<serde_json::Value>) -> serde_json::Value {
+    serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [string_attribute("service.name", SERVICE_NAME)]
+            },
+            "scopeSpans": [{
+                "scope": {
+                    "name": SERVICE_NAME,
+                    "version": misc_helpers::get_current_version()
+                },
+                "spans": spans
+            }]
+        }]
+    })
+}
+
+// the spans are exported on a background thread, so the requests never wait for the collector
+fn start_exporter() -> Option<SyncSender<serde_json::Value>> {
+    let endpoint = config::get_otlp_traces_endpoint()?;
+    let endpoint = match Url::parse(&endpoint) {
+        Ok(url) => url,
+        Err(e) => {
+            logger::write_warning(format!("Invalid otlpTracesEndpoint {}: {}", endpoint, e));
+            return None;
+        }
+    };
+
+    let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED_SPANS);
+    thread::spawn(move || export_spans(endpoint, receiver));
+    Some(sender)
+}
+
+fn export_spans(endpoint: Url, receiver: Receiver<serde_json::Value>) {
+    while let Ok(span) = receiver.recv() {
+        let mut spans = vec![span];
+        while spans.len() < MAX_EXPORT_BATCH {
+            match receiver.try_recv() {
+                Ok(span) => spans.push(span),
+                Err(_) => break,
+            }
+        }
+
+        let count = spans.len();
+        let body = to_export_request_json(spans).to_string();
+        let mut request = Request::new(endpoint.path().to_string(), "POST".to_string());
+        request
+            .headers
+            .add_header("Content-Type".to_string(), "application/json".to_string());
+        request
+            .headers
+            .add_header("Content-Length".to_string(), body.len().to_string());
+        request.set_body_as_string(body);
+        let mut http_request = HttpRequest::new(endpoint.clone(), request);
+        let host = http_request.get_host();
+        http_request
+            .request
+            .headers
+            .add_header("Host".to_string(), host);
+        match http::get_response_in_string_with_timeout(&mut http_request, EXPORT_TIMEOUT) {
+            Ok(response) if response.status.starts_with('2') => {}
+            Ok(response) => logger::write_warning(format!(
+                "Failed to export {} spans: {}",
+                count,
+                response.description()
+            )),
+            Err(e) => logger::write_warning(format!("Failed to export {} spans: {}", count, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Span, TraceContext};
+    use crate::common::http::request::Request;
+    use crate::proxy::proxy_summary::ProxySummary;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn trace_context_test() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let context = TraceContext::parse(traceparent).unwrap();
+        assert_eq!("4bf92f3577b34da6a3ce929d0e0e4736", context.trace_id);
+        assert_eq!("00f067aa0ba902b7", context.span_id);
+        assert_eq!(1, context.flags);
+        assert_eq!(traceparent, context.to_traceparent());
+
+        for invalid in [
+            "",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(
+                None,
+                TraceContext::parse(invalid),
+                "'{invalid}' is not valid"
+            );
+        }
+    }
+
+    #[test]
+    fn span_test() {
+        let mut request = Request::new("/metadata/instance".to_string(), "GET".to_string());
+        let span = Span::start(7, SystemTime::now(), &request);
+        assert_eq!(32, span.context.trace_id.len());
+        assert_eq!(16, span.context.span_id.len());
+        assert_eq!(None, span.parent_span_id, "a new trace without traceparent");
+
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00";
+        request.headers.add_header(
+            super::TRACEPARENT_HEADER.to_string(),
+            traceparent.to_string(),
+        );
+        let start_time = SystemTime::now() - Duration::from_millis(5);
+        let span = Span::start(7, start_time, &request);
+        assert_eq!("4bf92f3577b34da6a3ce929d0e0e4736", span.context.trace_id);
+        assert_eq!(0, span.context.flags, "the sampled flag is kept");
+        assert_ne!("00f067aa0ba902b7", span.context.span_id);
+        assert!(span
+            .context
+            .to_traceparent()
+            .starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+
+        let summary = ProxySummary {
+            method: "GET".to_string(),
+            url: "/metadata/instance".to_string(),
+            clientIp: "127.0.0.1".to_string(),
+            ip: "169.254.169.254".to_string(),
+            port: 80,
+            userId: 0,
+            userName: "root".to_string(),
+            userGroups: Vec::new(),
+            processFullPath: "/usr/bin/curl".to_string(),
+            processCmdLine: "curl".to_string(),
+            processExeSha256: None,
+            processSigner: None,
+            runAsElevated: true,
+            isSystemAccount: true,
+            responseStatus: "502 Bad Gateway".to_string(),
+            elapsedTime: 5,
+            tunnelBytesSent: None,
+            tunnelBytesReceived: None,
+            authorization: None,
+            upstreamError: Some("timeout".to_string()),
+        };
+        let json = span.to_otlp_json(&summary, SystemTime::now());
+        assert_eq!("4bf92f3577b34da6a3ce929d0e0e4736", json["traceId"]);
+        assert_eq!("00f067aa0ba902b7", json["parentSpanId"]);
+        assert_eq!("GET", json["name"]);
+        assert_eq!(2, json["status"]["code"], "5xx is an error");
+        let attributes = json["attributes"].as_array().unwrap();
+        assert!(attributes.contains(&super::int_attribute("http.response.status_code", 502)));
+        assert!(attributes.contains(&super::string_attribute("proxy_agent.connection_id", "7")));
+        assert!(attributes.contains(&super::string_attribute("error.type", "timeout")));
+        assert!(
+            json["startTimeUnixNano"].as_str().unwrap() < json["endTimeUnixNano"].as_str().unwrap()
+        );
+
+        let export = super::to_export_request_json(vec![json]);
+        assert_eq!(
+            1,
+            export["resourceSpans"][0]["scopeSpans"][0]["spans"]
+                .as_array()
+                .unwrap()
+                .len()
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+
+// drop the idle buckets when the number of tracked clients grows over this
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/*
+Token bucket rate limiter per client ip.
+Each client starts with a full bucket of 'burst' tokens, a request takes one token,
+and the tokens refill at 'rate_per_second' up to the burst size.
+The limiter is disabled if the rate is not positive.
+ */
+pub struct RateLimiter {
+    rate_per_second: f64,
+    burst: f64,
+    buckets: HashMap<IpAddr, (f64, Instant)>, // (tokens, last refill time)
+}
+
+impl RateLimiter {
+    pub fn new(rate_per_second: f64, burst: u32) -> Self {
+        RateLimiter {
+            rate_per_second,
+            burst: (burst as f64).max(1.0),
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rate_per_second > 0.0
+    }
+
+    // returns false if the client exceeds its rate limit
+    pub fn try_acquire(&mut self, client_ip: IpAddr) -> bool {
+        self.try_acquire_at(client_ip, Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, client_ip: IpAddr, now: Instant) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        if self.buckets.len() >= MAX_TRACKED_CLIENTS && !self.buckets.contains_key(&client_ip) {
+            // a full bucket is the same as an untracked one
+            let (rate, burst) = (self.rate_per_second, self.burst);
+            self.buckets
+                .retain(|_, bucket| refill(bucket, rate, burst, now) < burst);
+        }
+
+        let (rate, burst) = (self.rate_per_second, self.burst);
+        let bucket = self.buckets.entry(client_ip).or_insert((burst, now));
+        if refill(bucket, rate, burst, now) < 1.0 {
+            return false;
+        }
+        bucket.0 -= 1.0;
+        true
+    }
+}
+
+fn refill(bucket: &mut (f64, Instant), rate: f64, burst: f64, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.1).as_secs_f64();
+    bucket.0 = (bucket.0 + elapsed * rate).min(burst);
+    bucket.1 = now;
+    bucket.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn rate_limiter_test() {
+        let client1 = "10.0.0.1".parse::<IpAddr>().unwrap();
+        let client2 = "10.0.0.2".parse::<IpAddr>().unwrap();
+        let start = Instant::now();
+
+        let mut limiter = RateLimiter::new(2.0, 3);
+        assert!(limiter.is_enabled());
+        for _ in 0..3 {
+            assert!(limiter.try_acquire_at(client1, start), "within the burst");
+        }
+        assert!(!limiter.try_acquire_at(client1, start), "burst is used up");
+        assert!(
+            limiter.try_acquire_at(client2, start),
+            "the clients have their own buckets"
+        );
+
+        // 2 tokens per second
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.try_acquire_at(client1, later));
+        assert!(!limiter.try_acquire_at(client1, later));
+
+        // refill up to the burst size only
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.try_acquire_at(client1, much_later));
+        }
+        assert!(!limiter.try_acquire_at(client1, much_later));
+
+        let mut limiter = RateLimiter::new(0.0, 1);
+        assert!(!limiter.is_enabled());
+        for _ in 0..10 {
+            assert!(
+                limiter.try_acquire_at(client1, start),
+                "rate limit is disabled"
+            );
+        }
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+
+/*
+Exercise the proxy path end to end in the agent process for the field diagnostics.
+Nothing is sent to the real host and the eBPF maps are not touched: a mock host is bound on the loopback,
+the audit entry of the agent process to the mock host is synthesized and its claims are derived,
+the claims are authorized by the current WireServer rules, the request is signed by the current key,
+and the mock host verifies the claims header and the signature before it responds.
+The stages run in order and the first failed stage stops the self test.
+ */
+use super::proxy_authentication;
+use crate::common::constants;
+use crate::common::helpers;
+use crate::common::http;
+use crate::common::http::headers;
+use crate::common::http::http_request;
+use crate::common::http::request::Request;
+use crate::common::http::response::Response;
+use crate::common::logger;
+use crate::key_keeper;
+use crate::key_keeper::key::Key;
+use crate::proxy::{Claims, HostClaims};
+use crate::redirector::AuditEntry;
+use proxy_agent_shared::telemetry::event_logger;
+use serde_derive::Serialize;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+pub const BIND_STAGE: &str = "bind";
+pub const LOOKUP_STAGE: &str = "lookup";
+pub const AUTH_STAGE: &str = "auth";
+pub const SIGN_STAGE: &str = "sign";
+pub const FORWARD_STAGE: &str = "forward";
+
+const SELF_TEST_URL: &str = "/machine?comp=goalstate";
+const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+pub struct SelfTestStage {
+    pub name: String,
+    pub succeeded: bool,
+    pub message: String,
+    pub elapsedInMilliseconds: u128,
+}
+
+#[derive(Serialize)]
+#[allow(non_snake_case)]
+pub struct SelfTestResult {
+    pub succeeded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failedStage: Option<String>,
+    pub stages: Vec<SelfTestStage>,
+}
+
+impl SelfTestResult {
+    fn new() -> Self {
+        SelfTestResult {
+            succeeded: true,
+            failedStage: None,
+            stages: Vec::new(),
+        }
+    }
+
+    // record the stage, returns its value if it succeeded
+    fn run_stage<T, F>(&mut self, name: &str, stage: F) -> Option<T>
+    where
+        F: FnOnce() -> Result<(T, String), String>,
+    {
+        let start = Instant::now();
+        let (value, succeeded, message) = match stage() {
+            Ok((value, message)) => (Some(value), true, message),
+            Err(message) => (None, false, message),
+        };
+        self.stages.push(SelfTestStage {
+            name: name.to_string(),
+            succeeded,
+            message,
+            elapsedInMilliseconds: start.elapsed().as_millis(),
+        });
+        if !succeeded {
+            self.succeeded = false;
+            self.failedStage = Some(name.to_string());
+        }
+        value
+    }
+}
+
+pub fn run(connection_id: u128) -> SelfTestResult {
+    let result = run_with_key(connection_id, key_keeper::get_current_key_details());
+    let message = match &result.failedStage {
+        Some(stage) => format!("Self test failed at the '{}' stage.", stage),
+        None => "Self test succeeded.".to_string(),
+    };
+    event_logger::write_event(
+        if result.succeeded {
+            event_logger::INFO_LEVEL
+        } else {
+            event_logger::WARN_LEVEL
+        },
+        message,
+        "run",
+        "self_test",
+        logger::AGENT_LOGGER_KEY,
+    );
+    result
+}
+
+fn run_with_key(connection_id: u128, key: Key) -> SelfTestResult {
+    let mut result = SelfTestResult::new();
+
+    let mock_host = match result.run_stage(BIND_STAGE, || {
+        let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+        let address = listener.local_addr().map_err(|e| e.to_string())?;
+        Ok((listener, format!("Mock host is bound at {}.", address)))
+    }) {
+        Some(listener) => listener,
+        None => return result,
+    };
+    let destination = match mock_host.local_addr() {
+        Ok(address) => address,
+        Err(_) => return result,
+    };
+
+    let claims = match result.run_stage(LOOKUP_STAGE, || lookup(destination)) {
+        Some(claims) => claims,
+        None => return result,
+    };
+
+    if result
+        .run_stage(AUTH_STAGE, || authorize(connection_id, &claims))
+        .is_none()
+    {
+        return result;
+    }
+
+    let request = match result.run_stage(SIGN_STAGE, || sign(&claims, &key)) {
+        Some(request) => request,
+        None => return result,
+    };
+
+    result.run_stage(FORWARD_STAGE, || {
+        forward(mock_host, destination, request, key)
+    });
+    result
+}
+
+// synthesize the audit entry the redirector records for the agent process and derive its claims
+fn lookup(destination: SocketAddr) -> Result<(Claims, String), String> {
+    let mut entry = AuditEntry::empty();
+    #[cfg(not(windows))]
+    {
+        entry.logon_id = unsafe { libc::geteuid() } as u64;
+        entry.is_admin = (entry.logon_id == 0) as i32;
+    }
+    #[cfg(windows)]
+    {
+        // the agent service runs as LocalSystem
+        entry.logon_id = 0x3e7;
+        entry.is_admin = 1;
+    }
+    entry.process_id = std::process::id();
+    entry.set_destination(destination.ip(), destination.port());
+    if !entry.is_valid() {
+        return Err("The synthesized audit entry is not valid.".to_string());
+    }
+    let port = http::ntohs(entry.destination_port);
+    if SocketAddr::new(entry.destination_addr(), port) != destination {
+        return Err(format!(
+            "The audit entry destination {}:{} does not match {}.",
+            entry.destination_addr(),
+            port,
+            destination
+        ));
+    }
+
+    let claims = Claims::from_audit_entry(&entry, destination.ip(), SystemTime::now());
+    if claims.processId != entry.process_id || claims.processFullPath.is_empty() {
+        return Err(format!(
+            "Failed to derive the process claims of pid {}.",
+            entry.process_id
+        ));
+    }
+    let message = format!(
+        "Claims of process '{}' ({}) run by user '{}'.",
+        claims.processFullPath, claims.processId, claims.userName
+    );
+    Ok((claims, message))
+}
+
+// authorize the claims with the rules of the WireServer requests
+fn authorize(connection_id: u128, claims: &Claims) -> Result<((), String), String> {
+    let authenticate = proxy_authentication::get_authenticate(
+        constants::WIRE_SERVER_IP.to_string(),
+        constants::WIRE_SERVER_PORT,
+        claims.clone(),
+    );
+    if authenticate.authenticate(connection_id, SELF_TEST_URL.to_string()) {
+        Ok(((), format!("Allowed by {}.", authenticate.to_string())))
+    } else {
+        Err(format!("Denied by {}.", authenticate.to_string()))
+    }
+}
+
+fn sign(claims: &Claims, key: &Key) -> Result<(Request, String), String> {
+    let mut request = Request::new(SELF_TEST_URL.to_string(), "GET".to_string());
+    request.headers.add_header(
+        headers::HOST_HEADER_NAME.to_string(),
+        constants::WIRE_SERVER_IP.to_string(),
+    );
+    let host_claims = HostClaims::from_claims(claims)
+        .to_header_value()
+        .map_err(|e| e.to_string())?;
+    request
+        .headers
+        .add_header(constants::CLAIMS_HEADER.to_string(), host_claims);
+    request.headers.add_header(
+        constants::DATE_HEADER.to_string(),
+        proxy_agent_shared::misc_helpers::get_date_time_rfc1123_string(),
+    );
+    request.headers.add_header(
+        headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
+        "0".to_string(),
+    );
+
+    if key.key.is_empty() {
+        return Ok((
+            request,
+            "No key is latched, the request is not signed.".to_string(),
+        ));
+    }
+    http_request::add_authorization_header(&mut request, key)
+        .map_err(|e| format!("Failed to sign with key '{}': {}", key.guid, e))?;
+    let message = format!("Signed with key '{}'.", key.guid);
+    Ok((request, message))
+}
+
+// send the request to the mock host, which verifies it as the host would
+fn forward(
+    mock_host: TcpListener,
+    destination: SocketAddr,
+    request: Request,
+    key: Key,
+) -> Result<((), String), String> {
+    let host = thread::spawn(move || -> std::io::Result<()> {
+        // do not wait for the connection forever if the client fails to connect
+        mock_host.set_nonblocking(true)?;
+        let deadline = Instant::now() + SELF_TEST_TIMEOUT;
+        let stream = loop {
+            match mock_host.accept() {
+                Ok((stream, _)) => break stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock && Instant::now() < deadline => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        _ = stream.set_read_timeout(Some(SELF_TEST_TIMEOUT));
+        let request = http::receive_request_data(&stream)?;
+        let mut response = match verify(&request, &key) {
+            Ok(()) => Response::from_status(Response::OK.to_string()),
+            Err(message) => Response::new(Response::FORBIDDEN.to_string(), message),
+        };
+        response.headers.add_header(
+            headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
+            response.get_body_len().to_string(),
+        );
+        let mut stream = &stream;
+        stream.write_all(&response.to_raw_bytes())?;
+        stream.flush()
+    });
+
+    let response = http::connect_with_timeout(&destination.to_string(), SELF_TEST_TIMEOUT)
+        .and_then(|mut stream| {
+            _ = stream.set_read_timeout(Some(SELF_TEST_TIMEOUT));
+            stream.write_all(&request.to_raw_bytes())?;
+            stream.flush()?;
+            http::receive_response_data(&stream)
+        });
+    let host_result = host.join().unwrap_or(Ok(()));
+    let response = response.map_err(|e| format!("Failed to forward to the mock host: {}", e))?;
+    if let Err(e) = host_result {
+        return Err(format!("Mock host failed: {}", e));
+    }
+    if response.status != Response::OK {
+        return Err(format!(
+            "Mock host responded '{}': {}",
+            response.status,
+            response.get_body_as_string().unwrap_or_default()
+        ));
+    }
+    Ok(((), format!("Mock host responded '{}'.", response.status)))
+}
+
+// the mock host checks the claims header and the signature over the request as received
+fn verify(request: &Request, key: &Key) -> Result<(), String> {
+    if request
+        .headers
+        .get_header(constants::CLAIMS_HEADER)
+        .is_none()
+    {
+        return Err("The claims header is missing.".to_string());
+    }
+    let authorization = request.headers.get_header(constants::AUTHORIZATION_HEADER);
+    match (authorization, key.key.is_empty()) {
+        (None, true) => Ok(()),
+        (Some(authorization), false) => {
+            if helpers::verify_authorization_header(
+                &authorization,
+                &key.authorizationScheme,
+                &key.key,
+                &request.as_sig_input(),
+            ) {
+                Ok(())
+            } else {
+                Err("The signature does not match.".to_string())
+            }
+        }
+        (None, false) => Err("The authorization header is missing.".to_string()),
+        (Some(_), true) => Err("The request is signed without a key.".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        forward, lookup, run_with_key, sign, verify, AUTH_STAGE, BIND_STAGE, FORWARD_STAGE,
+        LOOKUP_STAGE, SIGN_STAGE,
+    };
+    use crate::common::logger;
+    use crate::key_keeper::key::Key;
+    use crate::proxy::proxy_listener;
+    use proxy_agent_shared::logger_manager;
+    use std::env;
+    use std::fs;
+    use std::net::{SocketAddr, TcpListener};
+
+    #[test]
+    fn self_test_test() {
+        let logger_key = "self_test_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        // the request is not signed without the key
+        let result = run_with_key(proxy_listener::next_connection_id(), Key::empty());
+        let stages: Vec<&str> = result.stages.iter().map(|s| s.name.as_str()).collect();
+        match &result.failedStage {
+            Some(stage) => {
+                assert_eq!(
+                    AUTH_STAGE, stage,
+                    "only the authorization depends on the user running the test"
+                );
+                assert_eq!(vec![BIND_STAGE, LOOKUP_STAGE, AUTH_STAGE], stages);
+                assert!(!result.succeeded);
+            }
+            None => {
+                assert!(
+                    result.succeeded,
+                    "{}",
+                    serde_json::to_string(&result).unwrap()
+                );
+                assert_eq!(
+                    vec![
+                        BIND_STAGE,
+                        LOOKUP_STAGE,
+                        AUTH_STAGE,
+                        SIGN_STAGE,
+                        FORWARD_STAGE
+                    ],
+                    stages
+                );
+            }
+        }
+
+        // the mock host verifies the signature
+        let mut key = Key::empty();
+        key.guid = "self-test-key".to_string();
+        key.key = "4A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59".to_string();
+        let destination = "127.0.0.1:80".parse::<SocketAddr>().unwrap();
+        let (claims, _) = lookup(destination).unwrap();
+        let (request, _) = sign(&claims, &key).unwrap();
+        assert!(verify(&request, &key).is_ok());
+        let mut other_key = Key::empty();
+        other_key.key =
+            "5A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59".to_string();
+        assert!(verify(&request, &other_key).is_err());
+        let (unsigned, _) = sign(&claims, &Key::empty()).unwrap();
+        assert!(verify(&unsigned, &key).is_err());
+
+        let mock_host = TcpListener::bind("127.0.0.1:0").unwrap();
+        let destination = mock_host.local_addr().unwrap();
+        assert!(forward(mock_host, destination, request, key).is_ok());
+
+        // the invalid key fails the sign stage
+        let mut invalid_key = Key::empty();
+        invalid_key.key = "zz".to_string();
+        assert!(sign(&claims, &invalid_key).is_err());
+
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+}
@@ -8,17 +8,53 @@ use once_cell::sync::Lazy;
 use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
 use std::mem::MaybeUninit;
+use std::net::{SocketAddr, SocketAddrV4};
 use std::ptr::null_mut;
-use windows_sys::Win32::Foundation::{BOOL, HANDLE, LUID, NTSTATUS, UNICODE_STRING};
+use windows_sys::Win32::Foundation::{
+    CloseHandle, BOOL, FILETIME, HANDLE, LUID, NTSTATUS, UNICODE_STRING,
+};
+use windows_sys::Win32::NetworkManagement::IpHelper::{
+    GetExtendedTcpTable, // iphlpapi.dll
+    MIB_TCPROW_OWNER_PID,
+    MIB_TCPTABLE_OWNER_PID,
+    TCP_TABLE_OWNER_PID_CONNECTIONS,
+};
+use windows_sys::Win32::Networking::WinSock::AF_INET;
 use windows_sys::Win32::Security::Authentication::Identity;
 use windows_sys::Win32::Security::Authentication::Identity::SECURITY_LOGON_SESSION_DATA;
+use windows_sys::Win32::Security::Cryptography::{
+    CertCloseStore,             // crypt32.dll
+    CertFindCertificateInStore, // crypt32.dll
+    CertFreeCertificateContext, // crypt32.dll
+    CertGetNameStringW,         // crypt32.dll
+    CryptMsgClose,              // crypt32.dll
+    CryptMsgGetParam,           // crypt32.dll
+    CryptQueryObject,           // crypt32.dll
+    CERT_FIND_SUBJECT_CERT,
+    CERT_INFO,
+    CERT_NAME_SIMPLE_DISPLAY_TYPE,
+    CERT_QUERY_CONTENT_FLAG_PKCS7_SIGNED_EMBED,
+    CERT_QUERY_FORMAT_FLAG_BINARY,
+    CERT_QUERY_OBJECT_FILE,
+    CMSG_SIGNER_INFO,
+    CMSG_SIGNER_INFO_PARAM,
+    HCERTSTORE,
+};
+use windows_sys::Win32::Security::{
+    GetTokenInformation, // advapi32.dll
+    TokenElevation,
+    TOKEN_ELEVATION,
+    TOKEN_QUERY,
+};
 use windows_sys::Win32::System::ProcessStatus::{
     K32GetModuleBaseNameW,   // kernel32.dll
     K32GetModuleFileNameExW, // kernel32.dll
 };
 use windows_sys::Win32::System::Threading::{
+    GetProcessTimes,           // kernel32.dll
     NtQueryInformationProcess, // ntdll.dll
     OpenProcess,               //kernel32.dll
+    OpenProcessToken,          // advapi32.dll
 };
 use windows_sys::Win32::System::Threading::{PROCESSINFOCLASS, PROCESS_BASIC_INFORMATION};
 
@@ -108,6 +144,27 @@ fn load_users() -> HashMap<u64, &'static str> {
     users
 }
 
+// the well-known groups of the built-in service accounts,
+// NetUserGetLocalGroups does not return the groups of these accounts
+static BUILTIN_USER_GROUPS: Lazy<HashMap<u64, Vec<&str>>> = Lazy::new(|| load_user_groups());
+fn load_user_groups() -> HashMap<u64, Vec<&'static str>> {
+    let service_groups = vec![
+        "Everyone",
+        "Users",
+        "Authenticated Users",
+        "SERVICE",
+        "LOCAL",
+    ];
+    let system_groups = vec!["Administrators", "Everyone", "Authenticated Users"];
+
+    let mut groups = HashMap::new();
+    groups.insert(0x3e4, service_groups.clone());
+    groups.insert(0x3e5, service_groups);
+    groups.insert(0x3e6, system_groups.clone());
+    groups.insert(0x3e7, system_groups);
+    groups
+}
+
 /*
     Get user name and user group names
 */
@@ -174,6 +231,13 @@ pub fn get_user(logon_id: u64) -> (String, Vec<String>) {
         if BUILTIN_USERS.contains_key(&logon_id) {
             user_name = BUILTIN_USERS[&logon_id].to_string();
         }
+        if let Some(groups) = BUILTIN_USER_GROUPS.get(&logon_id) {
+            for group in groups {
+                if !user_groups.iter().any(|g| g.eq_ignore_ascii_case(group)) {
+                    user_groups.push(group.to_string());
+                }
+            }
+        }
 
         (user_name, user_groups)
     }
@@ -226,7 +290,11 @@ fn to_pwstr(s: &str) -> Vec<u16> {
 */
 const PROCESS_QUERY_INFORMATION: u32 = 0x0400;
 const PROCESS_VM_READ: u32 = 0x0010;
+const PROCESS_QUERY_LIMITED_INFORMATION: u32 = 0x1000;
+// the FILETIME counts the 100-nanosecond intervals since 1601-01-01
+const FILETIME_UNIX_EPOCH_INTERVALS: u64 = 116444736000000000;
 const FALSE: BOOL = 0;
+const ERROR_INSUFFICIENT_BUFFER: u32 = 122;
 const MAX_PATH: usize = 260;
 const STATUS_BUFFER_OVERFLOW: NTSTATUS = -2147483643;
 const STATUS_BUFFER_TOO_SMALL: NTSTATUS = -1073741789;
@@ -253,6 +321,138 @@ pub fn query_basic_process_info(handler: isize) -> std::io::Result<PROCESS_BASIC
         Ok(process_basic_information)
     }
 }
+pub fn get_process_start_time(pid: u32) -> std::io::Result<std::time::SystemTime> {
+    unsafe {
+        let handler = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+        if handler == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut creation_time: FILETIME = std::mem::zeroed();
+        let mut exit_time: FILETIME = std::mem::zeroed();
+        let mut kernel_time: FILETIME = std::mem::zeroed();
+        let mut user_time: FILETIME = std::mem::zeroed();
+        let result = GetProcessTimes(
+            handler,
+            &mut creation_time,
+            &mut exit_time,
+            &mut kernel_time,
+            &mut user_time,
+        );
+        let error = std::io::Error::last_os_error();
+        CloseHandle(handler);
+        if result == 0 {
+            return Err(error);
+        }
+
+        let intervals =
+            ((creation_time.dwHighDateTime as u64) << 32) | creation_time.dwLowDateTime as u64;
+        let since_epoch = intervals.saturating_sub(FILETIME_UNIX_EPOCH_INTERVALS);
+        Ok(std::time::UNIX_EPOCH + std::time::Duration::from_nanos(since_epoch * 100))
+    }
+}
+
+// the client connected to the listener directly, the elevation of the process owns the client socket
+pub fn is_loopback_client_elevated(
+    client: SocketAddr,
+    listener: SocketAddr,
+) -> std::io::Result<bool> {
+    let (client, listener) = match (client, listener) {
+        (SocketAddr::V4(client), SocketAddr::V4(listener)) => (client, listener),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Only the ipv4 loopback clients are supported",
+            ))
+        }
+    };
+    let pid = get_tcp_connection_owner(client, listener)?;
+    is_process_elevated(pid)
+}
+
+// the pid owns the tcp connection from the local to the remote address
+fn get_tcp_connection_owner(local: SocketAddrV4, remote: SocketAddrV4) -> std::io::Result<u32> {
+    unsafe {
+        let mut size: u32 = 0;
+        let mut buffer: Vec<u32> = Vec::new();
+        // the table could grow between the size query and the read, retry with the new size
+        for _ in 0..3 {
+            let result = GetExtendedTcpTable(
+                buffer.as_mut_ptr() as *mut std::ffi::c_void,
+                &mut size,
+                FALSE,
+                AF_INET as u32,
+                TCP_TABLE_OWNER_PID_CONNECTIONS,
+                0,
+            );
+            if result == ERROR_INSUFFICIENT_BUFFER {
+                // the u32 buffer keeps the table rows aligned
+                buffer = vec![0; size as usize / std::mem::size_of::<u32>() + 1];
+                continue;
+            }
+            if result != 0 {
+                return Err(Error::from_raw_os_error(result as i32));
+            }
+
+            let table = &*(buffer.as_ptr() as *const MIB_TCPTABLE_OWNER_PID);
+            let rows: &[MIB_TCPROW_OWNER_PID] =
+                std::slice::from_raw_parts(table.table.as_ptr(), table.dwNumEntries as usize);
+            // the addresses and the ports are in the network byte order
+            return rows
+                .iter()
+                .find(|row| {
+                    row.dwLocalAddr == u32::from_ne_bytes(local.ip().octets())
+                        && u16::from_be(row.dwLocalPort as u16) == local.port()
+                        && row.dwRemoteAddr == u32::from_ne_bytes(remote.ip().octets())
+                        && u16::from_be(row.dwRemotePort as u16) == remote.port()
+                })
+                .map(|row| row.dwOwningPid)
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::NotFound,
+                        format!("No tcp connection found from {} to {}", local, remote),
+                    )
+                });
+        }
+
+        Err(Error::new(
+            ErrorKind::Other,
+            "The tcp table keeps growing, failed to read it",
+        ))
+    }
+}
+
+pub fn is_process_elevated(pid: u32) -> std::io::Result<bool> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid);
+        if process == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut token: HANDLE = 0;
+        if OpenProcessToken(process, TOKEN_QUERY, &mut token) == 0 {
+            let error = std::io::Error::last_os_error();
+            CloseHandle(process);
+            return Err(error);
+        }
+
+        let mut elevation: TOKEN_ELEVATION = std::mem::zeroed();
+        let mut return_length = 0;
+        let result = GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut TOKEN_ELEVATION as *mut std::ffi::c_void,
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut return_length,
+        );
+        let error = std::io::Error::last_os_error();
+        CloseHandle(token);
+        CloseHandle(process);
+        if result == 0 {
+            return Err(error);
+        }
+        Ok(elevation.TokenIsElevated != 0)
+    }
+}
+
 pub fn get_process_handler(pid: u32) -> std::io::Result<HANDLE> {
     if pid == 0 {
         return Err(std::io::Error::new(
@@ -344,6 +544,99 @@ pub fn get_process_full_name(handler: isize) -> std::io::Result<String> {
     }
 }
 
+/*
+The subject of the certificate which signed the file with an embedded Authenticode signature.
+The signature is not verified against the trusted roots here, the subject is recorded for the audit only.
+ */
+pub fn get_file_signer(file_path: &str) -> std::io::Result<String> {
+    let file_path: Vec<u16> = file_path.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe {
+        let mut encoding = 0;
+        let mut content_type = 0;
+        let mut format_type = 0;
+        let mut store = null_mut();
+        let mut msg = null_mut();
+        if CryptQueryObject(
+            CERT_QUERY_OBJECT_FILE,
+            file_path.as_ptr() as *const std::ffi::c_void,
+            CERT_QUERY_CONTENT_FLAG_PKCS7_SIGNED_EMBED,
+            CERT_QUERY_FORMAT_FLAG_BINARY,
+            0,
+            &mut encoding,
+            &mut content_type,
+            &mut format_type,
+            &mut store,
+            &mut msg,
+            null_mut(),
+        ) == 0
+        {
+            return Err(Error::last_os_error());
+        }
+        let signer = get_message_signer(store, msg, encoding);
+        CryptMsgClose(msg);
+        CertCloseStore(store, 0);
+        signer
+    }
+}
+
+unsafe fn get_message_signer(
+    store: HCERTSTORE,
+    msg: *const std::ffi::c_void,
+    encoding: u32,
+) -> std::io::Result<String> {
+    let mut size: u32 = 0;
+    if CryptMsgGetParam(msg, CMSG_SIGNER_INFO_PARAM, 0, null_mut(), &mut size) == 0 {
+        return Err(Error::last_os_error());
+    }
+    // u64 buffer keeps the signer info aligned
+    let mut buffer = vec![0u64; size as usize / 8 + 1];
+    if CryptMsgGetParam(
+        msg,
+        CMSG_SIGNER_INFO_PARAM,
+        0,
+        buffer.as_mut_ptr() as *mut std::ffi::c_void,
+        &mut size,
+    ) == 0
+    {
+        return Err(Error::last_os_error());
+    }
+    let signer_info = &*(buffer.as_ptr() as *const CMSG_SIGNER_INFO);
+
+    // the signer certificate is found in the message store by its issuer and serial number
+    let mut cert_info: CERT_INFO = std::mem::zeroed();
+    cert_info.Issuer = signer_info.Issuer;
+    cert_info.SerialNumber = signer_info.SerialNumber;
+    let context = CertFindCertificateInStore(
+        store,
+        encoding,
+        0,
+        CERT_FIND_SUBJECT_CERT,
+        &cert_info as *const CERT_INFO as *const std::ffi::c_void,
+        null_mut(),
+    );
+    if context.is_null() {
+        return Err(Error::last_os_error());
+    }
+    let mut name = [0u16; 256];
+    let length = CertGetNameStringW(
+        context,
+        CERT_NAME_SIMPLE_DISPLAY_TYPE,
+        0,
+        null_mut(),
+        name.as_mut_ptr(),
+        name.len() as u32,
+    );
+    CertFreeCertificateContext(context);
+    // the length includes the null terminator, 1 means the name is empty
+    if length <= 1 {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "The signer certificate has no subject name",
+        ));
+    }
+    Ok(String::from_utf16_lossy(&name[..length as usize - 1]))
+}
+
 #[cfg(test)]
 mod tests {
     use std::mem::MaybeUninit;
@@ -1,6 +1,6 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
-use crate::common::{config, logger};
+use crate::common::{config, helpers, logger};
 use crate::monitor;
 use crate::proxy::proxy_listener;
 use crate::proxy::proxy_summary::ProxySummary;
@@ -9,10 +9,10 @@ use once_cell::sync::Lazy;
 use proxy_agent_shared::misc_helpers;
 use proxy_agent_shared::proxy_agent_aggregate_status::{
     GuestProxyAgentAggregateStatus, ModuleState, OveralState, ProxyAgentStatus,
-    ProxyConnectionSummary,
+    ProxyConnectionSummary, ProxyResponseTimeSummary,
 };
 use proxy_agent_shared::telemetry::event_logger;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
@@ -24,6 +24,14 @@ static mut SUMMARY_MAP: Lazy<Mutex<HashMap<String, ProxyConnectionSummary>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 static mut FAILED_AUTHENTICATE_SUMMARY_MAP: Lazy<Mutex<HashMap<String, ProxyConnectionSummary>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
+// the (elapsed time, response status) of the latest proxied connections,
+// the window is fixed size to keep the memory bounded
+const RESPONSE_TIME_WINDOW_SIZE: usize = 1000;
+static RESPONSE_TIME_WINDOW: Lazy<Mutex<VecDeque<(u128, String)>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(RESPONSE_TIME_WINDOW_SIZE)));
+// ring buffer of the latest connection summaries, for live debugging
+static RECENT_SUMMARIES: Lazy<Mutex<VecDeque<ProxySummary>>> =
+    Lazy::new(|| Mutex::new(VecDeque::new()));
 
 pub fn start_async(interval: Duration) {
     _ = thread::Builder::new()
@@ -84,6 +92,9 @@ pub fn proxy_agent_status_new() -> ProxyAgentStatus {
     let key_latch_status = key_keeper::get_status();
     let ebpf_status = redirector::get_status();
     let proxy_status = proxy_listener::get_status();
+    let mut telemetry_logger_status = event_logger::get_status();
+    telemetry_logger_status.message =
+        helpers::cap_status_message(telemetry_logger_status.message, "event_logger");
     let mut status = OveralState::SUCCESS.to_string();
     if key_latch_status.status != ModuleState::RUNNING
         || ebpf_status.status != ModuleState::RUNNING
@@ -98,7 +109,7 @@ pub fn proxy_agent_status_new() -> ProxyAgentStatus {
         keyLatchStatus: key_latch_status,
         ebpfProgramStatus: ebpf_status,
         proxyListenerStatus: proxy_status,
-        telemetryLoggerStatus: event_logger::get_status(),
+        telemetryLoggerStatus: telemetry_logger_status,
         proxyConnectionsCount: proxy_listener::get_proxy_connection_count(),
     }
 }
@@ -125,6 +136,7 @@ pub fn guest_proxy_agent_aggregate_status_new() -> GuestProxyAgentAggregateStatu
         proxyAgentStatus: proxy_agent_status_new(),
         proxyConnectionSummary: get_all_connection_summary(false),
         failedAuthenticateSummary: get_all_connection_summary(true),
+        responseTimeSummary: get_response_time_summary(),
     }
 }
 
@@ -135,6 +147,16 @@ pub fn add_connection_summary(summary: ProxySummary, is_failed_authenticate: boo
         unsafe { SUMMARY_MAP.lock().unwrap() }
     };
 
+    add_recent_summary(
+        &mut RECENT_SUMMARIES.lock().unwrap(),
+        &summary,
+        config::get_recent_connection_summary_count(),
+    );
+    if !is_failed_authenticate {
+        // the failed authenticate summaries are not proxied and have no elapsed time
+        add_response_time(summary.elapsedTime, &summary.responseStatus);
+    }
+
     let summary_key = summary.to_key_string();
     if !summary_map.contains_key(&summary_key) {
         summary_map.insert(summary_key, proxy_connection_summary_new(summary));
@@ -145,6 +167,102 @@ pub fn add_connection_summary(summary: ProxySummary, is_failed_authenticate: boo
     }
 }
 
+fn add_recent_summary(
+    recent: &mut VecDeque<ProxySummary>,
+    summary: &ProxySummary,
+    capacity: usize,
+) {
+    while recent.len() >= capacity {
+        if recent.pop_front().is_none() {
+            return;
+        }
+    }
+    recent.push_back(summary.clone());
+}
+
+// the latest count summaries, newest first,
+// filtered by the response status code or full status text, or by the non-2xx status only
+pub fn get_recent_connection_summaries(
+    count: usize,
+    response_status: Option<&str>,
+    failed_only: bool,
+) -> Vec<ProxySummary> {
+    query_recent_summaries(
+        &RECENT_SUMMARIES.lock().unwrap(),
+        count,
+        response_status,
+        failed_only,
+    )
+}
+
+fn query_recent_summaries(
+    recent: &VecDeque<ProxySummary>,
+    count: usize,
+    response_status: Option<&str>,
+    failed_only: bool,
+) -> Vec<ProxySummary> {
+    recent
+        .iter()
+        .rev()
+        .filter(|summary| {
+            let status_code = summary.responseStatus.split(' ').next().unwrap_or_default();
+            if failed_only && status_code.starts_with('2') {
+                return false;
+            }
+            match response_status {
+                Some(status) => {
+                    summary.responseStatus.eq_ignore_ascii_case(status) || status_code == status
+                }
+                None => true,
+            }
+        })
+        .take(count)
+        .cloned()
+        .collect()
+}
+
+fn add_response_time(elapsed_time: u128, response_status: &str) {
+    let mut window = RESPONSE_TIME_WINDOW.lock().unwrap();
+    if window.len() >= RESPONSE_TIME_WINDOW_SIZE {
+        window.pop_front();
+    }
+    window.push_back((elapsed_time, response_status.to_string()));
+}
+
+// the elapsed time percentiles and the response status counts over the rolling window
+fn get_response_time_summary() -> Option<ProxyResponseTimeSummary> {
+    let window = RESPONSE_TIME_WINDOW.lock().unwrap();
+    if window.is_empty() {
+        return None;
+    }
+
+    let mut elapsed_times: Vec<u128> = window.iter().map(|(elapsed, _)| *elapsed).collect();
+    elapsed_times.sort_unstable();
+    let mut response_status_counts: HashMap<String, u64> = HashMap::new();
+    for (_, status) in window.iter() {
+        *response_status_counts
+            .entry(status.to_string())
+            .or_insert(0) += 1;
+    }
+
+    Some(ProxyResponseTimeSummary {
+        sampleCount: elapsed_times.len() as u64,
+        p50ElapsedTime: get_percentile(&elapsed_times, 50),
+        p90ElapsedTime: get_percentile(&elapsed_times, 90),
+        p99ElapsedTime: get_percentile(&elapsed_times, 99),
+        responseStatusCounts: response_status_counts,
+    })
+}
+
+// nearest-rank percentile of the sorted values
+fn get_percentile(sorted_values: &[u128], percentile: usize) -> u128 {
+    if sorted_values.is_empty() {
+        return 0;
+    }
+    let rank = (percentile * sorted_values.len() + 99) / 100;
+    sorted_values[rank.max(1) - 1]
+}
+
 fn get_all_connection_summary(is_failed_authenticate: bool) -> Vec<ProxyConnectionSummary> {
     let summary_map_lock = if is_failed_authenticate {
         unsafe { FAILED_AUTHENTICATE_SUMMARY_MAP.lock().unwrap() }
@@ -174,14 +292,95 @@ pub fn write_aggregate_status_to_file(
 
 #[cfg(test)]
 mod tests {
+    use crate::proxy::proxy_summary::ProxySummary;
     use crate::proxy_agent_status::{
         guest_proxy_agent_aggregate_status_new, write_aggregate_status_to_file,
     };
     use proxy_agent_shared::{
         misc_helpers, proxy_agent_aggregate_status::GuestProxyAgentAggregateStatus,
     };
+    use std::collections::VecDeque;
     use std::{env, fs};
 
+    #[test]
+    fn recent_connection_summaries_test() {
+        let summary = |status: &str, elapsed_time: u128| {
+            let mut summary: ProxySummary = serde_json::from_str(
+                r#"{"method":"GET","url":"/","clientIp":"127.0.0.1","ip":"168.63.129.16","port":80,"userId":0,"userName":"root","userGroups":[],"processFullPath":"/bin/curl","processCmdLine":"curl","runAsElevated":true,"responseStatus":"","elapsedTime":0}"#,
+            )
+            .unwrap();
+            summary.responseStatus = status.to_string();
+            summary.elapsedTime = elapsed_time;
+            summary
+        };
+
+        let mut buffer = VecDeque::new();
+        for i in 0..5 {
+            super::add_recent_summary(&mut buffer, &summary("200 OK", i), 3);
+        }
+        super::add_recent_summary(&mut buffer, &summary("404 Not Found", 5), 3);
+        let recent = super::query_recent_summaries(&buffer, 10, None, false);
+        assert_eq!(
+            3,
+            recent.len(),
+            "the buffer must evict the oldest summaries"
+        );
+        assert_eq!(5, recent[0].elapsedTime, "the newest summary comes first");
+        assert_eq!(3, recent[2].elapsedTime);
+
+        assert_eq!(
+            1,
+            super::query_recent_summaries(&buffer, 1, None, false).len()
+        );
+        let failed = super::query_recent_summaries(&buffer, 10, None, true);
+        assert_eq!(1, failed.len());
+        assert_eq!("404 Not Found", failed[0].responseStatus);
+        assert_eq!(
+            2,
+            super::query_recent_summaries(&buffer, 10, Some("200"), false).len()
+        );
+        assert_eq!(
+            2,
+            super::query_recent_summaries(&buffer, 10, Some("200 ok"), false).len()
+        );
+
+        // 0 capacity disables the buffer
+        super::add_recent_summary(&mut buffer, &summary("200 OK", 6), 0);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn response_time_summary_test() {
+        let values: Vec<u128> = (1..=100).collect();
+        assert_eq!(50, super::get_percentile(&values, 50));
+        assert_eq!(90, super::get_percentile(&values, 90));
+        assert_eq!(99, super::get_percentile(&values, 99));
+        assert_eq!(7, super::get_percentile(&[7], 99));
+        assert_eq!(0, super::get_percentile(&[], 50));
+
+        for i in 0..super::RESPONSE_TIME_WINDOW_SIZE + 10 {
+            let status = if i % 2 == 0 {
+                "200 OK"
+            } else {
+                "404 Not Found"
+            };
+            super::add_response_time(i as u128, status);
+        }
+        let summary = super::get_response_time_summary().unwrap();
+        assert_eq!(
+            super::RESPONSE_TIME_WINDOW_SIZE as u64,
+            summary.sampleCount,
+            "the window must not grow over its size"
+        );
+        assert!(summary.p50ElapsedTime <= summary.p90ElapsedTime);
+        assert!(summary.p90ElapsedTime <= summary.p99ElapsedTime);
+        assert_eq!(
+            summary.sampleCount,
+            summary.responseStatusCounts.values().sum::<u64>()
+        );
+        assert!(summary.responseStatusCounts.contains_key("404 Not Found"));
+    }
+
     #[test]
     fn write_aggregate_status_test() {
         let mut temp_test_path = env::temp_dir();
@@ -6,15 +6,28 @@ mod windows;
 #[cfg(not(windows))]
 mod linux;
 
-use crate::common::{config, constants, logger};
+use crate::common::cidr::Cidr;
+use crate::common::config::RedirectDestination;
+use crate::common::{config, constants, helpers, logger};
+use once_cell::sync::Lazy;
 use proxy_agent_shared::misc_helpers;
 use proxy_agent_shared::proxy_agent_aggregate_status::{ModuleState, ProxyAgentDetailStatus};
 use proxy_agent_shared::telemetry::event_logger;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::{env, thread};
 
-#[derive(Serialize, Deserialize)]
+pub const AF_INET: u16 = 2;
+#[cfg(windows)]
+pub const AF_INET6: u16 = 23;
+#[cfg(not(windows))]
+pub const AF_INET6: u16 = 10;
+
+#[derive(Serialize, Deserialize, Clone)]
 #[repr(C)]
 pub struct AuditEntry {
     pub logon_id: u64,
@@ -22,6 +35,8 @@ pub struct AuditEntry {
     pub is_admin: i32,
     pub destination_ipv4: u32,
     pub destination_port: u16,
+    pub address_family: u16, // AF_INET or AF_INET6, the older eBPF program leaves it 0 for AF_INET
+    pub destination_ipv6: [u8; 16], // network byte order, set when address_family is AF_INET6
 }
 
 impl AuditEntry {
@@ -32,11 +47,67 @@ impl AuditEntry {
             is_admin: 0,
             destination_ipv4: 0,
             destination_port: 0,
+            address_family: 0,
+            destination_ipv6: [0; 16],
+        }
+    }
+
+    pub fn destination_addr(&self) -> IpAddr {
+        if self.address_family == AF_INET6 {
+            IpAddr::V6(Ipv6Addr::from(self.destination_ipv6))
+        } else {
+            IpAddr::V4(ipv4_from_network_order(self.destination_ipv4))
+        }
+    }
+
+    // set the destination ip and port in network byte order as the eBPF program does
+    pub fn set_destination(&mut self, ip: IpAddr, port: u16) {
+        match ip {
+            IpAddr::V4(ip) => {
+                self.address_family = AF_INET;
+                self.destination_ipv4 = ipv4_to_network_order(ip);
+                self.destination_ipv6 = [0; 16];
+            }
+            IpAddr::V6(ip) => {
+                self.address_family = AF_INET6;
+                self.destination_ipv4 = 0;
+                self.destination_ipv6 = ip.octets();
+            }
+        }
+        self.destination_port = port.to_be();
+    }
+
+    // a partially written eBPF map entry could have zero destination or logon id
+    pub fn is_valid(&self) -> bool {
+        if self.destination_addr().is_unspecified() || self.destination_port == 0 {
+            return false;
+        }
+
+        // windows logon id is a non-zero LUID, linux logon id is the uid
+        #[cfg(windows)]
+        {
+            self.logon_id != 0
+        }
+        #[cfg(not(windows))]
+        {
+            self.logon_id <= u32::MAX as u64
         }
     }
 }
 
-const MAX_STATUS_MESSAGE_LENGTH: usize = 1024;
+// true when the audit map warning event has been emitted for the current threshold crossing
+static AUDIT_MAP_WARNING_EMITTED: AtomicBool = AtomicBool::new(false);
+// the audit map lookups since the agent started, a missed lookup fails the request as misdirected
+static AUDIT_LOOKUP_COUNT: AtomicU64 = AtomicU64::new(0);
+static AUDIT_LOOKUP_MISS_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// the max_entries of the policy_map in the eBPF programs
+const MAX_POLICY_MAP_ENTRIES: usize = 10;
+// the destination ranges redirected in addition to, or excluded from, the host endpoints
+static DESTINATION_ALLOWLIST: Lazy<Mutex<Vec<RedirectDestination>>> =
+    Lazy::new(|| Mutex::new(config::get_redirect_destinations()));
+// the (ipv4 in network byte order, port) destinations currently in the policy_map
+static POLICY_DESTINATIONS: Lazy<Mutex<Vec<(u32, u16)>>> = Lazy::new(|| Mutex::new(Vec::new()));
 
 pub fn start_async(local_port: u16) {
     thread::spawn(move || {
@@ -45,7 +116,14 @@ pub fn start_async(local_port: u16) {
 }
 
 fn start(local_port: u16) -> bool {
-    for _ in 0..5 {
+    let retry_count = config::get_redirector_start_retry_count();
+    let retry_delay = config::get_redirector_start_retry_delay();
+    for attempt in 0..=retry_count {
+        logger::write(format!(
+            "Starting the redirector, attempt {} of {}.",
+            attempt + 1,
+            retry_count + 1
+        ));
         #[cfg(windows)]
         {
             windows::start(local_port);
@@ -71,7 +149,9 @@ fn start(local_port: u16) -> bool {
         if is_started() {
             return true;
         }
-        std::thread::sleep(std::time::Duration::from_millis(10));
+        if attempt < retry_count {
+            thread::sleep(retry_delay);
+        }
     }
 
     return is_started();
@@ -86,36 +166,170 @@ pub fn close(local_port: u16) {
     {
         linux::close(local_port);
     }
+    // the policy_map is gone with the closed eBPF object
+    POLICY_DESTINATIONS.lock().unwrap().clear();
 }
 
-fn get_status_message() -> String {
+// point the redirection to the new local port of the proxy listener
+pub fn update_local_port(old_port: u16, new_port: u16) -> bool {
     #[cfg(windows)]
     {
-        return windows::get_status();
+        return windows::update_local_port(old_port, new_port);
     }
     #[cfg(not(windows))]
     {
-        return linux::get_status();
+        return linux::update_local_port(old_port, new_port);
     }
 }
 
-pub fn get_status() -> ProxyAgentDetailStatus {
-    let mut message = get_status_message();
-    if message.len() > MAX_STATUS_MESSAGE_LENGTH {
+// replace the destination allowlist and reprogram the policy_map if the redirector is started,
+// the previous allowlist is restored if the policy_map cannot be updated
+pub fn update_destination_allowlist(
+    allowlist: Vec<RedirectDestination>,
+    local_port: u16,
+) -> std::io::Result<()> {
+    resolve_policy_destinations(Vec::new(), &allowlist)?;
+    let previous = std::mem::replace(&mut *DESTINATION_ALLOWLIST.lock().unwrap(), allowlist);
+    if !is_started() {
+        return Ok(());
+    }
+
+    if update_policy_map(local_port) {
         event_logger::write_event(
-            event_logger::WARN_LEVEL,
+            event_logger::INFO_LEVEL,
             format!(
-                "Status message is too long, truncating to {} characters. Message: {}",
-                MAX_STATUS_MESSAGE_LENGTH,
-                message.to_string()
+                "Destination allowlist updated: {}",
+                get_destination_allowlist_state()
             ),
-            "get_status",
+            "update_destination_allowlist",
             "redirector",
             logger::AGENT_LOGGER_KEY,
         );
+        return Ok(());
+    }
+
+    *DESTINATION_ALLOWLIST.lock().unwrap() = previous;
+    update_policy_map(local_port);
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        format!(
+            "Failed to update the policy_map with the destination allowlist: {}",
+            get_status_message()
+        ),
+    ))
+}
+
+fn update_policy_map(local_port: u16) -> bool {
+    #[cfg(windows)]
+    {
+        return windows::update_policy_map(local_port);
+    }
+    #[cfg(not(windows))]
+    {
+        return linux::refresh_policy_map(local_port);
+    }
+}
+
+// the destinations to redirect, the host endpoints and the allowlist ranges minus the excluded ranges
+fn get_policy_destinations(host_endpoints: Vec<(u32, u16)>) -> std::io::Result<Vec<(u32, u16)>> {
+    let allowlist = DESTINATION_ALLOWLIST.lock().unwrap().clone();
+    resolve_policy_destinations(host_endpoints, &allowlist)
+}
 
-        message = format!("{}...", message[0..MAX_STATUS_MESSAGE_LENGTH].to_string());
+fn resolve_policy_destinations(
+    host_endpoints: Vec<(u32, u16)>,
+    allowlist: &[RedirectDestination],
+) -> std::io::Result<Vec<(u32, u16)>> {
+    let mut destinations = host_endpoints;
+    let mut excluded = Vec::new();
+    for destination in allowlist {
+        let cidr = Cidr::parse(&destination.cidr)?;
+        if destination.exclude.unwrap_or(false) {
+            excluded.push((cidr, destination.port));
+            continue;
+        }
+
+        let port = match destination.port {
+            Some(port) => port,
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Port is required to redirect CIDR '{}'", destination.cidr),
+                ));
+            }
+        };
+        for ip in cidr.ipv4_addresses(MAX_POLICY_MAP_ENTRIES)? {
+            let destination = (ipv4_to_network_order(ip), port);
+            if !destinations.contains(&destination) {
+                destinations.push(destination);
+            }
+        }
     }
+
+    destinations.retain(|(ip, port)| {
+        !excluded.iter().any(|(cidr, excluded_port)| {
+            cidr.contains(&IpAddr::V4(ipv4_from_network_order(*ip)))
+                && excluded_port.map_or(true, |p| p == *port)
+        })
+    });
+    if destinations.len() > MAX_POLICY_MAP_ENTRIES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "{} destinations to redirect, the policy_map holds at most {}",
+                destinations.len(),
+                MAX_POLICY_MAP_ENTRIES
+            ),
+        ));
+    }
+    Ok(destinations)
+}
+
+// record the destinations programmed to the policy_map, returns the previous ones to remove
+fn set_policy_destinations(destinations: &[(u32, u16)]) -> Vec<(u32, u16)> {
+    let previous = std::mem::replace(
+        &mut *POLICY_DESTINATIONS.lock().unwrap(),
+        destinations.to_vec(),
+    );
+    previous
+        .into_iter()
+        .filter(|destination| !destinations.contains(destination))
+        .collect()
+}
+
+// the active allowlist, e.g. "10.0.0.4/31:80,!169.254.169.254/32"
+fn get_destination_allowlist_state() -> String {
+    DESTINATION_ALLOWLIST
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|destination| {
+            let mut state = destination.cidr.to_string();
+            if destination.exclude.unwrap_or(false) {
+                state = format!("!{}", state);
+            }
+            if let Some(port) = destination.port {
+                state = format!("{}:{}", state, port);
+            }
+            state
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn get_status_message() -> String {
+    #[cfg(windows)]
+    {
+        return windows::get_status();
+    }
+    #[cfg(not(windows))]
+    {
+        return linux::get_status();
+    }
+}
+
+pub fn get_status() -> ProxyAgentDetailStatus {
+    let message = helpers::cap_status_message(get_status_message(), "redirector");
     let status;
     if is_started() {
         status = ModuleState::RUNNING.to_string();
@@ -123,13 +337,96 @@ pub fn get_status() -> ProxyAgentDetailStatus {
         status = ModuleState::STOPPED.to_string();
     }
 
+    let mut states = None;
+    if is_started() {
+        let mut map = get_audit_lookup_states();
+        match get_audit_map_usage() {
+            Ok((count, capacity)) => {
+                check_audit_map_usage(count, capacity, config::get_audit_map_warning_threshold());
+                map.insert("auditMapEntries".to_string(), count.to_string());
+                map.insert("auditMapCapacity".to_string(), capacity.to_string());
+            }
+            Err(e) => {
+                logger::write_warning(format!("Failed to get audit map usage: {}", e));
+            }
+        }
+        map.insert(
+            "destinationAllowlist".to_string(),
+            get_destination_allowlist_state(),
+        );
+        map.insert(
+            "policyDestinations".to_string(),
+            POLICY_DESTINATIONS
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(ip, port)| format!("{}:{}", ip_to_string(*ip), port))
+                .collect::<Vec<String>>()
+                .join(","),
+        );
+        states = Some(map);
+    }
+
     ProxyAgentDetailStatus {
         status,
         message,
-        states: None,
+        states,
+    }
+}
+
+// returns (current entry count, max entries) of the audit map
+pub fn get_audit_map_usage() -> std::io::Result<(u32, u32)> {
+    #[cfg(windows)]
+    let usage = windows::get_audit_map_usage();
+    #[cfg(not(windows))]
+    let usage = linux::get_audit_map_usage();
+    usage.map(|(count, capacity)| {
+        (
+            count,
+            get_capped_capacity(capacity, config::get_audit_map_max_entries()),
+        )
+    })
+}
+
+// the linux audit map is created with the configured max entries,
+// the windows one keeps the size built in the eBPF program and its usage is reported against the cap
+fn get_capped_capacity(capacity: u32, max_entries: Option<u32>) -> u32 {
+    match max_entries {
+        Some(max_entries) => capacity.min(max_entries),
+        None => capacity,
     }
 }
 
+// emits the warning event once the audit map occupancy crosses the threshold percentage,
+// it is re-armed after the occupancy drops below the threshold again.
+// returns true if the warning event is emitted in this call.
+fn check_audit_map_usage(count: u32, capacity: u32, threshold: u8) -> bool {
+    if capacity == 0 {
+        return false;
+    }
+
+    let occupancy = count as u64 * 100 / capacity as u64;
+    if occupancy < threshold as u64 {
+        AUDIT_MAP_WARNING_EMITTED.store(false, Ordering::Relaxed);
+        return false;
+    }
+    if AUDIT_MAP_WARNING_EMITTED.swap(true, Ordering::Relaxed) {
+        return false;
+    }
+
+    event_logger::write_event(
+        event_logger::WARN_LEVEL,
+        format!(
+            "Audit map occupancy {}% ({}/{}) crossed the warning threshold {}%, audit entries may be evicted.",
+            occupancy, count, capacity, threshold
+        ),
+        "check_audit_map_usage",
+        "redirector",
+        logger::AGENT_LOGGER_KEY,
+    );
+    true
+}
+
 pub fn is_started() -> bool {
     #[cfg(windows)]
     {
@@ -142,14 +439,35 @@ pub fn is_started() -> bool {
 }
 
 pub fn lookup_audit(source_port: u16) -> std::io::Result<AuditEntry> {
+    let result;
     #[cfg(windows)]
     {
-        windows::lookup_audit(source_port)
+        result = windows::lookup_audit(source_port);
     }
     #[cfg(not(windows))]
     {
-        linux::lookup_audit(source_port)
+        result = linux::lookup_audit(source_port);
     }
+
+    AUDIT_LOOKUP_COUNT.fetch_add(1, Ordering::Relaxed);
+    if result.is_err() {
+        AUDIT_LOOKUP_MISS_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+}
+
+// the audit map lookup counters reported in the redirector status states
+fn get_audit_lookup_states() -> HashMap<String, String> {
+    let mut states = HashMap::new();
+    states.insert(
+        "auditLookups".to_string(),
+        AUDIT_LOOKUP_COUNT.load(Ordering::Relaxed).to_string(),
+    );
+    states.insert(
+        "auditLookupMisses".to_string(),
+        AUDIT_LOOKUP_MISS_COUNT.load(Ordering::Relaxed).to_string(),
+    );
+    states
 }
 
 pub fn get_audit_from_stream(_tcp_stream: &std::net::TcpStream) -> std::io::Result<AuditEntry> {
@@ -166,60 +484,70 @@ pub fn get_audit_from_stream(_tcp_stream: &std::net::TcpStream) -> std::io::Resu
     }
 }
 
-pub fn ip_to_string(ip: u32) -> String {
-    let mut ip_str = String::new();
+/*
+Resolve the original destination and the process of the redirected connection from its source port or its stream.
+The proxy listener is given the resolver, so its authorization, signing and forwarding can be tested
+with the fixed audit entries without the eBPF maps.
+ */
+pub trait DestinationResolver: Send + Sync {
+    fn lookup_audit(&self, source_port: u16) -> std::io::Result<AuditEntry>;
+    fn get_audit_from_stream(
+        &self,
+        tcp_stream: &std::net::TcpStream,
+    ) -> std::io::Result<AuditEntry>;
+}
+
+// the production resolver, it reads the audit entries written by the redirector
+pub struct AuditMapResolver;
+
+impl DestinationResolver for AuditMapResolver {
+    fn lookup_audit(&self, source_port: u16) -> std::io::Result<AuditEntry> {
+        lookup_audit(source_port)
+    }
 
-    let seg_number = 16 * 16;
-    let seg = ip % seg_number;
-    ip_str.push_str(seg.to_string().as_str());
-    ip_str.push('.');
+    fn get_audit_from_stream(
+        &self,
+        tcp_stream: &std::net::TcpStream,
+    ) -> std::io::Result<AuditEntry> {
+        get_audit_from_stream(tcp_stream)
+    }
+}
 
-    let ip = ip / seg_number;
-    let seg = ip % seg_number;
-    ip_str.push_str(seg.to_string().as_str());
-    ip_str.push('.');
+/*
+The eBPF maps and the audit entries store the ipv4 address as a u32 in network byte order,
+i.e. the octets are in memory in the address order and the u32 is read in the host byte order.
+The u32 conversions of Ipv4Addr are in the host byte order, hence the to_be/from_be conversion.
+ */
+pub fn ipv4_to_network_order(ip: Ipv4Addr) -> u32 {
+    u32::from(ip).to_be()
+}
 
-    let ip = ip / seg_number;
-    let seg = ip % seg_number;
-    ip_str.push_str(seg.to_string().as_str());
-    ip_str.push('.');
+pub fn ipv4_from_network_order(ip: u32) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from_be(ip))
+}
 
-    let ip = ip / seg_number;
-    let seg = ip % seg_number;
-    ip_str.push_str(seg.to_string().as_str());
+// format the ipv4 address in network byte order
+pub fn ip_to_string(ip: u32) -> String {
+    ipv4_from_network_order(ip).to_string()
+}
 
-    ip_str
+// parse the ipv4 address to network byte order
+pub fn parse_ipv4(ip_str: &str) -> Result<u32, AddrParseError> {
+    ip_str.parse::<Ipv4Addr>().map(ipv4_to_network_order)
 }
 
+// same as parse_ipv4, returns 0 if the ip_str is invalid
 pub fn string_to_ip(ip_str: &str) -> u32 {
-    let ip_str_seg: Vec<&str> = ip_str.split('.').collect();
-    if ip_str_seg.len() != 4 {
-        logger::write_warning(format!("string_to_ip:: ip_str {} is invalid", ip_str));
-        return 0;
-    }
-
-    let mut ip: u32 = 0;
-    let mut seg: u32 = 1;
-    let seg_number = 16 * 16;
-    for str in ip_str_seg {
-        match str.parse::<u8>() {
-            Ok(n) => {
-                ip += (n as u32) * seg;
-            }
-            Err(e) => {
-                logger::write_warning(format!(
-                    "string_to_ip:: error parsing ip segment {} with error: {}",
-                    ip_str, e
-                ));
-                return 0;
-            }
-        }
-        if seg < 16777216 {
-            seg = seg * seg_number;
+    match parse_ipv4(ip_str) {
+        Ok(ip) => ip,
+        Err(e) => {
+            logger::write_warning(format!(
+                "string_to_ip:: ip_str {} is invalid: {}",
+                ip_str, e
+            ));
+            0
         }
     }
-
-    ip
 }
 
 pub fn get_ebpf_file_path() -> PathBuf {
@@ -246,12 +574,13 @@ pub fn get_ebpf_file_path() -> PathBuf {
 
 #[cfg(test)]
 mod tests {
-    use crate::common::constants;
-    use proxy_agent_shared::misc_helpers;
+    use crate::common::{constants, logger};
+    use proxy_agent_shared::{logger_manager, misc_helpers};
     use std::env;
     use std::fs;
     use std::fs::File;
     use std::io::Write;
+    use std::net::{IpAddr, Ipv4Addr};
     use std::path::PathBuf;
 
     #[test]
@@ -270,6 +599,173 @@ mod tests {
         assert_eq!(0, new_ip, "ip must be 0 since the 1270.0.0.1 is invalid.");
         let new_ip = super::string_to_ip("1270.0.1");
         assert_eq!(0, new_ip, "ip must be 0 since the 1270.0.1 is invalid.");
+        assert!(super::parse_ipv4("1270.0.0.1").is_err());
+        assert!(super::parse_ipv4("127.0.0.1.1").is_err());
+        assert_eq!(Ok(0x100007Fu32), super::parse_ipv4("127.0.0.1"));
+
+        // the octets are in memory in the address order
+        let ip = Ipv4Addr::new(169, 254, 169, 254);
+        let network_order = super::ipv4_to_network_order(ip);
+        assert_eq!(ip.octets(), network_order.to_ne_bytes());
+        assert_eq!(ip, super::ipv4_from_network_order(network_order));
+        assert_eq!(
+            constants::WIRE_SERVER_IP_NETWORK_BYTE_ORDER,
+            super::ipv4_to_network_order(Ipv4Addr::new(168, 63, 129, 16))
+        );
+    }
+
+    #[test]
+    fn audit_entry_destination_test() {
+        let mut entry = super::AuditEntry::empty();
+        entry.logon_id = 999;
+        entry.destination_ipv4 = 0x10813FA8;
+        entry.destination_port = 80u16.to_be();
+        assert_eq!(
+            "168.63.129.16",
+            entry.destination_addr().to_string(),
+            "address family 0 is ipv4"
+        );
+        assert!(entry.is_valid());
+
+        entry.set_destination("fd00::1".parse::<IpAddr>().unwrap(), 8080);
+        assert_eq!(super::AF_INET6, entry.address_family);
+        assert_eq!("fd00::1", entry.destination_addr().to_string());
+        assert_eq!(8080, u16::from_be(entry.destination_port));
+        assert!(entry.is_valid());
+
+        entry.set_destination("::".parse::<IpAddr>().unwrap(), 8080);
+        assert!(!entry.is_valid(), "unspecified ipv6 destination");
+
+        entry.set_destination("127.0.0.1".parse::<IpAddr>().unwrap(), 80);
+        assert_eq!(super::AF_INET, entry.address_family);
+        assert_eq!(0x100007F, entry.destination_ipv4);
+        assert_eq!("127.0.0.1", entry.destination_addr().to_string());
+    }
+
+    #[test]
+    fn resolve_policy_destinations_test() {
+        let destination = |cidr: &str, port: Option<u16>, exclude: Option<bool>| {
+            crate::common::config::RedirectDestination {
+                cidr: cidr.to_string(),
+                port,
+                exclude,
+            }
+        };
+        let wire_server = (super::string_to_ip("168.63.129.16"), 80);
+        let imds = (super::string_to_ip("169.254.169.254"), 80);
+
+        let allowlist = vec![
+            destination("10.0.0.4/31", Some(8080), None),
+            destination("169.254.169.254", None, Some(true)),
+        ];
+        let destinations =
+            super::resolve_policy_destinations(vec![wire_server, imds], &allowlist).unwrap();
+        assert_eq!(
+            vec![
+                wire_server,
+                (super::string_to_ip("10.0.0.4"), 8080),
+                (super::string_to_ip("10.0.0.5"), 8080)
+            ],
+            destinations,
+            "the excluded range must remove the host endpoint in it"
+        );
+
+        // the excluded port only removes the destinations on that port
+        let allowlist = vec![destination("168.63.129.16/32", Some(443), Some(true))];
+        let destinations =
+            super::resolve_policy_destinations(vec![wire_server], &allowlist).unwrap();
+        assert_eq!(vec![wire_server], destinations);
+
+        let allowlist = vec![destination("10.0.0.0/24", Some(80), None)];
+        assert!(
+            super::resolve_policy_destinations(Vec::new(), &allowlist).is_err(),
+            "the range is larger than the policy_map"
+        );
+        let allowlist = vec![destination("10.0.0.4", None, None)];
+        assert!(
+            super::resolve_policy_destinations(Vec::new(), &allowlist).is_err(),
+            "the port is required to redirect"
+        );
+        let allowlist = vec![destination("10.0.0/8", Some(80), None)];
+        assert!(super::resolve_policy_destinations(Vec::new(), &allowlist).is_err());
+
+        // not started, only the allowlist is replaced
+        let allowlist = vec![destination("10.0.0.4/31", Some(8080), None)];
+        super::update_destination_allowlist(allowlist, 3080).unwrap();
+        assert_eq!("10.0.0.4/31:8080", super::get_destination_allowlist_state());
+        super::update_destination_allowlist(Vec::new(), 3080).unwrap();
+        assert_eq!("", super::get_destination_allowlist_state());
+    }
+
+    #[test]
+    fn audit_lookup_states_test() {
+        let states = super::get_audit_lookup_states();
+        let lookups: u64 = states["auditLookups"].parse().unwrap();
+        let misses: u64 = states["auditLookupMisses"].parse().unwrap();
+
+        // the redirector is not started in the test, the lookup misses
+        assert!(super::lookup_audit(1234).is_err());
+        let states = super::get_audit_lookup_states();
+        assert!(states["auditLookups"].parse::<u64>().unwrap() > lookups);
+        assert!(states["auditLookupMisses"].parse::<u64>().unwrap() > misses);
+    }
+
+    #[test]
+    fn check_audit_map_usage_test() {
+        let logger_key = "check_audit_map_usage_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        let threshold = 80;
+        assert!(
+            !super::check_audit_map_usage(100, 200, threshold),
+            "warning must not fire below the threshold"
+        );
+        assert!(
+            super::check_audit_map_usage(160, 200, threshold),
+            "warning must fire when occupancy crosses the threshold"
+        );
+        assert!(
+            !super::check_audit_map_usage(180, 200, threshold),
+            "warning must fire only once while occupancy stays above the threshold"
+        );
+        assert!(
+            !super::check_audit_map_usage(200, 200, threshold),
+            "warning must fire only once while occupancy stays above the threshold"
+        );
+
+        // drop below the threshold and cross it again
+        assert!(!super::check_audit_map_usage(10, 200, threshold));
+        assert!(
+            super::check_audit_map_usage(190, 200, threshold),
+            "warning must fire again after occupancy dropped below the threshold"
+        );
+
+        assert!(
+            !super::check_audit_map_usage(0, 0, threshold),
+            "warning must not fire for an unknown capacity"
+        );
+
+        // clean up and ignore the clean up errors
+        _ = fs::remove_dir_all(&temp_test_path);
+    }
+
+    #[test]
+    fn get_capped_capacity_test() {
+        assert_eq!(200, super::get_capped_capacity(200, None));
+        assert_eq!(100, super::get_capped_capacity(200, Some(100)));
+        assert_eq!(
+            200,
+            super::get_capped_capacity(200, Some(400)),
+            "the cap must not report more than the map holds"
+        );
     }
 
     #[test]
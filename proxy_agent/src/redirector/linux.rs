@@ -6,22 +6,26 @@ mod iptable_redirect;
 use crate::common::{config, constants, helpers, logger};
 use crate::provision;
 use crate::redirector::AuditEntry;
-use aya::maps::{HashMap, MapData};
+use aya::maps::{HashMap, IterableMap, MapData};
 use aya::programs::{CgroupSockAddr, KProbe};
 use aya::{Bpf, BpfLoader, Btf};
 use ebpf_obj::{
     destination_entry, sock_addr_aduit_key, sock_addr_audit_entry, sock_addr_skip_process_entry,
+    AuditMapKey, AuditMapValue,
 };
 use once_cell::unsync::Lazy;
 use proxy_agent_shared::misc_helpers;
 use proxy_agent_shared::telemetry::event_logger;
 use std::convert::TryFrom;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 static mut IS_STARTED: bool = false;
 static mut STATUS_MESSAGE: Lazy<String> =
     Lazy::new(|| String::from("Redirector has not started yet."));
 static mut BPF_OBJECT: Option<Bpf> = None;
+// true when the redirection falls back to the iptables rules
+static IPTABLE_REDIRECT: AtomicBool = AtomicBool::new(false);
 
 pub fn start(local_port: u16) -> bool {
     let mut bpf = match open_ebpf_file(super::get_ebpf_file_path()) {
@@ -96,6 +100,7 @@ pub fn start(local_port: u16) -> bool {
         iptable_redirect = true;
     }
 
+    IPTABLE_REDIRECT.store(iptable_redirect, Ordering::Relaxed);
     unsafe {
         BPF_OBJECT = Some(bpf);
         IS_STARTED = true;
@@ -127,9 +132,15 @@ pub fn start(local_port: u16) -> bool {
 
 fn open_ebpf_file(bpf_file_path: PathBuf) -> Result<Bpf, bool> {
     let bpf: Bpf;
-    match BpfLoader::new()
-        // load the BTF data from /sys/kernel/btf/vmlinux
-        .btf(Btf::from_sys_fs().ok().as_ref())
+    let btf = Btf::from_sys_fs().ok();
+    let mut loader = BpfLoader::new();
+    // load the BTF data from /sys/kernel/btf/vmlinux
+    loader.btf(btf.as_ref());
+    if let Some(max_entries) = config::get_audit_map_max_entries() {
+        // the audit map is sized before it is created
+        loader.set_max_entries("audit_map", max_entries);
+    }
+    match loader
         // finally load the code
         .load_file(bpf_file_path.to_path_buf())
     {
@@ -236,80 +247,104 @@ fn get_local_ip() -> Option<String> {
 
 fn update_policy_map(bpf: &mut Bpf, local_port: u16) -> bool {
     match bpf.map_mut("policy_map") {
-        Some(map) => {
-            match HashMap::<&mut MapData, [u32; 6], [u32; 6]>::try_from(map) {
-                Ok(mut policy_map) => {
-                    let local_ip = match get_local_ip() {
+        Some(map) => match HashMap::<&mut MapData, [u32; 6], [u32; 6]>::try_from(map) {
+            Ok(mut policy_map) => {
+                // redirect to the configured listener address, it does not listen to the others
+                let local_ip = match config::get_listener_address() {
+                    Some(ip) => ip.to_string(),
+                    None => match get_local_ip() {
                         Some(ip) => ip,
                         None => constants::PROXY_AGENT_IP.to_string(),
-                    };
-                    event_logger::write_event(
-                        event_logger::WARN_LEVEL,
-                        format!(
-                            "update_policy_map with local ip address: {}",
-                            local_ip.to_string()
-                        ),
-                        "update_policy_map",
-                        "redirector/linux",
-                        logger::AGENT_LOGGER_KEY,
-                    );
-                    let local_ip = super::string_to_ip(&local_ip);
-                    let key = destination_entry::from_ipv4(
+                    },
+                };
+                event_logger::write_event(
+                    event_logger::WARN_LEVEL,
+                    format!(
+                        "update_policy_map with local ip address: {}",
+                        local_ip.to_string()
+                    ),
+                    "update_policy_map",
+                    "redirector/linux",
+                    logger::AGENT_LOGGER_KEY,
+                );
+                let local_ip = match super::parse_ipv4(&local_ip) {
+                    Ok(ip) => ip,
+                    Err(e) => {
+                        set_error_status(format!(
+                            "Invalid local ip address {} with error: {}",
+                            local_ip, e
+                        ));
+                        return false;
+                    }
+                };
+                let host_endpoints = vec![
+                    (
                         constants::WIRE_SERVER_IP_NETWORK_BYTE_ORDER,
                         constants::WIRE_SERVER_PORT,
-                    );
-                    let value = destination_entry::from_ipv4(local_ip, local_port);
-                    match policy_map.insert(key.to_array(), value.to_array(), 0) {
-                        Ok(_) => {
-                            logger::write("policy_map updated for WireServer endpoints".to_string())
-                        }
-                        Err(err) => {
-                            set_error_status(format!("Failed to insert WireServer endpoints to policy_map with error: {}", err));
-                            return false;
-                        }
+                    ),
+                    (constants::IMDS_IP_NETWORK_BYTE_ORDER, constants::IMDS_PORT),
+                    (
+                        constants::GA_PLUGIN_IP_NETWORK_BYTE_ORDER,
+                        constants::GA_PLUGIN_PORT,
+                    ),
+                ];
+                let destinations = match super::get_policy_destinations(host_endpoints) {
+                    Ok(destinations) => destinations,
+                    Err(e) => {
+                        set_error_status(format!(
+                            "Invalid destination allowlist with error: {}",
+                            e
+                        ));
+                        return false;
                     }
+                };
 
-                    let key = destination_entry::from_ipv4(
-                        constants::IMDS_IP_NETWORK_BYTE_ORDER,
-                        constants::IMDS_PORT,
-                    );
+                let value = destination_entry::from_ipv4(local_ip, local_port);
+                for (ip, port) in destinations.iter() {
+                    let key = destination_entry::from_ipv4(*ip, *port);
                     match policy_map.insert(key.to_array(), value.to_array(), 0) {
-                        Ok(_) => logger::write("policy_map updated for IMDS endpoints".to_string()),
+                        Ok(_) => logger::write(format!(
+                            "policy_map updated for destination {}:{}",
+                            super::ip_to_string(*ip),
+                            port
+                        )),
                         Err(err) => {
                             set_error_status(format!(
-                                "Failed to insert IMDS endpoints to policy_map with error: {}",
+                                "Failed to insert destination {}:{} to policy_map with error: {}",
+                                super::ip_to_string(*ip),
+                                port,
                                 err
                             ));
                             return false;
                         }
                     }
+                }
 
-                    let key = destination_entry::from_ipv4(
-                        constants::GA_PLUGIN_IP_NETWORK_BYTE_ORDER,
-                        constants::GA_PLUGIN_PORT,
-                    );
-                    match policy_map.insert(key.to_array(), value.to_array(), 0) {
-                        Ok(_) => logger::write(
-                            "policy_map updated for HostGAPlugin endpoints".to_string(),
-                        ),
-                        Err(err) => {
-                            set_error_status( format!(
-                                "Failed to insert HostGAPlugin endpoints to policy_map with error: {}",
-                                err
-                            ));
-                            return false;
-                        }
+                for (ip, port) in super::set_policy_destinations(&destinations) {
+                    let key = destination_entry::from_ipv4(ip, port);
+                    match policy_map.remove(&key.to_array()) {
+                        Ok(_) => logger::write(format!(
+                            "policy_map removed destination {}:{}",
+                            super::ip_to_string(ip),
+                            port
+                        )),
+                        Err(err) => logger::write_warning(format!(
+                            "Failed to remove destination {}:{} from policy_map with error: {}",
+                            super::ip_to_string(ip),
+                            port,
+                            err
+                        )),
                     }
                 }
-                Err(err) => {
-                    set_error_status(format!(
-                        "Failed to load HashMap 'policy_map' with error: {}",
-                        err
-                    ));
-                    return false;
-                }
             }
-        }
+            Err(err) => {
+                set_error_status(format!(
+                    "Failed to load HashMap 'policy_map' with error: {}",
+                    err
+                ));
+                return false;
+            }
+        },
         None => {
             set_error_status(format!("Failed to get map 'policy_map'."));
             return false;
@@ -451,6 +486,42 @@ pub fn close(local_port: u16) {
     }
 }
 
+pub fn update_local_port(old_port: u16, new_port: u16) -> bool {
+    if IPTABLE_REDIRECT.load(Ordering::Relaxed) {
+        iptable_redirect::cleanup_firewall_redirection(old_port);
+        if iptable_redirect::setup_firewall_redirection(new_port) == false {
+            set_error_status(format!(
+                "Failed to setup iptables redirection to local port {}.",
+                new_port
+            ));
+            return false;
+        }
+    }
+
+    unsafe {
+        match BPF_OBJECT {
+            Some(ref mut bpf) => update_policy_map(bpf, new_port),
+            None => {
+                set_error_status("BPF object is not initialized".to_string());
+                false
+            }
+        }
+    }
+}
+
+// reprogram the policy_map after the destination allowlist changed
+pub fn refresh_policy_map(local_port: u16) -> bool {
+    unsafe {
+        match BPF_OBJECT {
+            Some(ref mut bpf) => update_policy_map(bpf, local_port),
+            None => {
+                set_error_status("BPF object is not initialized".to_string());
+                false
+            }
+        }
+    }
+}
+
 pub fn lookup_audit(source_port: u16) -> std::io::Result<AuditEntry> {
     unsafe {
         match BPF_OBJECT {
@@ -465,7 +536,7 @@ pub fn lookup_audit(source_port: u16) -> std::io::Result<AuditEntry> {
 
 fn lookup_audit_internal(bpf: &Bpf, source_port: u16) -> std::io::Result<AuditEntry> {
     match bpf.map("audit_map") {
-        Some(map) => match HashMap::try_from(map) {
+        Some(map) => match HashMap::<&MapData, AuditMapKey, AuditMapValue>::try_from(map) {
             Ok(audit_map) => {
                 let key = sock_addr_aduit_key::from_source_port(source_port);
                 match audit_map.get(&key.to_array(), 0) {
@@ -477,6 +548,8 @@ fn lookup_audit_internal(bpf: &Bpf, source_port: u16) -> std::io::Result<AuditEn
                             is_admin: audit_value.is_root as i32,
                             destination_ipv4: audit_value.destination_ipv4,
                             destination_port: audit_value.destination_port as u16,
+                            address_family: audit_value.address_family as u16,
+                            destination_ipv6: audit_value.destination_ipv6_octets(),
                         })
                     }
                     Err(err) => {
@@ -505,6 +578,47 @@ fn lookup_audit_internal(bpf: &Bpf, source_port: u16) -> std::io::Result<AuditEn
     }
 }
 
+pub fn get_audit_map_usage() -> std::io::Result<(u32, u32)> {
+    unsafe {
+        match BPF_OBJECT {
+            Some(ref bpf) => get_audit_map_usage_internal(bpf),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "BPF object is not initialized",
+            )),
+        }
+    }
+}
+
+// returns the current entry count and the max entries of the audit_map
+fn get_audit_map_usage_internal(bpf: &Bpf) -> std::io::Result<(u32, u32)> {
+    match bpf.map("audit_map") {
+        Some(map) => match HashMap::<&MapData, AuditMapKey, AuditMapValue>::try_from(map) {
+            Ok(audit_map) => {
+                let capacity = match audit_map.map().info() {
+                    Ok(info) => info.max_entries(),
+                    Err(err) => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::Other,
+                            format!("Failed to get 'audit_map' info with error: {}", err),
+                        ));
+                    }
+                };
+                let count = audit_map.keys().filter(|key| key.is_ok()).count() as u32;
+                Ok((count, capacity))
+            }
+            Err(err) => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("Failed to load HashMap 'audit_map' with error: {}", err),
+            )),
+        },
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "Failed to get map 'audit_map'.",
+        )),
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "test-with-root")]
 mod tests {
@@ -512,6 +626,7 @@ mod tests {
     use crate::common::logger;
     use crate::redirector::linux::ebpf_obj::sock_addr_aduit_key;
     use crate::redirector::linux::ebpf_obj::sock_addr_audit_entry;
+    use crate::redirector::linux::ebpf_obj::{AuditMapKey, AuditMapValue};
     use aya::maps::HashMap;
     use proxy_agent_shared::logger_manager;
     use proxy_agent_shared::misc_helpers;
@@ -568,6 +683,9 @@ mod tests {
         let source_port = 1;
         let audit = super::lookup_audit_internal(&bpf, source_port);
         assert!(!audit.is_ok(), "lookup_audit should not return Ok");
+        let (count, capacity) = super::get_audit_map_usage_internal(&bpf).unwrap();
+        assert_eq!(0, count, "audit_map should be empty");
+        assert!(capacity > 0, "audit_map capacity should be read");
         // insert to map an then look up
         let key = sock_addr_aduit_key::from_source_port(source_port);
         let value = sock_addr_audit_entry {
@@ -576,11 +694,13 @@ mod tests {
             is_root: 1,
             destination_ipv4: 0x10813FA8,
             destination_port: 80,
+            address_family: 2,
+            destination_ipv6: [0; 4],
         };
         {
             // drop map_mut("audit_map") within this scope
-            let mut audit_map: HashMap<&mut aya::maps::MapData, [u32; 2], [u32; 5]> =
-                HashMap::<&mut aya::maps::MapData, [u32; 2], [u32; 5]>::try_from(
+            let mut audit_map =
+                HashMap::<&mut aya::maps::MapData, AuditMapKey, AuditMapValue>::try_from(
                     bpf.map_mut("audit_map").unwrap(),
                 )
                 .unwrap();
@@ -588,6 +708,8 @@ mod tests {
                 .insert(key.to_array(), value.to_array(), 0)
                 .unwrap();
         }
+        let (count, _) = super::get_audit_map_usage_internal(&bpf).unwrap();
+        assert_eq!(1, count, "the inserted audit entry should be counted");
         let audit = super::lookup_audit_internal(&bpf, source_port);
         match audit {
             Ok(entry) => {
@@ -85,6 +85,10 @@ impl sock_addr_skip_process_entry {
     }
 }
 
+// the key and value layouts of the audit_map, shared by its lookup and its usage
+pub type AuditMapKey = [u32; 2];
+pub type AuditMapValue = [u32; 10];
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct sock_addr_aduit_key {
@@ -100,14 +104,14 @@ impl sock_addr_aduit_key {
         }
     }
 
-    pub fn to_array(&self) -> [u32; 2] {
-        let mut array: [u32; 2] = [0; 2];
+    pub fn to_array(&self) -> AuditMapKey {
+        let mut array: AuditMapKey = [0; 2];
         array[0] = self.protocol;
         array[1] = self.source_port;
         array
     }
 
-    pub fn from_array(array: [u32; 2]) -> Self {
+    pub fn from_array(array: AuditMapKey) -> Self {
         sock_addr_aduit_key {
             protocol: array[0],
             source_port: array[1],
@@ -123,30 +127,45 @@ pub struct sock_addr_audit_entry {
     pub is_root: u32,
     pub destination_ipv4: u32,
     pub destination_port: u32,
+    pub address_family: u32,        // AF_INET or AF_INET6
+    pub destination_ipv6: [u32; 4], // set when address_family is AF_INET6
 }
 
 #[allow(dead_code)]
 impl sock_addr_audit_entry {
-    pub fn from_array(array: [u32; 5]) -> Self {
+    pub fn from_array(array: AuditMapValue) -> Self {
         sock_addr_audit_entry {
             logon_id: array[0],
             process_id: array[1],
             is_root: array[2],
             destination_ipv4: array[3],
             destination_port: array[4],
+            address_family: array[5],
+            destination_ipv6: [array[6], array[7], array[8], array[9]],
         }
     }
 
     #[allow(dead_code)]
-    pub fn to_array(&self) -> [u32; 5] {
-        let mut array: [u32; 5] = [0; 5];
+    pub fn to_array(&self) -> AuditMapValue {
+        let mut array: AuditMapValue = [0; 10];
         array[0] = self.logon_id;
         array[1] = self.process_id;
         array[2] = self.is_root;
         array[3] = self.destination_ipv4;
         array[4] = self.destination_port;
+        array[5] = self.address_family;
+        array[6..10].copy_from_slice(&self.destination_ipv6);
         array
     }
+
+    // the ipv6 address bytes in network byte order as they are in the kernel memory
+    pub fn destination_ipv6_octets(&self) -> [u8; 16] {
+        let mut octets = [0u8; 16];
+        for (i, part) in self.destination_ipv6.iter().enumerate() {
+            octets[i * 4..(i + 1) * 4].copy_from_slice(&part.to_ne_bytes());
+        }
+        octets
+    }
 }
 
 #[cfg(test)]
@@ -202,6 +221,8 @@ mod tests {
             is_root: 1,
             destination_ipv4: 4,
             destination_port: 5,
+            address_family: 10,
+            destination_ipv6: [6, 7, 8, 9],
         };
         let audit_value = super::sock_addr_audit_entry::from_array(audit.to_array());
         assert_eq!(
@@ -221,5 +242,26 @@ mod tests {
             audit_value.destination_port, audit.destination_port,
             "destination_port is not equal"
         );
+        assert_eq!(
+            audit_value.address_family, audit.address_family,
+            "address_family is not equal"
+        );
+        assert_eq!(
+            audit_value.destination_ipv6, audit.destination_ipv6,
+            "destination_ipv6 is not equal"
+        );
+    }
+
+    #[test]
+    fn audit_map_layout_test() {
+        // the audit_map is opened with these layouts, a size mismatch fails to open it
+        assert_eq!(
+            std::mem::size_of::<super::sock_addr_aduit_key>(),
+            std::mem::size_of::<super::AuditMapKey>()
+        );
+        assert_eq!(
+            std::mem::size_of::<super::sock_addr_audit_entry>(),
+            std::mem::size_of::<super::AuditMapValue>()
+        );
     }
 }
@@ -1,6 +1,7 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
 use crate::common::{constants, logger};
+use crate::proxy::proxy_listener;
 use proxy_agent_shared::misc_helpers;
 
 pub fn setup_firewall_redirection(local_port: u16) -> bool {
@@ -91,7 +92,7 @@ fn config_one_firewall_redirection(
     } else {
         iptable_cmd = "-D";
     }
-    let local_endpoint = format!("127.0.0.1:{}", local_port);
+    let local_endpoint = format!("{}:{}", proxy_listener::get_local_ip(), local_port);
 
     let args = vec![
         "-t",
@@ -8,6 +8,7 @@ mod bpf_prog;
 
 use crate::common::{self, config, constants, helpers, logger};
 use crate::key_keeper;
+use crate::key_keeper::secure_channel_state::SecureChannelState;
 use crate::provision;
 use crate::redirector::AuditEntry;
 use core::ffi::c_void;
@@ -60,74 +61,97 @@ pub fn start(local_port: u16) -> bool {
         ));
     }
 
-    if (key_keeper::get_secure_channel_state() != key_keeper::DISABLE_STATE)
+    if update_policy_map(local_port) == false {
+        return false;
+    }
+
+    unsafe {
+        IS_STARTED = true;
+    }
+
+    let message = helpers::write_startup_event(
+        "Started Redirector with eBPF maps",
+        "start",
+        "redirector",
+        logger::AGENT_LOGGER_KEY,
+    );
+    unsafe {
+        *STATUS_MESSAGE = message.to_string();
+    }
+    provision::redirector_ready();
+
+    return true;
+}
+
+// redirect the supported host endpoints and the allowlist destinations to the proxy listener local port
+pub fn update_policy_map(local_port: u16) -> bool {
+    let mut host_endpoints = Vec::new();
+    if (key_keeper::get_secure_channel_state() != SecureChannelState::Disabled)
         || (config::get_wire_server_support() > 0)
     {
-        let result = bpf_prog::update_bpf_map(
-            local_port,
+        host_endpoints.push((
             constants::WIRE_SERVER_IP_NETWORK_BYTE_ORDER, //0x10813FA8 - 168.63.129.16
             constants::WIRE_SERVER_PORT,
-        );
-        if result != 0 {
-            set_error_status(format!(
-                "Failed to update bpf map for WireServer support with result: {result}"
-            ));
-            return false;
-        } else {
-            logger::write("Success updated bpf map for WireServer support.".to_string());
-        }
+        ));
     }
     if config::get_host_gaplugin_support() > 0 {
-        let result = bpf_prog::update_bpf_map(
-            local_port,
+        host_endpoints.push((
             constants::GA_PLUGIN_IP_NETWORK_BYTE_ORDER, //0x10813FA8, // 168.63.129.16
             constants::GA_PLUGIN_PORT,
-        );
-        if result != 0 {
-            set_error_status(format!(
-                "Failed to update bpf map for Host GAPlugin support with result: {result}"
-            ));
-            return false;
-        } else {
-            logger::write("Success updated bpf map for Host GAPlugin support.".to_string());
-        }
+        ));
     }
-    if (key_keeper::get_secure_channel_state() == key_keeper::MUST_SIG_WIRESERVER_IMDS)
+    if (key_keeper::get_secure_channel_state() == SecureChannelState::WireServerAndImds)
         || (config::get_imds_support() > 0)
     {
-        let result = bpf_prog::update_bpf_map(
-            local_port,
+        host_endpoints.push((
             constants::IMDS_IP_NETWORK_BYTE_ORDER, //0xFEA9FEA9, // 169.254.169.254
             constants::IMDS_PORT,
-        );
+        ));
+    }
+    let destinations = match super::get_policy_destinations(host_endpoints) {
+        Ok(destinations) => destinations,
+        Err(e) => {
+            set_error_status(format!("Invalid destination allowlist with error: {e}"));
+            return false;
+        }
+    };
+
+    for (ip, port) in destinations.iter() {
+        let result = bpf_prog::update_bpf_map(local_port, *ip, *port);
         if result != 0 {
             set_error_status(format!(
-                "Failed to update bpf map for IMDS support with result: {result}"
+                "Failed to update bpf map for destination {}:{} with result: {result}",
+                super::ip_to_string(*ip),
+                port
             ));
             return false;
         } else {
-            logger::write("Success updated bpf map for IMDS support.".to_string());
+            logger::write(format!(
+                "Success updated bpf map for destination {}:{}.",
+                super::ip_to_string(*ip),
+                port
+            ));
         }
     }
 
-    unsafe {
-        IS_STARTED = true;
-    }
-
-    let message = helpers::write_startup_event(
-        "Started Redirector with eBPF maps",
-        "start",
-        "redirector",
-        logger::AGENT_LOGGER_KEY,
-    );
-    unsafe {
-        *STATUS_MESSAGE = message.to_string();
+    for (ip, port) in super::set_policy_destinations(&destinations) {
+        let result = bpf_prog::delete_bpf_map(ip, port);
+        if result != 0 {
+            logger::write_warning(format!(
+                "Failed to delete destination {}:{} from bpf map with result: {result}",
+                super::ip_to_string(ip),
+                port
+            ));
+        }
     }
-    provision::redirector_ready();
 
     return true;
 }
 
+pub fn update_local_port(_old_port: u16, new_port: u16) -> bool {
+    update_policy_map(new_port)
+}
+
 fn set_error_status(message: String) {
     unsafe {
         *STATUS_MESSAGE = message.to_string();
@@ -155,6 +179,10 @@ pub fn lookup_audit(source_port: u16) -> std::io::Result<AuditEntry> {
     bpf_prog::lookup_bpf_audit_map(source_port)
 }
 
+pub fn get_audit_map_usage() -> std::io::Result<(u32, u32)> {
+    bpf_prog::get_bpf_audit_map_usage()
+}
+
 pub fn get_audit_from_redirect_context(tcp_stream: &TcpStream) -> std::io::Result<AuditEntry> {
     unsafe {
         // WSAIoctl - SIO_QUERY_WFP_CONNECTION_REDIRECT_CONTEXT
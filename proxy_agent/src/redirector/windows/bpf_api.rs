@@ -82,8 +82,12 @@ type BpfMapUpdateElem = unsafe extern "C" fn(
     value: *const c_void,
     flags: c_uint,
 ) -> c_int;
+type BpfMapDeleteElem = unsafe extern "C" fn(map_fd: c_int, key: *const c_void) -> c_int;
 type BpfMapLookupElem =
     unsafe extern "C" fn(map_fd: c_int, key: *const c_void, value: *mut c_void) -> c_int;
+type BpfMapGetNextKey =
+    unsafe extern "C" fn(map_fd: c_int, key: *const c_void, next_key: *mut c_void) -> c_int;
+type BpfMapMaxEntries = unsafe extern "C" fn(map: *const bpf_map) -> c_uint;
 
 fn get_cstring(s: &str) -> std::io::Result<CString> {
     CString::new(s).map_err(|e| Error::new(ErrorKind::InvalidInput, e))
@@ -189,6 +193,15 @@ pub fn bpf_map_update_elem(
     }
 }
 
+pub fn bpf_map_delete_elem(map_fd: c_int, key: *const c_void) -> std::io::Result<c_int> {
+    unsafe {
+        let ebpf_api = get_ebpf_api()?;
+        let map_delete_elem: Symbol<BpfMapDeleteElem> =
+            get_ebpf_api_fun(&ebpf_api, "bpf_map_delete_elem\0")?;
+        Ok(map_delete_elem(map_fd, key))
+    }
+}
+
 pub fn bpf_map_lookup_elem(
     map_fd: c_int,
     key: *const c_void,
@@ -201,3 +214,25 @@ pub fn bpf_map_lookup_elem(
         Ok(map_lookup_elem(map_fd, key, value))
     }
 }
+
+pub fn bpf_map_get_next_key(
+    map_fd: c_int,
+    key: *const c_void,
+    next_key: *mut c_void,
+) -> std::io::Result<c_int> {
+    unsafe {
+        let ebpf_api = get_ebpf_api()?;
+        let map_get_next_key: Symbol<BpfMapGetNextKey> =
+            get_ebpf_api_fun(&ebpf_api, "bpf_map_get_next_key\0")?;
+        Ok(map_get_next_key(map_fd, key, next_key))
+    }
+}
+
+pub fn bpf_map__max_entries(map: *mut bpf_map) -> std::io::Result<c_uint> {
+    unsafe {
+        let ebpf_api = get_ebpf_api()?;
+        let map__max_entries: Symbol<BpfMapMaxEntries> =
+            get_ebpf_api_fun(&ebpf_api, "bpf_map__max_entries\0")?;
+        Ok(map__max_entries(map))
+    }
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: MIT
 use super::bpf_api::*;
 use super::bpf_obj::*;
+use crate::common::config;
 use crate::common::constants;
 use crate::common::logger;
 use crate::redirector::AuditEntry;
@@ -186,10 +187,11 @@ pub fn update_bpf_map(local_port: u16, dest_ipv4: u32, dest_port: u16) -> i32 {
                 };
 
                 let key = destination_entry_t::from_ipv4(dest_ipv4, dest_port);
-                let value = destination_entry_t::from_ipv4(
-                    constants::PROXY_AGENT_IP_NETWORK_BYTE_ORDER, //0x100007F - 127.0.0.1
-                    local_port,
-                );
+                let local_ip = match config::get_listener_address() {
+                    Some(ip) => crate::redirector::ipv4_to_network_order(ip),
+                    None => constants::PROXY_AGENT_IP_NETWORK_BYTE_ORDER, //0x100007F - 127.0.0.1
+                };
+                let value = destination_entry_t::from_ipv4(local_ip, local_port);
 
                 match bpf_map_update_elem(
                     map_fd,
@@ -211,6 +213,64 @@ pub fn update_bpf_map(local_port: u16, dest_ipv4: u32, dest_port: u16) -> i32 {
     }
 }
 
+/**
+Routine Description:
+
+    This routine delete element from policy_map.
+
+Arguments:
+
+    dest_ipv4  - destination ipv4 address.
+    dest_port  - destination port.
+
+Return Value:
+
+    0 on success. On failure appropriate RESULT is returned.
+ */
+pub fn delete_bpf_map(dest_ipv4: u32, dest_port: u16) -> i32 {
+    unsafe {
+        match BPF_OBJECT {
+            Some(obj) => {
+                let proxy_map = match bpf_object__find_map_by_name(obj, "policy_map") {
+                    Ok(m) => m,
+                    Err(e) => {
+                        logger::write_error(format!("{}", e));
+                        return EBPF_FIND_MAP_ERROR;
+                    }
+                };
+                if proxy_map.is_null() {
+                    logger::write_error(
+                        "bpf_object__find_map_by_name 'policy_map' return null".to_string(),
+                    );
+                    return EBPF_FIND_MAP_ERROR;
+                }
+                let map_fd = match bpf_map__fd(proxy_map) {
+                    Ok(fd) => fd,
+                    Err(e) => {
+                        logger::write_error(format!("{}", e));
+                        return EBPF_FIND_MAP_ERROR;
+                    }
+                };
+
+                let key = destination_entry_t::from_ipv4(dest_ipv4, dest_port);
+                match bpf_map_delete_elem(
+                    map_fd,
+                    &key as *const destination_entry_t as *const c_void,
+                ) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        logger::write_error(format!("{}", e));
+                        return EBPF_UPDATE_MAP_ERROR;
+                    }
+                }
+            }
+            None => {
+                return EBPF_OBJECT_NULL;
+            }
+        }
+    }
+}
+
 /**
 Routine Description:
 
@@ -315,6 +375,77 @@ pub fn lookup_bpf_audit_map(source_port: u16) -> std::io::Result<AuditEntry> {
     }
 }
 
+/**
+Routine Description:
+
+    This routine counts the elements in audit_map.
+
+Return Value:
+
+    (current entry count, max entries) of the audit_map on success.
+ */
+pub fn get_bpf_audit_map_usage() -> std::io::Result<(u32, u32)> {
+    unsafe {
+        match BPF_OBJECT {
+            Some(obj) => {
+                let audit_map = match bpf_object__find_map_by_name(obj, "audit_map") {
+                    Ok(m) => m,
+                    Err(e) => {
+                        let message = format!(
+                            "Failed to find audit map in bpf object with error: {error}.",
+                            error = e
+                        );
+                        return Err(Error::new(ErrorKind::InvalidInput, message));
+                    }
+                };
+                if audit_map.is_null() {
+                    let message =
+                        "bpf_object__find_map_by_name 'audit_map' return null.".to_string();
+                    return Err(Error::new(ErrorKind::InvalidInput, message));
+                }
+                let map_fd = match bpf_map__fd(audit_map) {
+                    Ok(fd) => fd,
+                    Err(e) => {
+                        let message = format!(
+                            "Failed to get audit map fd in bpf object with error: {error}.",
+                            error = e
+                        );
+                        return Err(Error::new(ErrorKind::InvalidInput, message));
+                    }
+                };
+                let capacity = bpf_map__max_entries(audit_map)?;
+
+                // walk through the keys, a null key starts from the first element.
+                let mut count: u32 = 0;
+                let mut key = sock_addr_aduit_key_t::from_source_port(0);
+                let mut next_key = sock_addr_aduit_key_t::from_source_port(0);
+                let mut key_ptr: *const c_void = std::ptr::null();
+                while count < capacity {
+                    let result = bpf_map_get_next_key(
+                        map_fd,
+                        key_ptr,
+                        &mut next_key as *mut sock_addr_aduit_key_t as *mut c_void,
+                    )?;
+                    if result != 0 {
+                        break;
+                    }
+                    count += 1;
+                    key.protocol = next_key.protocol;
+                    key.source_port = next_key.source_port;
+                    key_ptr = &key as *const sock_addr_aduit_key_t as *const c_void;
+                }
+
+                Ok((count, capacity))
+            }
+            None => {
+                let message =
+                    "Failed to get bpf audit map usage because bpf has not loaded.".to_string();
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        }
+    }
+}
+
 /**
 Routine Description:
 
@@ -3,19 +3,27 @@
 #[cfg(windows)]
 pub mod windows;
 
-use crate::common::{config, constants, helpers, logger};
+use crate::common::http::http_request::RequestBuilder;
+use crate::common::http::response::Response;
+use crate::common::{config, constants, helpers, http, logger};
 use crate::proxy::proxy_listener;
 use crate::telemetry::event_reader;
+use crate::{provision, redirector};
 use proxy_agent_shared::logger_manager;
 use proxy_agent_shared::telemetry::event_logger;
 use url::Url;
 
+const PROXY_LISTENER_POOL_SIZE: u16 = 20;
+// covers the whole self test run, its stages are bounded by their own timeouts
+const SELF_TEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
 #[cfg(not(windows))]
 use std::thread;
 #[cfg(not(windows))]
 use std::time::Duration;
 
-pub fn start_service() {
+// fails fast if the config is invalid, before any module is started with it
+pub fn start_service() -> std::io::Result<()> {
     logger_manager::init_logger(
         logger::AGENT_LOGGER_KEY.to_string(),
         config::get_logs_dir(),
@@ -30,6 +38,24 @@ pub fn start_service() {
         helpers::get_elapsed_time_in_millisec()
     ));
 
+    if let Err(e) = config::validate() {
+        event_logger::write_event(
+            event_logger::ERROR_LEVEL,
+            e.to_string(),
+            "start_service",
+            "service",
+            logger::AGENT_LOGGER_KEY,
+        );
+        return Err(e);
+    }
+    event_logger::write_event(
+        event_logger::INFO_LEVEL,
+        config::get_effective_config(),
+        "start_service",
+        "service",
+        logger::AGENT_LOGGER_KEY,
+    );
+
     let config_start_redirector = config::get_start_redirector();
 
     crate::key_keeper::poll_status_async(
@@ -39,15 +65,19 @@ pub fn start_service() {
         config_start_redirector,
     );
 
-    proxy_listener::start_async(constants::PROXY_AGENT_PORT, 20);
+    proxy_listener::start_async(constants::PROXY_AGENT_PORT, PROXY_LISTENER_POOL_SIZE);
 
     // TODO:: need start the monitor thread and write proxy agent status to the file
     // monitor::start_async(config::get_monitor_duration());
+    Ok(())
 }
 
 #[cfg(not(windows))]
 pub fn start_service_wait(){
-    start_service();
+    if let Err(e) = start_service() {
+        logger::write_error(format!("Failed to start the service: {}", e));
+        std::process::exit(1);
+    }
 
     loop {
         // continue to sleep until the service is stopped
@@ -55,11 +85,198 @@ pub fn start_service_wait(){
     }
 }
 
+/*
+Move the proxy listener to the new port without restarting the service.
+The redirector is pointed to the new port once the listener serves on it,
+the listener goes back to the previous port if the redirector cannot be updated.
+ */
+pub fn rebind_proxy_port(new_port: u16) -> std::io::Result<()> {
+    rebind_proxy_port_with(new_port, &|old_port, new_port| {
+        !redirector::is_started() || redirector::update_local_port(old_port, new_port)
+    })
+}
+
+// redirect returns false if the redirected traffic cannot be moved from the old port to the new one
+fn rebind_proxy_port_with(
+    new_port: u16,
+    redirect: &dyn Fn(u16, u16) -> bool,
+) -> std::io::Result<()> {
+    let old_port = proxy_listener::get_port();
+    if old_port == new_port {
+        return Ok(());
+    }
+
+    event_logger::write_event(
+        event_logger::INFO_LEVEL,
+        format!(
+            "Rebinding proxy listener from port {} to {}.",
+            old_port, new_port
+        ),
+        "rebind_proxy_port",
+        "service",
+        logger::AGENT_LOGGER_KEY,
+    );
+    provision::listener_rebinding();
+    // the listener serves the old port again if the new port cannot be bound,
+    // the redirector has not been moved yet
+    proxy_listener::rebind(new_port, PROXY_LISTENER_POOL_SIZE)?;
+
+    if !redirect(old_port, new_port) {
+        let message = format!(
+            "Failed to redirect to the new proxy listener port {}, rolling back to port {}.",
+            new_port, old_port
+        );
+        event_logger::write_event(
+            event_logger::ERROR_LEVEL,
+            message.to_string(),
+            "rebind_proxy_port",
+            "service",
+            logger::AGENT_LOGGER_KEY,
+        );
+        provision::listener_rebinding();
+        if let Err(e) = proxy_listener::rebind(old_port, PROXY_LISTENER_POOL_SIZE) {
+            // the listener stays on the new port if the old port cannot be bound again,
+            // the redirector and the provision state follow the port it serves
+            let message = if proxy_listener::is_running() {
+                let port = proxy_listener::get_port();
+                let redirected = redirect(old_port, port);
+                provision::listener_started();
+                format!(
+                    "{} Failed to roll back: {}, the listener stays on port {} and the redirection to it {}.",
+                    message,
+                    e,
+                    port,
+                    if redirected { "succeeded" } else { "failed" }
+                )
+            } else {
+                format!(
+                    "{} Failed to roll back: {}, the listener is not serving.",
+                    message, e
+                )
+            };
+            event_logger::write_event(
+                event_logger::ERROR_LEVEL,
+                message.to_string(),
+                "rebind_proxy_port",
+                "service",
+                logger::AGENT_LOGGER_KEY,
+            );
+            return Err(std::io::Error::new(e.kind(), message));
+        }
+        redirect(new_port, old_port);
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, message));
+    }
+
+    event_logger::write_event(
+        event_logger::INFO_LEVEL,
+        format!("Rebound proxy listener to port {}.", new_port),
+        "rebind_proxy_port",
+        "service",
+        logger::AGENT_LOGGER_KEY,
+    );
+    Ok(())
+}
+
+/*
+Ask the running agent to run the self test on its listener port, from the elevated command line.
+Returns the succeeded flag and the json result with the stages; fails if the agent cannot run it.
+ */
+pub fn request_self_test(port: u16) -> std::io::Result<(bool, String)> {
+    let url = Url::parse(&format!(
+        "http://{}:{}{}",
+        proxy_listener::get_local_ip(),
+        port,
+        constants::SELF_TEST_ENDPOINT
+    ))
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut request = RequestBuilder::new(url)
+        .method("POST")
+        .body(Vec::new())
+        .build()?;
+    let response = http::get_response_in_string_with_timeout(&mut request, SELF_TEST_TIMEOUT)?;
+    let body = response.get_body_as_string()?;
+    if response.status != Response::OK {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("Self test responded '{}': {}", response.status, body),
+        ));
+    }
+    let result: serde_json::Value = serde_json::from_str(&body)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok((result["succeeded"] == true, body))
+}
+
 pub fn stop_service() {
+    let port = proxy_listener::get_port();
     crate::monitor::stop();
-    crate::redirector::close(constants::PROXY_AGENT_PORT);
+    crate::redirector::close(port);
     crate::key_keeper::stop();
-    proxy_listener::stop(constants::PROXY_AGENT_PORT);
+    proxy_listener::stop(port);
+    #[cfg(not(windows))]
+    crate::proxy::control_listener::stop();
     event_logger::stop();
     event_reader::stop();
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::common::logger;
+    use crate::provision;
+    use crate::proxy::proxy_listener;
+    use proxy_agent_shared::logger_manager;
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use std::{env, fs, thread};
+
+    #[test]
+    fn rebind_proxy_port_rollback_test() {
+        let logger_key = "rebind_proxy_port_rollback_test";
+        let mut temp_test_path = env::temp_dir();
+        temp_test_path.push(logger_key);
+        logger_manager::init_logger(
+            logger::AGENT_LOGGER_KEY.to_string(), // production code uses 'Agent_Log' to write.
+            temp_test_path.clone(),
+            logger_key.to_string(),
+            10 * 1024 * 1024,
+            20,
+        );
+
+        // start listener, the ports must different from the one used in production code
+        let old_port: u16 = 8094;
+        let new_port: u16 = 8095;
+        proxy_listener::start_async(old_port, 1);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(old_port, proxy_listener::get_port());
+
+        // the redirector cannot be moved, and the old port is taken before the rollback
+        let old_port_holder = Mutex::new(None);
+        let redirections = Mutex::new(Vec::new());
+        let result = super::rebind_proxy_port_with(new_port, &|from, to| {
+            redirections.lock().unwrap().push((from, to));
+            let mut holder = old_port_holder.lock().unwrap();
+            if holder.is_none() {
+                *holder = Some(TcpListener::bind(format!("127.0.0.1:{}", old_port)).unwrap());
+            }
+            false
+        });
+        assert!(result.is_err(), "the rebind must fail");
+        assert!(
+            proxy_listener::is_running(),
+            "the listener must stay on the new port"
+        );
+        assert_eq!(new_port, proxy_listener::get_port());
+        assert!(provision::get_readiness().listenerReady);
+        assert_eq!(
+            vec![(old_port, new_port), (old_port, new_port)],
+            *redirections.lock().unwrap(),
+            "the redirection must follow the port the listener serves"
+        );
+
+        proxy_listener::stop(new_port);
+        assert!(!proxy_listener::is_running());
+
+        // clean up and ignore the clean up errors
+        _ = fs::remove_dir_all(temp_test_path);
+    }
+}
@@ -51,7 +51,10 @@ pub fn run_service(_args: Vec<OsString>) -> windows_service::Result<()> {
     };
 
     // start service
-    service::start_service();
+    if let Err(e) = service::start_service() {
+        logger::write_error(format!("Failed to start the service: {}", e));
+        return Err(windows_service::Error::Winapi(e));
+    }
 
     // set the service state to Running
     let status_handle =
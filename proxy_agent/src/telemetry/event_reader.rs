@@ -3,9 +3,12 @@
 use super::telemetry_event::TelemetryData;
 use super::telemetry_event::TelemetryEvent;
 use crate::common::constants;
-use crate::common::logger;
+use crate::common::{config, helpers, logger};
+use crate::host_clients::goal_state::{GoalState, SharedConfig};
 use crate::host_clients::imds_client::ImdsClient;
-use crate::host_clients::wire_server_client::WireServerClient;
+use crate::host_clients::instance_info::InstanceInfo;
+use crate::host_clients::retry_policy;
+use crate::host_clients::wire_server_client::{WireServerClient, WireServerErrorType};
 use once_cell::sync::Lazy;
 use proxy_agent_shared::misc_helpers;
 use proxy_agent_shared::telemetry::event_logger;
@@ -161,30 +164,110 @@ pub fn stop() {
 }
 
 fn update_vm_meta_data() -> std::io::Result<()> {
-    let wire_server_client = WireServerClient::new(get_wire_server_ip(), get_wire_server_port());
-    let goal_state = wire_server_client.get_goalstate()?;
-    let shared_config = wire_server_client.get_shared_config(goal_state.get_shared_config_uri())?;
-
-    let imds_client = ImdsClient::new(get_imds_ip(), get_imds_port());
-    let instance_info = imds_client.get_imds_instance_info()?;
-    let vm_meta_data = VMMetaData {
-        container_id: goal_state.get_container_id(),
-        role_name: shared_config.get_role_name(),
-        role_instance_name: shared_config.get_role_instance_name(),
-        tenant_name: shared_config.get_deployment_name(),
-        subscription_id: instance_info.get_subscription_id(),
-        resource_group_name: instance_info.get_resource_group_name(),
-        vm_id: instance_info.get_vm_id(),
-        image_origin: instance_info.get_image_origin(),
-    };
+    let (wire_server_ip, wire_server_port) = (get_wire_server_ip(), get_wire_server_port());
+    let (imds_ip, imds_port) = (get_imds_ip(), get_imds_port());
+    let (vm_meta_data, wire_server_errors, imds_result) = fetch_vm_meta_data(
+        get_vm_meta_data(),
+        || WireServerClient::new(wire_server_ip, wire_server_port).get_goalstate(),
+        move |url| WireServerClient::new(wire_server_ip, wire_server_port).get_shared_config(url),
+        move || ImdsClient::new(imds_ip, imds_port).get_instance_info(),
+        config::get_shared_config_fetch_timeout(),
+        config::get_metadata_fetch_concurrency(),
+    );
 
     unsafe {
         *VM_META_DATA = Some(vm_meta_data);
     }
 
+    let mut errors = Vec::new();
+    for (error_type, e) in wire_server_errors {
+        if retry_policy::get_throttled_error(&e).is_some() {
+            errors.push(format!("{} fetch throttled: {}", error_type, e));
+        } else {
+            errors.push(format!("{} fetch failed: {}", error_type, e));
+        }
+    }
+    if let Err(e) = imds_result {
+        errors.push(format!("InstanceInfo fetch failed: {}", e));
+    }
+    if errors.len() > 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            errors.join(" "),
+        ));
+    }
+
     Ok(())
 }
 
+/*
+    Fetch the goal state, the shared config and the IMDS instance info, and update the vm metadata
+    with the ones fetched successfully; the others keep their current values.
+    The shared config fetch is bounded by its own timeout so a slow endpoint does not stall the poll,
+    and the instance info is fetched in parallel with the WireServer fetches if the concurrency allows.
+*/
+fn fetch_vm_meta_data<G, S, I>(
+    mut vm_meta_data: VMMetaData,
+    get_goal_state: G,
+    get_shared_config: S,
+    get_instance_info: I,
+    shared_config_timeout: Duration,
+    concurrency: usize,
+) -> (
+    VMMetaData,
+    Vec<(WireServerErrorType, std::io::Error)>,
+    std::io::Result<()>,
+)
+where
+    G: FnOnce() -> std::io::Result<GoalState>,
+    S: FnOnce(String) -> std::io::Result<SharedConfig> + Send + 'static,
+    I: FnOnce() -> std::io::Result<InstanceInfo> + Send + 'static,
+{
+    // the instance info does not depend on the goal state,
+    // fetch it in parallel with the WireServer fetches if the concurrency allows
+    let instance_info_fetch = if concurrency > 1 {
+        Ok(thread::spawn(get_instance_info))
+    } else {
+        Err(get_instance_info)
+    };
+
+    let mut wire_server_errors = Vec::new();
+    match get_goal_state() {
+        Ok(goal_state) => {
+            vm_meta_data.container_id = goal_state.get_container_id();
+            let url = goal_state.get_shared_config_uri();
+            match helpers::run_with_timeout(move || get_shared_config(url), shared_config_timeout) {
+                Ok(shared_config) => {
+                    vm_meta_data.role_name = shared_config.get_role_name();
+                    vm_meta_data.role_instance_name = shared_config.get_role_instance_name();
+                    vm_meta_data.tenant_name = shared_config.get_deployment_name();
+                }
+                Err(e) => wire_server_errors.push((WireServerErrorType::SharedConfig, e)),
+            }
+        }
+        Err(e) => wire_server_errors.push((WireServerErrorType::GoalState, e)),
+    }
+
+    let instance_info = match instance_info_fetch {
+        Ok(handle) => match handle.join() {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "The instance info fetch thread panicked.",
+            )),
+        },
+        Err(get_instance_info) => get_instance_info(),
+    };
+    let imds_result = instance_info.map(|instance_info| {
+        vm_meta_data.subscription_id = instance_info.get_subscription_id();
+        vm_meta_data.resource_group_name = instance_info.get_resource_group_name();
+        vm_meta_data.vm_id = instance_info.get_vm_id();
+        vm_meta_data.image_origin = instance_info.get_image_origin();
+    });
+
+    (vm_meta_data, wire_server_errors, imds_result)
+}
+
 pub fn get_vm_meta_data() -> VMMetaData {
     unsafe {
         match &*VM_META_DATA {
@@ -318,8 +401,11 @@ fn send_data_to_wire_server(telemetry_data: TelemetryData) {
                     "Failed to send telemetry data to host with error: {}",
                     e
                 ));
-                // wait 15 seconds and retry
-                thread::sleep(Duration::from_secs(15));
+                // wait 15 seconds, or longer if the host asked to, and retry
+                let retry_after = retry_policy::get_throttled_error(&e)
+                    .and_then(|throttled| throttled.retry_after)
+                    .unwrap_or_default();
+                thread::sleep(retry_after.max(Duration::from_secs(15)));
             }
         }
     }
@@ -517,4 +603,132 @@ mod tests {
         }
         server_mock::stop(ip.to_string(), port);
     }
+
+    #[test]
+    fn fetch_vm_meta_data_test() {
+        const GOAL_STATE: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+        <GoalState>
+          <Version>2015-04-05</Version>
+          <Incarnation>16</Incarnation>
+          <Machine>
+            <ExpectedState>Started</ExpectedState>
+            <StopRolesDeadlineHint>300000</StopRolesDeadlineHint>
+            <LBProbePorts>
+              <Port>16001</Port>
+            </LBProbePorts>
+            <ExpectHealthReport>FALSE</ExpectHealthReport>
+          </Machine>
+          <Container>
+            <ContainerId>test-container-id</ContainerId>
+            <RoleInstanceList>
+              <RoleInstance>
+                <InstanceId>test-instance-id</InstanceId>
+                <State>Started</State>
+                <Configuration>
+                  <HostingEnvironmentConfig>http://127.0.0.1/hostingEnvironmentConfig</HostingEnvironmentConfig>
+                  <SharedConfig>http://127.0.0.1/sharedConfig</SharedConfig>
+                  <ExtensionsConfig>http://127.0.0.1/extensionsConfig</ExtensionsConfig>
+                  <FullConfig>http://127.0.0.1/fullConfig</FullConfig>
+                  <Certificates>http://127.0.0.1/certificates</Certificates>
+                  <ConfigName>test.xml</ConfigName>
+                </Configuration>
+              </RoleInstance>
+            </RoleInstanceList>
+          </Container>
+        </GoalState>"#;
+        const SHARED_CONFIG: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+        <SharedConfig version="1.0.0.0" goalStateIncarnation="16">
+          <Deployment name="test-deployment" guid="{00000000-0000-0000-0000-000000000000}" incarnation="1" isNonCancellableTopologyChangeEnabled="false">
+            <Service name="test-service" guid="{00000000-0000-0000-0000-000000000000}" />
+            <ServiceInstance name="test-service-instance" guid="{00000000-0000-0000-0000-000000000000}" />
+          </Deployment>
+          <Incarnation number="1" instance="test-role-instance" guid="{00000000-0000-0000-0000-000000000000}" />
+          <Role guid="{00000000-0000-0000-0000-000000000000}" name="test-role" settleTimeSeconds="0" />
+          <Instances>
+            <Instance id="test-role-instance" address="10.0.0.4">
+            </Instance>
+          </Instances>
+        </SharedConfig>"#;
+        const INSTANCE_INFO: &str = r#"{
+            "compute": {
+                "location": "westus",
+                "name": "test-vm",
+                "resourceGroupName": "test-resource-group",
+                "subscriptionId": "test-subscription-id",
+                "vmId": "test-vm-id",
+                "vmSize": "Standard_D2s_v3"
+            }
+        }"#;
+        let get_goal_state = || Ok(serde_xml_rs::from_str::<GoalState>(GOAL_STATE).unwrap());
+        let get_instance_info = || Ok(serde_json::from_str::<InstanceInfo>(INSTANCE_INFO).unwrap());
+
+        // all fetches succeed
+        let (vm_meta_data, wire_server_errors, imds_result) = fetch_vm_meta_data(
+            VMMetaData::default(),
+            get_goal_state,
+            |_| Ok(serde_xml_rs::from_str::<SharedConfig>(SHARED_CONFIG).unwrap()),
+            get_instance_info,
+            Duration::from_secs(10),
+            1,
+        );
+        assert_eq!(0, wire_server_errors.len());
+        assert!(imds_result.is_ok());
+        assert_eq!("test-container-id", vm_meta_data.container_id);
+        assert_eq!("test-deployment", vm_meta_data.tenant_name);
+        assert_eq!("test-vm-id", vm_meta_data.vm_id);
+
+        // slow shared config endpoint must not block the poll beyond its own timeout
+        let start = std::time::Instant::now();
+        let (vm_meta_data, wire_server_errors, imds_result) = fetch_vm_meta_data(
+            VMMetaData::default(),
+            get_goal_state,
+            |_| {
+                thread::sleep(Duration::from_secs(3));
+                Ok(serde_xml_rs::from_str::<SharedConfig>(SHARED_CONFIG).unwrap())
+            },
+            get_instance_info,
+            Duration::from_millis(200),
+            1,
+        );
+        assert!(
+            start.elapsed() < Duration::from_secs(2),
+            "shared config fetch must time out"
+        );
+        assert_eq!(1, wire_server_errors.len());
+        assert_eq!(WireServerErrorType::SharedConfig, wire_server_errors[0].0);
+        assert_eq!(std::io::ErrorKind::TimedOut, wire_server_errors[0].1.kind());
+        assert!(imds_result.is_ok());
+        assert_eq!(
+            "test-container-id", vm_meta_data.container_id,
+            "goal state must be updated even the shared config fetch timed out"
+        );
+        assert_eq!(constants::EMPTY_GUID, vm_meta_data.tenant_name);
+        assert_eq!("test-vm-id", vm_meta_data.vm_id);
+
+        // goal state failure skips the shared config, instance info is fetched in parallel
+        let start = std::time::Instant::now();
+        let (vm_meta_data, wire_server_errors, imds_result) = fetch_vm_meta_data(
+            VMMetaData::default(),
+            || {
+                thread::sleep(Duration::from_millis(500));
+                Err(std::io::Error::new(std::io::ErrorKind::Other, "goal state"))
+            },
+            |_| panic!("shared config must not be fetched without goal state"),
+            || {
+                thread::sleep(Duration::from_millis(500));
+                Ok(serde_json::from_str::<InstanceInfo>(INSTANCE_INFO).unwrap())
+            },
+            Duration::from_secs(10),
+            2,
+        );
+        assert!(
+            start.elapsed() < Duration::from_millis(900),
+            "instance info must be fetched in parallel"
+        );
+        assert_eq!(1, wire_server_errors.len());
+        assert_eq!(WireServerErrorType::GoalState, wire_server_errors[0].0);
+        assert!(imds_result.is_ok());
+        assert_eq!(constants::EMPTY_GUID, vm_meta_data.container_id);
+        assert_eq!("test-vm-id", vm_meta_data.vm_id);
+    }
 }
@@ -1,3 +1,4 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+pub mod redirector_mock;
 pub mod server_mock;
\ No newline at end of file
@@ -0,0 +1,36 @@
+// Copyright (c) Microsoft Corporation
+// SPDX-License-Identifier: MIT
+use crate::redirector::{AuditEntry, DestinationResolver};
+use std::io::{Error, ErrorKind};
+use std::net::TcpStream;
+
+// resolves every connection to the same audit entry, or fails both lookups if there is none
+pub struct FixedDestinationResolver {
+    entry: Option<AuditEntry>,
+}
+
+impl FixedDestinationResolver {
+    pub fn new(entry: AuditEntry) -> Self {
+        FixedDestinationResolver { entry: Some(entry) }
+    }
+
+    pub fn unresolved() -> Self {
+        FixedDestinationResolver { entry: None }
+    }
+
+    fn get_entry(&self) -> std::io::Result<AuditEntry> {
+        self.entry
+            .clone()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "No audit entry is set."))
+    }
+}
+
+impl DestinationResolver for FixedDestinationResolver {
+    fn lookup_audit(&self, _source_port: u16) -> std::io::Result<AuditEntry> {
+        self.get_entry()
+    }
+
+    fn get_audit_from_stream(&self, _tcp_stream: &TcpStream) -> std::io::Result<AuditEntry> {
+        self.get_entry()
+    }
+}
@@ -1,19 +1,61 @@
 // Copyright (c) Microsoft Corporation
 // SPDX-License-Identifier: MIT
+/*
+The mock WireServer, IMDS and HostGAPlugin endpoint for the tests, one per listening port.
+Besides the built-in responses, each port can be configured with the canned responses,
+the server errors and the delay before responding, so the retry, timeout and signature paths are deterministic.
+The received requests are recorded with their signature verified against MOCK_KEY.
+ */
+use crate::common::constants;
+use crate::common::helpers;
 use crate::common::http::request::Request;
 use crate::common::http::{self, response::Response};
 use crate::common::logger;
 use crate::key_keeper;
 use crate::key_keeper::key::{Key, KeyStatus};
 use once_cell::sync::Lazy;
+use std::collections::HashMap;
 use std::io::Write;
 use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use url::Position;
 use uuid::Uuid;
 
+// the key delivered by the mock, the signed requests are verified with it
+pub const MOCK_KEY: &str = "4A404E635266556A586E3272357538782F413F4428472B4B6250645367566B59";
+
 static EMPTY_GUID: Lazy<String> = Lazy::new(|| "00000000-0000-0000-0000-000000000000".to_string());
 static GUID: Lazy<String> = Lazy::new(|| Uuid::new_v4().to_string());
 static mut CURRENT_STATE: Lazy<String> =
     Lazy::new(|| String::from(key_keeper::MUST_SIG_WIRESERVER));
+// the number of the next requests responded with 503 by the listening port
+static SERVER_ERROR_COUNTS: Lazy<Mutex<HashMap<u16, u32>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// responded instead of the built-in responses by the listening port
+static CANNED_RESPONSES: Lazy<Mutex<HashMap<u16, Vec<CannedResponse>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// the delay before responding to each request by the listening port
+static RESPONSE_DELAYS: Lazy<Mutex<HashMap<u16, Duration>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+static RECEIVED_REQUESTS: Lazy<Mutex<HashMap<u16, Vec<ReceivedRequest>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone)]
+struct CannedResponse {
+    path_prefix: String,
+    status: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+pub struct ReceivedRequest {
+    pub method: String,
+    pub url: String, // the path and query
+    // None if the request is not signed, otherwise whether its signature matches MOCK_KEY
+    pub signature_verified: Option<bool>,
+}
 
 pub fn start(ip: String, port: u16) {
     logger::write_information("WireServer starting...".to_string());
@@ -40,10 +82,13 @@ pub fn stop(ip: String, port: u16) {
 fn handle_request(mut stream: TcpStream, ip: String, port: u16) -> bool {
     logger::write_information("WireServer processing request.".to_string());
 
-    let request = http::receive_request_data(&stream).unwrap();
+    let mut request = http::receive_request_data(&stream).unwrap();
     if request.url == "stop" {
         return false;
     }
+    if let Some(delay) = RESPONSE_DELAYS.lock().unwrap().get(&port) {
+        thread::sleep(*delay);
+    }
     let path: String;
     match request.get_url() {
         Some(url) => {
@@ -53,6 +98,29 @@ fn handle_request(mut stream: TcpStream, ip: String, port: u16) -> bool {
     }
     let segments: Vec<&str> = path.split('/').collect();
 
+    if take_server_error(port) {
+        record_request(port, &request);
+        let mut response = Response::from_status(Response::SERVICE_UNAVAILABLE.to_string());
+        _ = stream.write_all(response.to_raw_string().as_bytes());
+        _ = stream.flush();
+        return true;
+    }
+    if let Some(canned) = get_canned_response(port, &request) {
+        record_request(port, &request);
+        let mut response = Response::from_status(canned.status);
+        for (name, value) in canned.headers {
+            response.headers.add_header(name, value);
+        }
+        response.headers.add_header(
+            http::headers::CONTENT_LENGTH_HEADER_NAME.to_string(),
+            canned.body.len().to_string(),
+        );
+        response.set_body_as_string(canned.body);
+        _ = stream.write_all(response.to_raw_string().as_bytes());
+        _ = stream.flush();
+        return true;
+    }
+
     let mut response = Response::from_status(Response::OK.to_string());
     if request.method == "GET" {
         if segments.len() > 0 && segments[0] == "secure-channel" {
@@ -147,6 +215,18 @@ fn handle_request(mut stream: TcpStream, ip: String, port: u16) -> bool {
               </Instances>
             </SharedConfig>"#;
             response.set_body_as_string(shared_config_str.to_string());
+        } else if path.starts_with("metadata/identity/oauth2/token") {
+            let response_data = r#"{
+                "access_token": "eyJ0eXAi.test.token",
+                "client_id": "00000000-0000-0000-0000-000000000000",
+                "expires_in": "3599",
+                "expires_on": "1506484173",
+                "ext_expires_in": "3599",
+                "not_before": "1506480273",
+                "resource": "https://management.azure.com/",
+                "token_type": "Bearer"
+            }"#;
+            response.set_body_as_string(response_data.to_string());
         } else if path.starts_with("metadata/instance") {
             let response_data = r#"{
                 "compute": {
@@ -339,11 +419,13 @@ fn handle_request(mut stream: TcpStream, ip: String, port: u16) -> bool {
                 let content_length = request.headers.get_content_length().unwrap();
 
                 // receive body content from client
-                http::receive_body(&stream, content_length).unwrap();
+                let body = http::receive_body(&stream, content_length).unwrap();
+                request.set_body(body);
             }
         }
     }
 
+    record_request(port, &request);
     _ = stream.write_all(response.to_raw_string().as_bytes());
     _ = stream.flush();
     logger::write_information("WireServer processed request.".to_string());
@@ -362,3 +444,112 @@ pub fn set_secure_channel_state(enabled: bool) {
         }
     }
 }
+
+// respond 503 to the next count of requests to the port
+pub fn set_server_error_count(port: u16, count: u32) {
+    SERVER_ERROR_COUNTS.lock().unwrap().insert(port, count);
+}
+
+fn take_server_error(port: u16) -> bool {
+    let mut counts = SERVER_ERROR_COUNTS.lock().unwrap();
+    match counts.get_mut(&port) {
+        Some(count) if *count > 0 => {
+            *count -= 1;
+            true
+        }
+        _ => false,
+    }
+}
+
+// respond the status and body to the next requests whose path and query start with the prefix,
+// the canned responses set first are matched first
+pub fn set_canned_response(port: u16, path_prefix: &str, status: &str, body: &str) {
+    set_canned_response_with_headers(port, path_prefix, status, &[], body);
+}
+
+pub fn set_canned_response_with_headers(
+    port: u16,
+    path_prefix: &str,
+    status: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) {
+    CANNED_RESPONSES
+        .lock()
+        .unwrap()
+        .entry(port)
+        .or_default()
+        .push(CannedResponse {
+            path_prefix: path_prefix.to_string(),
+            status: status.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: body.to_string(),
+        });
+}
+
+pub fn set_response_delay(port: u16, delay: Duration) {
+    RESPONSE_DELAYS.lock().unwrap().insert(port, delay);
+}
+
+// the requests received by the port since the last take, in order
+pub fn take_received_requests(port: u16) -> Vec<ReceivedRequest> {
+    RECEIVED_REQUESTS
+        .lock()
+        .unwrap()
+        .remove(&port)
+        .unwrap_or_default()
+}
+
+// clear the canned responses, the server errors, the delay and the received requests of the port
+pub fn reset(port: u16) {
+    CANNED_RESPONSES.lock().unwrap().remove(&port);
+    SERVER_ERROR_COUNTS.lock().unwrap().remove(&port);
+    RESPONSE_DELAYS.lock().unwrap().remove(&port);
+    RECEIVED_REQUESTS.lock().unwrap().remove(&port);
+}
+
+fn get_path_and_query(request: &Request) -> String {
+    match request.get_url() {
+        Some(url) => url[Position::BeforePath..].to_string(),
+        None => request.url.to_string(),
+    }
+}
+
+fn get_canned_response(port: u16, request: &Request) -> Option<CannedResponse> {
+    let path_and_query = get_path_and_query(request);
+    CANNED_RESPONSES
+        .lock()
+        .unwrap()
+        .get(&port)?
+        .iter()
+        .find(|canned| path_and_query.starts_with(canned.path_prefix.as_str()))
+        .cloned()
+}
+
+fn record_request(port: u16, request: &Request) {
+    let signature_verified = request
+        .headers
+        .get_header(constants::AUTHORIZATION_HEADER)
+        .map(|authorization| {
+            let scheme = authorization.split_whitespace().next().unwrap_or_default();
+            helpers::verify_authorization_header(
+                &authorization,
+                scheme,
+                MOCK_KEY,
+                &request.as_sig_input(),
+            )
+        });
+    RECEIVED_REQUESTS
+        .lock()
+        .unwrap()
+        .entry(port)
+        .or_default()
+        .push(ReceivedRequest {
+            method: request.method.to_string(),
+            url: get_path_and_query(request),
+            signature_verified,
+        });
+}
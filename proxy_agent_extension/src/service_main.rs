@@ -785,6 +785,7 @@ mod tests {
             proxyAgentStatus: proxy_agent_status_obj,
             proxyConnectionSummary: vec![proxy_connection_summary_obj],
             failedAuthenticateSummary: vec![],
+            responseTimeSummary: None,
         };
 
         let mut status = StatusObj {
@@ -4,7 +4,9 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::{fs, fs::File, path::PathBuf, process::Command};
 use thread_id;
-use time::{format_description, OffsetDateTime};
+use time::{
+    format_description, format_description::well_known::Rfc3339, OffsetDateTime, PrimitiveDateTime,
+};
 
 #[cfg(windows)]
 use super::windows;
@@ -33,12 +35,12 @@ pub fn get_date_time_string() -> String {
     time_str.chars().collect()
 }
 
+const RFC1123_FORMAT: &str =
+    "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT";
+
 // This format is also the preferred HTTP date format. https://httpwg.org/specs/rfc9110.html#http.date
 pub fn get_date_time_rfc1123_string() -> String {
-    let date_format = format_description::parse(
-        "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT",
-    )
-    .unwrap();
+    let date_format = format_description::parse(RFC1123_FORMAT).unwrap();
 
     let time_str = OffsetDateTime::now_utc().format(&date_format).unwrap();
     time_str.chars().collect()
@@ -48,6 +50,29 @@ pub fn get_date_time_unix_nano() -> i128 {
     OffsetDateTime::now_utc().unix_timestamp_nanos()
 }
 
+// parse the RFC3339 date time string, e.g. "2024-01-01T00:00:00Z", to the unix timestamp in nanoseconds
+pub fn parse_date_time_rfc3339_unix_nano(date_time: &str) -> std::io::Result<i128> {
+    match OffsetDateTime::parse(date_time.trim(), &Rfc3339) {
+        Ok(time) => Ok(time.unix_timestamp_nanos()),
+        Err(e) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid RFC3339 date time '{}', error {}", date_time, e),
+        )),
+    }
+}
+
+// parse the HTTP date string, e.g. "Wed, 21 Oct 2015 07:28:00 GMT", to the unix timestamp in nanoseconds
+pub fn parse_date_time_rfc1123_unix_nano(date_time: &str) -> std::io::Result<i128> {
+    let date_format = format_description::parse(RFC1123_FORMAT).unwrap();
+    match PrimitiveDateTime::parse(date_time.trim(), &date_format) {
+        Ok(time) => Ok(time.assume_utc().unix_timestamp_nanos()),
+        Err(e) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid RFC1123 date time '{}', error {}", date_time, e),
+        )),
+    }
+}
+
 pub fn try_create_folder(dir: PathBuf) -> std::io::Result<()> {
     match dir.try_exists() {
         Ok(exists) => {
@@ -136,6 +161,18 @@ pub fn path_to_string(path: PathBuf) -> String {
     }
 }
 
+// the longest prefix within max_len bytes, it never splits a multibyte char
+pub fn truncate_on_char_boundary(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
 pub fn get_file_name(path: PathBuf) -> String {
     match path.file_name() {
         Some(s) => s.to_str().unwrap_or("InvalidPath").to_string(),
@@ -257,6 +294,23 @@ mod tests {
         _ = fs::remove_dir_all(&temp_test_path);
     }
 
+    #[test]
+    fn parse_date_time_rfc1123_test() {
+        assert_eq!(
+            1445412480 * 1_000_000_000,
+            super::parse_date_time_rfc1123_unix_nano("Wed, 21 Oct 2015 07:28:00 GMT").unwrap()
+        );
+        let now = super::get_date_time_unix_nano();
+        let parsed =
+            super::parse_date_time_rfc1123_unix_nano(&super::get_date_time_rfc1123_string())
+                .unwrap();
+        assert!(
+            (now - parsed).abs() < 2_000_000_000,
+            "the current date must round trip to the second"
+        );
+        assert!(super::parse_date_time_rfc1123_unix_nano("2015-10-21T07:28:00Z").is_err());
+    }
+
     #[test]
     fn path_to_string_test() {
         let path = "path_to_string_test";
@@ -264,6 +318,16 @@ mod tests {
         assert_eq!(path_str, path, "path_str mismatch");
     }
 
+    #[test]
+    fn truncate_on_char_boundary_test() {
+        // 'é' takes 2 bytes and '中' takes 3 bytes
+        assert_eq!("abé", super::truncate_on_char_boundary("abé", 4));
+        assert_eq!("ab", super::truncate_on_char_boundary("abé", 3));
+        assert_eq!("", super::truncate_on_char_boundary("é", 1));
+        assert_eq!("中", super::truncate_on_char_boundary("中文", 5));
+        assert_eq!("", super::truncate_on_char_boundary("", 0));
+    }
+
     #[test]
     fn execute_command_test() {
         let mut temp_test_path = env::temp_dir();
@@ -65,6 +65,16 @@ impl Clone for ProxyConnectionSummary {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+#[allow(non_snake_case)]
+pub struct ProxyResponseTimeSummary {
+    pub sampleCount: u64,     // the connections in the rolling window
+    pub p50ElapsedTime: u128, // in milliseconds
+    pub p90ElapsedTime: u128,
+    pub p99ElapsedTime: u128,
+    pub responseStatusCounts: HashMap<String, u64>,
+}
+
 #[derive(Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct GuestProxyAgentAggregateStatus {
@@ -72,4 +82,6 @@ pub struct GuestProxyAgentAggregateStatus {
     pub proxyAgentStatus: ProxyAgentStatus,
     pub proxyConnectionSummary: Vec<ProxyConnectionSummary>,
     pub failedAuthenticateSummary: Vec<ProxyConnectionSummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub responseTimeSummary: Option<ProxyResponseTimeSummary>, // over the latest proxied connections
 }
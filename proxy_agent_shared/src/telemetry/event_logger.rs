@@ -201,7 +201,8 @@ pub fn write_event(
 ) {
     let event_message;
     if message.len() > MAX_MESSAGE_LENGTH {
-        event_message = message[..MAX_MESSAGE_LENGTH].to_string();
+        event_message =
+            misc_helpers::truncate_on_char_boundary(&message, MAX_MESSAGE_LENGTH).to_string();
     } else {
         event_message = message.to_string();
     }